@@ -1,7 +1,8 @@
 MEMORY
 {
   /* NOTE K = KiBi = 1024 bytes */
-  FLASH : ORIGIN = 0x08000000, LENGTH = 1024K
+  /* The last 256K sector (sector 7) is kept for settings, see storage.rs */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 768K
   RAM : ORIGIN = 0x20000000, LENGTH = 320K
 }
 
@@ -2,7 +2,8 @@
 MEMORY
 {
   /* NOTE K = KiBi = 1024 bytes */
-  FLASH : ORIGIN = 0x08000000, LENGTH = 1M
+  /* The last 256K sector (sector 7) is kept for settings, see storage.rs */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 768K
   RAM : ORIGIN = 0x20000000, LENGTH = 64K + 240K + 16K
 }
 
@@ -0,0 +1,146 @@
+//! Controller alarms, decoded for the alarm page.
+//!
+//! The codes are GRBL's `ALARM:N` numbers, which grblHAL shares. Other
+//! backends raise the ones that fit, or `Locked` when they only know the
+//! machine won't move.
+
+use core::fmt::{self, Write};
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Alarm {
+    HardLimit,
+    SoftLimit,
+    ResetWhileMoving,
+    ProbeNotReady,
+    ProbeMissed,
+    HomingReset,
+    HomingDoor,
+    HomingPullOff,
+    HomingNotFound,
+    HomingSecondSwitch,
+    // Alarmed without saying why, e.g. at power up when homing is required
+    Locked,
+    Other(u8),
+}
+
+impl Alarm {
+    pub fn from_code(code: u8) -> Alarm {
+        match code {
+            1 => Alarm::HardLimit,
+            2 => Alarm::SoftLimit,
+            3 => Alarm::ResetWhileMoving,
+            4 => Alarm::ProbeNotReady,
+            5 => Alarm::ProbeMissed,
+            6 => Alarm::HomingReset,
+            7 => Alarm::HomingDoor,
+            8 => Alarm::HomingPullOff,
+            9 => Alarm::HomingNotFound,
+            10 => Alarm::HomingSecondSwitch,
+            code => Alarm::Other(code),
+        }
+    }
+
+    /// Parses an `ALARM:N` line, returning None for any other line
+    pub fn parse(line: &str) -> Option<Alarm> {
+        let code = line.trim().strip_prefix("ALARM:")?;
+        Some(Alarm::from_code(code.trim().parse().unwrap_or(0)))
+    }
+
+    /// What happened, in a few words
+    pub fn describe<W: Write>(self, out: &mut W) -> fmt::Result {
+        match self {
+            Alarm::HardLimit => write!(out, "Limit switch hit"),
+            Alarm::SoftLimit => write!(out, "Move beyond travel"),
+            Alarm::ResetWhileMoving => write!(out, "Reset while moving"),
+            Alarm::ProbeNotReady => write!(out, "Probe already touching"),
+            Alarm::ProbeMissed => write!(out, "Probe didn't touch"),
+            Alarm::HomingReset => write!(out, "Homing was reset"),
+            Alarm::HomingDoor => write!(out, "Door opened homing"),
+            Alarm::HomingPullOff => write!(out, "Homing pull off failed"),
+            Alarm::HomingNotFound => write!(out, "Homing switch not found"),
+            Alarm::HomingSecondSwitch => write!(out, "Second switch not found"),
+            Alarm::Locked => write!(out, "Machine locked"),
+            Alarm::Other(code) => write!(out, "Alarm {}", code),
+        }
+    }
+
+    /// What to do about it. Lines are split with '\n' to fit the page.
+    pub fn action(self) -> &'static str {
+        match self {
+            Alarm::HardLimit => "Position may be lost.\nClear the switch, then\nhome the machine.",
+            Alarm::ResetWhileMoving => "Position may be lost.\nHome the machine again.",
+            Alarm::SoftLimit => {
+                "Position is still good.\nUnlock, then check the\nprogram and work offsets."
+            }
+            Alarm::ProbeNotReady => {
+                "Check the probe wiring\nand that it is clear\nof the work, then unlock."
+            }
+            Alarm::ProbeMissed => "Check the probe and the\ndistance asked for,\nthen unlock.",
+            Alarm::HomingReset | Alarm::HomingDoor => "Close the door and\nhome again.",
+            Alarm::HomingPullOff => {
+                "Check the switch wiring,\nor increase pull off,\nthen home again."
+            }
+            Alarm::HomingNotFound | Alarm::HomingSecondSwitch => {
+                "Check the switches and\ntheir wiring, then\nhome again."
+            }
+            Alarm::Locked => "Home the machine, or\nunlock if its position\nis known to be good.",
+            Alarm::Other(_) => "See the controller's\ndocumentation, then\nreset or unlock.",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes() {
+        let alarms = [
+            Alarm::HardLimit,
+            Alarm::SoftLimit,
+            Alarm::ResetWhileMoving,
+            Alarm::ProbeNotReady,
+            Alarm::ProbeMissed,
+            Alarm::HomingReset,
+            Alarm::HomingDoor,
+            Alarm::HomingPullOff,
+            Alarm::HomingNotFound,
+            Alarm::HomingSecondSwitch,
+        ];
+        for (code, alarm) in (1..).zip(alarms) {
+            assert_eq!(Alarm::from_code(code), alarm);
+        }
+        assert_eq!(Alarm::from_code(0), Alarm::Other(0));
+        assert_eq!(Alarm::from_code(11), Alarm::Other(11));
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(Alarm::parse("ALARM:1"), Some(Alarm::HardLimit));
+        assert_eq!(Alarm::parse("ALARM: 9\r\n"), Some(Alarm::HomingNotFound));
+        assert_eq!(Alarm::parse("ALARM:14"), Some(Alarm::Other(14)));
+        // GRBL 0.9 said it in words
+        assert_eq!(Alarm::parse("ALARM:Hard/soft limit"), Some(Alarm::Other(0)));
+        assert_eq!(Alarm::parse("ok"), None);
+        assert_eq!(Alarm::parse("[MSG:'$H'|'$X' to unlock]"), None);
+        assert_eq!(Alarm::parse("<Alarm|MPos:0.000,0.000,0.000>"), None);
+    }
+
+    #[test]
+    fn fits_the_page() {
+        // Across the 480 pixel page, less its margins, in 16 and 12 pixel
+        // wide characters
+        let alarms = (0..=12).map(Alarm::from_code).chain([Alarm::Locked]);
+        for alarm in alarms {
+            let mut text = String::new();
+            alarm.describe(&mut text).unwrap();
+            assert!(!text.is_empty() && text.len() <= 28, "{}", text);
+            let action = alarm.action();
+            assert!(action.lines().count() <= 3, "{}", action);
+            assert!(action.lines().all(|line| line.len() <= 38), "{}", action);
+        }
+        let mut text = String::new();
+        Alarm::Other(42).describe(&mut text).unwrap();
+        assert_eq!(text, "Alarm 42");
+    }
+}
@@ -0,0 +1,390 @@
+//! Machine controller backends.
+//!
+//! The UI only ever talks to a `MachineBackend`: it gets `Status` back from
+//! `poll`, and queues `Command`s that the main loop hands to `send`. Which
+//! firmware is on the other end of the serial port is picked in main.rs.
+//! Programs go a line at a time through `stream`, see job.rs. Lines typed
+//! on the MDI page are answered too, and anything else the controller says
+//! is passed on by `take_message`.
+
+use core::fmt::Write;
+
+use crate::alarm::Alarm;
+use crate::jog::JogCommand;
+use crate::text::TextBuffer;
+use crate::ui;
+
+pub const MDI_LEN: usize = 64;
+pub const MESSAGE_LEN: usize = 48;
+const QUEUE_LEN: usize = 8;
+const REPLIES_LEN: usize = 40;
+const MESSAGES_LEN: usize = 4;
+
+/// A line from the controller that isn't a status or an answer
+pub type Message = TextBuffer<MESSAGE_LEN>;
+
+/// Where the machine is and what it is doing, whatever the firmware
+#[derive(PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub struct Status {
+    pub running: ui::Running,
+    pub mpos: [f32; 3],
+    pub wpos: [f32; 3],
+    pub feed: f32,
+    pub spindle: f32,
+    pub overrides: Overrides,
+    pub accessories: Accessories,
+    // Why the machine is alarmed, while it is
+    pub alarm: Option<Alarm>,
+    // The controller's work offset changed with this report
+    pub new_offset: bool,
+}
+
+/// Percentages the controller scales the programmed feed, rapid and
+/// spindle speed by
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Overrides {
+    pub feed: u16,
+    pub rapid: u16,
+    pub spindle: u16,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl Overrides {
+    pub fn new() -> Overrides {
+        Overrides {
+            feed: 100,
+            rapid: 100,
+            spindle: 100,
+        }
+    }
+}
+
+/// Whether the controller has the spindle (M3/M4) and coolant (M7/M8) on.
+/// Only GRBL says, the other backends always report them off.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Accessories {
+    pub spindle: bool,
+    pub coolant: bool,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl Accessories {
+    pub fn new() -> Accessories {
+        Accessories {
+            spindle: false,
+            coolant: false,
+        }
+    }
+}
+
+/// A new override percentage. GRBL's limits apply to every backend: feed
+/// and spindle 10 to 200%, rapids 25, 50 or 100%.
+#[derive(PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub enum Override {
+    Feed(u16),
+    Rapid(u16),
+    Spindle(u16),
+}
+
+pub const MIN_OVERRIDE: u16 = 10;
+pub const MAX_OVERRIDE: u16 = 200;
+
+impl Override {
+    /// Brings the percentage into the range the controller allows
+    pub fn limited(self) -> Override {
+        match self {
+            Override::Feed(p) => Override::Feed(p.clamp(MIN_OVERRIDE, MAX_OVERRIDE)),
+            Override::Spindle(p) => Override::Spindle(p.clamp(MIN_OVERRIDE, MAX_OVERRIDE)),
+            Override::Rapid(p) => Override::Rapid(match p {
+                0..=37 => 25,
+                38..=75 => 50,
+                _ => 100,
+            }),
+        }
+    }
+
+    /// Changes the matching percentage in `overrides`
+    pub fn apply(self, overrides: &mut Overrides) {
+        match self.limited() {
+            Override::Feed(p) => overrides.feed = p,
+            Override::Rapid(p) => overrides.rapid = p,
+            Override::Spindle(p) => overrides.spindle = p,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub enum Command {
+    Jog(JogCommand),
+    // Make an axis read this value at its current position
+    SetWork(usize, f32),
+    FeedHold,
+    Resume,
+    Reset,
+    Mdi(TextBuffer<MDI_LEN>),
+    Override(Override),
+    Home,
+    Unlock,
+}
+
+impl Command {
+    /// Commands that are safe to send while a program is streaming. They
+    /// don't go through the controller's line buffer, except Marlin's
+    /// override lines, which go one at a time between the job's anyway.
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn is_realtime(&self) -> bool {
+        matches!(
+            self,
+            Command::FeedHold | Command::Resume | Command::Reset | Command::Override(_)
+        )
+    }
+}
+
+/// The controller's answer to one line
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Reply {
+    Ok,
+    Error(u8),
+}
+
+/// A byte stream to the controller, e.g. the UART
+#[cfg_attr(test, allow(dead_code))]
+pub trait Port {
+    fn read(&mut self) -> Option<u8>;
+    fn write(&mut self, data: &[u8]);
+}
+
+pub trait MachineBackend {
+    /// Called every time round the main loop to send polls and read
+    /// replies. Returns the latest status, if a new one came in.
+    #[cfg_attr(test, allow(dead_code))]
+    fn poll(&mut self, now_ms: u32) -> Option<Status>;
+    #[cfg_attr(test, allow(dead_code))]
+    fn connected(&self, now_ms: u32) -> bool;
+    fn jog(&mut self, command: JogCommand);
+    fn set_work(&mut self, axis: usize, value: f32);
+    fn feed_hold(&mut self);
+    fn resume(&mut self);
+    fn reset(&mut self);
+    /// Changes an override, as soon as possible even in the middle of a job
+    fn set_override(&mut self, value: Override);
+    /// Runs the homing cycle, which also clears an alarm
+    fn home(&mut self);
+    /// Clears an alarm without homing, trusting the position
+    fn unlock(&mut self);
+    /// Sends a line typed in by hand. It gets a `Reply` like a streamed one.
+    fn mdi(&mut self, line: &str);
+    /// Sends one line of a program. Every line gets a `Reply`, in order.
+    fn stream(&mut self, line: &str);
+    fn take_reply(&mut self) -> Option<Reply>;
+    /// The next line from the controller that wasn't a status or reply,
+    /// such as a startup banner, alarm or setting
+    #[cfg_attr(test, allow(dead_code))]
+    fn take_message(&mut self) -> Option<Message> {
+        None
+    }
+    /// Bytes of streamed lines the controller can hold before answering
+    /// them. Zero means send a line and wait for its reply.
+    fn rx_buffer(&self) -> usize;
+
+    #[cfg_attr(test, allow(dead_code))]
+    fn send(&mut self, command: &Command) {
+        match command {
+            Command::Jog(jog) => self.jog(*jog),
+            Command::SetWork(axis, value) => self.set_work(*axis, *value),
+            Command::FeedHold => self.feed_hold(),
+            Command::Resume => self.resume(),
+            Command::Reset => self.reset(),
+            Command::Mdi(line) => self.mdi(line.as_str()),
+            Command::Override(value) => self.set_override(value.limited()),
+            Command::Home => self.home(),
+            Command::Unlock => self.unlock(),
+        }
+    }
+}
+
+/// Commands waiting for the main loop. The oldest is dropped when full.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub struct Commands {
+    items: [Option<Command>; QUEUE_LEN],
+    head: usize,
+    len: usize,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl Commands {
+    pub fn new() -> Commands {
+        Commands {
+            items: [None; QUEUE_LEN],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, command: Command) {
+        if self.len == QUEUE_LEN {
+            self.pop();
+        }
+        self.items[(self.head + self.len) % QUEUE_LEN] = Some(command);
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<Command> {
+        if self.len == 0 {
+            return None;
+        }
+        let command = self.items[self.head].take();
+        self.head = (self.head + 1) % QUEUE_LEN;
+        self.len -= 1;
+        command
+    }
+}
+
+/// Matches the controller's `ok` and `error` answers up with the lines
+/// that caused them. Controllers answer every line in order, but only the
+/// answers to streamed and MDI lines are wanted, not those to polls or
+/// jogs.
+#[derive(Copy, Clone, Debug)]
+pub struct Replies {
+    streamed: [bool; REPLIES_LEN],
+    sent_head: usize,
+    sent_len: usize,
+    replies: [Reply; REPLIES_LEN],
+    head: usize,
+    len: usize,
+}
+
+impl Replies {
+    pub fn new() -> Replies {
+        Replies {
+            streamed: [false; REPLIES_LEN],
+            sent_head: 0,
+            sent_len: 0,
+            replies: [Reply::Ok; REPLIES_LEN],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// A line went to the controller. `streamed` is true if its answer is
+    /// wanted.
+    pub fn sent(&mut self, streamed: bool) {
+        if self.sent_len == REPLIES_LEN {
+            // Lost track already, forget the oldest
+            self.sent_head = (self.sent_head + 1) % REPLIES_LEN;
+            self.sent_len -= 1;
+        }
+        self.streamed[(self.sent_head + self.sent_len) % REPLIES_LEN] = streamed;
+        self.sent_len += 1;
+    }
+
+    /// The controller answered the oldest line it hadn't yet
+    pub fn answered(&mut self, reply: Reply) {
+        if self.sent_len == 0 {
+            return; // Sent before we started, or by someone else
+        }
+        let streamed = self.streamed[self.sent_head];
+        self.sent_head = (self.sent_head + 1) % REPLIES_LEN;
+        self.sent_len -= 1;
+        if streamed && self.len < REPLIES_LEN {
+            self.replies[(self.head + self.len) % REPLIES_LEN] = reply;
+            self.len += 1;
+        }
+    }
+
+    /// The next answer to a streamed line
+    pub fn take(&mut self) -> Option<Reply> {
+        if self.len == 0 {
+            return None;
+        }
+        let reply = self.replies[self.head];
+        self.head = (self.head + 1) % REPLIES_LEN;
+        self.len -= 1;
+        Some(reply)
+    }
+
+    /// The controller threw away whatever it hadn't answered, e.g. on reset
+    pub fn clear(&mut self) {
+        *self = Replies::new();
+    }
+}
+
+/// Makes a message of a line, cut short if it doesn't fit. Blank lines
+/// aren't worth one.
+#[cfg_attr(test, allow(dead_code))]
+pub fn message(line: &str) -> Option<Message> {
+    let line = line.trim();
+    if line.is_empty() {
+        return None;
+    }
+    let mut message = Message::new();
+    for c in line.chars() {
+        if message.write_char(c).is_err() {
+            break;
+        }
+    }
+    Some(message)
+}
+
+/// Lines the controller sent that nobody has read yet. The oldest is
+/// dropped when full.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub struct Messages {
+    items: [Message; MESSAGES_LEN],
+    head: usize,
+    len: usize,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl Messages {
+    pub fn new() -> Messages {
+        Messages {
+            items: [TextBuffer::new(); MESSAGES_LEN],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, message: Message) {
+        if self.len == MESSAGES_LEN {
+            self.pop();
+        }
+        self.items[(self.head + self.len) % MESSAGES_LEN] = message;
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<Message> {
+        if self.len == 0 {
+            return None;
+        }
+        let message = self.items[self.head];
+        self.head = (self.head + 1) % MESSAGES_LEN;
+        self.len -= 1;
+        Some(message)
+    }
+}
+
+/// Reads `X10 Y-2.5 F300` (or Marlin's `X:10.00`) style words from a line,
+/// ignoring anything it doesn't understand. Used by backends that only need
+/// a few words.
+#[cfg(any(feature = "marlin", feature = "sim"))]
+pub fn word(line: &str, letter: char) -> Option<f32> {
+    let upper = letter.to_ascii_uppercase();
+    let mut rest = line;
+    while let Some(i) = rest.find(|c: char| c.to_ascii_uppercase() == upper) {
+        rest = &rest[i + 1..];
+        let rest = rest.strip_prefix(':').unwrap_or(rest);
+        let end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-' || c == '+'))
+            .unwrap_or(rest.len());
+        if let Ok(value) = rest[..end].parse() {
+            return Some(value);
+        }
+    }
+    None
+}
@@ -0,0 +1,36 @@
+use cortex_m::peripheral::{DCB, DWT};
+
+/// Millisecond time base built on the DWT cycle counter.
+///
+/// The cycle counter wraps after about 20 seconds at 216 MHz, so `now_ms`
+/// has to be called more often than that to keep the count monotonic. The
+/// main loop does so every few milliseconds.
+pub struct Clock {
+    cycles_per_ms: u32,
+    last_cycles: u32,
+    spare_cycles: u32,
+    ms: u32,
+}
+
+impl Clock {
+    pub fn new(mut dcb: DCB, mut dwt: DWT, sysclk_hz: u32) -> Clock {
+        dcb.enable_trace();
+        dwt.enable_cycle_counter();
+        Clock {
+            cycles_per_ms: sysclk_hz / 1000,
+            last_cycles: DWT::cycle_count(),
+            spare_cycles: 0,
+            ms: 0,
+        }
+    }
+
+    /// Milliseconds since the clock was created. Wraps after ~49 days.
+    pub fn now_ms(&mut self) -> u32 {
+        let now = DWT::cycle_count();
+        let elapsed = now.wrapping_sub(self.last_cycles) + self.spare_cycles;
+        self.last_cycles = now;
+        self.ms = self.ms.wrapping_add(elapsed / self.cycles_per_ms);
+        self.spare_cycles = elapsed % self.cycles_per_ms;
+        self.ms
+    }
+}
@@ -1,5 +1,11 @@
+// Mostly screen layout, only read by the display code, which the host
+// tests leave out
+#![cfg_attr(test, allow(dead_code))]
+
 use embedded_graphics::pixelcolor::{Rgb565, RgbColor};
 
+use crate::leadscrew::Pitch;
+
 // DIMENSIONS
 const WIDTH: u16 = 480;
 const HEIGHT: u16 = 272;
@@ -36,4 +42,147 @@ pub const KEY_X_SPACING: u16 = 57;
 pub const KEY_Y_OFFSET: u16 = 2;
 pub const KEY_Y_SPACING: u16 = 55; //270 / 5;
 
-pub const MAXKEYS: usize = 30; // No vecs, so touchzones are stored in array
+pub const MAXKEYS: usize = 40; // No vecs, so touchzones are stored in array
+pub const BUTTON_LABEL_LEN: usize = 8; // Longer labels are cut short
+
+// Encoders and feed rate readout
+pub const ENCODER_MM_PER_COUNT: [f32; 3] = [0.005, 0.005, 0.005]; // 5um scales, negate to reverse
+pub const FEED_SAMPLE_MS: u32 = 100;
+pub const FEED_FILTER_ALPHA: f32 = 0.4;
+pub const FEED_OVERSPEED_MM_MIN: f32 = 1500.0; // Faster than any sane manual feed
+pub const FEED_LEFT: u16 = SEVEN_SEG_LEFT;
+pub const FEED_TOP: u16 = SEVEN_SEG_TOP + 3 * SEVEN_SEG_VSPACE;
+pub const FEED_WIDTH: u16 = SEVEN_SEG_WIDTH;
+pub const FEED_HEIGHT: u16 = 56;
+pub const FEED_TEXT_COLOR: Rgb565 = <Rgb565>::GREEN;
+pub const FEED_OVERSPEED_COLOR: Rgb565 = <Rgb565>::RED;
+
+// Distance to go
+pub const DTG_APPROACH_BAND: f32 = 1.0; // mm either side of the target that shows orange
+pub const DTG_TOLERANCE: f32 = 0.005; // mm either side of the target that counts as there
+pub const DTG_TEXT_COLOR: Rgb565 = <Rgb565>::WHITE;
+pub const DTG_APPROACH_COLOR: Rgb565 = ORANGE;
+pub const DTG_ARRIVED_COLOR: Rgb565 = <Rgb565>::GREEN;
+
+// Menu page
+pub const MENU_LEFT: u16 = 10;
+pub const MENU_TOP: u16 = 10;
+pub const MENU_COLUMNS: u16 = 5;
+pub const MENU_BUTTON_WIDTH: u16 = 88;
+pub const MENU_BUTTON_HEIGHT: u16 = 56;
+pub const MENU_X_SPACING: u16 = 93;
+pub const MENU_Y_SPACING: u16 = 64;
+
+// Row of soft keys along the bottom left of a page
+pub const SOFT_KEY_LEFT: u16 = 1;
+pub const SOFT_KEY_TOP: u16 = FEED_TOP + (FEED_HEIGHT - BUTTON_HEIGHT) / 2;
+pub const SOFT_KEY_WIDTH: u16 = 46;
+pub const SOFT_KEY_SPACING: u16 = 51;
+
+// Point memory page
+pub const POINTS_LIST_TOP: u16 = 2;
+pub const POINTS_LIST_WIDTH: u16 = SEVEN_SEG_WIDTH + 7 + BUTTON_WIDTH - 1;
+pub const POINTS_ROW_HEIGHT: u16 = 34;
+pub const POINTS_ROWS: usize = 4;
+pub const POINTS_EDIT_TOP: u16 = POINTS_LIST_TOP + POINTS_ROWS as u16 * POINTS_ROW_HEIGHT + 6;
+pub const POINTS_SELECTED_COLOR: Rgb565 = Rgb565::new(0, 0, 14);
+
+// Datum history page
+pub const HISTORY_ROWS: usize = 12;
+pub const HISTORY_ROW_HEIGHT: u16 = 22;
+pub const HISTORY_LIST_WIDTH: u16 = 300;
+pub const HISTORY_UNDONE_COLOR: Rgb565 = Rgb565::new(12, 24, 12);
+pub const PAGE_KEY_LEFT: u16 = 320;
+
+// Touch probe page
+pub const PROBE_RADIUS: f32 = 2.0; // mm, a common 4mm edge finder tip
+pub const PROBE_STATUS_TOP: u16 = SEVEN_SEG_TOP + SEVEN_SEG_VSPACE;
+pub const PROBE_STATUS_HEIGHT: u16 = 2 * SEVEN_SEG_VSPACE - 9;
+
+// Power feed page, see powerfeed.rs
+pub const POWER_FEED_SLOW_MM: f32 = 2.0; // Creep this close to a stop
+pub const POWER_FEED_CREEP: u8 = 10; // Percent of full speed
+pub const POWER_FEED_COAST_MM: f32 = 0.05; // Cut out this far short of a stop
+
+// Soft limits page, see limits.rs
+pub const SOFT_LIMIT_WARN_MM: f32 = 5.0; // Readouts warn this close to a limit
+pub const SOFT_LIMIT_NEAR_COLOR: Rgb565 = ORANGE;
+pub const SOFT_LIMIT_OVER_COLOR: Rgb565 = <Rgb565>::RED;
+
+// Electronic leadscrew page, see leadscrew.rs
+pub const SPINDLE_COUNTS_PER_REV: u32 = 4096; // 1024 line encoder, counted x4
+pub const LEADSCREW_STEPS_PER_REV: u32 = 1600; // 200 step motor, 8 microsteps
+pub const LEADSCREW_PITCH: Pitch = Pitch::Metric(2000); // Motor to carriage, per turn
+
+// Threading dial page, see dial.rs
+pub const DIAL_LEADSCREW_PITCH: Pitch = Pitch::Tpi(80); // The lathe's own, 8 TPI
+pub const DIAL_TOLERANCE_REV: f32 = 0.05; // Off by less than this is in step
+pub const DIAL_ENGAGED_MM: f32 = 1.0; // Moving in step this far, the nuts are closed
+pub const DIAL_FLASH_MS: u32 = 250;
+pub const DIAL_INDICATOR_TOP: u16 = KEY_Y_OFFSET + KEY_Y_SPACING;
+pub const DIAL_ENGAGE_COLOR: Rgb565 = <Rgb565>::GREEN;
+pub const DIAL_WAIT_COLOR: Rgb565 = ORANGE;
+pub const DIAL_WRONG_COLOR: Rgb565 = <Rgb565>::RED;
+
+// Relay outputs page, see relays.rs
+pub const RELAY_VACUUM_RUN_ON_MS: u32 = 5000; // Vacuum keeps going after the spindle
+pub const RELAY_ON_COLOR: Rgb565 = <Rgb565>::GREEN;
+pub const RELAY_OFF_COLOR: Rgb565 = Rgb565::new(12, 24, 12);
+pub const RELAY_ROW_HEIGHT: u16 = 40;
+
+// Macro page: the buttons from the card laid out like the menu, over a
+// status line and keys, see macros.rs
+pub const MACRO_STATUS_WIDTH: u16 = KEY_X_OFFSET + 2 * KEY_X_SPACING - 4;
+pub const MACRO_KEY_TOP: u16 = 272 - BUTTON_HEIGHT - 2;
+
+// Machine controller on USART6
+pub const MACHINE_BAUD: u32 = 115_200;
+pub const MACHINE_POLL_MS: u32 = 200;
+pub const MACHINE_TIMEOUT_MS: u32 = 1000;
+pub const RUNNING_IDLE_COLOR: Rgb565 = <Rgb565>::GREEN;
+pub const RUNNING_BUSY_COLOR: Rgb565 = ORANGE;
+pub const RUNNING_ALARM_COLOR: Rgb565 = <Rgb565>::RED;
+
+// Jog page
+pub const JOG_FEED_MM_MIN: f32 = 1000.0;
+pub const JOG_SEGMENT_MS: u32 = 100; // Length of each move while a key is held
+
+// SD card files and job pages
+pub const FILES_ROWS: usize = 8;
+pub const FILES_ROW_HEIGHT: u16 = 30;
+pub const JOB_STATUS_HEIGHT: u16 = 268;
+pub const JOB_BAR_COLOR: Rgb565 = <Rgb565>::GREEN;
+pub const JOB_ERROR_COLOR: Rgb565 = <Rgb565>::RED;
+pub const JOB_OVERRIDES_HEIGHT: u16 = 26; // Along the bottom of the status
+
+// Toolpath preview page
+pub const PLOT_WIDTH: u16 = HISTORY_LIST_WIDTH;
+pub const PLOT_HEIGHT: u16 = JOB_STATUS_HEIGHT;
+pub const PLOT_FEED_COLOR: Rgb565 = <Rgb565>::GREEN;
+pub const PLOT_RAPID_COLOR: Rgb565 = Rgb565::new(8, 16, 8);
+pub const PLOT_ORIGIN_COLOR: Rgb565 = LIGHT_BLUE;
+pub const PLOT_TOOL_COLOR: Rgb565 = <Rgb565>::RED;
+pub const PLOT_TOOL_SIZE: i32 = 6; // Pixels either side of the tool cross
+
+// Conversational wizards page
+pub const WIZARD_TITLE_HEIGHT: u16 = 22;
+pub const WIZARD_ROWS: usize = 5;
+pub const WIZARD_ROW_HEIGHT: u16 = 24;
+
+// MDI page: scrollback and command line over a keyboard of small keys
+pub const MDI_KEY_ROWS: u16 = 5;
+pub const MDI_KEY_WIDTH: u16 = 44;
+pub const MDI_KEY_HEIGHT: u16 = 27;
+pub const MDI_KEY_X_SPACING: u16 = 48;
+pub const MDI_KEY_Y_SPACING: u16 = 30;
+pub const MDI_KEYS_LEFT: u16 = 2;
+pub const MDI_KEYS_TOP: u16 = 272 - MDI_KEY_ROWS * MDI_KEY_Y_SPACING;
+pub const MDI_LINE_HEIGHT: u16 = 19;
+pub const MDI_SENT_COLOR: Rgb565 = LIGHT_BLUE;
+pub const MDI_OK_COLOR: Rgb565 = <Rgb565>::GREEN;
+pub const MDI_ERROR_COLOR: Rgb565 = <Rgb565>::RED;
+
+// Alarm page: the banner fills the screen above a row of large keys
+pub const ALARM_KEY_TOP: u16 = 272 - MENU_BUTTON_HEIGHT - 6;
+pub const ALARM_KEY_WIDTH: u16 = 110;
+pub const ALARM_KEY_SPACING: u16 = 118;
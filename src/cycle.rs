@@ -0,0 +1,583 @@
+//! Probing cycles run by the controller with G38.2: a touch plate for Z,
+//! and a 3D touch block for a corner in X, Y and Z.
+//!
+//! Each touch is a fast probe to find the surface, a short retract, then a
+//! slow probe for the reading that counts. The axis is then zeroed so the
+//! point of contact reads the plate's thickness (Z), or the block's wall
+//! plus the tool radius short of the stock edge (X and Y).
+//!
+//! The corner cycle starts with the tool over the top of the block, less
+//! than the probe distance less the tool radius from its X and Y faces. It
+//! probes Z, goes out past the X face and drops beside it to probe in +X,
+//! then does the same for Y, and ends back over the start point.
+//!
+//! Nothing here touches the display or the controller. The view sends the
+//! lines `take_step` gives it, one at a time, and feeds back the answers, any
+//! `[PRB:]` report, and the machine status. A cycle that fails or is stopped
+//! part way still sends the G90 that `abort` gives back, so the controller
+//! isn't left in relative distance mode.
+
+use core::fmt::{self, Write};
+
+use crate::backend::{Reply, MDI_LEN};
+use crate::text::TextBuffer;
+use crate::ui::Running;
+use crate::wizard::{field, Field, Fields};
+
+const AXIS_NAMES: [char; 3] = ['X', 'Y', 'Z'];
+const THICKNESS: usize = 0;
+const FAST: usize = 1;
+const SLOW: usize = 2;
+const DISTANCE: usize = 3;
+const RETRACT: usize = 4;
+const TOOL: usize = 5;
+const WALL: usize = 6;
+
+pub const N_SETTINGS: usize = 7;
+const SETTINGS: [Field; N_SETTINGS] = [
+    field("Plate thick", 10.0),
+    field("Fast feed", 100.0),
+    field("Slow feed", 25.0),
+    field("Distance", 20.0),
+    field("Retract", 2.0),
+    field("Tool dia", 6.0),
+    field("Block wall", 10.0),
+];
+
+pub type Line = TextBuffer<MDI_LEN>;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Kind {
+    Z,
+    Corner,
+}
+
+impl Kind {
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Z => "Z touch plate",
+            Kind::Corner => "XYZ corner",
+        }
+    }
+}
+
+/// The numbers typed in for the cycles, shared by both
+#[derive(Copy, Clone, Debug)]
+pub struct Settings {
+    values: [f32; N_SETTINGS],
+}
+
+impl Settings {
+    pub fn new() -> Settings {
+        let mut values = [0.0; N_SETTINGS];
+        for (value, setting) in values.iter_mut().zip(SETTINGS.iter()) {
+            *value = setting.default;
+        }
+        Settings { values }
+    }
+
+    pub fn set(&mut self, i: usize, value: f32) {
+        if i < N_SETTINGS {
+            self.values[i] = value;
+        }
+    }
+
+    /// Writes the values for storage, returning the bytes used
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn write_bytes(&self, buf: &mut [u8]) -> Option<usize> {
+        let bytes = buf.get_mut(..N_SETTINGS * 4)?;
+        for (chunk, value) in bytes.chunks_exact_mut(4).zip(self.values.iter()) {
+            chunk.copy_from_slice(&value.to_le_bytes());
+        }
+        Some(N_SETTINGS * 4)
+    }
+
+    /// Reads back what `write_bytes` wrote. Values missing from an older
+    /// record keep their defaults.
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn read_bytes(&mut self, buf: &[u8]) {
+        for (value, chunk) in self.values.iter_mut().zip(buf.chunks_exact(4)) {
+            *value = f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+    }
+
+    // Checks a setting is above zero, or at it if `zero_ok`
+    fn check(&self, i: usize, zero_ok: bool) -> Result<f32, Problem> {
+        let value = self.values[i];
+        if value > 0.0 || (zero_ok && value == 0.0) {
+            Ok(value)
+        } else {
+            Err(Problem::Field(i))
+        }
+    }
+}
+
+impl Fields for Settings {
+    fn title(&self) -> &str {
+        "Probe cycle"
+    }
+
+    fn count(&self) -> usize {
+        N_SETTINGS
+    }
+
+    fn get(&self, i: usize) -> Option<(&'static str, f32)> {
+        Some((SETTINGS.get(i)?.name, self.values[i]))
+    }
+}
+
+/// Why a cycle won't start or didn't finish
+#[derive(PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub enum Problem {
+    // The setting with this index is out of range
+    Field(usize),
+    // The retract must leave room to probe again within the distance
+    Retract,
+    // The controller refused a line
+    Error(u8),
+    // The controller alarmed, e.g. the probe didn't touch
+    Alarm,
+    Stopped,
+}
+
+impl Problem {
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn describe<W: Write>(self, out: &mut W) -> fmt::Result {
+        match self {
+            Problem::Field(i) => write!(out, "Check {}", SETTINGS[i].name),
+            Problem::Retract => write!(out, "Retract over half distance"),
+            Problem::Error(code) => write!(out, "Probe failed: error {}", code),
+            Problem::Alarm => write!(out, "Probe failed: alarm"),
+            Problem::Stopped => write!(out, "Probe cycle stopped"),
+        }
+    }
+
+    /// The field to select to put it right, if there is one
+    pub fn field(self) -> Option<usize> {
+        match self {
+            Problem::Field(i) => Some(i),
+            Problem::Retract => Some(RETRACT),
+            _ => None,
+        }
+    }
+}
+
+/// What the view should do next
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Step {
+    // Send a line, and feed back its answer
+    Send(Line),
+    // Offset an axis so the machine position `contact` reads `value`
+    Zero(usize, f32, f32),
+    Done,
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+enum Waiting {
+    Nothing,
+    Reply,
+    // A probe line was answered, the machine is coming to rest
+    Settle,
+}
+
+// One line, or zero, of a cycle, with the axis a probe line moves
+enum Action {
+    Line(Option<usize>),
+    Zero(usize, f32),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Cycle {
+    kind: Kind,
+    values: [f32; N_SETTINGS],
+    start: [f32; 3],            // Machine position when started
+    contact: [f32; 3],          // Machine position of each axis's last touch
+    reported: Option<[f32; 3]>, // From `[PRB:]`, for the last probe line
+    probing: Option<usize>,     // Axis of the line waiting for an answer
+    waiting: Waiting,
+    step: u32,
+    relative: bool, // Sent G91 since the last G90
+}
+
+impl Cycle {
+    /// Checks the settings, and starts a cycle with the machine at `start`
+    pub fn new(kind: Kind, settings: &Settings, start: [f32; 3]) -> Result<Cycle, Problem> {
+        settings.check(THICKNESS, true)?;
+        settings.check(FAST, false)?;
+        settings.check(SLOW, false)?;
+        let distance = settings.check(DISTANCE, false)?;
+        let retract = settings.check(RETRACT, false)?;
+        settings.check(TOOL, true)?;
+        settings.check(WALL, true)?;
+        if 2.0 * retract > distance {
+            return Err(Problem::Retract);
+        }
+        Ok(Cycle {
+            kind,
+            values: settings.values,
+            start,
+            contact: start,
+            reported: None,
+            probing: None,
+            waiting: Waiting::Nothing,
+            step: 0,
+            relative: false,
+        })
+    }
+
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    /// The next thing to do, or None while waiting on the controller
+    pub fn take_step(&mut self) -> Option<Step> {
+        if self.waiting != Waiting::Nothing {
+            return None;
+        }
+        let mut line = Line::new();
+        let action = match self.kind {
+            Kind::Z => self.z_step(self.step, &mut line),
+            Kind::Corner => self.corner_step(self.step, &mut line),
+        };
+        self.step += 1;
+        match action {
+            Some(Action::Line(axis)) => {
+                if line.as_str().starts_with("G91") {
+                    self.relative = true;
+                } else if line.as_str() == "G90" {
+                    self.relative = false;
+                }
+                self.probing = axis;
+                self.reported = None;
+                self.waiting = Waiting::Reply;
+                Some(Step::Send(line))
+            }
+            Some(Action::Zero(axis, value)) => Some(Step::Zero(axis, value, self.contact[axis])),
+            None => Some(Step::Done),
+        }
+    }
+
+    /// Ends the cycle early, on an error, alarm or stop. Returns the line to
+    /// send to put the controller back in absolute distance mode, if the
+    /// cycle could have left it relative.
+    pub fn abort(self) -> Option<Line> {
+        let mut line = Line::new();
+        if self.relative && write!(line, "G90").is_ok() {
+            Some(line)
+        } else {
+            None
+        }
+    }
+
+    /// The controller answered the last line sent
+    pub fn reply(&mut self, reply: Reply) -> Result<(), Problem> {
+        if self.waiting != Waiting::Reply {
+            return Ok(());
+        }
+        match reply {
+            Reply::Ok if self.probing.is_some() => self.waiting = Waiting::Settle,
+            Reply::Ok => self.waiting = Waiting::Nothing,
+            Reply::Error(code) => return Err(Problem::Error(code)),
+        }
+        Ok(())
+    }
+
+    /// GRBL reported where the probe touched, in machine coordinates
+    pub fn probed(&mut self, contact: [f32; 3]) {
+        if self.probing.is_some() {
+            self.reported = Some(contact);
+        }
+    }
+
+    /// A status report. Once the machine is at rest after a probe, the
+    /// contact is taken from the report, or where it stopped without one.
+    pub fn status(&mut self, running: Running, mpos: [f32; 3]) {
+        if self.waiting != Waiting::Settle || running != Running::No {
+            return;
+        }
+        if let Some(axis) = self.probing {
+            self.contact[axis] = self.reported.unwrap_or(mpos)[axis];
+        }
+        self.probing = None;
+        self.waiting = Waiting::Nothing;
+    }
+
+    // Fast probe, retract, slow probe and zero along `axis`, towards
+    // `direction`, as steps `n` 0 to 3
+    fn touch<W: Write>(
+        &self,
+        n: u32,
+        axis: usize,
+        direction: f32,
+        zero: f32,
+        out: &mut W,
+    ) -> Option<Action> {
+        let name = AXIS_NAMES[axis];
+        let retract = self.values[RETRACT];
+        match n {
+            0 => write!(
+                out,
+                "G91 G38.2 {}{:.3} F{:.0}",
+                name,
+                direction * self.values[DISTANCE],
+                self.values[FAST]
+            ),
+            1 => write!(out, "G0 {}{:.3}", name, -direction * retract),
+            2 => write!(
+                out,
+                "G38.2 {}{:.3} F{:.0}",
+                name,
+                direction * 2.0 * retract,
+                self.values[SLOW]
+            ),
+            3 => return Some(Action::Zero(axis, zero)),
+            _ => return None,
+        }
+        .ok()?;
+        Some(Action::Line(if n == 1 { None } else { Some(axis) }))
+    }
+
+    // Touch the top, zero Z to the plate, and back off
+    fn z_step<W: Write>(&self, n: u32, out: &mut W) -> Option<Action> {
+        let retract = self.values[RETRACT];
+        if n < 4 {
+            return self.touch(n, 2, -1.0, self.values[THICKNESS], out);
+        }
+        match n {
+            4 => write!(out, "G0 Z{:.3}", retract),
+            5 => write!(out, "G90"),
+            _ => return None,
+        }
+        .ok()?;
+        Some(Action::Line(None))
+    }
+
+    fn corner_step<W: Write>(&self, n: u32, out: &mut W) -> Option<Action> {
+        // Z first, then X and Y the same way, 9 steps each
+        if n < 4 {
+            return self.touch(n, 2, -1.0, self.values[THICKNESS], out);
+        }
+        let (axis, n) = match n - 4 {
+            n @ 0..=8 => (0, n),
+            n @ 9..=17 => (1, n - 9),
+            18 => {
+                write!(out, "G53 G0 X{:.3} Y{:.3}", self.start[0], self.start[1]).ok()?;
+                return Some(Action::Line(None));
+            }
+            19 => {
+                write!(out, "G90").ok()?;
+                return Some(Action::Line(None));
+            }
+            _ => return None,
+        };
+
+        let name = AXIS_NAMES[axis];
+        let retract = self.values[RETRACT];
+        let top = self.contact[2];
+        // The centre is a radius short of the face, which is the wall
+        // short of the edge
+        let zero = -(self.values[WALL] + self.values[TOOL] / 2.0);
+        match n {
+            // Up clear of the block, then out past the face. Y goes out
+            // from over the start point, once X is clear of the block.
+            0 => write!(out, "G53 G0 Z{:.3}", top + retract),
+            1 if axis == 0 => write!(out, "G53 G0 X{:.3}", self.start[0] - self.values[DISTANCE]),
+            1 => write!(
+                out,
+                "G53 G0 X{:.3} Y{:.3}",
+                self.start[0],
+                self.start[1] - self.values[DISTANCE]
+            ),
+            // Beside the block, halfway down it
+            2 => write!(out, "G53 G0 Z{:.3}", top - self.values[THICKNESS] / 2.0),
+            3..=6 => return self.touch(n - 3, axis, 1.0, zero, out),
+            7 => write!(out, "G0 {}{:.3}", name, -retract),
+            _ => write!(out, "G53 G0 Z{:.3}", top + retract),
+        }
+        .ok()?;
+        Some(Action::Line(None))
+    }
+}
+
+/// Reads GRBL's `[PRB:x,y,z:1]` report, returning the machine position of
+/// the contact. None for any other line, or a probe that didn't touch.
+pub fn parse_probe(line: &str) -> Option<[f32; 3]> {
+    let report = line.trim().strip_prefix("[PRB:")?.strip_suffix(']')?;
+    let (position, touched) = report.rsplit_once(':')?;
+    if touched != "1" {
+        return None;
+    }
+    let mut contact = [0.0; 3];
+    let mut values = position.split(',');
+    for value in contact.iter_mut() {
+        *value = values.next()?.trim().parse().ok()?;
+    }
+    Some(contact)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs a cycle to the end, touching at `contact` and answering every
+    // line with ok. Stops early, returning what was done, once `fail_at`
+    // lines have been sent.
+    fn run(cycle: &mut Cycle, contact: [f32; 3], fail_at: usize) -> Vec<String> {
+        let mut steps = Vec::new();
+        let mut sent = 0;
+        while let Some(step) = cycle.take_step() {
+            match step {
+                Step::Send(line) => {
+                    steps.push(line.as_str().to_string());
+                    sent += 1;
+                    if sent == fail_at {
+                        return steps;
+                    }
+                    cycle.reply(Reply::Ok).unwrap();
+                    // A probe waits for the machine to stop where it touched
+                    if cycle.probing.is_some() {
+                        cycle.probed(contact);
+                        cycle.status(Running::Yes, [0.0; 3]);
+                        assert_eq!(cycle.take_step(), None);
+                        cycle.status(Running::No, [0.0; 3]);
+                    }
+                }
+                Step::Zero(axis, value, at) => {
+                    steps.push(format!("zero {} {} at {}", AXIS_NAMES[axis], value, at))
+                }
+                Step::Done => break,
+            }
+        }
+        steps
+    }
+
+    fn start(kind: Kind) -> Cycle {
+        Cycle::new(kind, &Settings::new(), [100.0, 50.0, -10.0]).unwrap()
+    }
+
+    #[test]
+    fn z_plate() {
+        let mut cycle = start(Kind::Z);
+        let steps = run(&mut cycle, [0.0, 0.0, -15.0], 0);
+        assert_eq!(
+            steps,
+            [
+                "G91 G38.2 Z-20.000 F100",
+                "G0 Z2.000",
+                "G38.2 Z-4.000 F25",
+                "zero Z 10 at -15",
+                "G0 Z2.000",
+                "G90",
+            ]
+        );
+        // Back in G90 already
+        assert_eq!(cycle.abort(), None);
+    }
+
+    #[test]
+    fn corner() {
+        let mut cycle = start(Kind::Corner);
+        let steps = run(&mut cycle, [112.0, 62.0, -15.0], 0);
+        assert_eq!(
+            steps,
+            [
+                "G91 G38.2 Z-20.000 F100",
+                "G0 Z2.000",
+                "G38.2 Z-4.000 F25",
+                "zero Z 10 at -15",
+                // Out past the X face and down beside it
+                "G53 G0 Z-13.000",
+                "G53 G0 X80.000",
+                "G53 G0 Z-20.000",
+                "G91 G38.2 X20.000 F100",
+                "G0 X-2.000",
+                "G38.2 X4.000 F25",
+                "zero X -13 at 112",
+                "G0 X-2.000",
+                "G53 G0 Z-13.000",
+                // The same for Y, from over the start point
+                "G53 G0 Z-13.000",
+                "G53 G0 X100.000 Y30.000",
+                "G53 G0 Z-20.000",
+                "G91 G38.2 Y20.000 F100",
+                "G0 Y-2.000",
+                "G38.2 Y4.000 F25",
+                "zero Y -13 at 62",
+                "G0 Y-2.000",
+                "G53 G0 Z-13.000",
+                "G53 G0 X100.000 Y50.000",
+                "G90",
+            ]
+        );
+        assert_eq!(cycle.abort(), None);
+    }
+
+    #[test]
+    fn contact_without_report() {
+        // Where the machine stopped stands in for a missing [PRB:]
+        let mut cycle = start(Kind::Z);
+        cycle.take_step();
+        cycle.reply(Reply::Ok).unwrap();
+        cycle.status(Running::No, [1.0, 2.0, -12.5]);
+        cycle.take_step();
+        cycle.reply(Reply::Ok).unwrap();
+        cycle.take_step();
+        cycle.reply(Reply::Ok).unwrap();
+        cycle.status(Running::No, [1.0, 2.0, -12.0]);
+        assert_eq!(cycle.take_step(), Some(Step::Zero(2, 10.0, -12.0)));
+    }
+
+    #[test]
+    fn failures_go_back_to_g90() {
+        // Refused on the first line, after it switched to G91
+        let mut cycle = start(Kind::Corner);
+        run(&mut cycle, [0.0; 3], 1);
+        assert_eq!(cycle.reply(Reply::Error(9)), Err(Problem::Error(9)));
+        assert_eq!(cycle.abort().unwrap().as_str(), "G90");
+
+        // Alarmed or stopped anywhere along the way
+        for fail_at in 2..21 {
+            let mut cycle = start(Kind::Corner);
+            run(&mut cycle, [112.0, 62.0, -15.0], fail_at);
+            assert_eq!(cycle.abort().unwrap().as_str(), "G90", "{}", fail_at);
+        }
+
+        // Nothing sent yet
+        assert_eq!(start(Kind::Z).abort(), None);
+    }
+
+    #[test]
+    fn bad_settings() {
+        let mut settings = Settings::new();
+        settings.set(RETRACT, 11.0);
+        assert_eq!(
+            Cycle::new(Kind::Z, &settings, [0.0; 3]).err(),
+            Some(Problem::Retract)
+        );
+        settings.set(FAST, 0.0);
+        let problem = Cycle::new(Kind::Z, &settings, [0.0; 3]).err().unwrap();
+        assert_eq!(
+            (problem, problem.field()),
+            (Problem::Field(FAST), Some(FAST))
+        );
+
+        // No plate is fine
+        let mut settings = Settings::new();
+        settings.set(THICKNESS, 0.0);
+        assert!(Cycle::new(Kind::Z, &settings, [0.0; 3]).is_ok());
+    }
+
+    #[test]
+    fn probe_reports() {
+        assert_eq!(
+            parse_probe("[PRB:1.000,-2.500,-10.125:1]"),
+            Some([1.0, -2.5, -10.125])
+        );
+        assert_eq!(parse_probe("[PRB:1.000,-2.500,-10.125:0]"), None);
+        assert_eq!(parse_probe("[PRB:1.000,-2.500:1]"), None);
+        assert_eq!(parse_probe("[GC:G0 G54]"), None);
+    }
+}
@@ -0,0 +1,339 @@
+//! Virtual threading dial, for cutting threads on a lathe with its own
+//! leadscrew and half-nuts.
+//!
+//! Once the half-nuts are closed, the carriage moves a thread pitch `P` each
+//! turn of the spindle, so the spindle count less the carriage travel in
+//! turns (the phase) stays put. Each pass has to close them at the same
+//! phase, give or take whole turns, to follow the groove already cut.
+//!
+//! The nuts can only drop into the leadscrew's thread, which comes round
+//! every `L / P` turns for a leadscrew pitch `L`. With `P / L` as `a / b` in
+//! its lowest terms, both line up every `b` turns. A physical dial shows
+//! that for a few threads. Here it comes from the spindle encoder and the
+//! X scale, for any thread in the table.
+//!
+//! The dial learns the phase from the first pass, seeing the carriage move
+//! in step with the spindle. Closing the nuts at any time in the last `b / a`
+//! turns before the next right moment drops them in at that moment, so
+//! that's when it says to engage.
+
+use crate::consts::*;
+use crate::leadscrew::{gcd, THREADS};
+use crate::wizard::Fields;
+
+/// What the dial says to do
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Signal {
+    // No pass seen since the thread was picked or cleared
+    NoReference,
+    // Turns of the spindle until it's time to engage
+    Wait(u32),
+    Engage,
+    Engaged,
+    // Engaged, but out of step with the first pass
+    WrongGroove,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Dial {
+    thread: usize,
+    // Spindle count and carriage mm as the first pass started, and which
+    // way the carriage went as the count went up
+    reference: Option<(i64, f32, f32)>,
+    // Since when the carriage has kept in step with the spindle
+    run: Option<(i64, f32)>,
+    engaged: bool,
+    forward: bool, // The spindle turned the counting up way last
+    last_spindle: i64,
+}
+
+impl Dial {
+    pub fn new() -> Dial {
+        Dial {
+            thread: 0,
+            reference: None,
+            run: None,
+            engaged: false,
+            forward: true,
+            last_spindle: 0,
+        }
+    }
+
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn thread(&self) -> usize {
+        self.thread
+    }
+
+    /// Picks another thread from the table, which starts afresh
+    pub fn select(&mut self, i: usize) {
+        if i < THREADS.len() && i != self.thread {
+            self.thread = i;
+            self.clear();
+        }
+    }
+
+    /// Forgets the first pass, for a new thread
+    pub fn clear(&mut self) {
+        self.reference = None;
+        self.run = None;
+        self.engaged = false;
+    }
+
+    /// Spindle turns between chances to engage
+    pub fn every(&self) -> u32 {
+        self.turns().0 as u32
+    }
+
+    // Spindle turns between the right moments, and before one that the
+    // nuts can be closed, as b and b / a above
+    fn turns(&self) -> (u64, f32) {
+        let (pn, pd) = THREADS[self.thread].1.mm_fraction();
+        let (ln, ld) = DIAL_LEADSCREW_PITCH.mm_fraction();
+        let (a, b) = (pn * ld, pd * ln);
+        let common = gcd(a, b);
+        let (a, b) = (a / common, b / common);
+        (b, b as f32 / a as f32)
+    }
+
+    /// Takes the spindle count and the carriage position in mm
+    pub fn update(&mut self, spindle: i64, x: f32) -> Signal {
+        if spindle != self.last_spindle {
+            self.forward = spindle > self.last_spindle;
+            self.last_spindle = spindle;
+        }
+        let counts_per_mm = SPINDLE_COUNTS_PER_REV as f32 / THREADS[self.thread].1.mm();
+        let tolerance = DIAL_TOLERANCE_REV * SPINDLE_COUNTS_PER_REV as f32;
+
+        // In step either way for long enough means the nuts are closed
+        let run = self.run.unwrap_or((spindle, x));
+        let turned = (spindle - run.0) as f32;
+        let travel = (x - run.1) * counts_per_mm;
+        let sign = if (turned - travel).abs() < tolerance {
+            1.0
+        } else if (turned + travel).abs() < tolerance {
+            -1.0
+        } else {
+            self.engaged = false;
+            self.run = Some((spindle, x));
+            return self.signal(spindle, x, counts_per_mm);
+        };
+        self.run = Some(run);
+        if (x - run.1).abs() >= DIAL_ENGAGED_MM {
+            self.engaged = true;
+            if self.reference.is_none() {
+                self.reference = Some((run.0, run.1, sign));
+            }
+        }
+        self.signal(spindle, x, counts_per_mm)
+    }
+
+    fn signal(&self, spindle: i64, x: f32, counts_per_mm: f32) -> Signal {
+        let (start, at, sign) = match self.reference {
+            Some(reference) => reference,
+            None => return Signal::NoReference,
+        };
+        let (turns, window) = self.turns();
+        let period = turns as i64 * SPINDLE_COUNTS_PER_REV as i64;
+        let turned = ((spindle - start) % period) as f32;
+        let period = period as f32;
+        let mut phase = (turned - sign * (x - at) * counts_per_mm) % period;
+        if phase < 0.0 {
+            phase += period;
+        }
+
+        if self.engaged {
+            let off = phase.min(period - phase);
+            return if off < DIAL_TOLERANCE_REV * SPINDLE_COUNTS_PER_REV as f32 {
+                Signal::Engaged
+            } else {
+                Signal::WrongGroove
+            };
+        }
+        let to_go = if self.forward { period - phase } else { phase };
+        let to_go = to_go / SPINDLE_COUNTS_PER_REV as f32;
+        if to_go <= window {
+            Signal::Engage
+        } else {
+            Signal::Wait((to_go - window) as u32 + 1)
+        }
+    }
+
+    /// Writes the thread for storage, returning the bytes used
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn write_bytes(&self, buf: &mut [u8]) -> Option<usize> {
+        *buf.get_mut(0)? = self.thread as u8;
+        Some(1)
+    }
+
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn read_bytes(&mut self, buf: &[u8]) {
+        if let Some(thread) = buf.first() {
+            self.select(*thread as usize);
+        }
+    }
+}
+
+impl Fields for Dial {
+    fn title(&self) -> &str {
+        "Thread dial, mm pitch"
+    }
+
+    fn count(&self) -> usize {
+        THREADS.len()
+    }
+
+    fn get(&self, i: usize) -> Option<(&'static str, f32)> {
+        THREADS.get(i).map(|(name, pitch)| (*name, pitch.mm()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 12 TPI on the 8 TPI leadscrew: P / L is 2 / 3, so the right moment
+    // comes every 3 turns, and the nuts can be closed 1.5 turns before it
+    const TPI_12: usize = 14;
+    const STEPS_PER_REV: i64 = 16;
+
+    fn turns(t: f32) -> i64 {
+        (t * SPINDLE_COUNTS_PER_REV as f32) as i64
+    }
+
+    fn dial() -> Dial {
+        assert_eq!(THREADS[TPI_12].0, "12 TPI");
+        let mut dial = Dial::new();
+        dial.select(TPI_12);
+        assert_eq!(dial.every(), 3);
+        dial
+    }
+
+    // Turns the spindle by `by` turns a bit at a time with the carriage
+    // following it at `direction` pitches a turn, or standing still for 0.
+    // Returns the spindle count, where the carriage ended and the last signal.
+    fn turn(dial: &mut Dial, from: (i64, f32), by: f32, direction: f32) -> (i64, f32, Signal) {
+        let pitch = THREADS[TPI_12].1.mm();
+        let step = SPINDLE_COUNTS_PER_REV as i64 / STEPS_PER_REV * by.signum() as i64;
+        let n = (by.abs() * STEPS_PER_REV as f32) as i64;
+        let mut signal = dial.update(from.0, from.1);
+        let mut at = from;
+        for i in 1..=n {
+            let x = from.1 + direction * pitch * (i * step) as f32 / SPINDLE_COUNTS_PER_REV as f32;
+            at = (from.0 + i * step, x);
+            signal = dial.update(at.0, at.1);
+        }
+        (at.0, at.1, signal)
+    }
+
+    // A first pass from the spindle at zero, and back to the start
+    fn first_pass(dial: &mut Dial) -> i64 {
+        let (spindle, _, signal) = turn(dial, (0, 0.0), 5.0, 1.0);
+        assert_eq!(signal, Signal::Engaged);
+        spindle
+    }
+
+    #[test]
+    fn no_reference() {
+        let mut dial = dial();
+        assert_eq!(dial.update(0, 0.0), Signal::NoReference);
+        // The spindle turning on its own, or the carriage moving by hand
+        assert_eq!(turn(&mut dial, (0, 0.0), 4.0, 0.0).2, Signal::NoReference);
+        assert_eq!(dial.update(turns(4.0), 20.0), Signal::NoReference);
+        // Not far enough to count as a pass
+        assert_eq!(
+            turn(&mut dial, (turns(4.0), 20.0), 0.25, 1.0).2,
+            Signal::NoReference
+        );
+    }
+
+    #[test]
+    fn waiting() {
+        let mut dial = dial();
+        let spindle = first_pass(&mut dial);
+        // The first pass ended 5 turns on, a turn before a right moment.
+        // Back at the start with the spindle still turning, it counts down
+        // to the moment and round again.
+        let moment = spindle + turns(1.0);
+        let expected = [
+            (0.0, Signal::Wait(2)),
+            (0.5, Signal::Wait(2)),
+            (1.0, Signal::Wait(1)),
+            (1.25, Signal::Wait(1)),
+            (1.5, Signal::Engage),
+            (2.75, Signal::Engage),
+            (3.0, Signal::Wait(2)),
+        ];
+        for (t, signal) in expected {
+            assert_eq!(dial.update(moment + turns(t), 0.0), signal, "{}", t);
+        }
+        // A pitch along the thread, the moment is a turn later
+        let pitch = THREADS[TPI_12].1.mm();
+        assert_eq!(dial.update(moment + turns(3.5), pitch), Signal::Engage);
+        assert_eq!(dial.update(moment + turns(4.5), pitch), Signal::Wait(2));
+    }
+
+    #[test]
+    fn engaging() {
+        let mut dial = dial();
+        let spindle = first_pass(&mut dial);
+        let moment = spindle + turns(4.0);
+        assert_eq!(dial.update(moment - turns(1.0), 0.0), Signal::Engage);
+        // The nuts drop in at the moment, and the carriage follows
+        let (_, x, signal) = turn(&mut dial, (moment, 0.0), 4.0, 1.0);
+        assert_eq!(signal, Signal::Engaged);
+        // Opened at the end of the pass
+        assert_eq!(dial.update(moment + turns(7.5), x), Signal::Wait(2));
+        assert!(!dial.engaged);
+    }
+
+    #[test]
+    fn wrong_groove() {
+        let mut dial = dial();
+        let spindle = first_pass(&mut dial);
+        // Closed two turns after a right moment
+        let late = spindle + turns(3.0);
+        assert_eq!(
+            turn(&mut dial, (late, 0.0), 4.0, 1.0).2,
+            Signal::WrongGroove
+        );
+
+        // Picking the thread again starts afresh
+        dial.select(TPI_12 + 1);
+        dial.select(TPI_12);
+        assert_eq!(dial.update(late, 0.0), Signal::NoReference);
+    }
+
+    // The signal with the spindle turning forwards to `at`
+    fn forwards_to(dial: &mut Dial, at: i64) -> Signal {
+        dial.update(at - 1, 0.0);
+        dial.update(at, 0.0)
+    }
+
+    #[test]
+    fn wrap_around() {
+        // The first pass the other way, from a spindle count far from zero
+        let mut dial = dial();
+        let start = turns(3000.0);
+        let (spindle, x, signal) = turn(&mut dial, (start, 0.0), 5.0, -1.0);
+        assert_eq!((signal, x < -1.0), (Signal::Engaged, true));
+
+        // Counts either side of the first pass, many periods away
+        let moment = spindle + turns(1.0);
+        for periods in [-2000, -1, 0, 1, 2000] {
+            let at = moment + periods * turns(3.0);
+            assert_eq!(forwards_to(&mut dial, at), Signal::Wait(2), "{}", periods);
+            let at = at + turns(2.0);
+            assert_eq!(forwards_to(&mut dial, at), Signal::Engage, "{}", periods);
+        }
+
+        // Running backwards, the moment is the one behind
+        dial.update(moment + turns(3.0), 0.0);
+        assert_eq!(dial.update(moment + turns(2.5), 0.0), Signal::Wait(2));
+        assert!(!dial.forward);
+        assert_eq!(dial.update(moment + turns(0.5), 0.0), Signal::Engage);
+        assert_eq!(
+            turn(&mut dial, (moment, 0.0), -4.0, -1.0).2,
+            Signal::Engaged
+        );
+    }
+}
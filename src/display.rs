@@ -3,17 +3,35 @@ use embedded_graphics::{
     mono_font::{mapping::StrGlyphMapping, DecorationDimensions, MonoFont, MonoTextStyle},
     pixelcolor::{Rgb565, RgbColor},
     prelude::*,
-    primitives::{PrimitiveStyleBuilder, Rectangle, RoundedRectangle},
+    primitives::{Line, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle, RoundedRectangle},
     text::Text,
 };
 use rtt_target::rprintln;
 
-use stm32f7xx_hal::ltdc::Layer;
+use core::fmt::Write;
 
+use crate::alarm::Alarm;
+use crate::backend::Overrides;
 use crate::consts::*;
+use crate::dial::Signal;
+use crate::files::{FileNames, NAME_LEN};
+use crate::history::History;
+use crate::job::{JobState, Progress};
+use crate::jog::JogStep;
+use crate::limits::Zone;
+use crate::mdi::{EntryKind, Mdi, SCROLLBACK_LEN};
+use crate::points::{PointMemory, N_POINTS};
+use crate::powerfeed::{self, Direction, PowerFeed, Speed};
+use crate::probe::ProbeMode;
+use crate::relays::{Relays, OUTPUTS};
+use crate::safety::{Input, Interlocks, INPUTS};
 use crate::screen::Stm32F7DiscoDisplay;
+use crate::text::TextBuffer;
+use crate::toolpath::{Toolpath, Viewport};
 use crate::ui;
-use profont::PROFONT_24_POINT;
+use crate::velocity::{self, FeedRate};
+use crate::wizard::Fields;
+use profont::{PROFONT_12_POINT, PROFONT_14_POINT, PROFONT_18_POINT, PROFONT_24_POINT};
 
 const SEVENT_SEGMENT_FONT: MonoFont = MonoFont {
     image: ImageRaw::new_binary(include_bytes!("assets/seven-segment-font.raw"), 224),
@@ -47,9 +65,15 @@ pub struct SevenSegDisplay {
     fill_color: Rgb565,
     text_clr: Rgb565,
     highlight_text_color: Rgb565,
+    warning: Option<Rgb565>, // Near or over a soft limit
     text: Option<[char; 6]>,
     value: f32,
     backup_value: f32,
+    machine: f32, // Position from the encoder, value = machine + offset
+    offset: f32,
+    target: Option<f32>, // When set the readout shows distance to go
+    approach_band: f32,
+    tolerance: f32,
     decimal_digits: Option<u8>, // None when before decimal, Some(0) when decimal is entered
     // then Some(n) holding the count of decimals
     negative: bool,
@@ -71,19 +95,53 @@ impl SevenSegDisplay {
             fill_color: DISPLAY_BACKGROUND_COLOR,
             text_clr: DISPLAY_TEXT_COLOR,
             highlight_text_color: DISPLAY_HIGHLIGHT_TEXT_COLOR,
+            warning: None,
             text: None,
             value: 0.0,
             backup_value: 0.0,
+            machine: 0.0,
+            offset: 0.0,
+            target: None,
+            approach_band: DTG_APPROACH_BAND,
+            tolerance: DTG_TOLERANCE,
             negative: false,
             decimal_digits: None,
         };
     }
 
+    pub fn inside(&self, x: u16, y: u16) -> bool {
+        x >= self.x && x <= (self.x + self.width) && y >= self.y && y <= (self.y + self.height)
+    }
+
     pub fn text_color(&self) -> Rgb565 {
         if self.highlight {
-            self.highlight_text_color
-        } else {
-            self.text_clr
+            return self.highlight_text_color;
+        }
+        if let Some(color) = self.warning {
+            return color;
+        }
+        match self.target {
+            None => self.text_clr,
+            Some(_) => {
+                let to_go = self.shown();
+                let to_go = if to_go < 0.0 { 0.0 - to_go } else { to_go };
+                if to_go <= self.tolerance {
+                    DTG_ARRIVED_COLOR
+                } else if to_go <= self.approach_band {
+                    DTG_APPROACH_COLOR
+                } else {
+                    DTG_TEXT_COLOR
+                }
+            }
+        }
+    }
+
+    /// The number on the readout: the axis value, or the distance still to
+    /// go to the target once one is set. Number entry always shows the value.
+    pub fn shown(&self) -> f32 {
+        match self.target {
+            Some(target) if !self.highlight => self.value - target,
+            _ => self.value,
         }
     }
 
@@ -93,6 +151,7 @@ impl SevenSegDisplay {
     pub fn set_value(&mut self, value: f32) {
         // rprintln!("value coming in: {:.3}", value);
         self.value = value;
+        let value = self.shown();
         self.negative = value < 0.0;
         let value = if value < 0.0 { 0.0 - value } else { value }; // can't use abs - it is in standard
 
@@ -130,6 +189,64 @@ impl SevenSegDisplay {
         self.value
     }
 
+    /// Takes the machine position of the axis and updates the displayed
+    /// value from it. Returns true if the digits changed and need drawing.
+    /// Ignored while a number is being typed in.
+    pub fn set_position(&mut self, machine: f32) -> bool {
+        self.machine = machine;
+        if self.highlight {
+            return false;
+        }
+        let text = self.text;
+        let negative = self.negative;
+        self.set_value(self.machine + self.offset);
+        text != self.text || negative != self.negative
+    }
+
+    /// Makes the axis read `value` at its current position
+    pub fn preset(&mut self, value: f32) {
+        self.offset = value - self.machine;
+        self.set_value(value);
+    }
+
+    pub fn zero(&mut self) {
+        self.preset(0.0);
+    }
+
+    /// The machine position last fed in
+    pub fn machine(&self) -> f32 {
+        self.machine
+    }
+
+    pub fn get_offset(&self) -> f32 {
+        self.offset
+    }
+
+    /// Puts back an offset, e.g. from the undo history
+    pub fn set_offset(&mut self, offset: f32) {
+        self.offset = offset;
+        self.restore();
+    }
+
+    /// Sets or clears the distance to go target. The target is kept apart
+    /// from the axis value, which is restored if an entry was in progress.
+    pub fn set_target(&mut self, target: Option<f32>) {
+        self.target = target;
+        self.restore();
+    }
+
+    /// Puts the axis value back after the digits were used to enter
+    /// something other than a preset
+    pub fn restore(&mut self) {
+        self.set_value(self.machine + self.offset);
+    }
+
+    /// Within `band` of the target digits turn orange, within `tolerance` green
+    pub fn set_approach(&mut self, band: f32, tolerance: f32) {
+        self.approach_band = band;
+        self.tolerance = tolerance;
+    }
+
     fn draw_background(&mut self, display: &mut Stm32F7DiscoDisplay<u16>) {
         let style = PrimitiveStyleBuilder::new()
             .stroke_width(BUTTON_STROKE_WIDTH)
@@ -154,47 +271,42 @@ impl SevenSegDisplay {
     /// Takes number of digits to get place of minus. This can 1-3. Numbers
     /// larger than 3 are treated as 3: minus in LH position
     fn draw_minus(self, n_digits: u16, display: &mut Stm32F7DiscoDisplay<u16>) {
-        unsafe {
-            let c = self.text_color();
-            let background_color: u32 =
-                c.b() as u32 & 0x1F | ((c.g() as u32 & 0x3F) << 5) | ((c.r() as u32 & 0x1F) << 11);
-
-            let x_pos = self.text_x + (3 as u16 - n_digits) * 27;
-
-            display.controller.draw_rectangle(
-                Layer::L1,
-                ((x_pos) as usize, self.text_y as usize + 6),
-                ((x_pos + MINUS_WIDTH) as usize, (self.text_y + 10) as usize),
-                background_color,
-            );
-        }
+        let x_pos = self.text_x + (3 as u16 - n_digits) * 27;
+        display
+            .fill_solid(
+                &Rectangle::new(
+                    Point::new(x_pos as i32, self.text_y as i32 + 6),
+                    Size::new(MINUS_WIDTH as u32, 4),
+                ),
+                self.text_color(),
+            )
+            .ok();
     }
 
     pub fn set_highlight_text(&mut self) {
         self.highlight = true;
+        self.set_value(self.value);
     }
 
     pub fn clear_highlight_text(&mut self) {
         self.highlight = false;
+        self.set_value(self.value);
     }
 
     pub fn draw(&mut self, display: &mut Stm32F7DiscoDisplay<u16>) {
         self.draw_background(display);
 
-        let c = self.text_color();
-        let text_color: u32 =
-            c.b() as u32 & 0x1F | ((c.g() as u32 & 0x3F) << 5) | ((c.r() as u32 & 0x1F) << 11);
-
         // Font is 22x40
 
         // Optional minus sign:
 
-        if self.value < 0.0 {
+        let shown = self.shown();
+        if shown < 0.0 {
             let mut minus_digits = 3;
-            if self.value <= -10.0 && self.value > -100.0 {
+            if shown <= -10.0 && shown > -100.0 {
                 minus_digits = 2;
             }
-            if self.value <= -1.0 && self.value > -10.0 {
+            if shown <= -1.0 && shown > -10.0 {
                 minus_digits = 1;
             }
 
@@ -203,47 +315,50 @@ impl SevenSegDisplay {
             self.draw_minus(minus_digits, display);
         }
 
-        let style = MonoTextStyle::new(&SEVENT_SEGMENT_FONT, self.text_color());
         let mut offset = MINUS_WIDTH + 4;
         let mut lead_zero = true;
         for (i, c) in self.text.unwrap().iter().enumerate() {
             if i == 3 {
                 // Insert decimal place into view
                 offset += 8;
-                unsafe {
-                    display.controller.draw_rectangle(
-                        Layer::L1,
-                        (
-                            (self.text_x + 81 + MINUS_WIDTH + 4) as usize,
-                            (self.text_y + 24) as usize,
-                        ),
-                        (
-                            (self.text_x + 81 + MINUS_WIDTH + 8) as usize,
-                            (self.text_y + 24 + 4) as usize,
+                display
+                    .fill_solid(
+                        &Rectangle::new(
+                            Point::new(
+                                (self.text_x + 81 + MINUS_WIDTH + 4) as i32,
+                                (self.text_y + 24) as i32,
+                            ),
+                            Size::new(4, 4),
                         ),
-                        text_color,
-                    );
-                }
+                        self.text_color(),
+                    )
+                    .ok();
             };
             // Don't draw leading zeros - they'll remain blank
             if !(lead_zero && i == 0 && *c == '0' || lead_zero && i == 1 && *c == '0') {
                 lead_zero = false;
                 let mut b = [0; 4];
-                let txt = c.encode_utf8(&mut b);
-                Text::new(
-                    &txt,
+                display.draw_glyphs(
+                    c.encode_utf8(&mut b),
+                    &SEVENT_SEGMENT_FONT,
                     Point::new(
                         (self.text_x + i as u16 * 27 + offset as u16) as i32,
                         (self.text_y - 5) as i32,
                     ),
-                    style,
-                )
-                .draw(display)
-                .ok();
+                    self.text_color(),
+                );
             }
         }
     }
 
+    /// Overrides the text colour while near or over a soft limit. Returns
+    /// true if it changed.
+    pub fn set_warning(&mut self, warning: Option<Rgb565>) -> bool {
+        let changed = warning != self.warning;
+        self.warning = warning;
+        changed
+    }
+
     pub fn change_colors(&mut self, fill: Rgb565, text: Rgb565) {
         self.fill_color = fill;
         self.text_clr = text;
@@ -259,7 +374,7 @@ impl SevenSegDisplay {
 
     pub fn plus_minus(&mut self, display: &mut Stm32F7DiscoDisplay<u16>) {
         self.clear_highlight_text();
-        self.set_value(0.0 - self.value);
+        self.preset(0.0 - self.value);
         self.draw(display);
     }
 
@@ -267,7 +382,7 @@ impl SevenSegDisplay {
     // contents of that axis
     pub fn half(&mut self, display: &mut Stm32F7DiscoDisplay<u16>) {
         self.clear_highlight_text();
-        self.set_value(self.value / 2.0);
+        self.preset(self.value / 2.0);
         self.draw(display);
     }
 
@@ -345,3 +460,1950 @@ impl SevenSegDisplay {
         }
     }
 }
+
+/// Small readout under the axes showing the vector feed rate and the
+/// feed rate of each axis. Text turns red when a feed is over speed.
+#[derive(Copy, Clone, Debug)]
+pub struct FeedDisplay {
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+    is_metric: bool,
+    vector: TextBuffer<20>,
+    axes: [TextBuffer<10>; 3],
+    overspeed: [bool; 4], // X, Y, Z, then vector
+}
+
+impl FeedDisplay {
+    pub fn new(x: u16, y: u16, width: u16, height: u16) -> FeedDisplay {
+        FeedDisplay {
+            x,
+            y,
+            width,
+            height,
+            is_metric: true,
+            vector: TextBuffer::new(),
+            axes: [TextBuffer::new(); 3],
+            overspeed: [false; 4],
+        }
+    }
+
+    pub fn inside(&self, x: u16, y: u16) -> bool {
+        x >= self.x && x <= (self.x + self.width) && y >= self.y && y <= (self.y + self.height)
+    }
+
+    /// Switches between mm/min and inches per minute
+    pub fn toggle_units(&mut self, feed: &FeedRate) {
+        self.is_metric = !self.is_metric;
+        self.set_feed(feed);
+    }
+
+    /// Formats the feed rates. Returns true if the text changed and needs drawing.
+    pub fn set_feed(&mut self, feed: &FeedRate) -> bool {
+        let old_vector = self.vector;
+        let old_axes = self.axes;
+        let old_overspeed = self.overspeed;
+
+        let vector = feed.vector_mm_per_min();
+        self.overspeed[3] = vector > FEED_OVERSPEED_MM_MIN;
+        self.vector.clear();
+        if self.is_metric {
+            write!(self.vector, "F{:6.0} mm/min", vector).ok();
+        } else {
+            write!(self.vector, "F{:6.1} IPM", velocity::to_ipm(vector)).ok();
+        }
+
+        for (i, name) in ['X', 'Y', 'Z'].iter().enumerate() {
+            let rate = feed.axis_mm_per_min(i);
+            self.overspeed[i] = rate.abs() > FEED_OVERSPEED_MM_MIN;
+            self.axes[i].clear();
+            if self.is_metric {
+                write!(self.axes[i], "{}{:5.0}", name, rate).ok();
+            } else {
+                write!(self.axes[i], "{}{:5.1}", name, velocity::to_ipm(rate)).ok();
+            }
+        }
+
+        self.vector.as_str() != old_vector.as_str()
+            || self.overspeed != old_overspeed
+            || self
+                .axes
+                .iter()
+                .zip(old_axes.iter())
+                .any(|(a, b)| a.as_str() != b.as_str())
+    }
+
+    fn color(&self, index: usize) -> Rgb565 {
+        if self.overspeed[index] {
+            FEED_OVERSPEED_COLOR
+        } else {
+            FEED_TEXT_COLOR
+        }
+    }
+
+    pub fn draw(&mut self, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let style = PrimitiveStyleBuilder::new()
+            .stroke_width(BUTTON_STROKE_WIDTH)
+            .stroke_color(BUTTON_STROKE_COLOR)
+            .fill_color(DISPLAY_BACKGROUND_COLOR)
+            .build();
+
+        RoundedRectangle::with_equal_corners(
+            Rectangle::new(
+                Point::new(self.x as i32, self.y as i32),
+                Size::new(self.width as u32, self.height as u32),
+            ),
+            Size::new(CORNER_RADIUS, CORNER_RADIUS),
+        )
+        .into_styled(style)
+        .draw(display)
+        .ok();
+
+        let style = MonoTextStyle::new(&PROFONT_18_POINT, self.color(3));
+        Text::new(
+            self.vector.as_str(),
+            Point::new(self.x as i32 + 7, self.y as i32 + 24),
+            style,
+        )
+        .draw(display)
+        .ok();
+
+        for i in 0..3 {
+            let style = MonoTextStyle::new(&PROFONT_12_POINT, self.color(i));
+            Text::new(
+                self.axes[i].as_str(),
+                Point::new(self.x as i32 + 7 + i as i32 * 62, self.y as i32 + 46),
+                style,
+            )
+            .draw(display)
+            .ok();
+        }
+    }
+}
+
+/// Scrolling list of the point memory slots, a few rows at a time
+#[derive(Copy, Clone, Debug)]
+pub struct PointList {
+    x: u16,
+    y: u16,
+    width: u16,
+    first: usize,
+    selected: usize,
+}
+
+impl PointList {
+    pub fn new(x: u16, y: u16, width: u16) -> PointList {
+        PointList {
+            x,
+            y,
+            width,
+            first: 0,
+            selected: 0,
+        }
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Returns the slot shown in the row under x, y
+    pub fn row_at(&self, x: u16, y: u16) -> Option<usize> {
+        let height = POINTS_ROWS as u16 * POINTS_ROW_HEIGHT;
+        if x < self.x || x > self.x + self.width || y < self.y || y >= self.y + height {
+            return None;
+        }
+        let slot = self.first + ((y - self.y) / POINTS_ROW_HEIGHT) as usize;
+        if slot < N_POINTS {
+            Some(slot)
+        } else {
+            None
+        }
+    }
+
+    pub fn select(&mut self, slot: usize) {
+        self.selected = slot.min(N_POINTS - 1);
+        if self.selected < self.first {
+            self.first = self.selected;
+        } else if self.selected >= self.first + POINTS_ROWS {
+            self.first = self.selected + 1 - POINTS_ROWS;
+        }
+    }
+
+    /// Moves the selection up (negative) or down the list
+    pub fn step(&mut self, by: i32) {
+        let slot = (self.selected as i32 + by).clamp(0, N_POINTS as i32 - 1);
+        self.select(slot as usize);
+    }
+
+    pub fn draw(&self, points: &PointMemory, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let label_style = MonoTextStyle::new(&PROFONT_14_POINT, DISPLAY_TEXT_COLOR);
+        let coord_style = MonoTextStyle::new(&PROFONT_12_POINT, DISPLAY_TEXT_COLOR);
+        let empty_style = MonoTextStyle::new(&PROFONT_14_POINT, BUTTON_STROKE_COLOR);
+
+        for row in 0..POINTS_ROWS {
+            let slot = self.first + row;
+            let top = self.y as i32 + (row as u16 * POINTS_ROW_HEIGHT) as i32;
+            let fill = if slot == self.selected {
+                POINTS_SELECTED_COLOR
+            } else {
+                DISPLAY_BACKGROUND_COLOR
+            };
+            Rectangle::new(
+                Point::new(self.x as i32, top),
+                Size::new(self.width as u32, POINTS_ROW_HEIGHT as u32 - 2),
+            )
+            .into_styled(PrimitiveStyle::with_fill(fill))
+            .draw(display)
+            .ok();
+
+            if slot >= N_POINTS {
+                continue;
+            }
+
+            let mut text: TextBuffer<40> = TextBuffer::new();
+            match points.get(slot) {
+                Some(point) => {
+                    write!(text, "{:02} {}", slot + 1, point.label()).ok();
+                    Text::new(
+                        text.as_str(),
+                        Point::new(self.x as i32 + 4, top + 13),
+                        label_style,
+                    )
+                    .draw(display)
+                    .ok();
+                    text.clear();
+                    let p = point.position;
+                    write!(text, "X{:8.3} Y{:8.3} Z{:8.3}", p[0], p[1], p[2]).ok();
+                    Text::new(
+                        text.as_str(),
+                        Point::new(self.x as i32 + 4, top + 28),
+                        coord_style,
+                    )
+                    .draw(display)
+                    .ok();
+                }
+                None => {
+                    write!(text, "{:02} -", slot + 1).ok();
+                    Text::new(
+                        text.as_str(),
+                        Point::new(self.x as i32 + 4, top + 13),
+                        empty_style,
+                    )
+                    .draw(display)
+                    .ok();
+                }
+            }
+        }
+    }
+
+    /// Fills the space used by the edit field with a reminder of how to edit
+    pub fn draw_hint(&self, top: u16, display: &mut Stm32F7DiscoDisplay<u16>) {
+        Rectangle::new(
+            Point::new(self.x as i32, top as i32),
+            Size::new(SEVEN_SEG_WIDTH as u32, SEVEN_SEG_HEIGHT as u32),
+        )
+        .into_styled(PrimitiveStyle::with_fill(BACKGROUND_COLOR))
+        .draw(display)
+        .ok();
+        let style = MonoTextStyle::new(&PROFONT_12_POINT, BUTTON_FILL_COLOR);
+        Text::new(
+            "Tap a row, then X/Y/Z\nto type a coordinate,\nor Name to label it",
+            Point::new(self.x as i32 + 4, top as i32 + 20),
+            style,
+        )
+        .draw(display)
+        .ok();
+    }
+}
+
+/// The label being typed for a point, above the keyboard
+#[derive(Copy, Clone, Debug)]
+pub struct LabelEntry {
+    x: u16,
+    y: u16,
+    width: u16,
+}
+
+impl LabelEntry {
+    pub fn new(x: u16, y: u16, width: u16) -> LabelEntry {
+        LabelEntry { x, y, width }
+    }
+
+    pub fn draw(&self, slot: usize, label: &str, display: &mut Stm32F7DiscoDisplay<u16>) {
+        Rectangle::new(
+            Point::new(self.x as i32, self.y as i32),
+            Size::new(self.width as u32, 70),
+        )
+        .into_styled(PrimitiveStyle::with_fill(DISPLAY_BACKGROUND_COLOR))
+        .draw(display)
+        .ok();
+
+        let mut text: TextBuffer<40> = TextBuffer::new();
+        write!(text, "Label for point {:02}", slot + 1).ok();
+        Text::new(
+            text.as_str(),
+            Point::new(self.x as i32 + 4, self.y as i32 + 20),
+            MonoTextStyle::new(&PROFONT_18_POINT, DISPLAY_TEXT_COLOR),
+        )
+        .draw(display)
+        .ok();
+
+        Rectangle::new(
+            Point::new(self.x as i32, self.y as i32 + 32),
+            Size::new(self.width as u32, 34),
+        )
+        .into_styled(PrimitiveStyle::with_stroke(BUTTON_FILL_COLOR, 1))
+        .draw(display)
+        .ok();
+        text.clear();
+        write!(text, "{}_", label).ok();
+        Text::new(
+            text.as_str(),
+            Point::new(self.x as i32 + 6, self.y as i32 + 58),
+            MonoTextStyle::new(&PROFONT_24_POINT, DISPLAY_TEXT_COLOR),
+        )
+        .draw(display)
+        .ok();
+    }
+}
+
+/// Datum changes, newest at the top. Undone changes are greyed out.
+#[derive(Copy, Clone, Debug)]
+pub struct HistoryList {
+    x: u16,
+    y: u16,
+    width: u16,
+}
+
+impl HistoryList {
+    pub fn new(x: u16, y: u16, width: u16) -> HistoryList {
+        HistoryList { x, y, width }
+    }
+
+    /// Returns the number of the change shown in the row under x, y
+    pub fn row_at(&self, history: &History, x: u16, y: u16) -> Option<usize> {
+        let height = HISTORY_ROWS as u16 * HISTORY_ROW_HEIGHT;
+        if x < self.x || x > self.x + self.width || y < self.y || y >= self.y + height {
+            return None;
+        }
+        let row = ((y - self.y) / HISTORY_ROW_HEIGHT) as usize;
+        if row < history.count() {
+            Some(history.count() - 1 - row)
+        } else {
+            None
+        }
+    }
+
+    pub fn draw(&self, history: &History, display: &mut Stm32F7DiscoDisplay<u16>) {
+        Rectangle::new(
+            Point::new(self.x as i32, self.y as i32),
+            Size::new(
+                self.width as u32,
+                (HISTORY_ROWS as u16 * HISTORY_ROW_HEIGHT) as u32,
+            ),
+        )
+        .into_styled(PrimitiveStyle::with_fill(DISPLAY_BACKGROUND_COLOR))
+        .draw(display)
+        .ok();
+
+        if history.count() == 0 {
+            let style = MonoTextStyle::new(&PROFONT_14_POINT, BUTTON_FILL_COLOR);
+            Text::new(
+                "No datum changes yet",
+                Point::new(self.x as i32 + 4, self.y as i32 + 16),
+                style,
+            )
+            .draw(display)
+            .ok();
+            return;
+        }
+
+        for row in 0..HISTORY_ROWS.min(history.count()) {
+            let i = history.count() - 1 - row;
+            if let Some((change, applied)) = history.get(i) {
+                let color = if applied {
+                    DISPLAY_TEXT_COLOR
+                } else {
+                    HISTORY_UNDONE_COLOR
+                };
+                let mut text: TextBuffer<40> = TextBuffer::new();
+                change.describe(&mut text).ok();
+                Text::new(
+                    text.as_str(),
+                    Point::new(
+                        self.x as i32 + 4,
+                        self.y as i32 + (row as u16 * HISTORY_ROW_HEIGHT) as i32 + 16,
+                    ),
+                    MonoTextStyle::new(&PROFONT_14_POINT, color),
+                )
+                .draw(display)
+                .ok();
+            }
+        }
+    }
+}
+
+/// Caption for the probe radius and a summary of the mode and last contact
+#[derive(Copy, Clone, Debug)]
+pub struct ProbeStatus {
+    x: u16,
+    y: u16,
+    width: u16,
+    ignored: bool, // The last contact came while a controller was connected
+}
+
+impl ProbeStatus {
+    pub fn new(x: u16, y: u16, width: u16) -> ProbeStatus {
+        ProbeStatus {
+            x,
+            y,
+            width,
+            ignored: false,
+        }
+    }
+
+    pub fn set_ignored(&mut self, ignored: bool) {
+        self.ignored = ignored;
+    }
+
+    pub fn draw(
+        &self,
+        mode: ProbeMode,
+        contact: Option<[f32; 3]>,
+        display: &mut Stm32F7DiscoDisplay<u16>,
+    ) {
+        let caption = MonoTextStyle::new(&PROFONT_12_POINT, BUTTON_FILL_COLOR);
+        Text::new(
+            "Probe radius / plate",
+            Point::new(self.x as i32 + 4, SEVEN_SEG_TOP as i32 - 4),
+            caption,
+        )
+        .draw(display)
+        .ok();
+
+        Rectangle::new(
+            Point::new(self.x as i32, self.y as i32),
+            Size::new(self.width as u32, PROBE_STATUS_HEIGHT as u32),
+        )
+        .into_styled(PrimitiveStyle::with_fill(DISPLAY_BACKGROUND_COLOR))
+        .draw(display)
+        .ok();
+
+        let style = MonoTextStyle::new(&PROFONT_14_POINT, DISPLAY_TEXT_COLOR);
+        let mut text: TextBuffer<40> = TextBuffer::new();
+        write!(text, "Armed: ").ok();
+        mode.describe(&mut text).ok();
+        let mut at = Point::new(self.x as i32 + 4, self.y as i32 + 18);
+        Text::new(text.as_str(), at, style).draw(display).ok();
+
+        at.y += 24;
+        if self.ignored {
+            let warning = MonoTextStyle::new(&PROFONT_14_POINT, JOB_ERROR_COLOR);
+            Text::new("Contact ignored:", at, warning)
+                .draw(display)
+                .ok();
+            at.y += 18;
+            Text::new("controller connected", at, warning)
+                .draw(display)
+                .ok();
+            return;
+        }
+        match contact {
+            Some(p) => {
+                Text::new("Last contact:", at, style).draw(display).ok();
+                for (i, name) in ['X', 'Y', 'Z'].iter().enumerate() {
+                    text.clear();
+                    write!(text, "{} {:9.3}", name, p[i]).ok();
+                    at.y += 18;
+                    Text::new(text.as_str(), at, style).draw(display).ok();
+                }
+            }
+            None => {
+                Text::new("No contact yet", at, style).draw(display).ok();
+            }
+        }
+    }
+}
+
+/// One line under the macro keys: what is running, or how the last one
+/// or loading the file went
+#[derive(Copy, Clone, Debug)]
+pub struct MacroStatus {
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+    message: TextBuffer<40>,
+    error: bool,
+}
+
+impl MacroStatus {
+    pub fn new(x: u16, y: u16, width: u16, height: u16) -> MacroStatus {
+        MacroStatus {
+            x,
+            y,
+            width,
+            height,
+            message: TextBuffer::new(),
+            error: false,
+        }
+    }
+
+    /// Errors are shown in red
+    pub fn set_message(&mut self, message: &str, error: bool) {
+        self.message.clear();
+        self.message.write_str(message).ok();
+        self.error = error;
+    }
+
+    pub fn draw(&self, display: &mut Stm32F7DiscoDisplay<u16>) {
+        Rectangle::new(
+            Point::new(self.x as i32, self.y as i32),
+            Size::new(self.width as u32, self.height as u32),
+        )
+        .into_styled(PrimitiveStyle::with_fill(DISPLAY_BACKGROUND_COLOR))
+        .draw(display)
+        .ok();
+
+        let color = if self.error {
+            JOB_ERROR_COLOR
+        } else {
+            DTG_ARRIVED_COLOR
+        };
+        Text::new(
+            self.message.as_str(),
+            Point::new(self.x as i32 + 4, (self.y + self.height / 2) as i32 + 5),
+            MonoTextStyle::new(&PROFONT_14_POINT, color),
+        )
+        .draw(display)
+        .ok();
+    }
+}
+
+/// The threading dial's big light: when to close the half-nuts, flashing
+/// while it's time
+#[derive(Copy, Clone, Debug)]
+pub struct DialIndicator {
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+    signal: Signal,
+    lit: bool,
+    every: u32, // Spindle turns between chances
+}
+
+impl DialIndicator {
+    pub fn new(x: u16, y: u16, width: u16, height: u16) -> DialIndicator {
+        DialIndicator {
+            x,
+            y,
+            width,
+            height,
+            signal: Signal::NoReference,
+            lit: false,
+            every: 0,
+        }
+    }
+
+    /// Returns true if it needs redrawing
+    pub fn set(&mut self, signal: Signal, lit: bool, every: u32) -> bool {
+        let changed = (signal, lit, every) != (self.signal, self.lit, self.every);
+        self.signal = signal;
+        self.lit = lit;
+        self.every = every;
+        changed
+    }
+
+    pub fn draw(&self, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let (fill, color) = match self.signal {
+            Signal::Engage if self.lit => (DIAL_ENGAGE_COLOR, Rgb565::BLACK),
+            Signal::Engage => (DISPLAY_BACKGROUND_COLOR, DIAL_ENGAGE_COLOR),
+            Signal::WrongGroove => (DIAL_WRONG_COLOR, BUTTON_FILL_COLOR),
+            Signal::Engaged => (DISPLAY_BACKGROUND_COLOR, DIAL_ENGAGE_COLOR),
+            Signal::Wait(_) => (DISPLAY_BACKGROUND_COLOR, DIAL_WAIT_COLOR),
+            Signal::NoReference => (DISPLAY_BACKGROUND_COLOR, DISPLAY_TEXT_COLOR),
+        };
+        Rectangle::new(
+            Point::new(self.x as i32, self.y as i32),
+            Size::new(self.width as u32, self.height as u32),
+        )
+        .into_styled(PrimitiveStyle::with_fill(fill))
+        .draw(display)
+        .ok();
+
+        let mut text: TextBuffer<24> = TextBuffer::new();
+        let (big, small) = match self.signal {
+            Signal::NoReference => ("Ready", "Cut a first pass"),
+            Signal::Wait(turns) => {
+                write!(text, "in {} turns", turns).ok();
+                ("WAIT", text.as_str())
+            }
+            Signal::Engage => ("ENGAGE", "Close the half-nuts"),
+            Signal::Engaged => ("Engaged", "In step"),
+            Signal::WrongGroove => ("WRONG", "Open the half-nuts"),
+        };
+        let mut at = Point::new(self.x as i32 + 10, self.y as i32 + self.height as i32 / 2);
+        Text::new(big, at, MonoTextStyle::new(&PROFONT_24_POINT, color))
+            .draw(display)
+            .ok();
+        at.y += 26;
+        Text::new(small, at, MonoTextStyle::new(&PROFONT_14_POINT, color))
+            .draw(display)
+            .ok();
+
+        let mut text: TextBuffer<24> = TextBuffer::new();
+        write!(text, "Every {} turns", self.every).ok();
+        Text::new(
+            text.as_str(),
+            Point::new(self.x as i32 + 10, (self.y + self.height) as i32 - 8),
+            MonoTextStyle::new(&PROFONT_12_POINT, color),
+        )
+        .draw(display)
+        .ok();
+    }
+}
+
+/// Each relay output with its setting and whether it's on
+#[derive(Copy, Clone, Debug)]
+pub struct RelayList {
+    x: u16,
+    y: u16,
+    width: u16,
+}
+
+impl RelayList {
+    pub fn new(x: u16, y: u16, width: u16) -> RelayList {
+        RelayList { x, y, width }
+    }
+
+    pub fn draw(&self, relays: &Relays, display: &mut Stm32F7DiscoDisplay<u16>) {
+        Rectangle::new(
+            Point::new(self.x as i32, self.y as i32),
+            Size::new(
+                self.width as u32,
+                (OUTPUTS.len() as u16 * RELAY_ROW_HEIGHT) as u32,
+            ),
+        )
+        .into_styled(PrimitiveStyle::with_fill(DISPLAY_BACKGROUND_COLOR))
+        .draw(display)
+        .ok();
+
+        let mut text: TextBuffer<32> = TextBuffer::new();
+        let mut at = Point::new(self.x as i32 + 6, self.y as i32 + 26);
+        for output in OUTPUTS {
+            let (state, color) = if relays.is_on(output) {
+                ("ON", RELAY_ON_COLOR)
+            } else {
+                ("off", DISPLAY_TEXT_COLOR)
+            };
+            text.clear();
+            write!(
+                text,
+                "{:8}{:5} {}",
+                output.name(),
+                relays.setting(output).name(),
+                state
+            )
+            .ok();
+            Text::new(
+                text.as_str(),
+                at,
+                MonoTextStyle::new(&PROFONT_18_POINT, color),
+            )
+            .draw(display)
+            .ok();
+            at.y += RELAY_ROW_HEIGHT as i32;
+        }
+    }
+}
+
+/// Power feed stops, speed and state, below the X readout
+#[derive(Copy, Clone, Debug)]
+pub struct PowerFeedStatus {
+    x: u16,
+    y: u16,
+    width: u16,
+    problem: Option<powerfeed::Problem>, // Why the last start was refused
+}
+
+impl PowerFeedStatus {
+    pub fn new(x: u16, y: u16, width: u16) -> PowerFeedStatus {
+        PowerFeedStatus {
+            x,
+            y,
+            width,
+            problem: None,
+        }
+    }
+
+    pub fn set_problem(&mut self, problem: Option<powerfeed::Problem>) {
+        self.problem = problem;
+    }
+
+    /// Stops are shown in work coordinates, `offset` from machine
+    pub fn draw(&self, feed: &PowerFeed, offset: f32, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let caption = MonoTextStyle::new(&PROFONT_12_POINT, BUTTON_FILL_COLOR);
+        Text::new(
+            "X power feed",
+            Point::new(self.x as i32 + 4, SEVEN_SEG_TOP as i32 - 4),
+            caption,
+        )
+        .draw(display)
+        .ok();
+
+        Rectangle::new(
+            Point::new(self.x as i32, self.y as i32),
+            Size::new(self.width as u32, PROBE_STATUS_HEIGHT as u32),
+        )
+        .into_styled(PrimitiveStyle::with_fill(DISPLAY_BACKGROUND_COLOR))
+        .draw(display)
+        .ok();
+
+        let style = MonoTextStyle::new(&PROFONT_14_POINT, DISPLAY_TEXT_COLOR);
+        let mut text: TextBuffer<40> = TextBuffer::new();
+        let mut at = Point::new(self.x as i32 + 4, self.y as i32 + 18);
+        for (name, direction) in [("Left ", Direction::Left), ("Right", Direction::Right)] {
+            text.clear();
+            match feed.stop_at(direction) {
+                Some(stop) => write!(text, "{} stop {:9.3}", name, stop + offset).ok(),
+                None => write!(text, "{} stop   not set", name).ok(),
+            };
+            Text::new(text.as_str(), at, style).draw(display).ok();
+            at.y += 20;
+        }
+
+        text.clear();
+        match feed.speed() {
+            Speed::Feed => write!(text, "Speed: feed {}%", feed.feed_percent()).ok(),
+            Speed::Rapid => write!(text, "Speed: rapid").ok(),
+        };
+        Text::new(text.as_str(), at, style).draw(display).ok();
+
+        at.y += 28;
+        text.clear();
+        let color = match (self.problem, feed.running(), feed.reached()) {
+            (Some(problem), _, _) => {
+                problem.describe(&mut text).ok();
+                RUNNING_ALARM_COLOR
+            }
+            (None, Some(direction), _) => {
+                write!(text, "Running {}", direction.name()).ok();
+                RUNNING_BUSY_COLOR
+            }
+            (None, None, Some(direction)) => {
+                write!(text, "At {} stop", direction.name()).ok();
+                RUNNING_IDLE_COLOR
+            }
+            (None, None, None) => {
+                write!(text, "Stopped").ok();
+                RUNNING_IDLE_COLOR
+            }
+        };
+        Text::new(
+            text.as_str(),
+            at,
+            MonoTextStyle::new(&PROFONT_14_POINT, color),
+        )
+        .draw(display)
+        .ok();
+    }
+}
+
+/// One line above the readouts showing what the machine controller is doing.
+/// Blank when there is no controller.
+#[derive(Copy, Clone, Debug)]
+pub struct MachineStatus {
+    x: u16,
+    y: u16,
+    width: u16,
+    running: Option<ui::Running>,
+    feed: f32,
+    spindle: f32,
+    limit: Zone,
+    relays: [bool; 3], // On or off, in `OUTPUTS` order
+}
+
+impl MachineStatus {
+    pub fn new(x: u16, y: u16, width: u16) -> MachineStatus {
+        MachineStatus {
+            x,
+            y,
+            width,
+            running: None,
+            feed: 0.0,
+            spindle: 0.0,
+            limit: Zone::Inside,
+            relays: [false; 3],
+        }
+    }
+
+    pub fn running(&self) -> Option<ui::Running> {
+        self.running
+    }
+
+    /// Returns true if anything shown changed
+    pub fn set(&mut self, running: Option<ui::Running>, feed: f32, spindle: f32) -> bool {
+        let changed = running != self.running || feed != self.feed || spindle != self.spindle;
+        self.running = running;
+        self.feed = feed;
+        self.spindle = spindle;
+        changed
+    }
+
+    /// Takes where the machine is against the soft limits. Returns true if
+    /// that changed.
+    pub fn set_limit(&mut self, limit: Zone) -> bool {
+        let changed = limit != self.limit;
+        self.limit = limit;
+        changed
+    }
+
+    /// Takes which relay outputs are on. Returns true if that changed.
+    pub fn set_relays(&mut self, relays: [bool; 3]) -> bool {
+        let changed = relays != self.relays;
+        self.relays = relays;
+        changed
+    }
+
+    pub fn draw(&self, display: &mut Stm32F7DiscoDisplay<u16>) {
+        Rectangle::new(
+            Point::new(self.x as i32, self.y as i32),
+            Size::new(self.width as u32, SEVEN_SEG_TOP as u32 - 1),
+        )
+        .into_styled(PrimitiveStyle::with_fill(BACKGROUND_COLOR))
+        .draw(display)
+        .ok();
+
+        // A letter per relay at the right hand end, lit while it's on
+        let mut at = Point::new(
+            (self.x + self.width) as i32 - 8 * OUTPUTS.len() as i32,
+            self.y as i32 + 11,
+        );
+        for (output, on) in OUTPUTS.iter().zip(self.relays.iter()) {
+            let color = if *on { RELAY_ON_COLOR } else { RELAY_OFF_COLOR };
+            Text::new(
+                output.short_name(),
+                at,
+                MonoTextStyle::new(&PROFONT_12_POINT, color),
+            )
+            .draw(display)
+            .ok();
+            at.x += 8;
+        }
+
+        // Being near or over a soft limit matters more than the status
+        let axes = ['X', 'Y', 'Z'];
+        let mut text: TextBuffer<32> = TextBuffer::new();
+        let color = match self.limit {
+            Zone::Near(axis, side) => {
+                write!(text, "{} near {} limit", axes[axis], side.name()).ok();
+                SOFT_LIMIT_NEAR_COLOR
+            }
+            Zone::Over(axis, side) => {
+                write!(text, "{} OVER {} limit", axes[axis], side.name()).ok();
+                SOFT_LIMIT_OVER_COLOR
+            }
+            Zone::Inside => {
+                let (name, color) = match self.running {
+                    Some(ui::Running::No) => ("Idle", RUNNING_IDLE_COLOR),
+                    Some(ui::Running::Yes) => ("Run", RUNNING_BUSY_COLOR),
+                    Some(ui::Running::Jog) => ("Jog", RUNNING_BUSY_COLOR),
+                    Some(ui::Running::Hold) => ("Hold", RUNNING_BUSY_COLOR),
+                    Some(ui::Running::Alarm) => ("Alarm", RUNNING_ALARM_COLOR),
+                    Some(ui::Running::Home) => ("Home", RUNNING_BUSY_COLOR),
+                    None => return,
+                };
+                write!(text, "{} F{:.0} S{:.0}", name, self.feed, self.spindle).ok();
+                color
+            }
+        };
+        Text::new(
+            text.as_str(),
+            Point::new(self.x as i32 + 4, self.y as i32 + 11),
+            MonoTextStyle::new(&PROFONT_12_POINT, color),
+        )
+        .draw(display)
+        .ok();
+    }
+}
+
+/// Jog step size and feed, in the space the feed readout uses on the DRO page
+#[derive(Copy, Clone, Debug)]
+pub struct JogStatus {
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+}
+
+impl JogStatus {
+    pub fn new(x: u16, y: u16, width: u16, height: u16) -> JogStatus {
+        JogStatus {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn draw(&self, step: JogStep, feed: f32, display: &mut Stm32F7DiscoDisplay<u16>) {
+        Rectangle::new(
+            Point::new(self.x as i32, self.y as i32),
+            Size::new(self.width as u32, self.height as u32),
+        )
+        .into_styled(PrimitiveStyle::with_fill(DISPLAY_BACKGROUND_COLOR))
+        .draw(display)
+        .ok();
+
+        let style = MonoTextStyle::new(&PROFONT_18_POINT, FEED_TEXT_COLOR);
+        let mut text: TextBuffer<24> = TextBuffer::new();
+        match step {
+            JogStep::Step(step) => write!(text, "Step {:.3}", step).ok(),
+            JogStep::Continuous => write!(text, "Continuous").ok(),
+        };
+        Text::new(
+            text.as_str(),
+            Point::new(self.x as i32 + 6, self.y as i32 + 22),
+            style,
+        )
+        .draw(display)
+        .ok();
+        text.clear();
+        write!(text, "F{:.0} mm/min", feed).ok();
+        Text::new(
+            text.as_str(),
+            Point::new(self.x as i32 + 6, self.y as i32 + 46),
+            style,
+        )
+        .draw(display)
+        .ok();
+    }
+}
+
+/// The G-code files on the SD card, a page at a time
+#[derive(Copy, Clone, Debug)]
+pub struct FileList {
+    x: u16,
+    y: u16,
+    width: u16,
+    first: usize,
+    selected: usize,
+}
+
+impl FileList {
+    pub fn new(x: u16, y: u16, width: u16) -> FileList {
+        FileList {
+            x,
+            y,
+            width,
+            first: 0,
+            selected: 0,
+        }
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Returns the file shown in the row under x, y
+    pub fn row_at(&self, files: &FileNames, x: u16, y: u16) -> Option<usize> {
+        let height = FILES_ROWS as u16 * FILES_ROW_HEIGHT;
+        if x < self.x || x > self.x + self.width || y < self.y || y >= self.y + height {
+            return None;
+        }
+        let i = self.first + ((y - self.y) / FILES_ROW_HEIGHT) as usize;
+        if i < files.count() {
+            Some(i)
+        } else {
+            None
+        }
+    }
+
+    pub fn select(&mut self, i: usize, files: &FileNames) {
+        self.selected = i.min(files.count().max(1) - 1);
+        if self.selected < self.first {
+            self.first = self.selected;
+        } else if self.selected >= self.first + FILES_ROWS {
+            self.first = self.selected + 1 - FILES_ROWS;
+        }
+    }
+
+    /// Moves the selection up (negative) or down the list
+    pub fn step(&mut self, by: i32, files: &FileNames) {
+        let i = (self.selected as i32 + by).max(0);
+        self.select(i as usize, files);
+    }
+
+    /// `files` is None when the card couldn't be read
+    pub fn draw(&self, files: Option<&FileNames>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        Rectangle::new(
+            Point::new(self.x as i32, self.y as i32),
+            Size::new(
+                self.width as u32,
+                (FILES_ROWS as u16 * FILES_ROW_HEIGHT) as u32,
+            ),
+        )
+        .into_styled(PrimitiveStyle::with_fill(DISPLAY_BACKGROUND_COLOR))
+        .draw(display)
+        .ok();
+
+        let style = MonoTextStyle::new(&PROFONT_14_POINT, DISPLAY_TEXT_COLOR);
+        let message = match files {
+            None => Some("Can't read the SD card"),
+            Some(files) if files.count() == 0 => Some("No .NC files on the card"),
+            Some(_) => None,
+        };
+        if let Some(message) = message {
+            Text::new(
+                message,
+                Point::new(self.x as i32 + 4, self.y as i32 + 16),
+                MonoTextStyle::new(&PROFONT_14_POINT, BUTTON_FILL_COLOR),
+            )
+            .draw(display)
+            .ok();
+            return;
+        }
+
+        let files = match files {
+            Some(files) => files,
+            None => return,
+        };
+        for row in 0..FILES_ROWS {
+            let i = self.first + row;
+            let (name, size) = match files.get(i) {
+                Some(file) => file,
+                None => break,
+            };
+            let top = self.y as i32 + (row as u16 * FILES_ROW_HEIGHT) as i32;
+            if i == self.selected {
+                Rectangle::new(
+                    Point::new(self.x as i32, top),
+                    Size::new(self.width as u32, FILES_ROW_HEIGHT as u32 - 2),
+                )
+                .into_styled(PrimitiveStyle::with_fill(POINTS_SELECTED_COLOR))
+                .draw(display)
+                .ok();
+            }
+            let mut text: TextBuffer<32> = TextBuffer::new();
+            write!(text, "{:12} {:6}k", name, size.div_ceil(1024)).ok();
+            Text::new(
+                text.as_str(),
+                Point::new(self.x as i32 + 4, top + 19),
+                style,
+            )
+            .draw(display)
+            .ok();
+        }
+    }
+}
+
+// Writes a time as h:mm:ss
+fn write_duration<W: Write>(ms: u32, out: &mut W) -> core::fmt::Result {
+    let s = ms / 1000;
+    write!(out, "{}:{:02}:{:02}", s / 3600, s / 60 % 60, s % 60)
+}
+
+/// How far through the program a job is, and what went wrong if anything
+#[derive(Copy, Clone, Debug)]
+pub struct JobStatus {
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+    name: TextBuffer<NAME_LEN>,
+    progress: Option<Progress>,
+    overrides: Overrides,
+}
+
+impl JobStatus {
+    pub fn new(x: u16, y: u16, width: u16, height: u16) -> JobStatus {
+        JobStatus {
+            x,
+            y,
+            width,
+            height,
+            name: TextBuffer::new(),
+            progress: None,
+            overrides: Overrides::new(),
+        }
+    }
+
+    pub fn inside(&self, x: u16, y: u16) -> bool {
+        x >= self.x && x <= (self.x + self.width) && y >= self.y && y <= (self.y + self.height)
+    }
+
+    /// Returns true if x, y is on the overrides along the bottom
+    pub fn overrides_inside(&self, x: u16, y: u16) -> bool {
+        let top = self.y + self.height - JOB_OVERRIDES_HEIGHT;
+        x >= self.x && x <= (self.x + self.width) && y >= top && y <= (self.y + self.height)
+    }
+
+    /// Returns true if they changed
+    pub fn set_overrides(&mut self, overrides: Overrides) -> bool {
+        let changed = overrides != self.overrides;
+        self.overrides = overrides;
+        changed
+    }
+
+    /// A job is running, held or paused
+    pub fn active(&self) -> bool {
+        self.progress.is_some_and(|p| p.state.active())
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.name.clear();
+        self.name.write_str(name).ok();
+    }
+
+    /// Returns true if anything shown changed
+    pub fn set(&mut self, progress: Option<Progress>) -> bool {
+        // Everything is drawn to whole seconds and tenths of a percent
+        let shown = |p: &Option<Progress>| {
+            p.map(|p| {
+                (
+                    p.state,
+                    p.line,
+                    (p.percent * 10.0) as u32,
+                    p.elapsed_ms / 1000,
+                    p.remaining_ms.map(|ms| ms / 1000),
+                    p.error.map(|e| e.line),
+                )
+            })
+        };
+        let changed = shown(&progress) != shown(&self.progress);
+        self.progress = progress;
+        changed
+    }
+
+    /// A box along the bottom showing the overrides, tapped to change them
+    pub fn draw_overrides(&self, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let top = (self.y + self.height - JOB_OVERRIDES_HEIGHT) as i32;
+        Rectangle::new(
+            Point::new(self.x as i32, top),
+            Size::new(self.width as u32, JOB_OVERRIDES_HEIGHT as u32),
+        )
+        .into_styled(
+            PrimitiveStyleBuilder::new()
+                .stroke_width(1)
+                .stroke_color(BUTTON_FILL_COLOR)
+                .fill_color(DISPLAY_BACKGROUND_COLOR)
+                .build(),
+        )
+        .draw(display)
+        .ok();
+        let o = self.overrides;
+        let mut text: TextBuffer<48> = TextBuffer::new();
+        write!(
+            text,
+            "Feed {}%  Rapid {}%  Spin {}%",
+            o.feed, o.rapid, o.spindle
+        )
+        .ok();
+        Text::new(
+            text.as_str(),
+            Point::new(self.x as i32 + 6, top + 18),
+            MonoTextStyle::new(&PROFONT_14_POINT, DISPLAY_TEXT_COLOR),
+        )
+        .draw(display)
+        .ok();
+    }
+
+    pub fn draw(&self, display: &mut Stm32F7DiscoDisplay<u16>) {
+        Rectangle::new(
+            Point::new(self.x as i32, self.y as i32),
+            Size::new(self.width as u32, self.height as u32),
+        )
+        .into_styled(PrimitiveStyle::with_fill(DISPLAY_BACKGROUND_COLOR))
+        .draw(display)
+        .ok();
+
+        self.draw_overrides(display);
+
+        let style = MonoTextStyle::new(&PROFONT_18_POINT, DISPLAY_TEXT_COLOR);
+        let mut at = Point::new(self.x as i32 + 6, self.y as i32 + 24);
+        let p = match self.progress {
+            Some(p) => p,
+            None => {
+                Text::new("No job running", at, style).draw(display).ok();
+                at.y += 26;
+                Text::new(
+                    "Pick a file on the\nFiles page",
+                    at,
+                    MonoTextStyle::new(&PROFONT_14_POINT, BUTTON_FILL_COLOR),
+                )
+                .draw(display)
+                .ok();
+                return;
+            }
+        };
+
+        Text::new(self.name.as_str(), at, style).draw(display).ok();
+
+        at.y += 26;
+        let mut text: TextBuffer<48> = TextBuffer::new();
+        p.state.describe(&mut text).ok();
+        let color = match p.state {
+            JobState::Running | JobState::Finished => DISPLAY_TEXT_COLOR,
+            JobState::Held | JobState::Stopped => RUNNING_BUSY_COLOR,
+            JobState::Paused => JOB_ERROR_COLOR,
+        };
+        Text::new(
+            text.as_str(),
+            at,
+            MonoTextStyle::new(&PROFONT_18_POINT, color),
+        )
+        .draw(display)
+        .ok();
+
+        at.y += 26;
+        text.clear();
+        write!(text, "Line {}  {:.1}%", p.line, p.percent).ok();
+        Text::new(text.as_str(), at, style).draw(display).ok();
+
+        at.y += 10;
+        let bar_width = self.width as u32 - 12;
+        Rectangle::new(at, Size::new(bar_width, 12))
+            .into_styled(PrimitiveStyle::with_stroke(BUTTON_FILL_COLOR, 1))
+            .draw(display)
+            .ok();
+        let done = (bar_width as f32 * p.percent.clamp(0.0, 100.0) / 100.0) as u32;
+        Rectangle::new(at, Size::new(done, 12))
+            .into_styled(PrimitiveStyle::with_fill(JOB_BAR_COLOR))
+            .draw(display)
+            .ok();
+
+        at.y += 40;
+        text.clear();
+        write!(text, "Elapsed   ").ok();
+        write_duration(p.elapsed_ms, &mut text).ok();
+        Text::new(text.as_str(), at, style).draw(display).ok();
+
+        at.y += 26;
+        text.clear();
+        write!(text, "Remaining ").ok();
+        match p.remaining_ms {
+            Some(ms) => write_duration(ms, &mut text).ok(),
+            None => write!(text, "-").ok(),
+        };
+        Text::new(text.as_str(), at, style).draw(display).ok();
+
+        if let Some(error) = p.error {
+            let error_style = MonoTextStyle::new(&PROFONT_14_POINT, JOB_ERROR_COLOR);
+            at.y += 30;
+            text.clear();
+            error.describe(&mut text).ok();
+            Text::new(text.as_str(), at, error_style).draw(display).ok();
+
+            // As much of the line as fits, 8 pixels a character
+            at.y += 20;
+            let line = error.text.as_str();
+            let fits = (self.width as usize - 12) / 8;
+            Text::new(
+                &line[..line.len().min(fits)],
+                at,
+                MonoTextStyle::new(&PROFONT_12_POINT, JOB_ERROR_COLOR),
+            )
+            .draw(display)
+            .ok();
+        }
+    }
+}
+
+/// Feed, rapid and spindle overrides, one per row of keys
+#[derive(Copy, Clone, Debug)]
+pub struct OverrideStatus {
+    x: u16,
+    y: u16,
+    width: u16,
+    row_height: u16,
+    overrides: Overrides,
+}
+
+impl OverrideStatus {
+    pub fn new(x: u16, y: u16, width: u16, row_height: u16) -> OverrideStatus {
+        OverrideStatus {
+            x,
+            y,
+            width,
+            row_height,
+            overrides: Overrides::new(),
+        }
+    }
+
+    /// Returns true if they changed
+    pub fn set(&mut self, overrides: Overrides) -> bool {
+        let changed = overrides != self.overrides;
+        self.overrides = overrides;
+        changed
+    }
+
+    pub fn draw(&self, display: &mut Stm32F7DiscoDisplay<u16>) {
+        Rectangle::new(
+            Point::new(self.x as i32, self.y as i32),
+            Size::new(self.width as u32, 3 * self.row_height as u32),
+        )
+        .into_styled(PrimitiveStyle::with_fill(DISPLAY_BACKGROUND_COLOR))
+        .draw(display)
+        .ok();
+
+        let o = self.overrides;
+        let rows = [("Feed", o.feed), ("Rapid", o.rapid), ("Spindle", o.spindle)];
+        for (i, (name, percent)) in rows.iter().enumerate() {
+            let middle = (self.y + i as u16 * self.row_height + self.row_height / 2) as i32;
+            let color = if *percent == 100 {
+                DISPLAY_TEXT_COLOR
+            } else {
+                RUNNING_BUSY_COLOR
+            };
+            Text::new(
+                name,
+                Point::new(self.x as i32 + 6, middle + 6),
+                MonoTextStyle::new(&PROFONT_18_POINT, BUTTON_FILL_COLOR),
+            )
+            .draw(display)
+            .ok();
+            let mut text: TextBuffer<8> = TextBuffer::new();
+            write!(text, "{:3}%", percent).ok();
+            Text::new(
+                text.as_str(),
+                Point::new(self.x as i32 + 120, middle + 8),
+                MonoTextStyle::new(&PROFONT_24_POINT, color),
+            )
+            .draw(display)
+            .ok();
+        }
+    }
+}
+
+/// The toolpath of a file, with a cross where the tool is
+#[derive(Copy, Clone, Debug)]
+pub struct ToolpathPlot {
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+    viewport: Viewport,
+    name: TextBuffer<NAME_LEN>,
+    loaded: Option<bool>, // None while the file is being read
+    tool: [f32; 3],       // Work position
+    shown_tool: (i32, i32),
+}
+
+impl ToolpathPlot {
+    pub fn new(x: u16, y: u16, width: u16, height: u16) -> ToolpathPlot {
+        ToolpathPlot {
+            x,
+            y,
+            width,
+            height,
+            viewport: Viewport::new(width, height),
+            name: TextBuffer::new(),
+            loaded: None,
+            tool: [0.0; 3],
+            shown_tool: (0, 0),
+        }
+    }
+
+    pub fn inside(&self, x: u16, y: u16) -> bool {
+        x >= self.x && x <= (self.x + self.width) && y >= self.y && y <= (self.y + self.height)
+    }
+
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// Shows `name` is being read
+    pub fn start(&mut self, name: &str) {
+        self.name.clear();
+        self.name.write_str(name).ok();
+        self.loaded = None;
+    }
+
+    /// Fits the path once the file has been read, or shows it couldn't be
+    pub fn loaded(&mut self, ok: bool, path: &Toolpath) {
+        self.loaded = Some(ok);
+        self.viewport.fit(path);
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.loaded == Some(true)
+    }
+
+    pub fn viewport(&mut self) -> &mut Viewport {
+        &mut self.viewport
+    }
+
+    /// Moves the tool cross. Returns true if it moved on screen.
+    pub fn set_tool(&mut self, work: [f32; 3]) -> bool {
+        self.tool = work;
+        let shown = self.viewport.pixel(work);
+        let changed = shown != self.shown_tool;
+        self.shown_tool = shown;
+        changed
+    }
+
+    // Where a point of the path is on the screen
+    fn point(&self, position: [f32; 3]) -> Point {
+        let (x, y) = self.viewport.pixel(position);
+        Point::new(self.x as i32 + x, self.y as i32 + y)
+    }
+
+    // A cross centred on `at`
+    fn draw_cross<D: DrawTarget<Color = Rgb565>>(at: Point, color: Rgb565, target: &mut D) {
+        let style = PrimitiveStyle::with_stroke(color, 1);
+        let size = PLOT_TOOL_SIZE;
+        Line::new(at - Point::new(size, 0), at + Point::new(size, 0))
+            .into_styled(style)
+            .draw(target)
+            .ok();
+        Line::new(at - Point::new(0, size), at + Point::new(0, size))
+            .into_styled(style)
+            .draw(target)
+            .ok();
+    }
+
+    pub fn draw(&mut self, path: &Toolpath, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let area = Rectangle::new(
+            Point::new(self.x as i32, self.y as i32),
+            Size::new(self.width as u32, self.height as u32),
+        );
+        // The path can go well off the plot, and the display doesn't clip
+        let mut plot = display.clipped(&area);
+        area.into_styled(PrimitiveStyle::with_fill(DISPLAY_BACKGROUND_COLOR))
+            .draw(&mut plot)
+            .ok();
+
+        let mut text: TextBuffer<48> = TextBuffer::new();
+        let corner = Point::new(self.x as i32 + 4, self.y as i32 + 16);
+        let message_style = MonoTextStyle::new(&PROFONT_14_POINT, BUTTON_FILL_COLOR);
+        match self.loaded {
+            None => {
+                write!(text, "Reading {}...", self.name.as_str()).ok();
+                Text::new(text.as_str(), corner, message_style)
+                    .draw(&mut plot)
+                    .ok();
+                return;
+            }
+            Some(false) => {
+                write!(text, "Can't read {}", self.name.as_str()).ok();
+                Text::new(text.as_str(), corner, message_style)
+                    .draw(&mut plot)
+                    .ok();
+                return;
+            }
+            Some(true) => (),
+        }
+
+        Self::draw_cross(self.point([0.0; 3]), PLOT_ORIGIN_COLOR, &mut plot);
+
+        let bottom_right = area.bottom_right().unwrap_or(area.top_left);
+        let off_plot = |a: Point, b: Point| {
+            (a.x < area.top_left.x && b.x < area.top_left.x)
+                || (a.y < area.top_left.y && b.y < area.top_left.y)
+                || (a.x > bottom_right.x && b.x > bottom_right.x)
+                || (a.y > bottom_right.y && b.y > bottom_right.y)
+        };
+        let feed_style = PrimitiveStyle::with_stroke(PLOT_FEED_COLOR, 1);
+        let rapid_style = PrimitiveStyle::with_stroke(PLOT_RAPID_COLOR, 1);
+        let mut from = match path.vertex(0) {
+            Some((position, _)) => self.point(position),
+            None => Point::zero(),
+        };
+        for i in 1..path.count() {
+            let (position, rapid) = match path.vertex(i) {
+                Some(vertex) => vertex,
+                None => break,
+            };
+            let to = self.point(position);
+            // Lines entirely to one side of the plot would only be clipped
+            // a pixel at a time
+            if !off_plot(from, to) {
+                let style = if rapid { rapid_style } else { feed_style };
+                Line::new(from, to).into_styled(style).draw(&mut plot).ok();
+            }
+            from = to;
+        }
+
+        self.shown_tool = self.viewport.pixel(self.tool);
+        Self::draw_cross(self.point(self.tool), PLOT_TOOL_COLOR, &mut plot);
+
+        write!(
+            text,
+            "{} {}  {:.0} min",
+            self.name.as_str(),
+            self.viewport.projection().name(),
+            path.feed_minutes()
+        )
+        .ok();
+        Text::new(
+            text.as_str(),
+            corner,
+            MonoTextStyle::new(&PROFONT_14_POINT, DISPLAY_TEXT_COLOR),
+        )
+        .draw(&mut plot)
+        .ok();
+
+        if let Some(problem) = path.problem() {
+            text.clear();
+            problem.describe(&mut text).ok();
+            Text::new(
+                text.as_str(),
+                Point::new(corner.x, bottom_right.y - 6),
+                MonoTextStyle::new(&PROFONT_12_POINT, JOB_ERROR_COLOR),
+            )
+            .draw(&mut plot)
+            .ok();
+        }
+    }
+}
+
+/// The numbers a wizard asks for, under its name or the last message
+#[derive(Copy, Clone, Debug)]
+pub struct WizardList {
+    x: u16,
+    y: u16,
+    width: u16,
+    first: usize,
+    selected: usize,
+    message: TextBuffer<40>,
+    error: bool,
+}
+
+impl WizardList {
+    pub fn new(x: u16, y: u16, width: u16) -> WizardList {
+        WizardList {
+            x,
+            y,
+            width,
+            first: 0,
+            selected: 0,
+            message: TextBuffer::new(),
+            error: false,
+        }
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// Returns the field shown in the row under x, y
+    pub fn row_at<F: Fields>(&self, wizard: &F, x: u16, y: u16) -> Option<usize> {
+        let top = self.y + WIZARD_TITLE_HEIGHT;
+        let height = WIZARD_ROWS as u16 * WIZARD_ROW_HEIGHT;
+        if x < self.x || x > self.x + self.width || y < top || y >= top + height {
+            return None;
+        }
+        let i = self.first + ((y - top) / WIZARD_ROW_HEIGHT) as usize;
+        if i < wizard.count() {
+            Some(i)
+        } else {
+            None
+        }
+    }
+
+    pub fn select<F: Fields>(&mut self, i: usize, wizard: &F) {
+        self.selected = i.min(wizard.count().max(1) - 1);
+        if self.selected < self.first {
+            self.first = self.selected;
+        } else if self.selected >= self.first + WIZARD_ROWS {
+            self.first = self.selected + 1 - WIZARD_ROWS;
+        }
+    }
+
+    /// Moves the selection up (negative) or down the list
+    pub fn step<F: Fields>(&mut self, by: i32, wizard: &F) {
+        let i = (self.selected as i32 + by).max(0);
+        self.select(i as usize, wizard);
+    }
+
+    /// Shows `message` in place of the title until the next
+    /// `clear_message`. Errors are shown in red.
+    pub fn set_message(&mut self, message: &str, error: bool) {
+        self.message.clear();
+        self.message.write_str(message).ok();
+        self.error = error;
+    }
+
+    pub fn clear_message(&mut self) {
+        self.message.clear();
+    }
+
+    pub fn draw<F: Fields>(&self, wizard: &F, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let height = WIZARD_TITLE_HEIGHT + WIZARD_ROWS as u16 * WIZARD_ROW_HEIGHT;
+        Rectangle::new(
+            Point::new(self.x as i32, self.y as i32),
+            Size::new(self.width as u32, height as u32),
+        )
+        .into_styled(PrimitiveStyle::with_fill(DISPLAY_BACKGROUND_COLOR))
+        .draw(display)
+        .ok();
+
+        let (title, color) = if self.message.as_str().is_empty() {
+            (wizard.title(), BUTTON_FILL_COLOR)
+        } else if self.error {
+            (self.message.as_str(), JOB_ERROR_COLOR)
+        } else {
+            (self.message.as_str(), DTG_ARRIVED_COLOR)
+        };
+        Text::new(
+            title,
+            Point::new(self.x as i32 + 4, self.y as i32 + 16),
+            MonoTextStyle::new(&PROFONT_14_POINT, color),
+        )
+        .draw(display)
+        .ok();
+
+        let style = MonoTextStyle::new(&PROFONT_14_POINT, DISPLAY_TEXT_COLOR);
+        for row in 0..WIZARD_ROWS {
+            let i = self.first + row;
+            let (name, value) = match wizard.get(i) {
+                Some(field) => field,
+                None => break,
+            };
+            let top = (self.y + WIZARD_TITLE_HEIGHT + row as u16 * WIZARD_ROW_HEIGHT) as i32;
+            if i == self.selected {
+                Rectangle::new(
+                    Point::new(self.x as i32, top),
+                    Size::new(self.width as u32, WIZARD_ROW_HEIGHT as u32 - 2),
+                )
+                .into_styled(PrimitiveStyle::with_fill(POINTS_SELECTED_COLOR))
+                .draw(display)
+                .ok();
+            }
+            let mut text: TextBuffer<32> = TextBuffer::new();
+            // A field that isn't set, like a soft limit that is off
+            if value.is_finite() {
+                write!(text, "{:13}{:10.3}", name, value).ok();
+            } else {
+                write!(text, "{:13}{:>10}", name, "off").ok();
+            }
+            Text::new(
+                text.as_str(),
+                Point::new(self.x as i32 + 4, top + 16),
+                style,
+            )
+            .draw(display)
+            .ok();
+        }
+    }
+}
+
+/// The MDI scrollback, with the line being typed under it
+#[derive(Copy, Clone, Debug)]
+pub struct MdiConsole {
+    x: u16,
+    y: u16,
+    width: u16,
+}
+
+impl MdiConsole {
+    pub fn new(x: u16, y: u16, width: u16) -> MdiConsole {
+        MdiConsole { x, y, width }
+    }
+
+    fn clear(&self, top: u16, height: u16, display: &mut Stm32F7DiscoDisplay<u16>) {
+        Rectangle::new(
+            Point::new(self.x as i32, top as i32),
+            Size::new(self.width as u32, height as u32),
+        )
+        .into_styled(PrimitiveStyle::with_fill(DISPLAY_BACKGROUND_COLOR))
+        .draw(display)
+        .ok();
+    }
+
+    pub fn draw(&self, mdi: &Mdi, display: &mut Stm32F7DiscoDisplay<u16>) {
+        self.clear(self.y, SCROLLBACK_LEN as u16 * MDI_LINE_HEIGHT, display);
+        for i in 0..SCROLLBACK_LEN {
+            let entry = match mdi.entry(i) {
+                Some(entry) => entry,
+                None => break,
+            };
+            let color = match entry.kind {
+                EntryKind::Sent => MDI_SENT_COLOR,
+                EntryKind::Ok => MDI_OK_COLOR,
+                EntryKind::Error => MDI_ERROR_COLOR,
+                EntryKind::Message => DISPLAY_TEXT_COLOR,
+            };
+            let top = self.y + i as u16 * MDI_LINE_HEIGHT;
+            Text::new(
+                entry.text.as_str(),
+                Point::new(self.x as i32 + 4, top as i32 + 14),
+                MonoTextStyle::new(&PROFONT_14_POINT, color),
+            )
+            .draw(display)
+            .ok();
+        }
+        self.draw_line(mdi, display);
+    }
+
+    /// Redraws just the line being typed, showing its end if it's too long
+    pub fn draw_line(&self, mdi: &Mdi, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let top = self.y + SCROLLBACK_LEN as u16 * MDI_LINE_HEIGHT + 2;
+        self.clear(top, MDI_LINE_HEIGHT + 4, display);
+        Rectangle::new(
+            Point::new(self.x as i32, top as i32),
+            Size::new(self.width as u32, MDI_LINE_HEIGHT as u32 + 4),
+        )
+        .into_styled(PrimitiveStyle::with_stroke(BUTTON_FILL_COLOR, 1))
+        .draw(display)
+        .ok();
+
+        let font = &PROFONT_18_POINT;
+        let fits = (self.width as usize - 8)
+            / (font.character_size.width + font.character_spacing) as usize
+            - 2;
+        let line = mdi.line();
+        let start = line.len().saturating_sub(fits);
+        let mut text: TextBuffer<80> = TextBuffer::new();
+        write!(text, ">{}_", &line[start..]).ok();
+        Text::new(
+            text.as_str(),
+            Point::new(self.x as i32 + 4, top as i32 + 18),
+            MonoTextStyle::new(font, DISPLAY_TEXT_COLOR),
+        )
+        .draw(display)
+        .ok();
+    }
+}
+
+/// Fills the alarm page: why the machine is alarmed and what to do, the
+/// homing cycle while it runs, or that all is well
+#[derive(Copy, Clone, Debug)]
+pub struct AlarmBanner {
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+    alarm: Option<Alarm>,
+    running: Option<ui::Running>,
+    machine: [f32; 3],
+    moving: [bool; 3], // Axes that moved since the last status, while homing
+    homed: bool,
+}
+
+impl AlarmBanner {
+    pub fn new(x: u16, y: u16, width: u16, height: u16) -> AlarmBanner {
+        AlarmBanner {
+            x,
+            y,
+            width,
+            height,
+            alarm: None,
+            running: None,
+            machine: [0.0; 3],
+            moving: [false; 3],
+            homed: false,
+        }
+    }
+
+    pub fn alarm(&self) -> Option<Alarm> {
+        self.alarm
+    }
+
+    /// Takes the latest status. Returns true if anything shown changed.
+    pub fn set(
+        &mut self,
+        alarm: Option<Alarm>,
+        running: Option<ui::Running>,
+        machine: [f32; 3],
+    ) -> bool {
+        let homing = running == Some(ui::Running::Home);
+        let mut moving = [false; 3];
+        if homing {
+            for (i, m) in moving.iter_mut().enumerate() {
+                *m = machine[i] != self.machine[i];
+            }
+        }
+        if self.running == Some(ui::Running::Home) && !homing {
+            self.homed = alarm.is_none();
+        } else if homing || alarm.is_some() {
+            self.homed = false;
+        }
+        // Positions are only shown while homing, to a tenth
+        let tenths = |p: [f32; 3]| p.map(|v| (v * 10.0) as i32);
+        let changed = alarm != self.alarm
+            || running != self.running
+            || moving != self.moving
+            || (homing && tenths(machine) != tenths(self.machine));
+        self.alarm = alarm;
+        self.running = running;
+        self.machine = machine;
+        self.moving = moving;
+        changed
+    }
+
+    pub fn draw(&self, display: &mut Stm32F7DiscoDisplay<u16>) {
+        Rectangle::new(
+            Point::new(self.x as i32, self.y as i32),
+            Size::new(self.width as u32, self.height as u32),
+        )
+        .into_styled(PrimitiveStyle::with_fill(DISPLAY_BACKGROUND_COLOR))
+        .draw(display)
+        .ok();
+
+        let mut at = Point::new(self.x as i32 + 10, self.y as i32 + 34);
+        let mut text: TextBuffer<48> = TextBuffer::new();
+        let big = |color| MonoTextStyle::new(&PROFONT_24_POINT, color);
+        let small = MonoTextStyle::new(&PROFONT_18_POINT, BUTTON_FILL_COLOR);
+
+        if let Some(alarm) = self.alarm {
+            Rectangle::new(
+                Point::new(self.x as i32, self.y as i32),
+                Size::new(self.width as u32, 46),
+            )
+            .into_styled(PrimitiveStyle::with_fill(RUNNING_ALARM_COLOR))
+            .draw(display)
+            .ok();
+            Text::new("ALARM", at, big(BUTTON_FILL_COLOR))
+                .draw(display)
+                .ok();
+            at.y += 44;
+            alarm.describe(&mut text).ok();
+            Text::new(text.as_str(), at, big(RUNNING_ALARM_COLOR))
+                .draw(display)
+                .ok();
+            at.y += 32;
+            Text::new(alarm.action(), at, small).draw(display).ok();
+            return;
+        }
+
+        match self.running {
+            Some(ui::Running::Home) => {
+                Text::new("Homing", at, big(RUNNING_BUSY_COLOR))
+                    .draw(display)
+                    .ok();
+                at.y += 36;
+                write!(text, "Moving ").ok();
+                for (name, moving) in ['X', 'Y', 'Z'].iter().zip(self.moving.iter()) {
+                    if *moving {
+                        text.write_char(*name).ok();
+                    }
+                }
+                Text::new(text.as_str(), at, small).draw(display).ok();
+                at.y += 30;
+                text.clear();
+                let m = self.machine;
+                write!(text, "X{:.1} Y{:.1} Z{:.1}", m[0], m[1], m[2]).ok();
+                Text::new(text.as_str(), at, small).draw(display).ok();
+            }
+            None => {
+                Text::new("No controller", at, big(DISPLAY_TEXT_COLOR))
+                    .draw(display)
+                    .ok();
+            }
+            Some(_) => {
+                let title = if self.homed { "Homed" } else { "No alarm" };
+                Text::new(title, at, big(RUNNING_IDLE_COLOR))
+                    .draw(display)
+                    .ok();
+                at.y += 36;
+                Text::new("The machine is free to move", at, small)
+                    .draw(display)
+                    .ok();
+            }
+        }
+    }
+}
+
+/// Covers the screen while an E-stop, door or fault input is latched
+#[derive(Copy, Clone, Debug)]
+pub struct SafetyBanner {
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+}
+
+impl SafetyBanner {
+    pub fn new(x: u16, y: u16, width: u16, height: u16) -> SafetyBanner {
+        SafetyBanner {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn draw(&self, interlocks: &Interlocks, display: &mut Stm32F7DiscoDisplay<u16>) {
+        Rectangle::new(
+            Point::new(self.x as i32, self.y as i32),
+            Size::new(self.width as u32, self.height as u32),
+        )
+        .into_styled(PrimitiveStyle::with_fill(DISPLAY_BACKGROUND_COLOR))
+        .draw(display)
+        .ok();
+
+        let latched = interlocks.latched();
+        // The worst one goes in the title
+        let title = if latched.contains(Input::EStop) {
+            "E-STOP"
+        } else if latched.contains(Input::Fault) {
+            "EXTERNAL FAULT"
+        } else if latched.contains(Input::Door) {
+            "DOOR OPEN"
+        } else {
+            "Safety inputs clear"
+        };
+        let fill = if latched.stops() {
+            RUNNING_ALARM_COLOR
+        } else if latched.any() {
+            RUNNING_BUSY_COLOR
+        } else {
+            RUNNING_IDLE_COLOR
+        };
+        Rectangle::new(
+            Point::new(self.x as i32, self.y as i32),
+            Size::new(self.width as u32, 46),
+        )
+        .into_styled(PrimitiveStyle::with_fill(fill))
+        .draw(display)
+        .ok();
+        Text::new(
+            title,
+            Point::new(self.x as i32 + 10, self.y as i32 + 34),
+            MonoTextStyle::new(&PROFONT_24_POINT, Rgb565::BLACK),
+        )
+        .draw(display)
+        .ok();
+
+        let mut at = Point::new(self.x as i32 + 10, self.y as i32 + 80);
+        let mut text: TextBuffer<40> = TextBuffer::new();
+        for input in INPUTS {
+            let (state, color) = if interlocks.active().contains(input) {
+                ("TRIPPED", RUNNING_ALARM_COLOR)
+            } else if latched.contains(input) {
+                ("tripped, now OK", RUNNING_BUSY_COLOR)
+            } else {
+                ("OK", RUNNING_IDLE_COLOR)
+            };
+            text.clear();
+            write!(text, "{:8}{}", input.name(), state).ok();
+            Text::new(
+                text.as_str(),
+                at,
+                MonoTextStyle::new(&PROFONT_18_POINT, color),
+            )
+            .draw(display)
+            .ok();
+            at.y += 26;
+        }
+
+        at.y += 10;
+        let hint = if !latched.any() {
+            ""
+        } else if interlocks.can_acknowledge() {
+            "Check the machine, then Ack"
+        } else {
+            "Make safe to clear"
+        };
+        Text::new(
+            hint,
+            at,
+            MonoTextStyle::new(&PROFONT_18_POINT, BUTTON_FILL_COLOR),
+        )
+        .draw(display)
+        .ok();
+    }
+}
@@ -0,0 +1,107 @@
+use stm32f7xx_hal::pac::{RCC, TIM2, TIM3, TIM5};
+
+use crate::consts::*;
+
+// Quadrature encoders are read by timers in encoder mode 3 (count on both
+// edges of both channels):
+//   X: TIM2, PA15 (CH1, AF1) and PB3 (CH2, AF1)
+//   Y: TIM3, PB4 (CH1, AF2) and PB5 (CH2, AF2)
+//   Z: TIM5, PH10 (CH1, AF2) and PH11 (CH2, AF2), DCMI_D1 and DCMI_D2 on
+//      the camera connector. PA0 and PA1 would do, but PA1 is the Ethernet
+//      PHY's 50 MHz RMII_REF_CLK on the DISCO board.
+// TIM2 and TIM5 are 32 bit, TIM3 is only 16 bit so it is extended in software.
+// Channel 3 of TIM2 is left free for the power feed's cut out compare.
+
+// Puts a timer into encoder mode with a light input filter on both channels
+macro_rules! encoder_mode {
+    ($tim:expr) => {{
+        // CC1S = CC2S = 01 (inputs on TI1 and TI2), IC1F = IC2F = 0011
+        $tim.ccmr1_input()
+            .write(|w| unsafe { w.bits(0b01 | (0b0011 << 4) | (0b01 << 8) | (0b0011 << 12)) });
+        // CC1E and CC2E, both non-inverted
+        $tim.ccer.write(|w| unsafe { w.bits(1 | (1 << 4)) });
+        // SMS = 011: encoder mode 3
+        $tim.smcr.write(|w| unsafe { w.bits(0b011) });
+        $tim.arr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+        $tim.cnt.write(|w| unsafe { w.bits(0) });
+        $tim.cr1.write(|w| w.cen().set_bit());
+    }};
+}
+
+#[cfg_attr(test, allow(dead_code))]
+pub struct Encoders {
+    x: TIM2,
+    y: TIM3,
+    z: TIM5,
+    last_y: u16,
+    y_count: i32,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl Encoders {
+    pub fn new(x: TIM2, y: TIM3, z: TIM5) -> Encoders {
+        // NOTE(unsafe) only touches the enable bits of timers we own
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc.apb1enr
+            .modify(|_, w| w.tim2en().set_bit().tim3en().set_bit().tim5en().set_bit());
+
+        encoder_mode!(x);
+        encoder_mode!(y);
+        encoder_mode!(z);
+
+        Encoders {
+            x,
+            y,
+            z,
+            last_y: 0,
+            y_count: 0,
+        }
+    }
+
+    /// Raw counts for X, Y and Z. Must be called often enough that the 16 bit
+    /// Y counter can't move more than 32767 counts between calls.
+    pub fn counts(&mut self) -> [i32; 3] {
+        let y = self.y.cnt.read().bits() as u16;
+        self.y_count = self
+            .y_count
+            .wrapping_add(y.wrapping_sub(self.last_y) as i16 as i32);
+        self.last_y = y;
+
+        [
+            self.x.cnt.read().bits() as i32,
+            self.y_count,
+            self.z.cnt.read().bits() as i32,
+        ]
+    }
+
+    /// Turns counter values from `latch` into counts like those from `counts`.
+    /// The latch must have been taken close to the last call to `counts`.
+    pub fn extend(&self, raw: [u32; 3]) -> [i32; 3] {
+        let y = self
+            .y_count
+            .wrapping_add((raw[1] as u16).wrapping_sub(self.last_y) as i16 as i32);
+        [raw[0] as i32, y, raw[2] as i32]
+    }
+}
+
+/// Reads the counters without owning the timers, for use in interrupts
+pub fn latch() -> [u32; 3] {
+    // NOTE(unsafe) atomic reads of the count registers, which nothing else writes
+    unsafe {
+        [
+            (*TIM2::ptr()).cnt.read().bits(),
+            (*TIM3::ptr()).cnt.read().bits(),
+            (*TIM5::ptr()).cnt.read().bits(),
+        ]
+    }
+}
+
+/// Converts raw encoder counts to machine position in mm
+#[cfg_attr(test, allow(dead_code))]
+pub fn counts_to_mm(counts: [i32; 3]) -> [f32; 3] {
+    [
+        counts[0] as f32 * ENCODER_MM_PER_COUNT[0],
+        counts[1] as f32 * ENCODER_MM_PER_COUNT[1],
+        counts[2] as f32 * ENCODER_MM_PER_COUNT[2],
+    ]
+}
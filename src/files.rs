@@ -0,0 +1,273 @@
+//! G-code files on the SD card.
+//!
+//! Only the root directory of the first FAT partition is looked at, and
+//! only `.NC` files are listed. One file at a time is open for a job, and
+//! is read a line at a time through `job::LineSource`, leaving out
+//! comments so only what gets sent has to fit in `LINE_LEN`. Generated
+//! programs are written out the same way, a line at a time.
+
+use core::fmt::Write;
+
+use embedded_sdmmc::{
+    Directory, File, Mode, ShortFileName, TimeSource, Timestamp, Volume, VolumeIdx, VolumeManager,
+};
+
+use crate::job::{LineSource, Read, LINE_LEN};
+use crate::sdmmc::{self, SdCard};
+use crate::text::TextBuffer;
+
+pub const MAX_FILES: usize = 32;
+pub const NAME_LEN: usize = 12; // 8.3
+
+#[cfg_attr(test, allow(dead_code))]
+pub type Error = embedded_sdmmc::Error<sdmmc::Error>;
+
+// There's no clock, so files written are dated 1980
+#[cfg_attr(test, allow(dead_code))]
+struct NoClock;
+
+impl TimeSource for NoClock {
+    fn get_timestamp(&self) -> Timestamp {
+        Timestamp {
+            year_since_1970: 0,
+            zero_indexed_month: 0,
+            zero_indexed_day: 0,
+            hours: 0,
+            minutes: 0,
+            seconds: 0,
+        }
+    }
+}
+
+/// Names and sizes of the G-code files found on the card
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub struct FileNames {
+    names: [TextBuffer<NAME_LEN>; MAX_FILES],
+    sizes: [u32; MAX_FILES],
+    count: usize,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl FileNames {
+    pub fn new() -> FileNames {
+        FileNames {
+            names: [TextBuffer::new(); MAX_FILES],
+            sizes: [0; MAX_FILES],
+            count: 0,
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Name and size in bytes
+    pub fn get(&self, i: usize) -> Option<(&str, u32)> {
+        if i < self.count {
+            Some((self.names[i].as_str(), self.sizes[i]))
+        } else {
+            None
+        }
+    }
+
+    // Files past MAX_FILES are left out
+    fn push(&mut self, name: &ShortFileName, size: u32) {
+        if self.count == MAX_FILES {
+            return;
+        }
+        write!(self.names[self.count], "{}", name).ok();
+        self.sizes[self.count] = size;
+        self.count += 1;
+    }
+}
+
+#[cfg_attr(test, allow(dead_code))]
+pub struct SdFiles {
+    volumes: VolumeManager<SdCard, NoClock>,
+    mounted: Option<(Volume, Directory)>,
+    file: Option<File>,
+    buf: [u8; 512],
+    start: usize,
+    end: usize,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl SdFiles {
+    pub fn new(card: SdCard) -> SdFiles {
+        SdFiles {
+            volumes: VolumeManager::new(card, NoClock),
+            mounted: None,
+            file: None,
+            buf: [0; 512],
+            start: 0,
+            end: 0,
+        }
+    }
+
+    // Brings the card up and opens the root directory, if not done already
+    fn root(&mut self) -> Result<Directory, Error> {
+        if let Some((_, root)) = self.mounted {
+            return Ok(root);
+        }
+        self.volumes.device().init().map_err(Error::DeviceError)?;
+        let volume = self.volumes.open_volume(VolumeIdx(0))?;
+        let root = match self.volumes.open_root_dir(volume) {
+            Ok(root) => root,
+            Err(e) => {
+                self.volumes.close_volume(volume).ok();
+                return Err(e);
+            }
+        };
+        self.mounted = Some((volume, root));
+        Ok(root)
+    }
+
+    // Forgets everything open, so the next access starts again with
+    // whatever card is in the slot now
+    fn unmount(&mut self) {
+        if let Some(file) = self.file.take() {
+            self.volumes.close_file(file).ok();
+        }
+        if let Some((volume, root)) = self.mounted.take() {
+            self.volumes.close_dir(root).ok();
+            self.volumes.close_volume(volume).ok();
+        }
+    }
+
+    /// Lists the `.NC` files in the root directory
+    pub fn list(&mut self, names: &mut FileNames) -> Result<(), Error> {
+        *names = FileNames::new();
+        // Always look at the card afresh, it may have been swapped
+        if self.file.is_none() {
+            self.unmount();
+        }
+        let root = self.root()?;
+        let result = self.volumes.iterate_dir(root, |entry| {
+            if !entry.attributes.is_directory() && entry.name.extension() == b"NC" {
+                names.push(&entry.name, entry.size);
+            }
+        });
+        if result.is_err() {
+            self.unmount();
+        }
+        result
+    }
+
+    /// Opens a file for `next_line`, closing any open one. Returns its size.
+    pub fn open(&mut self, name: &str) -> Result<u32, Error> {
+        self.close();
+        let root = self.root()?;
+        let file = self.volumes.open_file_in_dir(root, name, Mode::ReadOnly)?;
+        self.file = Some(file);
+        self.start = 0;
+        self.end = 0;
+        self.volumes.file_length(file)
+    }
+
+    /// Writes a program to `name`, replacing any file of that name.
+    /// Returns its size.
+    pub fn save<S: LineSource>(&mut self, name: &str, source: &mut S) -> Result<u32, Error> {
+        self.close();
+        let root = self.root()?;
+        let file = self
+            .volumes
+            .open_file_in_dir(root, name, Mode::ReadWriteCreateOrTruncate)?;
+        let result = self.write_lines(file, source);
+        self.volumes.close_file(file)?;
+        self.start = 0;
+        self.end = 0;
+        result
+    }
+
+    // Copies lines to the file through `buf`, so the card gets whole blocks
+    // rather than a read-modify-write for every line
+    fn write_lines<S: LineSource>(&mut self, file: File, source: &mut S) -> Result<u32, Error> {
+        let mut line: TextBuffer<LINE_LEN> = TextBuffer::new();
+        let mut used = 0;
+        let mut size = 0;
+        loop {
+            match source.next_line(&mut line) {
+                Read::Line(_) => (),
+                Read::End => break,
+                Read::TooLong(_) | Read::Failed => return Err(Error::Unsupported),
+            }
+            for &byte in line.as_str().as_bytes().iter().chain(b"\n") {
+                if used == self.buf.len() {
+                    self.volumes.write(file, &self.buf)?;
+                    used = 0;
+                }
+                self.buf[used] = byte;
+                used += 1;
+                size += 1;
+            }
+        }
+        self.volumes.write(file, &self.buf[..used])?;
+        Ok(size)
+    }
+
+    pub fn close(&mut self) {
+        if let Some(file) = self.file.take() {
+            self.volumes.close_file(file).ok();
+        }
+    }
+
+    fn next_byte(&mut self) -> Result<Option<u8>, Error> {
+        if self.start == self.end {
+            let file = match self.file {
+                Some(file) => file,
+                None => return Ok(None),
+            };
+            self.end = self.volumes.read(file, &mut self.buf)?;
+            self.start = 0;
+            if self.end == 0 {
+                return Ok(None);
+            }
+        }
+        let byte = self.buf[self.start];
+        self.start += 1;
+        Ok(Some(byte))
+    }
+}
+
+impl LineSource for SdFiles {
+    fn next_line(&mut self, line: &mut TextBuffer<LINE_LEN>) -> Read {
+        line.clear();
+        let mut bytes = 0;
+        let mut too_long = false;
+        let mut in_comment = false;
+        let mut ended = false; // By a ; comment
+        loop {
+            let byte = match self.next_byte() {
+                Ok(Some(byte)) => byte,
+                Ok(None) if bytes == 0 => return Read::End,
+                Ok(None) => break,
+                Err(_) => {
+                    self.unmount();
+                    return Read::Failed;
+                }
+            };
+            bytes += 1;
+            match byte {
+                b'\n' => break,
+                b'\r' => (),
+                // Comments are never sent, so they can be as long as they like
+                _ if ended => (),
+                b';' if !in_comment => ended = true,
+                b'(' => in_comment = true,
+                b')' if in_comment => in_comment = false,
+                _ if in_comment => (),
+                _ => {
+                    // G-code is ASCII, anything else can only be in a comment
+                    let c = if byte.is_ascii() { byte as char } else { '?' };
+                    too_long |= line.write_char(c).is_err();
+                }
+            }
+        }
+        if too_long {
+            Read::TooLong(bytes)
+        } else {
+            Read::Line(bytes)
+        }
+    }
+}
@@ -0,0 +1,1027 @@
+//! G-code, as far as the previews, estimates and wizards need it.
+//!
+//! A line goes through three steps: `Lexer` splits it into words and drops
+//! comments, `Block::parse` checks the words make sense together, and
+//! `Interpreter::execute` applies the block to the modal state, giving the
+//! move it makes, if any, as a `Segment` in absolute machine millimetres.
+//!
+//! Understood: G0-G3, G17-G19, G20/G21, G90/G91, G54-G59, F, and numbered
+//! parameters (`#5=2.5`, `X#5`). M, S, T and the G codes that don't move
+//! anything are accepted and ignored. Anything else that would move the
+//! machine, like G28 or G38.2, is an error so callers know they can't
+//! follow the program past it.
+//!
+//! Nothing here allocates or touches hardware, so it can be checked on the
+//! host against real programs.
+
+use core::f32::consts::PI;
+use core::fmt::{self, Write};
+
+#[cfg(not(test))]
+use micromath::F32Ext;
+
+pub const N_PARAMS: usize = 100; // #0 to #99
+const MAX_ASSIGNMENTS: usize = 4;
+const MM_PER_INCH: f32 = 25.4;
+const N_WCS: usize = 6; // G54 to G59
+
+// Start and end radius of an I/J/K arc may differ by this much, as GRBL
+const ARC_TOLERANCE_MM: f32 = 0.005;
+const ARC_TOLERANCE_RATIO: f32 = 0.001;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Error {
+    BadNumber,
+    UnclosedComment,
+    Expression, // [...] isn't supported
+    UnknownWord(char),
+    Repeated(char),
+    // Two words from the same modal group
+    ModalConflict,
+    // G code, in tenths so G38.2 is 382
+    Unsupported(u16),
+    BadParameter,
+    NoFeed,
+    NoAxes,
+    ArcRadius,
+}
+
+impl Error {
+    pub fn describe<W: Write>(self, out: &mut W) -> fmt::Result {
+        match self {
+            Error::BadNumber => write!(out, "Bad number"),
+            Error::UnclosedComment => write!(out, "Comment not closed"),
+            Error::Expression => write!(out, "Expressions not supported"),
+            Error::UnknownWord(c) => write!(out, "Unknown word {}", c),
+            Error::Repeated(c) => write!(out, "{} given twice", c),
+            Error::ModalConflict => write!(out, "Conflicting G codes"),
+            Error::Unsupported(code) if code % 10 == 0 => {
+                write!(out, "G{} not supported", code / 10)
+            }
+            Error::Unsupported(code) => write!(out, "G{}.{} not supported", code / 10, code % 10),
+            Error::BadParameter => write!(out, "Bad parameter"),
+            Error::NoFeed => write!(out, "No feed rate"),
+            Error::NoAxes => write!(out, "No axis words"),
+            Error::ArcRadius => write!(out, "Arc radius doesn't fit"),
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Value {
+    Number(f32),
+    Param(usize), // #n
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Token {
+    // Letter, always upper case, and its value
+    Word(char, Value),
+    // #n=value
+    Assign(usize, Value),
+}
+
+/// Splits a line into words, skipping spaces, comments and `%`
+pub struct Lexer<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(line: &'a str) -> Lexer<'a> {
+        Lexer { rest: line }
+    }
+
+    fn skip_spaces(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    fn number(&mut self) -> Result<f32, Error> {
+        self.skip_spaces();
+        let end = self
+            .rest
+            .char_indices()
+            .find(|&(i, c)| !(c.is_ascii_digit() || c == '.' || (i == 0 && (c == '-' || c == '+'))))
+            .map_or(self.rest.len(), |(i, _)| i);
+        let (number, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        number.parse().map_err(|_| Error::BadNumber)
+    }
+
+    fn param(&mut self) -> Result<usize, Error> {
+        let n = self.number()?;
+        if n < 0.0 || n >= N_PARAMS as f32 || n != (n as usize) as f32 {
+            return Err(Error::BadParameter);
+        }
+        Ok(n as usize)
+    }
+
+    fn value(&mut self) -> Result<Value, Error> {
+        self.skip_spaces();
+        if let Some(rest) = self.rest.strip_prefix('#') {
+            self.rest = rest;
+            Ok(Value::Param(self.param()?))
+        } else if self.rest.starts_with('[') {
+            Err(Error::Expression)
+        } else {
+            Ok(Value::Number(self.number()?))
+        }
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<Token, Error>;
+
+    fn next(&mut self) -> Option<Result<Token, Error>> {
+        loop {
+            self.skip_spaces();
+            let c = self.rest.chars().next()?;
+            self.rest = &self.rest[c.len_utf8()..];
+            match c {
+                ';' => {
+                    self.rest = "";
+                    return None;
+                }
+                '(' => match self.rest.find(')') {
+                    Some(i) => self.rest = &self.rest[i + 1..],
+                    None => {
+                        self.rest = "";
+                        return Some(Err(Error::UnclosedComment));
+                    }
+                },
+                '%' | '/' => (), // Program start/end and block delete
+                '#' => {
+                    let token = self.param().and_then(|n| {
+                        self.skip_spaces();
+                        match self.rest.strip_prefix('=') {
+                            Some(rest) => {
+                                self.rest = rest;
+                                Ok(Token::Assign(n, self.value()?))
+                            }
+                            None => Err(Error::BadParameter),
+                        }
+                    });
+                    return Some(token);
+                }
+                c if c.is_ascii_alphabetic() => {
+                    let letter = c.to_ascii_uppercase();
+                    return Some(self.value().map(|v| Token::Word(letter, v)));
+                }
+                c => return Some(Err(Error::UnknownWord(c))),
+            }
+        }
+    }
+}
+
+/// Numbered parameters, all zero to start with
+#[derive(Copy, Clone, Debug)]
+pub struct Parameters {
+    values: [f32; N_PARAMS],
+}
+
+impl Parameters {
+    pub fn new() -> Parameters {
+        Parameters {
+            values: [0.0; N_PARAMS],
+        }
+    }
+
+    pub fn get(&self, n: usize) -> f32 {
+        self.values.get(n).copied().unwrap_or(0.0)
+    }
+
+    pub fn set(&mut self, n: usize, value: f32) {
+        if let Some(v) = self.values.get_mut(n) {
+            *v = value;
+        }
+    }
+
+    fn resolve(&self, value: Value) -> f32 {
+        match value {
+            Value::Number(n) => n,
+            Value::Param(n) => self.get(n),
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Motion {
+    Rapid,
+    Linear,
+    ClockwiseArc,
+    CounterClockwiseArc,
+}
+
+/// Arc plane. Each lists its axes in the order that makes clockwise mean
+/// clockwise looking down the third axis from its positive end.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Plane {
+    Xy,
+    Zx,
+    Yz,
+}
+
+impl Plane {
+    /// First and second axis of the plane, and the one normal to it
+    pub fn axes(self) -> (usize, usize, usize) {
+        match self {
+            Plane::Xy => (0, 1, 2),
+            Plane::Zx => (2, 0, 1),
+            Plane::Yz => (1, 2, 0),
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Units {
+    Mm,
+    Inch,
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Distance {
+    Absolute,
+    Incremental,
+}
+
+/// The words of one line, checked but not yet applied. Values are as
+/// written, in the block's units.
+#[derive(Copy, Clone, Debug)]
+pub struct Block {
+    pub motion: Option<Motion>,
+    pub plane: Option<Plane>,
+    pub units: Option<Units>,
+    pub distance: Option<Distance>,
+    pub wcs: Option<usize>, // 0 for G54
+    pub axes: [Option<f32>; 3],
+    pub centre: [Option<f32>; 3], // I, J, K
+    pub radius: Option<f32>,
+    pub feed: Option<f32>,
+    assignments: [(usize, f32); MAX_ASSIGNMENTS],
+    n_assignments: usize,
+}
+
+// Sets a modal group's word, which may only appear once per block
+fn set_once<T>(slot: &mut Option<T>, value: T) -> Result<(), Error> {
+    if slot.is_some() {
+        return Err(Error::ModalConflict);
+    }
+    *slot = Some(value);
+    Ok(())
+}
+
+fn set_word(slot: &mut Option<f32>, letter: char, value: f32) -> Result<(), Error> {
+    if slot.is_some() {
+        return Err(Error::Repeated(letter));
+    }
+    *slot = Some(value);
+    Ok(())
+}
+
+impl Block {
+    pub fn new() -> Block {
+        Block {
+            motion: None,
+            plane: None,
+            units: None,
+            distance: None,
+            wcs: None,
+            axes: [None; 3],
+            centre: [None; 3],
+            radius: None,
+            feed: None,
+            assignments: [(0, 0.0); MAX_ASSIGNMENTS],
+            n_assignments: 0,
+        }
+    }
+
+    /// Parses a line. Parameters are read as they were before the line, as
+    /// assignments only take effect once it has been executed.
+    pub fn parse(line: &str, params: &Parameters) -> Result<Block, Error> {
+        let mut block = Block::new();
+        for token in Lexer::new(line) {
+            match token? {
+                Token::Assign(n, value) => {
+                    if block.n_assignments == MAX_ASSIGNMENTS {
+                        return Err(Error::BadParameter);
+                    }
+                    block.assignments[block.n_assignments] = (n, params.resolve(value));
+                    block.n_assignments += 1;
+                }
+                Token::Word(letter, value) => block.word(letter, params.resolve(value))?,
+            }
+        }
+        Ok(block)
+    }
+
+    fn word(&mut self, letter: char, value: f32) -> Result<(), Error> {
+        match letter {
+            'G' => self.g_code(value),
+            'X' => set_word(&mut self.axes[0], letter, value),
+            'Y' => set_word(&mut self.axes[1], letter, value),
+            'Z' => set_word(&mut self.axes[2], letter, value),
+            'I' => set_word(&mut self.centre[0], letter, value),
+            'J' => set_word(&mut self.centre[1], letter, value),
+            'K' => set_word(&mut self.centre[2], letter, value),
+            'R' => set_word(&mut self.radius, letter, value),
+            'F' => set_word(&mut self.feed, letter, value),
+            // Spindle, tool, dwell time, line numbers and so on
+            'M' | 'S' | 'T' | 'P' | 'Q' | 'H' | 'D' | 'L' | 'N' | 'O' => Ok(()),
+            _ => Err(Error::UnknownWord(letter)),
+        }
+    }
+
+    fn g_code(&mut self, value: f32) -> Result<(), Error> {
+        if value < 0.0 {
+            return Err(Error::BadNumber);
+        }
+        let code = (value * 10.0).round() as u16;
+        match code {
+            0 => set_once(&mut self.motion, Motion::Rapid),
+            10 => set_once(&mut self.motion, Motion::Linear),
+            20 => set_once(&mut self.motion, Motion::ClockwiseArc),
+            30 => set_once(&mut self.motion, Motion::CounterClockwiseArc),
+            170 => set_once(&mut self.plane, Plane::Xy),
+            180 => set_once(&mut self.plane, Plane::Zx),
+            190 => set_once(&mut self.plane, Plane::Yz),
+            200 => set_once(&mut self.units, Units::Inch),
+            210 => set_once(&mut self.units, Units::Mm),
+            900 => set_once(&mut self.distance, Distance::Absolute),
+            910 => set_once(&mut self.distance, Distance::Incremental),
+            540 | 550 | 560 | 570 | 580 | 590 => {
+                set_once(&mut self.wcs, (code as usize - 540) / 10)
+            }
+            // Dwell, cutter and length compensation off, path modes, feed
+            // modes, arc distance mode, canned cycle cancel: nothing moves
+            40 | 400 | 490 | 610 | 640 | 800 | 901 | 940 => Ok(()),
+            _ => Err(Error::Unsupported(code)),
+        }
+    }
+}
+
+/// The modal state, as it stands between lines
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Modal {
+    pub motion: Motion,
+    pub plane: Plane,
+    pub units: Units,
+    pub distance: Distance,
+    pub wcs: usize,
+    pub feed: f32, // mm/min
+}
+
+/// One move, in absolute machine mm. Feed is in mm/min, zero for rapids.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Segment {
+    Line {
+        from: [f32; 3],
+        to: [f32; 3],
+        feed: f32,
+    },
+    Arc {
+        from: [f32; 3],
+        to: [f32; 3],
+        centre: [f32; 3],
+        clockwise: bool,
+        plane: Plane,
+        feed: f32,
+    },
+}
+
+impl Segment {
+    pub fn from(&self) -> [f32; 3] {
+        match *self {
+            Segment::Line { from, .. } | Segment::Arc { from, .. } => from,
+        }
+    }
+
+    pub fn to(&self) -> [f32; 3] {
+        match *self {
+            Segment::Line { to, .. } | Segment::Arc { to, .. } => to,
+        }
+    }
+
+    pub fn feed(&self) -> f32 {
+        match *self {
+            Segment::Line { feed, .. } | Segment::Arc { feed, .. } => feed,
+        }
+    }
+
+    pub fn is_rapid(&self) -> bool {
+        self.feed() == 0.0
+    }
+
+    /// Angle swept by an arc, always positive, in radians. Zero for lines.
+    pub fn sweep(&self) -> f32 {
+        let (from, to, centre, clockwise, plane) = match *self {
+            Segment::Line { .. } => return 0.0,
+            Segment::Arc {
+                from,
+                to,
+                centre,
+                clockwise,
+                plane,
+                ..
+            } => (from, to, centre, clockwise, plane),
+        };
+        let (a, b, _) = plane.axes();
+        let start = (from[b] - centre[b]).atan2(from[a] - centre[a]);
+        let end = (to[b] - centre[b]).atan2(to[a] - centre[a]);
+        let mut sweep = if clockwise { start - end } else { end - start };
+        // The same start and end point is a full circle
+        if sweep <= 1e-6 {
+            sweep += 2.0 * PI;
+        }
+        sweep
+    }
+
+    /// Path length in mm, including the helical part of an arc
+    pub fn length(&self) -> f32 {
+        let (from, to) = (self.from(), self.to());
+        match *self {
+            Segment::Line { .. } => {
+                let d = [to[0] - from[0], to[1] - from[1], to[2] - from[2]];
+                (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt()
+            }
+            Segment::Arc { centre, plane, .. } => {
+                let (a, b, normal) = plane.axes();
+                let radius = ((from[a] - centre[a]).powi(2) + (from[b] - centre[b]).powi(2)).sqrt();
+                let around = self.sweep() * radius;
+                let along = to[normal] - from[normal];
+                (around * around + along * along).sqrt()
+            }
+        }
+    }
+
+    /// The point `fraction` of the way along, from 0 at the start to 1 at
+    /// the end
+    pub fn point_at(&self, fraction: f32) -> [f32; 3] {
+        let (from, to) = (self.from(), self.to());
+        let mut point = [0.0; 3];
+        for (axis, p) in point.iter_mut().enumerate() {
+            *p = from[axis] + (to[axis] - from[axis]) * fraction;
+        }
+        if let Segment::Arc {
+            centre,
+            clockwise,
+            plane,
+            ..
+        } = *self
+        {
+            let (a, b, _) = plane.axes();
+            let radius = ((from[a] - centre[a]).powi(2) + (from[b] - centre[b]).powi(2)).sqrt();
+            let start = (from[b] - centre[b]).atan2(from[a] - centre[a]);
+            let turn = self.sweep() * fraction;
+            let angle = if clockwise {
+                start - turn
+            } else {
+                start + turn
+            };
+            point[a] = centre[a] + radius * angle.cos();
+            point[b] = centre[b] + radius * angle.sin();
+        }
+        point
+    }
+}
+
+pub struct Interpreter {
+    modal: Modal,
+    position: [f32; 3], // Machine mm
+    offsets: [[f32; 3]; N_WCS],
+    params: Parameters,
+}
+
+impl Interpreter {
+    /// Starts in G0 G17 G21 G90 G54 at machine zero, like GRBL after reset
+    pub fn new() -> Interpreter {
+        Interpreter {
+            modal: Modal {
+                motion: Motion::Rapid,
+                plane: Plane::Xy,
+                units: Units::Mm,
+                distance: Distance::Absolute,
+                wcs: 0,
+                feed: 0.0,
+            },
+            position: [0.0; 3],
+            offsets: [[0.0; 3]; N_WCS],
+            params: Parameters::new(),
+        }
+    }
+
+    // Only the tests look inside so far
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn modal(&self) -> Modal {
+        self.modal
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn position(&self) -> [f32; 3] {
+        self.position
+    }
+
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn params(&mut self) -> &mut Parameters {
+        &mut self.params
+    }
+
+    /// Starts from somewhere other than machine zero
+    #[cfg_attr(any(test, not(feature = "stepper")), allow(dead_code))]
+    pub fn set_position(&mut self, machine: [f32; 3]) {
+        self.position = machine;
+    }
+
+    /// Where work zero of the active offset is, in machine mm
+    #[cfg_attr(not(feature = "stepper"), allow(dead_code))]
+    pub fn work_offset(&self) -> [f32; 3] {
+        self.offsets[self.modal.wcs]
+    }
+
+    /// Sets the active work offset
+    #[cfg_attr(not(feature = "stepper"), allow(dead_code))]
+    pub fn set_work_offset(&mut self, offset: [f32; 3]) {
+        self.offsets[self.modal.wcs] = offset;
+    }
+
+    /// Puts the modal state back as after power up, keeping the position,
+    /// offsets and parameters, like a GRBL reset
+    #[cfg_attr(not(feature = "stepper"), allow(dead_code))]
+    pub fn reset(&mut self) {
+        self.modal = Interpreter::new().modal;
+    }
+
+    /// Parses and executes a line, returning the move it makes
+    pub fn execute_line(&mut self, line: &str) -> Result<Option<Segment>, Error> {
+        let block = Block::parse(line, &self.params)?;
+        self.execute(&block)
+    }
+
+    /// Applies a block in the standard order: feed, plane, units, distance
+    /// mode, work offset, motion, then parameter assignments. Nothing
+    /// changes if it fails.
+    pub fn execute(&mut self, block: &Block) -> Result<Option<Segment>, Error> {
+        let mut modal = self.modal;
+        let scale = match block.units.unwrap_or(modal.units) {
+            Units::Mm => 1.0,
+            Units::Inch => MM_PER_INCH,
+        };
+        if let Some(feed) = block.feed {
+            modal.feed = feed * scale;
+        }
+        modal.plane = block.plane.unwrap_or(modal.plane);
+        modal.units = block.units.unwrap_or(modal.units);
+        modal.distance = block.distance.unwrap_or(modal.distance);
+        modal.wcs = block.wcs.unwrap_or(modal.wcs);
+        modal.motion = block.motion.unwrap_or(modal.motion);
+
+        let offset = self.offsets[modal.wcs];
+        let from = self.position;
+        let mut to = from;
+        for (axis, word) in block.axes.iter().enumerate() {
+            if let Some(value) = word {
+                to[axis] = match modal.distance {
+                    Distance::Absolute => value * scale + offset[axis],
+                    Distance::Incremental => from[axis] + value * scale,
+                };
+            }
+        }
+
+        let moves = block.axes.iter().any(|a| a.is_some());
+        let segment = match modal.motion {
+            _ if !moves && block.motion.is_none() => None,
+            Motion::Rapid | Motion::Linear if !moves => None,
+            Motion::Rapid => Some(Segment::Line {
+                from,
+                to,
+                feed: 0.0,
+            }),
+            Motion::Linear => {
+                if modal.feed <= 0.0 {
+                    return Err(Error::NoFeed);
+                }
+                Some(Segment::Line {
+                    from,
+                    to,
+                    feed: modal.feed,
+                })
+            }
+            Motion::ClockwiseArc | Motion::CounterClockwiseArc => {
+                if !moves {
+                    return Err(Error::NoAxes);
+                }
+                if modal.feed <= 0.0 {
+                    return Err(Error::NoFeed);
+                }
+                let clockwise = modal.motion == Motion::ClockwiseArc;
+                let centre = arc_centre(block, modal.plane, clockwise, from, to, scale)?;
+                Some(Segment::Arc {
+                    from,
+                    to,
+                    centre,
+                    clockwise,
+                    plane: modal.plane,
+                    feed: modal.feed,
+                })
+            }
+        };
+
+        for &(n, value) in block.assignments[..block.n_assignments].iter() {
+            self.params.set(n, value);
+        }
+        self.modal = modal;
+        self.position = to;
+        Ok(segment)
+    }
+}
+
+// Works out the centre of an arc from either I/J/K offsets or R
+fn arc_centre(
+    block: &Block,
+    plane: Plane,
+    clockwise: bool,
+    from: [f32; 3],
+    to: [f32; 3],
+    scale: f32,
+) -> Result<[f32; 3], Error> {
+    let (a, b, normal) = plane.axes();
+    let mut centre = from;
+    centre[normal] = 0.0;
+
+    if let Some(r) = block.radius {
+        // From GRBL: the centre lies on the perpendicular bisector of the
+        // chord, on the side picked by direction, or the other side for a
+        // negative radius (more than half a circle)
+        let r = r * scale;
+        let x = to[a] - from[a];
+        let y = to[b] - from[b];
+        let chord = (x * x + y * y).sqrt();
+        let h2 = 4.0 * r * r - x * x - y * y;
+        if chord == 0.0 || h2 < 0.0 {
+            return Err(Error::ArcRadius);
+        }
+        let mut h = -h2.sqrt() / chord;
+        if !clockwise {
+            h = -h;
+        }
+        if r < 0.0 {
+            h = -h;
+        }
+        centre[a] = from[a] + 0.5 * (x - y * h);
+        centre[b] = from[b] + 0.5 * (y + x * h);
+        return Ok(centre);
+    }
+
+    let (i, j) = (block.centre[a], block.centre[b]);
+    if i.is_none() && j.is_none() {
+        return Err(Error::NoAxes);
+    }
+    centre[a] = from[a] + i.unwrap_or(0.0) * scale;
+    centre[b] = from[b] + j.unwrap_or(0.0) * scale;
+
+    let start = ((from[a] - centre[a]).powi(2) + (from[b] - centre[b]).powi(2)).sqrt();
+    let end = ((to[a] - centre[a]).powi(2) + (to[b] - centre[b]).powi(2)).sqrt();
+    let diff = (start - end).abs();
+    if diff > ARC_TOLERANCE_MM && diff > ARC_TOLERANCE_RATIO * start {
+        return Err(Error::ArcRadius);
+    }
+    Ok(centre)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: [f32; 3], b: [f32; 3]) -> bool {
+        a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1e-3)
+    }
+
+    fn tokens(line: &str) -> Vec<Result<Token, Error>> {
+        Lexer::new(line).collect()
+    }
+
+    // What comes after an error is anyone's guess, so only the first counts
+    fn first_error(line: &str) -> Option<Error> {
+        Lexer::new(line).find_map(|t| t.err())
+    }
+
+    // Runs lines that must all succeed, returning the last move
+    fn run(machine: &mut Interpreter, lines: &[&str]) -> Option<Segment> {
+        let mut last = None;
+        for line in lines {
+            last = machine
+                .execute_line(line)
+                .unwrap_or_else(|e| panic!("{}: {:?}", line, e));
+        }
+        last
+    }
+
+    fn arc_centre_of(segment: Option<Segment>) -> [f32; 3] {
+        match segment {
+            Some(Segment::Arc { centre, .. }) => centre,
+            other => panic!("not an arc: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn lexer_words_and_comments() {
+        assert_eq!(
+            tokens("g1 x-1.5 Y+2 (comment) z.5 ; the rest"),
+            vec![
+                Ok(Token::Word('G', Value::Number(1.0))),
+                Ok(Token::Word('X', Value::Number(-1.5))),
+                Ok(Token::Word('Y', Value::Number(2.0))),
+                Ok(Token::Word('Z', Value::Number(0.5))),
+            ]
+        );
+        assert_eq!(tokens("% /G0 X 1"), tokens("G0X1"));
+        assert_eq!(first_error("(open"), Some(Error::UnclosedComment));
+        assert_eq!(first_error("X[1+2]"), Some(Error::Expression));
+        assert_eq!(first_error("X-"), Some(Error::BadNumber));
+        assert_eq!(
+            tokens("X1 @"),
+            vec![
+                Ok(Token::Word('X', Value::Number(1.0))),
+                Err(Error::UnknownWord('@'))
+            ]
+        );
+    }
+
+    #[test]
+    fn lexer_parameters() {
+        assert_eq!(
+            tokens("#5 = 2.5 X#5"),
+            vec![
+                Ok(Token::Assign(5, Value::Number(2.5))),
+                Ok(Token::Word('X', Value::Param(5))),
+            ]
+        );
+        assert_eq!(first_error("#100=1"), Some(Error::BadParameter));
+        assert_eq!(first_error("#1.5=1"), Some(Error::BadParameter));
+        assert_eq!(first_error("#5"), Some(Error::BadParameter));
+    }
+
+    #[test]
+    fn modal_groups_conflict() {
+        let params = Parameters::new();
+        for line in [
+            "G0 G1 X1", "G2 G3 X1", "G17 G18", "G20 G21", "G90 G91", "G54 G55",
+        ] {
+            assert_eq!(
+                Block::parse(line, &params).err(),
+                Some(Error::ModalConflict),
+                "{}",
+                line
+            );
+        }
+        assert_eq!(
+            Block::parse("X1 X2", &params).err(),
+            Some(Error::Repeated('X'))
+        );
+        assert_eq!(
+            Block::parse("F1 F2", &params).err(),
+            Some(Error::Repeated('F'))
+        );
+        assert_eq!(
+            Block::parse("E1", &params).err(),
+            Some(Error::UnknownWord('E'))
+        );
+        // Words from different groups go together
+        let block = Block::parse("G1 G18 G20 G91 G56 X1 F2 M3 S1000 T1 N10", &params).unwrap();
+        assert_eq!(block.motion, Some(Motion::Linear));
+        assert_eq!(block.plane, Some(Plane::Zx));
+        assert_eq!(block.units, Some(Units::Inch));
+        assert_eq!(block.distance, Some(Distance::Incremental));
+        assert_eq!(block.wcs, Some(2));
+    }
+
+    #[test]
+    fn modal_state_carries_over() {
+        let mut machine = Interpreter::new();
+        assert_eq!(machine.modal().motion, Motion::Rapid);
+        assert_eq!(
+            run(&mut machine, &["G1 X10 F100", "Y5"]),
+            Some(Segment::Line {
+                from: [10.0, 0.0, 0.0],
+                to: [10.0, 5.0, 0.0],
+                feed: 100.0,
+            })
+        );
+        // No axes, no move, but the mode still changes
+        assert_eq!(run(&mut machine, &["G0"]), None);
+        assert_eq!(machine.modal().motion, Motion::Rapid);
+        assert!(run(&mut machine, &["X0"]).unwrap().is_rapid());
+        // Things that don't move are accepted and ignored
+        assert_eq!(
+            run(
+                &mut machine,
+                &["G4 P1", "G40 G49 G80 G94", "M3 S1000", "T2 M6"]
+            ),
+            None
+        );
+        assert_eq!(machine.position(), [0.0, 5.0, 0.0]);
+    }
+
+    #[test]
+    fn units() {
+        let mut machine = Interpreter::new();
+        let segment = run(&mut machine, &["G20 G1 X1 F10"]).unwrap();
+        assert!(close(segment.to(), [25.4, 0.0, 0.0]));
+        assert!((segment.feed() - 254.0).abs() < 1e-3);
+        assert_eq!(machine.modal().units, Units::Inch);
+        // Back in mm, the feed stays as it was in mm/min
+        let segment = run(&mut machine, &["G21 X10"]).unwrap();
+        assert!(close(segment.to(), [10.0, 0.0, 0.0]));
+        assert!((segment.feed() - 254.0).abs() < 1e-3);
+        // Arc offsets are scaled too
+        let centre = arc_centre_of(run(&mut machine, &["G0 X0", "G20 G2 X1 I0.5"]));
+        assert!(close(centre, [12.7, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn distance_modes() {
+        let mut machine = Interpreter::new();
+        run(&mut machine, &["G91 G0 X5", "X5 Y-2"]);
+        assert_eq!(machine.position(), [10.0, -2.0, 0.0]);
+        run(&mut machine, &["G90 X1"]);
+        assert_eq!(machine.position(), [1.0, -2.0, 0.0]);
+        // Incremental inches
+        run(&mut machine, &["G20 G91 Z1"]);
+        assert!(close(machine.position(), [1.0, -2.0, 25.4]));
+    }
+
+    #[test]
+    fn work_offsets() {
+        let mut machine = Interpreter::new();
+        for (wcs, code) in ["G54", "G55", "G56", "G57", "G58", "G59"]
+            .iter()
+            .enumerate()
+        {
+            run(&mut machine, &[code]);
+            assert_eq!(machine.modal().wcs, wcs);
+            machine.set_work_offset([wcs as f32 * 10.0, 1.0, 0.0]);
+        }
+        run(&mut machine, &["G55 G0 X1 Y1"]);
+        assert_eq!(machine.position(), [11.0, 2.0, 0.0]);
+        run(&mut machine, &["G59 X0"]);
+        assert_eq!(machine.position(), [50.0, 2.0, 0.0]);
+        assert_eq!(machine.work_offset(), [50.0, 1.0, 0.0]);
+        // Incremental moves don't care which offset is active
+        run(&mut machine, &["G54 G91 X1"]);
+        assert_eq!(machine.position(), [51.0, 2.0, 0.0]);
+        // A reset goes back to G54 but keeps the offsets
+        machine.reset();
+        assert_eq!(machine.modal().wcs, 0);
+        assert_eq!(machine.modal().distance, Distance::Absolute);
+        run(&mut machine, &["G56 X0"]);
+        assert_eq!(machine.position()[0], 20.0);
+    }
+
+    #[test]
+    fn arcs_by_radius_in_each_plane() {
+        // The short way clockwise from 0,0 to 10,10 goes round 10,0 in the
+        // plane's own axes, the long way (negative R) round 0,10
+        let cases = [
+            ("G17", "X10 Y10", [10.0, 0.0, 0.0], [0.0, 10.0, 0.0]),
+            ("G18", "Z10 X10", [0.0, 0.0, 10.0], [10.0, 0.0, 0.0]),
+            ("G19", "Y10 Z10", [0.0, 10.0, 0.0], [0.0, 0.0, 10.0]),
+        ];
+        for (plane, axes, short, long) in cases.iter() {
+            for (motion, r, centre) in [
+                ("G2", "R10", short),
+                ("G2", "R-10", long),
+                ("G3", "R10", long),
+                ("G3", "R-10", short),
+            ] {
+                let mut machine = Interpreter::new();
+                let line = format!("{} {} {} {} F100", plane, motion, axes, r);
+                let segment = run(&mut machine, &[line.as_str()]);
+                let (_, _, normal) = machine.modal().plane.axes();
+                let mut found = arc_centre_of(segment);
+                found[normal] = 0.0;
+                assert!(close(found, *centre), "{}: {:?}", line, found);
+                let sweep = segment.unwrap().sweep();
+                let expected = if r == "R10" { PI / 2.0 } else { PI * 1.5 };
+                assert!((sweep - expected).abs() < 1e-3, "{}: {}", line, sweep);
+            }
+        }
+    }
+
+    #[test]
+    fn arcs_by_offset_in_each_plane() {
+        let cases = [
+            ("G17 G2 X10 I5", Plane::Xy, [5.0, 0.0, 0.0]),
+            ("G17 G3 Y10 J5", Plane::Xy, [0.0, 5.0, 0.0]),
+            ("G18 G2 X10 I5", Plane::Zx, [5.0, 0.0, 0.0]),
+            ("G18 G3 Z10 K5", Plane::Zx, [0.0, 0.0, 5.0]),
+            ("G19 G2 Y10 J5", Plane::Yz, [0.0, 5.0, 0.0]),
+            ("G19 G3 Z10 K5", Plane::Yz, [0.0, 0.0, 5.0]),
+        ];
+        for (line, plane, centre) in cases.iter() {
+            let mut machine = Interpreter::new();
+            let line = format!("{} F100", line);
+            let segment = run(&mut machine, &[line.as_str()]).unwrap();
+            assert_eq!(machine.modal().plane, *plane);
+            assert!(close(arc_centre_of(Some(segment)), *centre), "{}", line);
+            // Half circles, either way round
+            assert!((segment.length() - 5.0 * PI).abs() < 1e-3, "{}", line);
+            assert!(close(segment.point_at(1.0), segment.to()), "{}", line);
+        }
+        // Offsets along the normal axis are ignored
+        let mut machine = Interpreter::new();
+        let segment = run(&mut machine, &["G17 G2 X10 I5 K7 F100"]);
+        assert!(close(arc_centre_of(segment), [5.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn arc_paths() {
+        let mut machine = Interpreter::new();
+        // A whole circle
+        let circle = run(&mut machine, &["G2 X0 Y0 I5 F100"]).unwrap();
+        assert!((circle.sweep() - 2.0 * PI).abs() < 1e-3);
+        assert!((circle.length() - 10.0 * PI).abs() < 1e-2);
+        assert!(close(circle.point_at(0.5), [10.0, 0.0, 0.0]));
+        // Clockwise from the left of the centre goes up first
+        assert!(close(circle.point_at(0.25), [5.0, 5.0, 0.0]));
+        // A helix: half a turn of radius 5 while dropping 5
+        let helix = run(&mut machine, &["G3 X10 Z-5 I5"]).unwrap();
+        let expected = ((5.0 * PI).powi(2) + 25.0).sqrt();
+        assert!((helix.length() - expected).abs() < 1e-2);
+        assert!(close(helix.point_at(0.5), [5.0, -5.0, -2.5]));
+    }
+
+    #[test]
+    fn parameters() {
+        let mut machine = Interpreter::new();
+        // Assignments take effect after the line
+        run(&mut machine, &["#1=3 G0 X#1"]);
+        assert_eq!(machine.position(), [0.0, 0.0, 0.0]);
+        run(&mut machine, &["X#1 #2=#1"]);
+        assert_eq!(machine.position(), [3.0, 0.0, 0.0]);
+        assert_eq!(machine.params().get(2), 3.0);
+        machine.params().set(7, -1.5);
+        run(&mut machine, &["Y#7"]);
+        assert_eq!(machine.position(), [3.0, -1.5, 0.0]);
+        assert_eq!(
+            machine.execute_line("#1=1 #2=2 #3=3 #4=4 #5=5"),
+            Err(Error::BadParameter)
+        );
+    }
+
+    #[test]
+    fn unsupported_codes_are_refused() {
+        let mut machine = Interpreter::new();
+        for (line, code) in [
+            ("G92 X0", 920),
+            ("G53 G0 X0", 530),
+            ("G28", 280),
+            ("G30", 300),
+            ("G38.2 Z-10 F50", 382),
+            ("G10 L20 P1 X0", 100),
+            ("G43 H1", 430),
+            ("G81 X1 Y1 Z-1 R1", 810),
+        ] {
+            assert_eq!(
+                machine.execute_line(line),
+                Err(Error::Unsupported(code)),
+                "{}",
+                line
+            );
+        }
+        assert_eq!(machine.execute_line("G-1"), Err(Error::BadNumber));
+        assert_eq!(machine.position(), [0.0; 3]);
+    }
+
+    #[test]
+    fn failed_lines_change_nothing() {
+        let mut machine = Interpreter::new();
+        run(&mut machine, &["G0 X1"]);
+        let before = (machine.modal(), machine.position());
+        for (line, error) in [
+            ("G1 X5", Error::NoFeed),
+            ("G2 X5 I1", Error::NoFeed),
+            ("G2 F100", Error::NoAxes),
+            ("G2 X5 F100", Error::NoAxes),
+            ("G2 X11 R1 F100", Error::ArcRadius),
+            ("G2 X1 R5 F100", Error::ArcRadius),
+            ("G2 X5 I1 F100", Error::ArcRadius),
+            ("G20 G91 G55 #3=1 G1 X5", Error::NoFeed),
+        ] {
+            assert_eq!(machine.execute_line(line), Err(error), "{}", line);
+            assert_eq!((machine.modal(), machine.position()), before, "{}", line);
+        }
+        assert_eq!(machine.params().get(3), 0.0);
+    }
+
+    #[test]
+    fn error_messages() {
+        let mut text = String::new();
+        for error in [
+            Error::Unsupported(280),
+            Error::Unsupported(382),
+            Error::Repeated('X'),
+        ] {
+            error.describe(&mut text).unwrap();
+            text.push('|');
+        }
+        assert_eq!(text, "G28 not supported|G38.2 not supported|X given twice|");
+    }
+}
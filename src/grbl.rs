@@ -0,0 +1,838 @@
+//! GRBL status reports.
+//!
+//! GRBL answers a `?` with a line like
+//!
+//!   <Idle|MPos:10.000,0.000,-5.000|FS:0,0|WCO:0.000,0.000,-20.000>
+//!
+//! It sends either MPos or WPos depending on `$10`, and WCO (the work
+//! coordinate offset, WPos = MPos - WCO) only every so often. The client
+//! here remembers the last WCO so both positions are always available.
+//! Overrides (`Ov:100,100,100`) come every so often too, and are
+//! remembered the same way. Spindle and coolant (`A:SF`) come with them,
+//! left out when all are off. The `ALARM:N` line sent once when an alarm
+//! goes off is remembered too.
+//!
+//! Every line sent is answered with `ok` or `error:N` once GRBL has taken
+//! it out of its 128 byte receive buffer, which is what streaming counts on.
+//! Anything else it says, like `ALARM:1` or `[MSG:Reset to continue]`, is
+//! kept as a message for the MDI page.
+//!
+//! Nothing in here touches hardware: bytes are fed in from the UART by the
+//! main loop, so it can be checked on the host against captured output.
+
+use core::fmt::{self, Write};
+
+use crate::alarm::Alarm;
+use crate::backend::{
+    message, Accessories, MachineBackend, Message, Messages, Override, Overrides, Port, Replies,
+    Reply, Status,
+};
+use crate::jog::JogCommand;
+use crate::text::TextBuffer;
+use crate::ui;
+
+const LINE_LEN: usize = 128;
+const RX_BUFFER_SIZE: usize = 128; // GRBL's serial receive buffer
+const AXIS_NAMES: [char; 3] = ['X', 'Y', 'Z'];
+
+// Realtime bytes, acted on as soon as they arrive
+const STATUS_QUERY: u8 = b'?';
+const FEED_HOLD: u8 = b'!';
+const CYCLE_START: u8 = b'~';
+const SOFT_RESET: u8 = 0x18;
+const JOG_CANCEL: u8 = 0x85;
+const FEED_OVERRIDE: u8 = 0x90; // Then +10%, -10%, +1%, -1%
+const RAPID_OVERRIDE: u8 = 0x95; // Then 50%, 25%
+const SPINDLE_OVERRIDE: u8 = 0x99; // Then +10%, -10%, +1%, -1%
+const OVERRIDE_BYTES: usize = 20; // 100% to 10% or 200%, the longest way
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum MachineState {
+    Idle,
+    Run,
+    Hold,
+    Jog,
+    Alarm,
+    Door,
+    Check,
+    Home,
+    Sleep,
+}
+
+impl MachineState {
+    /// Parses the first field of a report. Sub states like `Hold:1` are ignored.
+    pub fn parse(field: &str) -> Option<MachineState> {
+        let name = field.split(':').next()?;
+        match name {
+            "Idle" => Some(MachineState::Idle),
+            "Run" => Some(MachineState::Run),
+            "Hold" => Some(MachineState::Hold),
+            "Jog" => Some(MachineState::Jog),
+            "Alarm" => Some(MachineState::Alarm),
+            "Door" => Some(MachineState::Door),
+            "Check" => Some(MachineState::Check),
+            "Home" => Some(MachineState::Home),
+            "Sleep" => Some(MachineState::Sleep),
+            _ => None,
+        }
+    }
+
+    pub fn running(self) -> ui::Running {
+        match self {
+            MachineState::Run => ui::Running::Yes,
+            MachineState::Home => ui::Running::Home,
+            MachineState::Jog => ui::Running::Jog,
+            MachineState::Hold | MachineState::Door => ui::Running::Hold,
+            MachineState::Alarm => ui::Running::Alarm,
+            MachineState::Idle | MachineState::Check | MachineState::Sleep => ui::Running::No,
+        }
+    }
+}
+
+/// The fields of one status report, as sent
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Report {
+    pub state: MachineState,
+    pub mpos: Option<[f32; 3]>,
+    pub wpos: Option<[f32; 3]>,
+    pub wco: Option<[f32; 3]>,
+    pub feed: Option<f32>,
+    pub spindle: Option<f32>,
+    pub overrides: Option<Overrides>,
+    pub accessories: Option<Accessories>,
+}
+
+fn parse_floats<const N: usize>(text: &str) -> Option<[f32; N]> {
+    let mut values = [0.0; N];
+    let mut parts = text.split(',');
+    for v in values.iter_mut() {
+        *v = parts.next()?.trim().parse().ok()?;
+    }
+    // Machines with more axes send more values, which we ignore
+    Some(values)
+}
+
+/// Parses a `<...>` status report, returning None for any other line
+pub fn parse_status(line: &str) -> Option<Report> {
+    let line = line.trim();
+    let body = line.strip_prefix('<')?.strip_suffix('>')?;
+    let mut fields = body.split('|');
+    let mut report = Report {
+        state: MachineState::parse(fields.next()?)?,
+        mpos: None,
+        wpos: None,
+        wco: None,
+        feed: None,
+        spindle: None,
+        overrides: None,
+        accessories: None,
+    };
+
+    let mut accessories = Accessories::new();
+    for field in fields {
+        let (name, value) = match field.find(':') {
+            Some(i) => (&field[..i], &field[i + 1..]),
+            None => continue,
+        };
+        match name {
+            "MPos" => report.mpos = parse_floats(value),
+            "WPos" => report.wpos = parse_floats(value),
+            "WCO" => report.wco = parse_floats(value),
+            "FS" => {
+                if let Some([feed, spindle]) = parse_floats::<2>(value) {
+                    report.feed = Some(feed);
+                    report.spindle = Some(spindle);
+                }
+            }
+            "F" => report.feed = parse_floats::<1>(value).map(|[feed]| feed),
+            "Ov" => {
+                report.overrides =
+                    parse_floats::<3>(value).map(|[feed, rapid, spindle]| Overrides {
+                        feed: feed as u16,
+                        rapid: rapid as u16,
+                        spindle: spindle as u16,
+                    })
+            }
+            // S or C for the spindle either way, F or M for flood or mist
+            "A" => {
+                accessories.spindle = value.contains(['S', 'C']);
+                accessories.coolant = value.contains(['F', 'M']);
+            }
+            _ => (), // Buffer, line number, pins...
+        }
+    }
+    if report.overrides.is_some() {
+        report.accessories = Some(accessories);
+    }
+    Some(report)
+}
+
+/// Parses `ok` or `error:N`, returning None for any other line
+pub fn parse_reply(line: &str) -> Option<Reply> {
+    let line = line.trim();
+    if line == "ok" {
+        return Some(Reply::Ok);
+    }
+    // GRBL 0.9 sent words rather than numbers
+    let code = line.strip_prefix("error:")?;
+    Some(Reply::Error(code.trim().parse().unwrap_or(0)))
+}
+
+/// Something worth acting on from GRBL
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Received {
+    Status(Status),
+    Reply(Reply),
+    Message(Message),
+}
+
+/// Writes a relative, metric jog of one axis as a `$J=` line
+fn write_jog<W: Write>(axis: usize, distance: f32, feed: f32, out: &mut W) -> fmt::Result {
+    writeln!(
+        out,
+        "$J=G91G21{}{:.3}F{:.0}",
+        AXIS_NAMES[axis], distance, feed
+    )
+}
+
+/// Writes the realtime bytes that set an override, returning how many.
+/// GRBL only steps feed and spindle overrides up and down, so they go back
+/// to 100% first.
+fn write_override(value: Override, out: &mut [u8; OVERRIDE_BYTES]) -> usize {
+    let (reset, percent) = match value {
+        Override::Feed(p) => (FEED_OVERRIDE, p),
+        Override::Spindle(p) => (SPINDLE_OVERRIDE, p),
+        Override::Rapid(p) => {
+            out[0] = match p {
+                100 => RAPID_OVERRIDE,
+                50 => RAPID_OVERRIDE + 1,
+                _ => RAPID_OVERRIDE + 2,
+            };
+            return 1;
+        }
+    };
+    out[0] = reset;
+    let mut len = 1;
+    let (coarse, fine) = if percent >= 100 {
+        (reset + 1, reset + 3)
+    } else {
+        (reset + 2, reset + 4)
+    };
+    let change = percent.abs_diff(100);
+    for _ in 0..change / 10 {
+        out[len] = coarse;
+        len += 1;
+    }
+    for _ in 0..change % 10 {
+        out[len] = fine;
+        len += 1;
+    }
+    len
+}
+
+pub struct Grbl {
+    line: [u8; LINE_LEN],
+    len: usize,
+    overflow: bool,
+    wco: Option<[f32; 3]>,
+    feed: f32,
+    spindle: f32,
+    overrides: Overrides,
+    accessories: Accessories,
+    alarm: Option<Alarm>,
+    last_poll_ms: u32,
+    last_report_ms: Option<u32>,
+    poll_ms: u32,
+    timeout_ms: u32,
+}
+
+impl Grbl {
+    /// Polls every `poll_ms`, and counts as disconnected after `timeout_ms`
+    /// without a report.
+    pub fn new(poll_ms: u32, timeout_ms: u32) -> Grbl {
+        Grbl {
+            line: [0; LINE_LEN],
+            len: 0,
+            overflow: false,
+            wco: None,
+            feed: 0.0,
+            spindle: 0.0,
+            overrides: Overrides::new(),
+            accessories: Accessories::new(),
+            alarm: None,
+            last_poll_ms: 0,
+            last_report_ms: None,
+            poll_ms,
+            timeout_ms,
+        }
+    }
+
+    /// Returns true when it is time to send another `?`
+    pub fn poll_due(&mut self, now_ms: u32) -> bool {
+        if now_ms.wrapping_sub(self.last_poll_ms) < self.poll_ms {
+            return false;
+        }
+        self.last_poll_ms = now_ms;
+        true
+    }
+
+    pub fn connected(&self, now_ms: u32) -> bool {
+        match self.last_report_ms {
+            Some(t) => now_ms.wrapping_sub(t) < self.timeout_ms,
+            None => false,
+        }
+    }
+
+    /// Feed in a received byte. Returns the status, reply or message when
+    /// it completes one.
+    pub fn receive(&mut self, byte: u8, now_ms: u32) -> Option<Received> {
+        match byte {
+            b'\r' => None,
+            b'\n' => {
+                let mut line = [0; LINE_LEN];
+                line[..self.len].copy_from_slice(&self.line[..self.len]);
+                let text = if self.overflow {
+                    ""
+                } else {
+                    core::str::from_utf8(&line[..self.len]).unwrap_or("")
+                };
+                let received = if let Some(reply) = parse_reply(text) {
+                    Some(Received::Reply(reply))
+                } else if !text.starts_with('<') {
+                    if let Some(alarm) = Alarm::parse(text) {
+                        self.alarm = Some(alarm);
+                    }
+                    message(text).map(Received::Message)
+                } else {
+                    let status = parse_status(text).and_then(|report| self.update(report));
+                    if status.is_some() {
+                        self.last_report_ms = Some(now_ms);
+                    }
+                    status.map(Received::Status)
+                };
+                self.len = 0;
+                self.overflow = false;
+                received
+            }
+            _ => {
+                if self.len < LINE_LEN {
+                    self.line[self.len] = byte;
+                    self.len += 1;
+                } else {
+                    self.overflow = true;
+                }
+                None
+            }
+        }
+    }
+
+    fn update(&mut self, report: Report) -> Option<Status> {
+        let new_offset = report.wco.is_some() && report.wco != self.wco;
+        if new_offset {
+            self.wco = report.wco;
+        }
+        if let Some(feed) = report.feed {
+            self.feed = feed;
+        }
+        if let Some(spindle) = report.spindle {
+            self.spindle = spindle;
+        }
+        if let Some(overrides) = report.overrides {
+            self.overrides = overrides;
+        }
+        if let Some(accessories) = report.accessories {
+            self.accessories = accessories;
+        }
+        // GRBL also starts up alarmed, without an ALARM line, if it must
+        // be homed first
+        if report.state != MachineState::Alarm {
+            self.alarm = None;
+        } else if self.alarm.is_none() {
+            self.alarm = Some(Alarm::Locked);
+        }
+
+        let wco = self.wco.unwrap_or([0.0; 3]);
+        let (mpos, wpos) = match (report.mpos, report.wpos) {
+            (Some(m), Some(w)) => (m, w),
+            (Some(m), None) => (m, [m[0] - wco[0], m[1] - wco[1], m[2] - wco[2]]),
+            (None, Some(w)) => ([w[0] + wco[0], w[1] + wco[1], w[2] + wco[2]], w),
+            (None, None) => return None,
+        };
+
+        Some(Status {
+            running: report.state.running(),
+            mpos,
+            wpos,
+            feed: self.feed,
+            spindle: self.spindle,
+            overrides: self.overrides,
+            accessories: self.accessories,
+            alarm: self.alarm,
+            new_offset,
+        })
+    }
+}
+
+/// GRBL (and grblHAL, which speaks the same protocol) on a serial port
+pub struct GrblBackend<P: Port> {
+    port: P,
+    grbl: Grbl,
+    replies: Replies,
+    messages: Messages,
+}
+
+impl<P: Port> GrblBackend<P> {
+    pub fn new(port: P, poll_ms: u32, timeout_ms: u32) -> GrblBackend<P> {
+        GrblBackend {
+            port,
+            grbl: Grbl::new(poll_ms, timeout_ms),
+            replies: Replies::new(),
+            messages: Messages::new(),
+        }
+    }
+
+    fn send_line(&mut self, line: &str) {
+        self.port.write(line.as_bytes());
+        self.port.write(b"\n");
+        self.replies.sent(false);
+    }
+}
+
+impl<P: Port> MachineBackend for GrblBackend<P> {
+    fn poll(&mut self, now_ms: u32) -> Option<Status> {
+        if self.grbl.poll_due(now_ms) {
+            self.port.write(&[STATUS_QUERY]);
+        }
+        let mut status = None;
+        while let Some(byte) = self.port.read() {
+            match self.grbl.receive(byte, now_ms) {
+                Some(Received::Status(s)) => status = Some(s),
+                Some(Received::Reply(reply)) => self.replies.answered(reply),
+                Some(Received::Message(message)) => self.messages.push(message),
+                None => (),
+            }
+        }
+        status
+    }
+
+    fn connected(&self, now_ms: u32) -> bool {
+        self.grbl.connected(now_ms)
+    }
+
+    fn jog(&mut self, command: JogCommand) {
+        match command {
+            JogCommand::Move {
+                axis,
+                distance,
+                feed,
+            } => {
+                let mut line: TextBuffer<48> = TextBuffer::new();
+                if write_jog(axis, distance, feed, &mut line).is_ok() {
+                    self.port.write(line.as_str().as_bytes());
+                    self.replies.sent(false);
+                }
+            }
+            JogCommand::Cancel => self.port.write(&[JOG_CANCEL]),
+        }
+    }
+
+    fn set_work(&mut self, axis: usize, value: f32) {
+        // L20 sets the offset of the current coordinate system (P0) so the
+        // axis reads value here
+        let mut line: TextBuffer<32> = TextBuffer::new();
+        if write!(line, "G10L20P0{}{:.3}", AXIS_NAMES[axis], value).is_ok() {
+            self.send_line(line.as_str());
+        }
+    }
+
+    fn feed_hold(&mut self) {
+        self.port.write(&[FEED_HOLD]);
+    }
+
+    fn resume(&mut self) {
+        self.port.write(&[CYCLE_START]);
+    }
+
+    fn reset(&mut self) {
+        self.port.write(&[SOFT_RESET]);
+        // GRBL empties its buffers without answering what was in them
+        self.replies.clear();
+    }
+
+    fn set_override(&mut self, value: Override) {
+        let mut bytes = [0; OVERRIDE_BYTES];
+        let len = write_override(value, &mut bytes);
+        self.port.write(&bytes[..len]);
+        // Shown until the next report with overrides in says otherwise
+        value.apply(&mut self.grbl.overrides);
+    }
+
+    fn home(&mut self) {
+        self.send_line("$H");
+    }
+
+    fn unlock(&mut self) {
+        self.send_line("$X");
+    }
+
+    fn mdi(&mut self, line: &str) {
+        self.port.write(line.as_bytes());
+        self.port.write(b"\n");
+        self.replies.sent(true);
+    }
+
+    fn stream(&mut self, line: &str) {
+        self.port.write(line.as_bytes());
+        self.port.write(b"\n");
+        self.replies.sent(true);
+    }
+
+    fn take_reply(&mut self) -> Option<Reply> {
+        self.replies.take()
+    }
+
+    fn take_message(&mut self) -> Option<Message> {
+        self.messages.pop()
+    }
+
+    fn rx_buffer(&self) -> usize {
+        RX_BUFFER_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bytes both ways, as the UART would carry them
+    struct Wire {
+        rx: Vec<u8>,
+        tx: Vec<u8>,
+    }
+
+    impl Port for Wire {
+        fn read(&mut self) -> Option<u8> {
+            if self.rx.is_empty() {
+                None
+            } else {
+                Some(self.rx.remove(0))
+            }
+        }
+
+        fn write(&mut self, data: &[u8]) {
+            self.tx.extend_from_slice(data);
+        }
+    }
+
+    fn backend() -> GrblBackend<Wire> {
+        let wire = Wire {
+            rx: Vec::new(),
+            tx: Vec::new(),
+        };
+        GrblBackend::new(wire, 200, 1000)
+    }
+
+    // Feeds a transcript in, returning whatever came out of it
+    fn receive(grbl: &mut Grbl, transcript: &str, now_ms: u32) -> Vec<Received> {
+        transcript
+            .bytes()
+            .filter_map(|b| grbl.receive(b, now_ms))
+            .collect()
+    }
+
+    fn status(received: &[Received]) -> Status {
+        match received {
+            [Received::Status(status)] => *status,
+            other => panic!("not one status: {:?}", other),
+        }
+    }
+
+    fn text(received: &[Received]) -> Vec<String> {
+        received
+            .iter()
+            .map(|r| match r {
+                Received::Message(m) => m.as_str().to_string(),
+                other => panic!("not a message: {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn status_fields() {
+        let report =
+            parse_status("<Hold:0|MPos:1.000,-2.500,3.125|FS:500,12000|WCO:0,0,-20>\r").unwrap();
+        assert_eq!(report.state, MachineState::Hold);
+        assert_eq!(report.mpos, Some([1.0, -2.5, 3.125]));
+        assert_eq!(report.wpos, None);
+        assert_eq!(report.wco, Some([0.0, 0.0, -20.0]));
+        assert_eq!(report.feed, Some(500.0));
+        assert_eq!(report.spindle, Some(12000.0));
+        assert_eq!(report.overrides, None);
+        assert_eq!(report.accessories, None);
+
+        // Without a spindle, F alone; extra axes and unknown fields ignored
+        let report = parse_status("<Jog|WPos:1,2,3,4|Bf:15,128|F:250|Pn:XZ>").unwrap();
+        assert_eq!(report.wpos, Some([1.0, 2.0, 3.0]));
+        assert_eq!(report.feed, Some(250.0));
+        assert_eq!(report.spindle, None);
+
+        let report = parse_status("<Run|MPos:0,0,0|Ov:120,50,80|A:SM>").unwrap();
+        let overrides = Overrides {
+            feed: 120,
+            rapid: 50,
+            spindle: 80,
+        };
+        assert_eq!(report.overrides, Some(overrides));
+        let accessories = Accessories {
+            spindle: true,
+            coolant: true,
+        };
+        assert_eq!(report.accessories, Some(accessories));
+        // A missing A field with the overrides means all off
+        let report = parse_status("<Run|MPos:0,0,0|Ov:100,100,100>").unwrap();
+        assert_eq!(report.accessories, Some(Accessories::new()));
+    }
+
+    #[test]
+    fn status_states() {
+        let states = [
+            ("Idle", MachineState::Idle, ui::Running::No),
+            ("Run", MachineState::Run, ui::Running::Yes),
+            ("Hold:1", MachineState::Hold, ui::Running::Hold),
+            ("Jog", MachineState::Jog, ui::Running::Jog),
+            ("Alarm", MachineState::Alarm, ui::Running::Alarm),
+            ("Door:2", MachineState::Door, ui::Running::Hold),
+            ("Check", MachineState::Check, ui::Running::No),
+            ("Home", MachineState::Home, ui::Running::Home),
+            ("Sleep", MachineState::Sleep, ui::Running::No),
+        ];
+        for (field, state, running) in states {
+            assert_eq!(MachineState::parse(field), Some(state));
+            assert_eq!(state.running(), running);
+        }
+        assert_eq!(MachineState::parse("Busy"), None);
+    }
+
+    #[test]
+    fn not_status() {
+        assert_eq!(parse_status("ok"), None);
+        assert_eq!(parse_status("<Idle|MPos:0,0,0"), None);
+        assert_eq!(parse_status("<Dancing|MPos:0,0,0>"), None);
+        // A position that doesn't parse is left out, not made up
+        let report = parse_status("<Idle|MPos:1,x,3>").unwrap();
+        assert_eq!(report.mpos, None);
+    }
+
+    #[test]
+    fn replies() {
+        assert_eq!(parse_reply("ok\r"), Some(Reply::Ok));
+        assert_eq!(parse_reply("error:20"), Some(Reply::Error(20)));
+        // GRBL 0.9 words
+        assert_eq!(
+            parse_reply("error:Bad number format"),
+            Some(Reply::Error(0))
+        );
+        assert_eq!(parse_reply("okay"), None);
+        assert_eq!(parse_reply("ALARM:1"), None);
+    }
+
+    #[test]
+    fn transcript_positions() {
+        let mut grbl = Grbl::new(200, 1000);
+        // WCO comes now and then, and is remembered for the reports between
+        let s = status(&receive(
+            &mut grbl,
+            "<Idle|MPos:10,0,-5|FS:0,0|WCO:1,2,-20>\r\n",
+            0,
+        ));
+        assert_eq!(s.mpos, [10.0, 0.0, -5.0]);
+        assert_eq!(s.wpos, [9.0, -2.0, 15.0]);
+        assert!(s.new_offset);
+        let s = status(&receive(&mut grbl, "<Run|MPos:11,0,-5|FS:300,0>\r\n", 10));
+        assert_eq!(s.wpos, [10.0, -2.0, 15.0]);
+        assert_eq!(s.feed, 300.0);
+        assert_eq!(s.running, ui::Running::Yes);
+        assert!(!s.new_offset);
+        // The same offset again is no news
+        let s = status(&receive(
+            &mut grbl,
+            "<Run|MPos:11,0,-5|WCO:1,2,-20>\r\n",
+            20,
+        ));
+        assert!(!s.new_offset);
+        // With $10 set for work positions
+        let s = status(&receive(&mut grbl, "<Idle|WPos:0,0,0>\r\n", 30));
+        assert_eq!(s.mpos, [1.0, 2.0, -20.0]);
+        assert_eq!(s.feed, 300.0); // Until a report says otherwise
+
+        // A report without either says nothing
+        assert_eq!(receive(&mut grbl, "<Idle|FS:0,0>\r\n", 40), []);
+    }
+
+    #[test]
+    fn transcript_overrides() {
+        let mut grbl = Grbl::new(200, 1000);
+        let s = status(&receive(
+            &mut grbl,
+            "<Run|MPos:0,0,0|Ov:150,25,100|A:SF>\n",
+            0,
+        ));
+        assert_eq!(s.overrides.feed, 150);
+        assert_eq!(s.overrides.rapid, 25);
+        assert!(s.accessories.spindle && s.accessories.coolant);
+        // Remembered between the reports that carry them
+        let s = status(&receive(&mut grbl, "<Run|MPos:0,0,0>\n", 10));
+        assert_eq!(s.overrides.feed, 150);
+        assert!(s.accessories.spindle);
+        let s = status(&receive(&mut grbl, "<Run|MPos:0,0,0|Ov:100,100,100>\n", 20));
+        assert_eq!(s.overrides, Overrides::new());
+        assert_eq!(s.accessories, Accessories::new());
+    }
+
+    #[test]
+    fn transcript_alarms_and_messages() {
+        let mut grbl = Grbl::new(200, 1000);
+        let received = receive(
+            &mut grbl,
+            "\r\nGrbl 1.1h ['$' for help]\r\n[MSG:'$H'|'$X' to unlock]\r\n",
+            0,
+        );
+        assert_eq!(
+            text(&received),
+            ["Grbl 1.1h ['$' for help]", "[MSG:'$H'|'$X' to unlock]"]
+        );
+        // Alarmed at power up, without saying why
+        let s = status(&receive(&mut grbl, "<Alarm|MPos:0,0,0>\r\n", 10));
+        assert_eq!(s.alarm, Some(Alarm::Locked));
+        assert_eq!(s.running, ui::Running::Alarm);
+
+        let received = receive(&mut grbl, "ALARM:1\r\n[MSG:Reset to continue]\r\n", 20);
+        assert_eq!(text(&received), ["ALARM:1", "[MSG:Reset to continue]"]);
+        let s = status(&receive(&mut grbl, "<Alarm|MPos:0,0,0>\r\n", 30));
+        assert_eq!(s.alarm, Some(Alarm::HardLimit));
+        // Cleared once it's out of alarm
+        let s = status(&receive(&mut grbl, "<Idle|MPos:0,0,0>\r\n", 40));
+        assert_eq!(s.alarm, None);
+        let s = status(&receive(&mut grbl, "<Alarm|MPos:0,0,0>\r\n", 50));
+        assert_eq!(s.alarm, Some(Alarm::Locked));
+    }
+
+    #[test]
+    fn transcript_replies_and_junk() {
+        let mut grbl = Grbl::new(200, 1000);
+        assert_eq!(
+            receive(&mut grbl, "ok\r\nerror:9\r\n", 0),
+            [Received::Reply(Reply::Ok), Received::Reply(Reply::Error(9))]
+        );
+        // An overlong line is dropped whole, and the next one still parses
+        let long = format!("<Idle|MPos:{}>\r\nok\r\n", "0,".repeat(LINE_LEN));
+        assert_eq!(receive(&mut grbl, &long, 0), [Received::Reply(Reply::Ok)]);
+        assert_eq!(grbl.receive(0xff, 0), None);
+        assert_eq!(receive(&mut grbl, "\n\n", 0), []);
+    }
+
+    #[test]
+    fn polling_and_timeout() {
+        let mut grbl = Grbl::new(200, 1000);
+        assert!(!grbl.connected(0));
+        assert!(grbl.poll_due(200));
+        assert!(!grbl.poll_due(399));
+        assert!(grbl.poll_due(400));
+        // Only status reports count as hearing from it
+        receive(&mut grbl, "ok\r\n", 500);
+        assert!(!grbl.connected(500));
+        receive(&mut grbl, "<Idle|MPos:0,0,0>\r\n", 500);
+        assert!(grbl.connected(1499));
+        assert!(!grbl.connected(1500));
+        // Across the millisecond counter wrapping
+        receive(&mut grbl, "<Idle|MPos:0,0,0>\r\n", u32::MAX - 10);
+        assert!(grbl.connected(100));
+        assert!(grbl.poll_due(u32::MAX));
+        assert!(!grbl.poll_due(100));
+    }
+
+    #[test]
+    fn override_bytes() {
+        let bytes = |value| {
+            let mut out = [0; OVERRIDE_BYTES];
+            let len = write_override(value, &mut out);
+            out[..len].to_vec()
+        };
+        assert_eq!(bytes(Override::Feed(100)), [0x90]);
+        assert_eq!(
+            bytes(Override::Feed(123)),
+            [0x90, 0x91, 0x91, 0x93, 0x93, 0x93]
+        );
+        assert_eq!(bytes(Override::Spindle(88)), [0x99, 0x9b, 0x9d, 0x9d]);
+        assert_eq!(bytes(Override::Feed(10)).len(), 10);
+        assert_eq!(bytes(Override::Feed(200)).len(), 11);
+        assert_eq!(bytes(Override::Rapid(100)), [0x95]);
+        assert_eq!(bytes(Override::Rapid(50)), [0x96]);
+        assert_eq!(bytes(Override::Rapid(25)), [0x97]);
+    }
+
+    #[test]
+    fn backend_commands() {
+        let mut grbl = backend();
+        grbl.jog(JogCommand::Move {
+            axis: 2,
+            distance: -0.1,
+            feed: 600.0,
+        });
+        grbl.jog(JogCommand::Cancel);
+        grbl.set_work(0, 12.5);
+        grbl.home();
+        grbl.unlock();
+        grbl.feed_hold();
+        grbl.resume();
+        let mut expected = b"$J=G91G21Z-0.100F600\n\x85G10L20P0X12.500\n$H\n$X\n!~".to_vec();
+        assert_eq!(grbl.port.tx, expected);
+
+        grbl.set_override(Override::Feed(110));
+        expected.extend_from_slice(&[0x90, 0x91]);
+        assert_eq!(grbl.port.tx, expected);
+        // Shown straight away, before GRBL reports it
+        grbl.port.rx = b"<Run|MPos:0,0,0>\r\n".to_vec();
+        assert_eq!(grbl.poll(0).unwrap().overrides.feed, 110);
+    }
+
+    #[test]
+    fn backend_streaming() {
+        let mut grbl = backend();
+        // Only streamed lines' answers are handed back, in order
+        grbl.home();
+        grbl.stream("G1 X1");
+        grbl.mdi("G0 Z5");
+        grbl.port.rx = b"ok\r\nerror:20\r\n[MSG:Caution]\r\nok\r\n".to_vec();
+        assert_eq!(grbl.poll(0), None);
+        assert_eq!(grbl.take_reply(), Some(Reply::Error(20)));
+        assert_eq!(grbl.take_reply(), Some(Reply::Ok));
+        assert_eq!(grbl.take_reply(), None);
+        assert_eq!(grbl.take_message().unwrap().as_str(), "[MSG:Caution]");
+        assert!(grbl.take_message().is_none());
+
+        // A reset throws away what was waiting for an answer
+        grbl.stream("G1 X2");
+        grbl.reset();
+        grbl.port.rx = b"ok\r\n".to_vec();
+        grbl.poll(0);
+        assert_eq!(grbl.take_reply(), None);
+        assert_eq!(grbl.rx_buffer(), 128);
+    }
+
+    #[test]
+    fn backend_polls() {
+        let mut grbl = backend();
+        grbl.poll(200);
+        grbl.poll(300);
+        grbl.poll(400);
+        assert_eq!(grbl.port.tx, b"??");
+        // Split across polls, as the UART delivers it
+        grbl.port.rx = b"<Idle|MPos:1,2".to_vec();
+        assert_eq!(grbl.poll(410), None);
+        assert!(!grbl.connected(410));
+        grbl.port.rx = b",3>\r\n".to_vec();
+        assert_eq!(grbl.poll(420).unwrap().mpos, [1.0, 2.0, 3.0]);
+        assert!(grbl.connected(420));
+    }
+}
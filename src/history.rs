@@ -0,0 +1,218 @@
+//! Bounded undo/redo history of datum changes.
+//!
+//! Each change keeps the X/Y/Z offsets from before and after it, so undo and
+//! redo just put a set of offsets back. Nothing here touches the display.
+
+use core::fmt::{self, Write};
+
+pub const HISTORY_LEN: usize = 32;
+
+const AXIS_NAMES: [char; 3] = ['X', 'Y', 'Z'];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(test, allow(dead_code))]
+pub enum ChangeKind {
+    Zero(usize),
+    Preset(usize, f32),
+    Half(usize),
+    PlusMinus(usize),
+    Recall(usize), // Point memory slot recalled as a preset
+    Probe(usize),  // Axis zeroed by the touch probe
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub before: [f32; 3],
+    pub after: [f32; 3],
+    pub was: f32, // What the axis read before the change, for the history list
+}
+
+impl Change {
+    /// One line description for the history list
+    pub fn describe<W: Write>(&self, out: &mut W) -> fmt::Result {
+        match self.kind {
+            ChangeKind::Zero(axis) => write!(out, "{} zero, was {:.3}", AXIS_NAMES[axis], self.was),
+            ChangeKind::Preset(axis, value) => write!(
+                out,
+                "{} set {:.3}, was {:.3}",
+                AXIS_NAMES[axis], value, self.was
+            ),
+            ChangeKind::Half(axis) => write!(out, "{} half, was {:.3}", AXIS_NAMES[axis], self.was),
+            ChangeKind::PlusMinus(axis) => {
+                write!(out, "{} sign, was {:.3}", AXIS_NAMES[axis], self.was)
+            }
+            ChangeKind::Recall(slot) => write!(out, "Point {:02} recalled", slot + 1),
+            ChangeKind::Probe(axis) => {
+                write!(out, "{} probed, was {:.3}", AXIS_NAMES[axis], self.was)
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct History {
+    entries: [Option<Change>; HISTORY_LEN],
+    len: usize,
+    applied: usize, // Entries before this are in effect, the rest can be redone
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            entries: [None; HISTORY_LEN],
+            len: 0,
+            applied: 0,
+        }
+    }
+
+    /// Adds a change, dropping anything that could have been redone and,
+    /// when full, the oldest change.
+    pub fn push(&mut self, change: Change) {
+        self.len = self.applied;
+        if self.len == HISTORY_LEN {
+            self.entries.copy_within(1.., 0);
+            self.len -= 1;
+        }
+        self.entries[self.len] = Some(change);
+        self.len += 1;
+        self.applied = self.len;
+    }
+
+    /// Returns the change to take back; put its `before` offsets back
+    pub fn undo(&mut self) -> Option<Change> {
+        if self.applied == 0 {
+            return None;
+        }
+        self.applied -= 1;
+        self.entries[self.applied]
+    }
+
+    /// Returns the change to make again; put its `after` offsets back
+    pub fn redo(&mut self) -> Option<Change> {
+        if self.applied == self.len {
+            return None;
+        }
+        self.applied += 1;
+        self.entries[self.applied - 1]
+    }
+
+    pub fn count(&self) -> usize {
+        self.len
+    }
+
+    pub fn applied(&self) -> usize {
+        self.applied
+    }
+
+    /// Change number `i`, oldest first, and whether it is in effect
+    pub fn get(&self, i: usize) -> Option<(Change, bool)> {
+        if i < self.len {
+            self.entries[i].map(|change| (change, i < self.applied))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Zeroing X when it read `was`
+    fn zero(was: f32) -> Change {
+        Change {
+            kind: ChangeKind::Zero(0),
+            before: [was, 0.0, 0.0],
+            after: [0.0; 3],
+            was,
+        }
+    }
+
+    fn was(change: Option<Change>) -> Option<f32> {
+        change.map(|change| change.was)
+    }
+
+    #[test]
+    fn undo_and_redo() {
+        let mut history = History::new();
+        assert_eq!(history.undo(), None);
+        assert_eq!(history.redo(), None);
+        for i in 1..=3 {
+            history.push(zero(i as f32));
+        }
+        assert_eq!(was(history.undo()), Some(3.0));
+        assert_eq!(was(history.undo()), Some(2.0));
+        assert_eq!((history.count(), history.applied()), (3, 1));
+        assert_eq!(history.get(0).map(|(_, applied)| applied), Some(true));
+        assert_eq!(history.get(1).map(|(_, applied)| applied), Some(false));
+        assert!(history.get(3).is_none());
+
+        assert_eq!(was(history.redo()), Some(2.0));
+        assert_eq!(was(history.redo()), Some(3.0));
+        assert_eq!(history.redo(), None);
+        for i in (1..=3).rev() {
+            assert_eq!(was(history.undo()), Some(i as f32));
+        }
+        assert_eq!(history.undo(), None);
+        assert_eq!(history.applied(), 0);
+    }
+
+    #[test]
+    fn new_change_clears_redo() {
+        let mut history = History::new();
+        for i in 1..=3 {
+            history.push(zero(i as f32));
+        }
+        history.undo();
+        history.undo();
+        history.push(zero(10.0));
+        assert_eq!((history.count(), history.applied()), (2, 2));
+        assert_eq!(history.redo(), None);
+        assert_eq!(was(history.undo()), Some(10.0));
+        assert_eq!(was(history.undo()), Some(1.0));
+    }
+
+    #[test]
+    fn depth() {
+        let mut history = History::new();
+        for i in 0..HISTORY_LEN + 5 {
+            history.push(zero(i as f32));
+        }
+        // The oldest are dropped
+        assert_eq!(history.count(), HISTORY_LEN);
+        assert_eq!(was(history.get(0).map(|(change, _)| change)), Some(5.0));
+        let mut undone = 0;
+        while history.undo().is_some() {
+            undone += 1;
+        }
+        assert_eq!(undone, HISTORY_LEN);
+        assert_eq!(was(history.redo()), Some(5.0));
+
+        // Full, but mostly undone: nothing more is dropped
+        let mut history = History::new();
+        for i in 0..HISTORY_LEN {
+            history.push(zero(i as f32));
+        }
+        history.undo();
+        history.push(zero(100.0));
+        assert_eq!(history.count(), HISTORY_LEN);
+        assert_eq!(was(history.get(0).map(|(change, _)| change)), Some(0.0));
+    }
+
+    #[test]
+    fn descriptions() {
+        let mut change = zero(1.5);
+        let mut text = String::new();
+        change.describe(&mut text).unwrap();
+        assert_eq!(text, "X zero, was 1.500");
+        change.kind = ChangeKind::Preset(2, -3.0);
+        text.clear();
+        change.describe(&mut text).unwrap();
+        assert_eq!(text, "Z set -3.000, was 1.500");
+        change.kind = ChangeKind::Recall(4);
+        text.clear();
+        change.describe(&mut text).unwrap();
+        assert_eq!(text, "Point 05 recalled");
+    }
+}
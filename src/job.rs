@@ -0,0 +1,655 @@
+//! Running a G-code program from the SD card.
+//!
+//! GRBL answers every line with `ok` or `error:N`, and holds up to 128
+//! bytes of lines it hasn't answered yet. Counting the bytes of the lines
+//! still waiting for an answer lets the next one go as soon as it fits,
+//! which keeps the planner full without overrunning the controller's serial
+//! buffer: "character counting" in the GRBL docs. A controller with no
+//! known buffer gets one line at a time instead.
+//!
+//! An error holds the machine and pauses the job on the line that caused
+//! it, until it is resumed or stopped. Nothing here touches hardware: lines
+//! come from a `LineSource` and go to a `MachineBackend`.
+
+use core::fmt::Write;
+
+use crate::backend::{MachineBackend, Reply};
+use crate::files::NAME_LEN;
+use crate::text::TextBuffer;
+use crate::wizard::Program;
+
+pub const LINE_LEN: usize = 96;
+const IN_FLIGHT: usize = 32; // Most lines ever waiting for an answer
+
+/// What the job, file, preview and wizard pages want the main loop to do
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub enum Request {
+    ListFiles,
+    Start(TextBuffer<NAME_LEN>),
+    // Read a file through for the toolpath preview
+    Plot(TextBuffer<NAME_LEN>),
+    // The same for generated programs, which can also be saved to the card
+    RunProgram(Program),
+    PlotProgram(Program),
+    SaveProgram(Program),
+    // Read the macro buttons from the card
+    LoadMacros,
+    Hold,
+    Resume,
+    Stop,
+}
+
+/// How reading a line went
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Read {
+    // A line, and how many bytes of the file it took up
+    Line(u32),
+    // Same, but it didn't fit in LINE_LEN
+    TooLong(u32),
+    End,
+    Failed,
+}
+
+pub trait LineSource {
+    /// Copies the next line of the program, without its line ending, into
+    /// `line`
+    fn next_line(&mut self, line: &mut TextBuffer<LINE_LEN>) -> Read;
+}
+
+/// Copies a line without comments or spaces, as GRBL would strip them
+/// anyway and they'd only take up room in its buffer. Returns false if
+/// what was left didn't fit in `out`.
+pub fn clean_line<const N: usize>(raw: &str, out: &mut TextBuffer<N>) -> bool {
+    out.clear();
+    let mut in_comment = false;
+    for c in raw.chars() {
+        match c {
+            '(' => in_comment = true,
+            ')' => in_comment = false,
+            ';' if !in_comment => break,
+            _ if in_comment || c.is_whitespace() || c == '%' => (),
+            _ => {
+                if out.write_char(c).is_err() {
+                    return false;
+                }
+            }
+        }
+    }
+    true
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum JobState {
+    Running,
+    Held,
+    // Stopped sending after an error, see `Progress::error`
+    Paused,
+    Finished,
+    Stopped,
+}
+
+impl JobState {
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn describe<W: Write>(self, out: &mut W) -> core::fmt::Result {
+        match self {
+            JobState::Running => write!(out, "Running"),
+            JobState::Held => write!(out, "Held"),
+            JobState::Paused => write!(out, "Paused on error"),
+            JobState::Finished => write!(out, "Finished"),
+            JobState::Stopped => write!(out, "Stopped"),
+        }
+    }
+
+    /// Still sending, or could be again
+    pub fn active(self) -> bool {
+        !matches!(self, JobState::Finished | JobState::Stopped)
+    }
+}
+
+/// A line that went wrong. `code` is the controller's error number, or None
+/// when the line couldn't even be read.
+#[derive(Copy, Clone, Debug)]
+pub struct JobError {
+    pub line: u32,
+    pub code: Option<u8>,
+    pub text: TextBuffer<LINE_LEN>,
+}
+
+impl JobError {
+    pub fn describe<W: Write>(&self, out: &mut W) -> core::fmt::Result {
+        match self.code {
+            Some(code) => write!(out, "Line {}: error {}", self.line, code),
+            None if self.text.as_str().is_empty() => {
+                write!(out, "Line {}: can't read the card", self.line)
+            }
+            None => write!(out, "Line {}: too long", self.line),
+        }
+    }
+}
+
+/// Everything the job screen shows
+#[derive(Copy, Clone, Debug)]
+pub struct Progress {
+    #[cfg_attr(test, allow(dead_code))]
+    pub state: JobState,
+    pub line: u32, // Last line the controller answered
+    pub percent: f32,
+    pub elapsed_ms: u32,
+    pub remaining_ms: Option<u32>,
+    pub error: Option<JobError>,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Sent {
+    line: u32,
+    bytes: usize,
+    offset: u32, // File position just after this line
+    text: TextBuffer<LINE_LEN>,
+}
+
+pub struct Job {
+    state: JobState,
+    size: u32,
+    offset: u32, // Bytes read from the file
+    line: u32,   // Lines read from the file
+    end_of_file: bool,
+    pending: Option<Sent>, // Read, but not sent yet
+    sent: [Option<Sent>; IN_FLIGHT],
+    head: usize,
+    count: usize,
+    bytes: usize, // Of the lines in `sent`
+    answered_line: u32,
+    answered_offset: u32,
+    error: Option<JobError>,
+    start_ms: u32,
+    held_ms: u32, // Total time spent held or paused
+    held_since: Option<u32>,
+    end_ms: Option<u32>,
+}
+
+impl Job {
+    /// Starts a job on a file of `size` bytes
+    pub fn new(size: u32, now_ms: u32) -> Job {
+        Job {
+            state: JobState::Running,
+            size,
+            offset: 0,
+            line: 0,
+            end_of_file: false,
+            pending: None,
+            sent: [None; IN_FLIGHT],
+            head: 0,
+            count: 0,
+            bytes: 0,
+            answered_line: 0,
+            answered_offset: 0,
+            error: None,
+            start_ms: now_ms,
+            held_ms: 0,
+            held_since: None,
+            end_ms: None,
+        }
+    }
+
+    /// Starts a job in `slot`, like `new`. Out of line, as a job is a few
+    /// KB and an unoptimised build makes copies of it in the caller's frame.
+    #[inline(never)]
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn start(slot: &mut Option<Job>, size: u32, now_ms: u32) {
+        *slot = Some(Job::new(size, now_ms));
+    }
+
+    pub fn state(&self) -> JobState {
+        self.state
+    }
+
+    /// Collects answers and sends as many lines as the controller has
+    /// room for. Call every time round the main loop.
+    pub fn service<S: LineSource, M: MachineBackend>(
+        &mut self,
+        source: &mut S,
+        machine: &mut M,
+        now_ms: u32,
+    ) {
+        while let Some(reply) = machine.take_reply() {
+            self.answered(reply, machine, now_ms);
+        }
+
+        let room = machine.rx_buffer();
+        while self.state == JobState::Running {
+            if self.pending.is_none() && !self.end_of_file {
+                self.read(source, machine, now_ms);
+                continue;
+            }
+            let line = match self.pending {
+                Some(line) => line,
+                None => break, // Everything is sent
+            };
+            let fits =
+                self.count == 0 || (self.count < IN_FLIGHT && self.bytes + line.bytes <= room);
+            if !fits {
+                break;
+            }
+            machine.stream(line.text.as_str());
+            self.sent[(self.head + self.count) % IN_FLIGHT] = Some(line);
+            self.count += 1;
+            self.bytes += line.bytes;
+            self.pending = None;
+        }
+
+        // The last few moves can still be running in the controller's planner
+        if self.end_of_file
+            && self.pending.is_none()
+            && self.count == 0
+            && self.state == JobState::Running
+        {
+            self.state = JobState::Finished;
+            self.end(now_ms);
+        }
+    }
+
+    // Reads the next line with anything in it into `pending`
+    fn read<S: LineSource, M: MachineBackend>(
+        &mut self,
+        source: &mut S,
+        machine: &mut M,
+        now_ms: u32,
+    ) {
+        let mut raw: TextBuffer<LINE_LEN> = TextBuffer::new();
+        match source.next_line(&mut raw) {
+            Read::Line(bytes) => {
+                self.line += 1;
+                self.offset += bytes;
+                let mut text = TextBuffer::new();
+                clean_line(raw.as_str(), &mut text);
+                if !text.as_str().is_empty() {
+                    self.pending = Some(Sent {
+                        line: self.line,
+                        bytes: text.as_str().len() + 1, // And the newline
+                        offset: self.offset,
+                        text,
+                    });
+                }
+            }
+            Read::TooLong(bytes) => {
+                self.line += 1;
+                self.offset += bytes;
+                self.fail(self.line, None, raw, machine, now_ms);
+            }
+            Read::End => self.end_of_file = true,
+            Read::Failed => self.fail(self.line + 1, None, TextBuffer::new(), machine, now_ms),
+        }
+    }
+
+    fn answered<M: MachineBackend>(&mut self, reply: Reply, machine: &mut M, now_ms: u32) {
+        if self.count == 0 {
+            return;
+        }
+        let sent = match self.sent[self.head].take() {
+            Some(sent) => sent,
+            None => return,
+        };
+        self.head = (self.head + 1) % IN_FLIGHT;
+        self.count -= 1;
+        self.bytes -= sent.bytes;
+        self.answered_line = sent.line;
+        self.answered_offset = sent.offset;
+        if let Reply::Error(code) = reply {
+            if self.state.active() && self.error.is_none() {
+                self.fail(sent.line, Some(code), sent.text, machine, now_ms);
+            }
+        }
+    }
+
+    // Stops sending and holds the machine until someone looks at the error
+    fn fail<M: MachineBackend>(
+        &mut self,
+        line: u32,
+        code: Option<u8>,
+        text: TextBuffer<LINE_LEN>,
+        machine: &mut M,
+        now_ms: u32,
+    ) {
+        self.error = Some(JobError { line, code, text });
+        if self.state == JobState::Running {
+            self.held_since = Some(now_ms);
+        }
+        self.state = JobState::Paused;
+        machine.feed_hold();
+    }
+
+    pub fn hold<M: MachineBackend>(&mut self, machine: &mut M, now_ms: u32) {
+        if self.state == JobState::Running {
+            machine.feed_hold();
+            self.state = JobState::Held;
+            self.held_since = Some(now_ms);
+        }
+    }
+
+    /// Carries on after a hold, or past an error
+    pub fn resume<M: MachineBackend>(&mut self, machine: &mut M, now_ms: u32) {
+        if self.state == JobState::Held || self.state == JobState::Paused {
+            machine.resume();
+            self.state = JobState::Running;
+            self.error = None;
+            if let Some(since) = self.held_since.take() {
+                self.held_ms += now_ms.wrapping_sub(since);
+            }
+        }
+    }
+
+    /// Resets the controller, throwing away everything it was given
+    pub fn stop<M: MachineBackend>(&mut self, machine: &mut M, now_ms: u32) {
+        if self.state.active() {
+            machine.reset();
+            self.state = JobState::Stopped;
+            self.end(now_ms);
+        }
+    }
+
+    fn end(&mut self, now_ms: u32) {
+        if let Some(since) = self.held_since.take() {
+            self.held_ms += now_ms.wrapping_sub(since);
+        }
+        self.end_ms = Some(now_ms);
+    }
+
+    pub fn progress(&self, now_ms: u32) -> Progress {
+        let now_ms = self.end_ms.unwrap_or(now_ms);
+        let elapsed_ms = now_ms.wrapping_sub(self.start_ms);
+        let held_ms = self.held_ms
+            + self
+                .held_since
+                .map_or(0, |since| now_ms.wrapping_sub(since));
+        let fraction = if self.state == JobState::Finished || self.size == 0 {
+            1.0
+        } else {
+            self.answered_offset as f32 / self.size as f32
+        };
+
+        // Time spent held says nothing about how long the rest will take
+        let remaining_ms = if self.state.active() && fraction > 0.01 {
+            let running_ms = elapsed_ms.saturating_sub(held_ms) as f32;
+            Some((running_ms * (1.0 - fraction) / fraction) as u32)
+        } else {
+            None
+        };
+
+        Progress {
+            state: self.state,
+            line: self.answered_line,
+            percent: fraction * 100.0,
+            elapsed_ms,
+            remaining_ms,
+            error: self.error,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Override;
+    use crate::jog::JogCommand;
+    use std::collections::VecDeque;
+
+    // A file on the card, a line at a time
+    struct File<'a>(core::str::Lines<'a>, bool);
+
+    impl<'a> File<'a> {
+        fn new(text: &'a str) -> File<'a> {
+            File(text.lines(), false)
+        }
+
+        // Can't read past the end of `text`
+        fn broken(text: &'a str) -> File<'a> {
+            File(text.lines(), true)
+        }
+    }
+
+    impl LineSource for File<'_> {
+        fn next_line(&mut self, line: &mut TextBuffer<LINE_LEN>) -> Read {
+            line.clear();
+            match self.0.next() {
+                Some(text) if line.write_str(text).is_ok() => Read::Line(text.len() as u32 + 1),
+                Some(text) => Read::TooLong(text.len() as u32 + 1),
+                None if self.1 => Read::Failed,
+                None => Read::End,
+            }
+        }
+    }
+
+    // A controller that keeps what it was sent, answered by hand
+    struct Machine {
+        rx_buffer: usize,
+        streamed: Vec<String>,
+        calls: Vec<&'static str>,
+        replies: VecDeque<Reply>,
+    }
+
+    impl Machine {
+        fn new(rx_buffer: usize) -> Machine {
+            Machine {
+                rx_buffer,
+                streamed: Vec::new(),
+                calls: Vec::new(),
+                replies: VecDeque::new(),
+            }
+        }
+
+        fn answer(&mut self, replies: &[Reply]) {
+            self.replies.extend(replies);
+        }
+    }
+
+    impl MachineBackend for Machine {
+        fn poll(&mut self, _now_ms: u32) -> Option<crate::backend::Status> {
+            None
+        }
+        fn connected(&self, _now_ms: u32) -> bool {
+            true
+        }
+        fn jog(&mut self, _command: JogCommand) {}
+        fn set_work(&mut self, _axis: usize, _value: f32) {}
+        fn feed_hold(&mut self) {
+            self.calls.push("hold");
+        }
+        fn resume(&mut self) {
+            self.calls.push("resume");
+        }
+        fn reset(&mut self) {
+            self.calls.push("reset");
+        }
+        fn set_override(&mut self, _value: Override) {}
+        fn home(&mut self) {}
+        fn unlock(&mut self) {}
+        fn mdi(&mut self, _line: &str) {}
+        fn stream(&mut self, line: &str) {
+            self.streamed.push(line.to_string());
+        }
+        fn take_reply(&mut self) -> Option<Reply> {
+            self.replies.pop_front()
+        }
+        fn rx_buffer(&self) -> usize {
+            self.rx_buffer
+        }
+    }
+
+    fn describe(error: JobError) -> String {
+        let mut text = String::new();
+        error.describe(&mut text).unwrap();
+        text
+    }
+
+    const PROGRAM: &str = "G1X1.000F100\nG1X2.000\nG1X3.000\nG1X4.000\n";
+
+    #[test]
+    fn clean() {
+        let mut out: TextBuffer<8> = TextBuffer::new();
+        assert!(clean_line("%G0 X1 (move) Y2 ; comment", &mut out));
+        assert_eq!(out.as_str(), "G0X1Y2");
+        assert!(clean_line("(all (comment)", &mut out));
+        assert_eq!(out.as_str(), "");
+        assert!(!clean_line("G1 X1.000 Y2.000", &mut out));
+    }
+
+    #[test]
+    fn character_counting() {
+        // Room for two of the shorter lines, with their newlines
+        let mut machine = Machine::new(20);
+        let mut file = File::new(PROGRAM);
+        let mut job = Job::new(PROGRAM.len() as u32, 0);
+        job.service(&mut file, &mut machine, 0);
+        assert_eq!(machine.streamed, ["G1X1.000F100"]);
+
+        machine.answer(&[Reply::Ok]);
+        job.service(&mut file, &mut machine, 10);
+        assert_eq!(machine.streamed.len(), 3);
+        assert_eq!(job.progress(10).line, 1);
+
+        // Nothing fits until an answer frees some room
+        job.service(&mut file, &mut machine, 20);
+        assert_eq!(machine.streamed.len(), 3);
+        machine.answer(&[Reply::Ok]);
+        job.service(&mut file, &mut machine, 30);
+        assert_eq!(
+            machine.streamed,
+            ["G1X1.000F100", "G1X2.000", "G1X3.000", "G1X4.000"]
+        );
+
+        // Finished once every line is answered
+        assert_eq!(job.state(), JobState::Running);
+        machine.answer(&[Reply::Ok, Reply::Ok]);
+        job.service(&mut file, &mut machine, 40);
+        assert_eq!(job.state(), JobState::Finished);
+        let progress = job.progress(100);
+        assert_eq!((progress.line, progress.percent), (4, 100.0));
+        assert_eq!(progress.elapsed_ms, 40);
+        assert!(progress.remaining_ms.is_none() && progress.error.is_none());
+    }
+
+    #[test]
+    fn one_line_at_a_time() {
+        let mut machine = Machine::new(0);
+        let mut file = File::new(PROGRAM);
+        let mut job = Job::new(PROGRAM.len() as u32, 0);
+        for sent in 1..=4 {
+            job.service(&mut file, &mut machine, 0);
+            job.service(&mut file, &mut machine, 0);
+            assert_eq!(machine.streamed.len(), sent);
+            machine.answer(&[Reply::Ok]);
+        }
+        job.service(&mut file, &mut machine, 0);
+        assert_eq!(job.state(), JobState::Finished);
+    }
+
+    #[test]
+    fn skips_empty_lines() {
+        let text = "(Facing)\n\nG0 X1 ; Over\n%\nG1 Z-1 F50\n";
+        let mut machine = Machine::new(128);
+        let mut file = File::new(text);
+        let mut job = Job::new(text.len() as u32, 0);
+        job.service(&mut file, &mut machine, 0);
+        assert_eq!(machine.streamed, ["G0X1", "G1Z-1F50"]);
+
+        // Line numbers and progress still count the skipped ones
+        machine.answer(&[Reply::Ok]);
+        job.service(&mut file, &mut machine, 1000);
+        let progress = job.progress(1000);
+        assert_eq!(progress.line, 3);
+        let percent = 100.0 * 23.0 / text.len() as f32;
+        assert!((progress.percent - percent).abs() < 1e-3);
+        assert!(progress.remaining_ms.is_some());
+    }
+
+    #[test]
+    fn error_pauses() {
+        let mut machine = Machine::new(128);
+        let mut file = File::new(PROGRAM);
+        let mut job = Job::new(PROGRAM.len() as u32, 0);
+        job.service(&mut file, &mut machine, 0);
+        assert_eq!(machine.streamed.len(), 4);
+
+        // The lines already sent are still answered, but the first error wins
+        machine.answer(&[Reply::Ok, Reply::Error(20), Reply::Error(33)]);
+        job.service(&mut file, &mut machine, 100);
+        assert_eq!(job.state(), JobState::Paused);
+        assert_eq!(machine.calls, ["hold"]);
+        let error = job.progress(100).error.unwrap();
+        assert_eq!((error.line, error.code), (2, Some(20)));
+        assert_eq!(error.text.as_str(), "G1X2.000");
+        assert_eq!(describe(error), "Line 2: error 20");
+
+        // Time spent paused isn't running time
+        job.resume(&mut machine, 600);
+        assert_eq!(machine.calls, ["hold", "resume"]);
+        assert_eq!(job.state(), JobState::Running);
+        assert!(job.progress(600).error.is_none());
+        machine.answer(&[Reply::Ok]);
+        job.service(&mut file, &mut machine, 700);
+        assert_eq!(job.state(), JobState::Finished);
+    }
+
+    #[test]
+    fn bad_lines() {
+        let long = format!("G1X1.000\n{}\nG1X2.000\n", "X".repeat(LINE_LEN + 1));
+        let mut machine = Machine::new(128);
+        let mut file = File::new(&long);
+        let mut job = Job::new(long.len() as u32, 0);
+        job.service(&mut file, &mut machine, 0);
+        assert_eq!(job.state(), JobState::Paused);
+        assert_eq!(machine.streamed, ["G1X1.000"]);
+        let error = job.progress(0).error.unwrap();
+        assert_eq!((error.line, error.code), (2, None));
+        assert_eq!(describe(error), "Line 2: too long");
+
+        // Skipped on resume
+        job.resume(&mut machine, 0);
+        job.service(&mut file, &mut machine, 0);
+        assert_eq!(machine.streamed, ["G1X1.000", "G1X2.000"]);
+
+        let mut file = File::broken("G1X1.000\n");
+        let mut job = Job::new(100, 0);
+        job.service(&mut file, &mut machine, 0);
+        assert_eq!(job.state(), JobState::Paused);
+        assert_eq!(
+            describe(job.progress(0).error.unwrap()),
+            "Line 2: can't read the card"
+        );
+    }
+
+    #[test]
+    fn hold_and_stop() {
+        let mut machine = Machine::new(0);
+        let mut file = File::new(PROGRAM);
+        let mut job = Job::new(PROGRAM.len() as u32, 0);
+        job.service(&mut file, &mut machine, 0);
+        job.hold(&mut machine, 100);
+        assert_eq!(job.state(), JobState::Held);
+
+        // Answers still come in, but nothing more goes out
+        machine.answer(&[Reply::Ok]);
+        job.service(&mut file, &mut machine, 200);
+        assert_eq!(machine.streamed.len(), 1);
+        assert_eq!(job.progress(200).line, 1);
+        job.resume(&mut machine, 300);
+        job.service(&mut file, &mut machine, 300);
+        assert_eq!(machine.streamed.len(), 2);
+
+        job.stop(&mut machine, 400);
+        assert_eq!(machine.calls, ["hold", "resume", "reset"]);
+        assert_eq!(job.state(), JobState::Stopped);
+        assert!(!job.state().active());
+
+        // Done with: nothing more is sent, and hold, resume and stop do nothing
+        machine.answer(&[Reply::Ok]);
+        job.service(&mut file, &mut machine, 500);
+        job.hold(&mut machine, 500);
+        job.resume(&mut machine, 500);
+        job.stop(&mut machine, 500);
+        assert_eq!(machine.streamed.len(), 2);
+        assert_eq!(machine.calls.len(), 3);
+        assert_eq!(job.progress(1000).elapsed_ms, 400);
+    }
+}
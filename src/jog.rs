@@ -0,0 +1,108 @@
+//! Jogging from the touch screen.
+//!
+//! A press either moves one step, or in continuous mode keeps sending short
+//! moves until the finger lifts, then cancels whatever is still queued.
+//! Nothing here touches hardware: the main loop sends whatever `next`
+//! returns to the machine controller.
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub enum JogStep {
+    Step(f32), // mm per press
+    Continuous,
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum JogCommand {
+    // Relative move of one axis, mm at mm/min
+    Move {
+        axis: usize,
+        distance: f32,
+        feed: f32,
+    },
+    // Stop and throw away any queued jog moves
+    Cancel,
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub struct Jogger {
+    step: JogStep,
+    feed: f32,
+    segment_ms: u32,
+    held: Option<(usize, f32)>, // Axis and direction while a key is down
+    pending: Option<JogCommand>,
+    last_ms: Option<u32>,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl Jogger {
+    /// Continuous jogs are sent as moves lasting `segment_ms` at `feed`
+    pub fn new(step: JogStep, feed: f32, segment_ms: u32) -> Jogger {
+        Jogger {
+            step,
+            feed,
+            segment_ms,
+            held: None,
+            pending: None,
+            last_ms: None,
+        }
+    }
+
+    pub fn step(&self) -> JogStep {
+        self.step
+    }
+
+    pub fn feed(&self) -> f32 {
+        self.feed
+    }
+
+    pub fn set_step(&mut self, step: JogStep) {
+        self.release();
+        self.step = step;
+    }
+
+    /// A jog key went down. `direction` is 1.0 or -1.0.
+    pub fn press(&mut self, axis: usize, direction: f32) {
+        self.held = Some((axis, direction));
+        match self.step {
+            JogStep::Step(step) => {
+                self.pending = Some(JogCommand::Move {
+                    axis,
+                    distance: direction * step,
+                    feed: self.feed,
+                })
+            }
+            JogStep::Continuous => self.last_ms = None,
+        }
+    }
+
+    /// The finger lifted
+    pub fn release(&mut self) {
+        if self.held.take().is_some() && self.step == JogStep::Continuous {
+            self.pending = Some(JogCommand::Cancel);
+        }
+    }
+
+    /// The next command to send, if any
+    pub fn next(&mut self, now_ms: u32) -> Option<JogCommand> {
+        if let Some(command) = self.pending.take() {
+            return Some(command);
+        }
+        let (axis, direction) = self.held?;
+        if self.step != JogStep::Continuous {
+            return None;
+        }
+        if let Some(last) = self.last_ms {
+            if now_ms.wrapping_sub(last) < self.segment_ms {
+                return None;
+            }
+        }
+        self.last_ms = Some(now_ms);
+        Some(JogCommand::Move {
+            axis,
+            distance: direction * self.feed * self.segment_ms as f32 / 60_000.0,
+            feed: self.feed,
+        })
+    }
+}
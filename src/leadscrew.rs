@@ -0,0 +1,751 @@
+//! Electronic leadscrew for the lathe: a stepper on the leadscrew follows a
+//! spindle encoder at a set ratio, for turning feeds in mm/rev and for
+//! threads picked from a table of metric pitches and TPI.
+//!
+//! The Arduino header is full, so it is wired to the camera connector:
+//!
+//!   Spindle A      PI5 (DCMI_VSYNC) TIM8_CH1
+//!   Spindle B      PI6 (DCMI_D6)    TIM8_CH2
+//!   Spindle index  PI7 (DCMI_D7)    TIM8_CH3, which latches the count
+//!   Step           PH14 (DCMI_D4)
+//!   Dir            PI4 (DCMI_D5)    high to move right, away from the chuck
+//!
+//! TIM6 interrupts at `TICK_HZ` and runs `Motion::tick`, which moves the
+//! stepper a step at a time towards where the spindle count says it should
+//! be. The ratio of steps to counts is kept as a reduced fraction, and
+//! `Follower` works through it the Bresenham way, adding and subtracting
+//! and never dividing, so a long thread doesn't drift.
+//!
+//! A threading pass only engages on an index pulse, starting from the
+//! count the pulse latched. Every pass then starts at the same spindle
+//! angle, so passes started from the same place on the carriage, as
+//! `Return` puts it, cut the same groove.
+//!
+//! Everything but `Drive` and the interrupt handler is free of hardware.
+
+use core::cell::RefCell;
+use core::convert::TryFrom;
+use core::fmt::{self, Write};
+
+use cortex_m::interrupt::{free, Mutex};
+use stm32f7xx_hal::{
+    pac::{interrupt, Interrupt, NVIC, RCC, TIM6, TIM8},
+    rcc::Clocks,
+};
+
+use crate::consts::*;
+use crate::powerfeed::Direction;
+use crate::wizard::Fields;
+
+pub const TICK_HZ: u32 = 50_000;
+const RETURN_TICKS: u32 = 10; // Ticks per step going back to the start
+
+// Step on PH14, dir on PI4
+const GPIOH_BSRR: usize = 0x4002_1C00 + 0x18;
+const GPIOI_BSRR: usize = 0x4002_2000 + 0x18;
+const STEP_PIN: u32 = 14;
+const DIR_PIN: u32 = 4;
+
+/// A thread pitch, or feed per revolution
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Pitch {
+    // Micrometres
+    Metric(u32),
+    // Tenths of a thread per inch, for the likes of 11.5 TPI
+    Tpi(u32),
+}
+
+impl Pitch {
+    /// Exactly, as a fraction of a millimetre
+    pub fn mm_fraction(self) -> (u64, u64) {
+        match self {
+            Pitch::Metric(um) => (um as u64, 1000),
+            // 25.4 / (t / 10) = 254 / t
+            Pitch::Tpi(tenths) => (254, tenths as u64),
+        }
+    }
+
+    pub fn mm(self) -> f32 {
+        let (num, den) = self.mm_fraction();
+        num as f32 / den as f32
+    }
+}
+
+pub const THREADS: [(&str, Pitch); 24] = [
+    ("M3  0.5", Pitch::Metric(500)),
+    ("M4  0.7", Pitch::Metric(700)),
+    ("M5  0.8", Pitch::Metric(800)),
+    ("M6  1.0", Pitch::Metric(1000)),
+    ("M8  1.25", Pitch::Metric(1250)),
+    ("M10 1.5", Pitch::Metric(1500)),
+    ("M12 1.75", Pitch::Metric(1750)),
+    ("M16 2.0", Pitch::Metric(2000)),
+    ("M20 2.5", Pitch::Metric(2500)),
+    ("M24 3.0", Pitch::Metric(3000)),
+    ("8 TPI", Pitch::Tpi(80)),
+    ("10 TPI", Pitch::Tpi(100)),
+    ("11 TPI", Pitch::Tpi(110)),
+    ("11.5 TPI", Pitch::Tpi(115)),
+    ("12 TPI", Pitch::Tpi(120)),
+    ("13 TPI", Pitch::Tpi(130)),
+    ("14 TPI", Pitch::Tpi(140)),
+    ("16 TPI", Pitch::Tpi(160)),
+    ("18 TPI", Pitch::Tpi(180)),
+    ("20 TPI", Pitch::Tpi(200)),
+    ("24 TPI", Pitch::Tpi(240)),
+    ("28 TPI", Pitch::Tpi(280)),
+    ("32 TPI", Pitch::Tpi(320)),
+    ("40 TPI", Pitch::Tpi(400)),
+];
+
+const FEEDS: [(&str, f32); 6] = [
+    ("Fine finish", 0.05),
+    ("Finish", 0.1),
+    ("Light", 0.15),
+    ("Medium", 0.2),
+    ("Roughing", 0.3),
+    ("Heavy", 0.4),
+];
+
+pub fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+    a
+}
+
+/// Leadscrew steps per spindle count, as a fraction in its lowest terms
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Ratio {
+    num: u32,
+    den: u32,
+}
+
+impl Ratio {
+    /// For the carriage to move `pitch` each turn of the spindle, with a
+    /// leadscrew of `leadscrew` pitch turned by `steps_per_rev` steps.
+    /// None if the fraction doesn't fit, or is zero.
+    pub fn new(
+        pitch: Pitch,
+        counts_per_rev: u32,
+        steps_per_rev: u32,
+        leadscrew: Pitch,
+    ) -> Option<Ratio> {
+        let (pn, pd) = pitch.mm_fraction();
+        let (ln, ld) = leadscrew.mm_fraction();
+        // (pn / pd) / (ln / ld) turns of the leadscrew per spindle turn
+        let num = pn.checked_mul(ld)?.checked_mul(steps_per_rev as u64)?;
+        let den = pd.checked_mul(ln)?.checked_mul(counts_per_rev as u64)?;
+        if num == 0 || den == 0 {
+            return None;
+        }
+        let common = gcd(num, den);
+        Some(Ratio {
+            num: u32::try_from(num / common).ok()?,
+            den: u32::try_from(den / common).ok()?,
+        })
+    }
+}
+
+/// Where the stepper should be, in steps, for the spindle counts so far
+#[derive(Copy, Clone, Debug)]
+pub struct Follower {
+    ratio: Ratio,
+    // Always from 0 up to den
+    error: i64,
+    target: i64,
+}
+
+impl Follower {
+    pub fn new(ratio: Ratio) -> Follower {
+        Follower {
+            ratio,
+            error: 0,
+            target: 0,
+        }
+    }
+
+    /// Moves on by `counts` of the spindle, either way
+    pub fn advance(&mut self, counts: i32) {
+        let den = self.ratio.den as i64;
+        self.error += counts as i64 * self.ratio.num as i64;
+        while self.error >= den {
+            self.error -= den;
+            self.target += 1;
+        }
+        while self.error < 0 {
+            self.error += den;
+            self.target -= 1;
+        }
+    }
+
+    pub fn target(&self) -> i64 {
+        self.target
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub enum Mode {
+    Feed,
+    Thread,
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub enum State {
+    Idle,
+    // Threading, until the next index pulse
+    WaitIndex,
+    Engaged,
+    Returning,
+}
+
+/// What the main loop wants the leadscrew to do
+#[derive(PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub enum Request {
+    // Follow the spindle, straight away or from the next index pulse
+    Engage(Ratio, Direction, bool),
+    Stop,
+    // Back to where the last pass started
+    Return,
+    // Disengage by itself here, or nowhere
+    SetEnd,
+    ClearEnd,
+}
+
+/// The step and dir outputs for one tick
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Output {
+    pub step: bool,
+    pub right: bool,
+}
+
+/// How things stand, for the screen
+#[derive(PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub struct Status {
+    pub state: State,
+    pub direction: Direction, // Of the last pass
+    pub end: bool,
+}
+
+impl Status {
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn describe<W: Write>(&self, out: &mut W) -> fmt::Result {
+        match self.state {
+            State::Idle => write!(out, "Stopped")?,
+            State::WaitIndex => write!(out, "Waiting for index")?,
+            State::Engaged => write!(out, "Feeding {}", self.direction.name())?,
+            State::Returning => write!(out, "Returning")?,
+        }
+        if self.end {
+            write!(out, ", end set")?;
+        }
+        Ok(())
+    }
+}
+
+/// The leadscrew's state, run from the tick interrupt
+#[derive(Copy, Clone, Debug)]
+pub struct Motion {
+    state: State,
+    follower: Option<Follower>,
+    sign: i64, // Of the steps for a turn of the spindle
+    base: i64, // Position when the follower started
+    position: i64,
+    start: Option<i64>, // Of the last pass
+    end: Option<i64>,
+    last_count: u16,
+    spindle: i64, // Counts from power up
+    right: bool,
+    pulse: bool, // A step pulse went out last tick
+    wait: u32,   // Ticks until the next step back to the start
+}
+
+impl Motion {
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn new() -> Motion {
+        Motion {
+            state: State::Idle,
+            follower: None,
+            sign: 1,
+            base: 0,
+            position: 0,
+            start: None,
+            end: None,
+            last_count: 0,
+            spindle: 0,
+            right: true,
+            pulse: false,
+            wait: 0,
+        }
+    }
+
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn request(&mut self, request: Request) {
+        match request {
+            Request::Engage(ratio, direction, sync) if self.state == State::Idle => {
+                self.follower = Some(Follower::new(ratio));
+                self.sign = match direction {
+                    Direction::Left => -1,
+                    Direction::Right => 1,
+                };
+                self.base = self.position;
+                self.start = Some(self.position);
+                self.state = if sync {
+                    State::WaitIndex
+                } else {
+                    State::Engaged
+                };
+            }
+            Request::Engage(_, _, _) => (),
+            Request::Stop => self.state = State::Idle,
+            Request::Return if self.state == State::Idle && self.start.is_some() => {
+                self.state = State::Returning
+            }
+            Request::Return => (),
+            Request::SetEnd => self.end = Some(self.position),
+            Request::ClearEnd => self.end = None,
+        }
+    }
+
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn status(&self) -> Status {
+        Status {
+            state: self.state,
+            direction: if self.sign < 0 {
+                Direction::Left
+            } else {
+                Direction::Right
+            },
+            end: self.end.is_some(),
+        }
+    }
+
+    /// Spindle encoder counts since power up, either way
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn spindle(&self) -> i64 {
+        self.spindle
+    }
+
+    /// Takes the spindle counter, and where it was at an index pulse since
+    /// the last tick if there was one. Returns the outputs, with at most a
+    /// step every other tick so pulses are a tick long and a tick apart.
+    pub fn tick(&mut self, count: u16, index: Option<u16>) -> Output {
+        let counts = count.wrapping_sub(self.last_count) as i16 as i32;
+        self.last_count = count;
+        self.spindle += counts as i64;
+
+        let wanted = match (self.state, &mut self.follower) {
+            (State::WaitIndex, Some(follower)) => match index {
+                Some(at) => {
+                    follower.advance(count.wrapping_sub(at) as i16 as i32);
+                    self.state = State::Engaged;
+                    Some(self.base + self.sign * follower.target())
+                }
+                None => None,
+            },
+            (State::Engaged, Some(follower)) => {
+                follower.advance(counts);
+                Some(self.base + self.sign * follower.target())
+            }
+            (State::Returning, _) if self.wait > 0 => {
+                self.wait -= 1;
+                None
+            }
+            (State::Returning, _) => {
+                self.wait = RETURN_TICKS;
+                self.start
+            }
+            _ => None,
+        };
+
+        let mut output = Output {
+            step: false,
+            right: self.right,
+        };
+        if self.pulse {
+            self.pulse = false;
+            return output;
+        }
+        let wanted = match wanted {
+            Some(wanted) => wanted,
+            None => return output,
+        };
+        if wanted == self.position {
+            if self.state == State::Returning {
+                self.state = State::Idle;
+            }
+            return output;
+        }
+        let right = wanted > self.position;
+        // Reaching the end disengages, but only heading away from where
+        // the pass started, so a spindle turned back doesn't stick there
+        if let (State::Engaged, Some(end)) = (self.state, self.end) {
+            let beyond = if right {
+                self.position >= end
+            } else {
+                self.position <= end
+            };
+            if beyond && (end - self.base) * (wanted - self.position) > 0 {
+                self.state = State::Idle;
+                return output;
+            }
+        }
+        self.position += if right { 1 } else { -1 };
+        self.right = right;
+        self.pulse = true;
+        output.step = true;
+        output.right = right;
+        output
+    }
+}
+
+/// What is typed in or picked on the leadscrew page
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub struct Settings {
+    mode: Mode,
+    feed: f32, // mm/rev
+    thread: usize,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl Settings {
+    pub fn new() -> Settings {
+        Settings {
+            mode: Mode::Feed,
+            feed: FEEDS[1].1,
+            thread: 3,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    /// mm per turn of the spindle
+    pub fn pitch(&self) -> Pitch {
+        match self.mode {
+            Mode::Feed => Pitch::Metric((self.feed * 1000.0 + 0.5) as u32),
+            Mode::Thread => THREADS[self.thread].1,
+        }
+    }
+
+    /// The row of the table for the mode in use, the first if a feed was
+    /// typed in
+    pub fn row(&self) -> usize {
+        match self.mode {
+            Mode::Feed => FEEDS
+                .iter()
+                .position(|(_, feed)| *feed == self.feed)
+                .unwrap_or(0),
+            Mode::Thread => self.thread,
+        }
+    }
+
+    /// Picks row `i` of the table for the mode
+    pub fn select(&mut self, i: usize) {
+        match self.mode {
+            Mode::Feed => {
+                if let Some((_, feed)) = FEEDS.get(i) {
+                    self.feed = *feed;
+                }
+            }
+            Mode::Thread => {
+                if i < THREADS.len() {
+                    self.thread = i;
+                }
+            }
+        }
+    }
+
+    /// A feed typed in rather than picked
+    pub fn set_feed(&mut self, feed: f32) {
+        self.feed = if feed < 0.0 { 0.0 - feed } else { feed };
+    }
+
+    /// The ratio for the leadscrew in consts.rs, or None if it can't be
+    /// done, e.g. a zero feed
+    pub fn ratio(&self) -> Option<Ratio> {
+        Ratio::new(
+            self.pitch(),
+            SPINDLE_COUNTS_PER_REV,
+            LEADSCREW_STEPS_PER_REV,
+            LEADSCREW_PITCH,
+        )
+    }
+
+    /// Writes the settings for storage, returning the bytes used
+    pub fn write_bytes(&self, buf: &mut [u8]) -> Option<usize> {
+        let buf = buf.get_mut(..6)?;
+        buf[0] = (self.mode == Mode::Thread) as u8;
+        buf[1] = self.thread as u8;
+        buf[2..6].copy_from_slice(&self.feed.to_le_bytes());
+        Some(6)
+    }
+
+    pub fn read_bytes(&mut self, buf: &[u8]) {
+        if buf.len() < 6 {
+            return;
+        }
+        self.mode = if buf[0] != 0 {
+            Mode::Thread
+        } else {
+            Mode::Feed
+        };
+        self.thread = (buf[1] as usize).min(THREADS.len() - 1);
+        let feed = f32::from_le_bytes([buf[2], buf[3], buf[4], buf[5]]);
+        if feed.is_finite() {
+            self.set_feed(feed);
+        }
+    }
+}
+
+impl Fields for Settings {
+    fn title(&self) -> &str {
+        match self.mode {
+            Mode::Feed => "Feed, mm/rev",
+            Mode::Thread => "Thread, mm pitch",
+        }
+    }
+
+    fn count(&self) -> usize {
+        match self.mode {
+            Mode::Feed => FEEDS.len(),
+            Mode::Thread => THREADS.len(),
+        }
+    }
+
+    fn get(&self, i: usize) -> Option<(&'static str, f32)> {
+        match self.mode {
+            Mode::Feed => FEEDS.get(i).copied(),
+            Mode::Thread => THREADS.get(i).map(|(name, pitch)| (*name, pitch.mm())),
+        }
+    }
+}
+
+static MOTION: Mutex<RefCell<Option<Motion>>> = Mutex::new(RefCell::new(None));
+
+/// The spindle encoder and tick timers
+#[cfg_attr(test, allow(dead_code))]
+pub struct Drive {
+    _tick: TIM6,
+    _spindle: TIM8,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl Drive {
+    /// Starts counting the spindle and ticking, disengaged. The spindle
+    /// pins must already be TIM8 inputs (AF3), step and dir push pull
+    /// outputs.
+    pub fn new(tick: TIM6, spindle: TIM8, clocks: &Clocks) -> Drive {
+        free(|cs| *MOTION.borrow(cs).borrow_mut() = Some(Motion::new()));
+
+        // NOTE(unsafe) only touches the TIM6 and TIM8 enable bits
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc.apb1enr.modify(|_, w| w.tim6en().set_bit());
+        rcc.apb2enr.modify(|_, w| w.tim8en().set_bit());
+
+        // Encoder mode 3 on CH1 and CH2 with a light filter, as encoder.rs,
+        // and CH3 capturing the count on the rising edge of the index
+        spindle
+            .ccmr1_input()
+            .write(|w| unsafe { w.bits(0b01 | (0b0011 << 4) | (0b01 << 8) | (0b0011 << 12)) });
+        spindle
+            .ccmr2_input()
+            .write(|w| unsafe { w.bits(0b01 | (0b0011 << 4)) });
+        spindle
+            .ccer
+            .write(|w| unsafe { w.bits(1 | (1 << 4) | (1 << 8)) });
+        spindle.smcr.write(|w| unsafe { w.bits(0b011) });
+        spindle.arr.write(|w| unsafe { w.bits(0xFFFF) });
+        spindle.cnt.write(|w| unsafe { w.bits(0) });
+        spindle.cr1.write(|w| w.cen().set_bit());
+
+        let reload = clocks.timclk1().0 / TICK_HZ - 1;
+        tick.psc.write(|w| unsafe { w.bits(0) });
+        tick.arr.write(|w| unsafe { w.bits(reload) });
+        tick.egr.write(|w| w.ug().set_bit());
+        tick.sr.write(|w| unsafe { w.bits(0) });
+        tick.dier.write(|w| w.uie().set_bit());
+        tick.cr1.write(|w| w.cen().set_bit());
+        // NOTE(unsafe) the handler only shares MOTION, behind a mutex
+        unsafe { NVIC::unmask(Interrupt::TIM6_DAC) };
+
+        Drive {
+            _tick: tick,
+            _spindle: spindle,
+        }
+    }
+
+    pub fn request(&mut self, request: Request) {
+        free(|cs| {
+            if let Some(motion) = MOTION.borrow(cs).borrow_mut().as_mut() {
+                motion.request(request);
+            }
+        });
+    }
+
+    pub fn status(&self) -> Option<Status> {
+        free(|cs| MOTION.borrow(cs).borrow().as_ref().map(Motion::status))
+    }
+
+    pub fn spindle(&self) -> Option<i64> {
+        free(|cs| MOTION.borrow(cs).borrow().as_ref().map(Motion::spindle))
+    }
+}
+
+#[interrupt]
+fn TIM6_DAC() {
+    // NOTE(unsafe) write to clear our own update flag, and reads of the
+    // spindle counter, which only this handler does after set up
+    let tick = unsafe { &(*TIM6::ptr()) };
+    tick.sr.write(|w| unsafe { w.bits(0) });
+    let spindle = unsafe { &(*TIM8::ptr()) };
+    let count = spindle.cnt.read().bits() as u16;
+    // Reading the capture clears its flag
+    let index = if spindle.sr.read().cc3if().bit_is_set() {
+        Some(spindle.ccr3.read().bits() as u16)
+    } else {
+        None
+    };
+
+    let output = match free(|cs| {
+        MOTION
+            .borrow(cs)
+            .borrow_mut()
+            .as_mut()
+            .map(|m| m.tick(count, index))
+    }) {
+        Some(output) => output,
+        None => return,
+    };
+
+    // NOTE(unsafe) atomic writes, only to our own pins. Last tick's pulse
+    // ends, then the direction, then a new pulse.
+    unsafe {
+        core::ptr::write_volatile(GPIOH_BSRR as *mut u32, 1 << (STEP_PIN + 16));
+        let dir = if output.right {
+            1 << DIR_PIN
+        } else {
+            1 << (DIR_PIN + 16)
+        };
+        core::ptr::write_volatile(GPIOI_BSRR as *mut u32, dir);
+        if output.step {
+            core::ptr::write_volatile(GPIOH_BSRR as *mut u32, 1 << STEP_PIN);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COUNTS: u32 = 4096; // 1024 lines, four edges each
+    const STEPS: u32 = 1600; // 200 steps at 8 microsteps
+    const LEADSCREW: Pitch = Pitch::Metric(2000);
+
+    fn ratio(pitch: Pitch, leadscrew: Pitch) -> Ratio {
+        Ratio::new(pitch, COUNTS, STEPS, leadscrew).expect("no ratio")
+    }
+
+    // Where the stepper should be after `counts`, worked out the long way
+    fn exact(ratio: Ratio, counts: i64) -> i64 {
+        (counts * ratio.num as i64).div_euclid(ratio.den as i64)
+    }
+
+    #[test]
+    fn ratio_in_lowest_terms() {
+        // 1.5 / 2 × 1600 / 4096 = 2400000000 / 8192000000
+        assert_eq!(
+            ratio(Pitch::Metric(1500), LEADSCREW),
+            Ratio { num: 75, den: 256 }
+        );
+        assert_eq!(
+            ratio(Pitch::Metric(2000), LEADSCREW),
+            Ratio { num: 25, den: 64 }
+        );
+        assert_eq!(
+            ratio(Pitch::Tpi(80), Pitch::Tpi(80)),
+            Ratio { num: 25, den: 64 }
+        );
+    }
+
+    #[test]
+    fn ratio_tenths_of_tpi() {
+        // 11.5 TPI on an 8 TPI leadscrew: 8 / 11.5 = 16 / 23 of a turn
+        assert_eq!(Pitch::Tpi(115).mm_fraction(), (254, 115));
+        assert_eq!(
+            ratio(Pitch::Tpi(115), Pitch::Tpi(80)),
+            Ratio { num: 25, den: 92 }
+        );
+        // Imperial on metric doesn't round 25.4 either
+        assert_eq!(
+            ratio(Pitch::Tpi(115), LEADSCREW),
+            Ratio {
+                num: 635,
+                den: 1472
+            }
+        );
+    }
+
+    #[test]
+    fn ratio_that_doesnt_fit() {
+        assert_eq!(Ratio::new(Pitch::Metric(0), COUNTS, STEPS, LEADSCREW), None);
+        assert_eq!(Ratio::new(Pitch::Metric(1000), 0, STEPS, LEADSCREW), None);
+        assert_eq!(Ratio::new(Pitch::Metric(1000), COUNTS, 0, LEADSCREW), None);
+        // Overflows the u64 working
+        let huge = Pitch::Metric(u32::MAX);
+        assert_eq!(Ratio::new(huge, COUNTS, u32::MAX, Pitch::Metric(1)), None);
+        // Fits the working but not the u32 fraction
+        let prime = 4099;
+        assert_eq!(Ratio::new(huge, prime, STEPS, Pitch::Metric(1)), None);
+    }
+
+    #[test]
+    fn follower_whole_turns() {
+        // 1.5 mm on a 2 mm leadscrew is exactly 1200 steps a turn
+        let mut follower = Follower::new(ratio(Pitch::Metric(1500), LEADSCREW));
+        follower.advance(COUNTS as i32);
+        assert_eq!(follower.target(), 1200);
+        follower.advance(-2 * COUNTS as i32);
+        assert_eq!(follower.target(), -1200);
+    }
+
+    #[test]
+    fn follower_doesnt_drift() {
+        for pitch in [Pitch::Tpi(115), Pitch::Metric(1750), Pitch::Tpi(130)] {
+            let ratio = ratio(pitch, Pitch::Tpi(80));
+            let mut follower = Follower::new(ratio);
+            let mut counts = 0i64;
+            // 1000 turns in uneven bites, forwards then further back
+            for bite in [7, 1, 13, 4096, 3] {
+                while counts < 1000 * COUNTS as i64 {
+                    follower.advance(bite);
+                    counts += bite as i64;
+                    assert_eq!(follower.target(), exact(ratio, counts));
+                }
+            }
+            for bite in [-5, -4096, -1, -11] {
+                while counts > -1000 * COUNTS as i64 {
+                    follower.advance(bite);
+                    counts += bite as i64;
+                    assert_eq!(follower.target(), exact(ratio, counts));
+                }
+            }
+            // And back to where it started, exactly
+            follower.advance(-counts as i32);
+            assert_eq!(follower.target(), 0);
+            assert_eq!(follower.error, 0);
+        }
+    }
+}
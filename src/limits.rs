@@ -0,0 +1,299 @@
+//! Soft limits: a travel envelope in machine coordinates.
+//!
+//! Each axis can have a minimum and a maximum, or neither. Within
+//! `SOFT_LIMIT_WARN_MM` of one the readouts warn, and going past one is an
+//! alarm. Machine coordinates only mean something once the controller has
+//! homed, or for the scales, relative to where they were at power up, so
+//! the alarm only goes off for crossing a limit from inside; starting
+//! outside the envelope just warns.
+//!
+//! Nothing here touches the display or hardware.
+
+use crate::consts::SOFT_LIMIT_WARN_MM;
+use crate::wizard::Fields;
+
+pub const N_LIMITS: usize = 6;
+const NAMES: [&str; N_LIMITS] = ["X min", "X max", "Y min", "Y max", "Z min", "Z max"];
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Side {
+    Min,
+    Max,
+}
+
+impl Side {
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn name(self) -> &'static str {
+        match self {
+            Side::Min => "min",
+            Side::Max => "max",
+        }
+    }
+}
+
+/// Where the machine is relative to the envelope
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Zone {
+    Inside,
+    Near(usize, Side),
+    Over(usize, Side),
+}
+
+/// The limits, as typed in or captured, and whether a crossing drives the
+/// relay. A limit that is off is stored as NaN.
+#[derive(Copy, Clone, Debug)]
+pub struct Envelope {
+    limits: [f32; N_LIMITS],
+    relay: bool,
+    // Been inside since power up or the last unlock, so a crossing counts
+    armed: bool,
+    tripped: bool,
+}
+
+impl Envelope {
+    pub fn new() -> Envelope {
+        Envelope {
+            limits: [f32::NAN; N_LIMITS],
+            relay: false,
+            armed: false,
+            tripped: false,
+        }
+    }
+
+    /// Field `i` is side `i % 2` of axis `i / 2`
+    pub fn set(&mut self, i: usize, value: f32) {
+        if let Some(limit) = self.limits.get_mut(i) {
+            *limit = value;
+        }
+    }
+
+    pub fn clear(&mut self, i: usize) {
+        self.set(i, f32::NAN);
+    }
+
+    pub fn relay(&self) -> bool {
+        self.relay
+    }
+
+    pub fn toggle_relay(&mut self) {
+        self.relay = !self.relay;
+    }
+
+    /// A limit was crossed and hasn't been unlocked since
+    pub fn tripped(&self) -> bool {
+        self.tripped
+    }
+
+    /// Clears the alarm. Until the machine is back inside, crossing again
+    /// doesn't raise another.
+    pub fn unlock(&mut self) {
+        self.tripped = false;
+        self.armed = false;
+    }
+
+    /// Where one axis is against its own limits
+    pub fn axis_zone(&self, axis: usize, position: f32) -> Zone {
+        let mut zone = Zone::Inside;
+        let mut closest = SOFT_LIMIT_WARN_MM;
+        let sides = [
+            (Side::Min, self.limits[2 * axis]),
+            (Side::Max, self.limits[2 * axis + 1]),
+        ];
+        for (side, limit) in sides {
+            if !limit.is_finite() {
+                continue;
+            }
+            let room = match side {
+                Side::Min => position - limit,
+                Side::Max => limit - position,
+            };
+            if room < 0.0 {
+                return Zone::Over(axis, side);
+            }
+            if room <= closest {
+                closest = room;
+                zone = Zone::Near(axis, side);
+            }
+        }
+        zone
+    }
+
+    /// Where `position` is, the worst axis first: past a limit, then near one
+    pub fn zone(&self, position: [f32; 3]) -> Zone {
+        let mut zone = Zone::Inside;
+        for (axis, p) in position.iter().enumerate() {
+            match self.axis_zone(axis, *p) {
+                Zone::Inside => (),
+                over @ Zone::Over(_, _) => return over,
+                near => {
+                    if zone == Zone::Inside {
+                        zone = near;
+                    }
+                }
+            }
+        }
+        zone
+    }
+
+    /// Takes the latest machine position. Returns the zone, and true if
+    /// this is a new crossing that should raise the alarm.
+    pub fn update(&mut self, position: [f32; 3]) -> (Zone, bool) {
+        let zone = self.zone(position);
+        let crossed = match zone {
+            Zone::Over(_, _) => self.armed && !self.tripped,
+            _ => {
+                self.armed = true;
+                false
+            }
+        };
+        if crossed {
+            self.tripped = true;
+        }
+        (zone, crossed)
+    }
+
+    /// Writes the limits and relay setting for storage, returning the bytes
+    /// used
+    pub fn write_bytes(&self, buf: &mut [u8]) -> Option<usize> {
+        let bytes = buf.get_mut(..N_LIMITS * 4 + 1)?;
+        for (chunk, limit) in bytes.chunks_exact_mut(4).zip(self.limits.iter()) {
+            chunk.copy_from_slice(&limit.to_le_bytes());
+        }
+        bytes[N_LIMITS * 4] = self.relay as u8;
+        Some(N_LIMITS * 4 + 1)
+    }
+
+    pub fn read_bytes(&mut self, buf: &[u8]) {
+        for (limit, chunk) in self.limits.iter_mut().zip(buf.chunks_exact(4)) {
+            *limit = f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        if let Some(relay) = buf.get(N_LIMITS * 4) {
+            self.relay = *relay != 0;
+        }
+    }
+}
+
+impl Fields for Envelope {
+    fn title(&self) -> &str {
+        if self.relay {
+            "Soft limits, relay on"
+        } else {
+            "Soft limits, relay off"
+        }
+    }
+
+    fn count(&self) -> usize {
+        N_LIMITS
+    }
+
+    fn get(&self, i: usize) -> Option<(&'static str, f32)> {
+        Some((NAMES.get(i)?, self.limits[i]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // X from 0 to 100, Z up to 10, Y free
+    fn envelope() -> Envelope {
+        let mut envelope = Envelope::new();
+        envelope.set(0, 0.0);
+        envelope.set(1, 100.0);
+        envelope.set(5, 10.0);
+        envelope
+    }
+
+    #[test]
+    fn zones() {
+        let envelope = envelope();
+        assert_eq!(envelope.zone([50.0, 1e6, -1e6]), Zone::Inside);
+        assert_eq!(envelope.axis_zone(0, 5.0), Zone::Near(0, Side::Min));
+        assert_eq!(envelope.axis_zone(0, 0.0), Zone::Near(0, Side::Min));
+        assert_eq!(envelope.axis_zone(0, -0.01), Zone::Over(0, Side::Min));
+        assert_eq!(envelope.axis_zone(0, 96.0), Zone::Near(0, Side::Max));
+        assert_eq!(envelope.axis_zone(0, 100.5), Zone::Over(0, Side::Max));
+        assert_eq!(envelope.axis_zone(1, 1e6), Zone::Inside);
+
+        // Past a limit beats near one, whichever axis comes first
+        assert_eq!(envelope.zone([1.0, 0.0, 11.0]), Zone::Over(2, Side::Max));
+        assert_eq!(envelope.zone([1.0, 0.0, 6.0]), Zone::Near(0, Side::Min));
+
+        // Both sides close, the nearer one warns
+        let mut narrow = Envelope::new();
+        narrow.set(0, 0.0);
+        narrow.set(1, 4.0);
+        assert_eq!(narrow.axis_zone(0, 1.0), Zone::Near(0, Side::Min));
+        assert_eq!(narrow.axis_zone(0, 3.0), Zone::Near(0, Side::Max));
+
+        // Off again
+        narrow.clear(0);
+        narrow.clear(1);
+        assert_eq!(narrow.axis_zone(0, -1e6), Zone::Inside);
+    }
+
+    #[test]
+    fn crossing_from_inside() {
+        let mut envelope = envelope();
+        // Starting outside only warns, until it has been inside
+        assert_eq!(
+            envelope.update([-5.0, 0.0, 0.0]),
+            (Zone::Over(0, Side::Min), false)
+        );
+        assert_eq!(envelope.update([50.0, 0.0, 0.0]), (Zone::Inside, false));
+        assert_eq!(
+            envelope.update([50.0, 0.0, 12.0]),
+            (Zone::Over(2, Side::Max), true)
+        );
+        assert!(envelope.tripped());
+
+        // Once only, however far it goes
+        assert_eq!(
+            envelope.update([150.0, 0.0, 12.0]),
+            (Zone::Over(0, Side::Max), false)
+        );
+        assert!(envelope.tripped());
+
+        // Unlocked while still outside, it has to come back in first
+        envelope.unlock();
+        assert!(!envelope.tripped());
+        assert!(!envelope.update([150.0, 0.0, 0.0]).1);
+        assert_eq!(
+            envelope.update([98.0, 0.0, 0.0]),
+            (Zone::Near(0, Side::Max), false)
+        );
+        assert!(envelope.update([101.0, 0.0, 0.0]).1);
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut envelope = envelope();
+        envelope.toggle_relay();
+        let mut buf = [0u8; 64];
+        let used = envelope.write_bytes(&mut buf).unwrap();
+        assert_eq!(used, N_LIMITS * 4 + 1);
+        assert_eq!(envelope.write_bytes(&mut buf[..used - 1]), None);
+
+        let mut read = Envelope::new();
+        read.read_bytes(&buf[..used]);
+        assert!(read.relay());
+        for (i, expected_name) in NAMES.iter().enumerate() {
+            let (name, value) = read.get(i).unwrap();
+            let (_, expected) = envelope.get(i).unwrap();
+            assert_eq!(name, *expected_name);
+            assert!(
+                value == expected || value.is_nan() && expected.is_nan(),
+                "{}",
+                name
+            );
+        }
+        // Tripped isn't saved
+        assert!(!read.tripped());
+
+        // An older record without the relay leaves it off
+        let mut read = Envelope::new();
+        read.read_bytes(&buf[..N_LIMITS * 4]);
+        assert!(!read.relay());
+        assert_eq!(read.get(1), Some(("X max", 100.0)));
+    }
+}
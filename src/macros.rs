@@ -0,0 +1,382 @@
+//! User macro buttons, read from `MACROS.CFG` in the root of the SD card.
+//!
+//! Each button is a label in square brackets, followed by the lines it
+//! runs:
+//!
+//!   [Park]
+//!   G53 G0 Z0
+//!   G53 G0 X0 Y0
+//!   [Edge X]
+//!   @set X 25.4
+//!
+//! A line starting with `@` is done here rather than sent:
+//!
+//!   @set X 25.4   make X read 25.4 where it is now, like typing it in
+//!   @zero X       the same as @set X 0
+//!   @hold, @resume, @reset, @home, @unlock
+//!
+//! Anything else is G-code, sent one line at a time, each waiting for the
+//! controller to answer before the next. Comments, spaces and blank lines
+//! are skipped, and what's left must fit in `MDI_LEN`. Labels longer than
+//! `BUTTON_LABEL_LEN` are cut short.
+//!
+//! Nothing here touches the display, the card or the controller. The view
+//! takes steps from `take_step` and feeds back the answers, like a probe
+//! cycle.
+
+use core::fmt::{self, Write};
+
+use crate::backend::{Command, Reply, MDI_LEN};
+use crate::consts::BUTTON_LABEL_LEN;
+use crate::job::{clean_line, LineSource, Read, LINE_LEN};
+use crate::text::TextBuffer;
+
+pub const FILE_NAME: &str = "MACROS.CFG";
+pub const MAX_MACROS: usize = 12;
+const MAX_STEPS: usize = 64; // Between all the buttons
+
+pub type Label = TextBuffer<BUTTON_LABEL_LEN>;
+pub type Line = TextBuffer<MDI_LEN>;
+
+#[derive(Copy, Clone, Debug)]
+pub enum Step {
+    Send(Line),
+    Command(Command),
+    // Make an axis read this value where it is
+    Set(usize, f32),
+    Done,
+}
+
+/// Why the file wasn't read, or not all of it
+#[derive(PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub enum Problem {
+    // No card, or no file on it
+    NoFile,
+    // The line with this number didn't make sense
+    Line(u32),
+    // More buttons or lines than fit, from this line on
+    TooMany(u32),
+    Failed,
+}
+
+impl Problem {
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn describe<W: Write>(self, out: &mut W) -> fmt::Result {
+        match self {
+            Problem::NoFile => write!(out, "No {} on the card", FILE_NAME),
+            Problem::Line(n) => write!(out, "{} line {} not understood", FILE_NAME, n),
+            Problem::TooMany(n) => write!(out, "Too much in {}, line {}", FILE_NAME, n),
+            Problem::Failed => write!(out, "Can't read {}", FILE_NAME),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Macros {
+    labels: [Label; MAX_MACROS],
+    // Steps of button i run up to ends[i], from where the one before ended
+    ends: [usize; MAX_MACROS],
+    steps: [Option<Step>; MAX_STEPS],
+    count: usize,
+    n_steps: usize,
+    // Button running and its next step
+    running: Option<(usize, usize)>,
+    // A line was sent and hasn't been answered
+    waiting: bool,
+}
+
+impl Macros {
+    pub const fn new() -> Macros {
+        Macros {
+            labels: [Label::new(); MAX_MACROS],
+            ends: [0; MAX_MACROS],
+            steps: [None; MAX_STEPS],
+            count: 0,
+            n_steps: 0,
+            running: None,
+            waiting: false,
+        }
+    }
+
+    /// Forgets every button, in place as it's too big to build on the stack
+    pub fn clear(&mut self) {
+        self.count = 0;
+        self.n_steps = 0;
+        self.running = None;
+        self.waiting = false;
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn label(&self, i: usize) -> Option<&str> {
+        if i < self.count {
+            Some(self.labels[i].as_str())
+        } else {
+            None
+        }
+    }
+
+    /// The button whose steps are running, if any
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn running(&self) -> Option<usize> {
+        self.running.map(|(i, _)| i)
+    }
+
+    // Starts another button, if there's room
+    fn add_label(&mut self, label: &str) -> bool {
+        if self.count == MAX_MACROS {
+            return false;
+        }
+        let mut text = Label::new();
+        text.write_str(label).ok();
+        self.labels[self.count] = text;
+        self.ends[self.count] = self.n_steps;
+        self.count += 1;
+        true
+    }
+
+    // Adds a step to the last button, if there's room
+    fn add_step(&mut self, step: Step) -> bool {
+        if self.n_steps == MAX_STEPS {
+            return false;
+        }
+        self.steps[self.n_steps] = Some(step);
+        self.n_steps += 1;
+        self.ends[self.count - 1] = self.n_steps;
+        true
+    }
+
+    /// Starts running button `i`
+    pub fn start(&mut self, i: usize) {
+        if i < self.count {
+            let first = if i == 0 { 0 } else { self.ends[i - 1] };
+            self.running = Some((i, first));
+            self.waiting = false;
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.running = None;
+        self.waiting = false;
+    }
+
+    /// The next thing to do, or None while waiting for an answer or when
+    /// nothing is running
+    pub fn take_step(&mut self) -> Option<Step> {
+        let (i, next) = self.running?;
+        if self.waiting {
+            return None;
+        }
+        if next >= self.ends[i] {
+            self.running = None;
+            return Some(Step::Done);
+        }
+        let step = self.steps[next]?;
+        self.running = Some((i, next + 1));
+        self.waiting = matches!(step, Step::Send(_));
+        Some(step)
+    }
+
+    /// The controller answered the last line sent. An error stops the
+    /// macro and is passed back.
+    pub fn reply(&mut self, reply: Reply) -> Result<(), u8> {
+        if !self.waiting {
+            return Ok(());
+        }
+        self.waiting = false;
+        match reply {
+            Reply::Ok => Ok(()),
+            Reply::Error(code) => {
+                self.stop();
+                Err(code)
+            }
+        }
+    }
+}
+
+/// Reads a whole file into `macros`. At a problem, the buttons read so
+/// far are kept.
+pub fn load<S: LineSource>(source: &mut S, macros: &mut Macros) -> Result<(), Problem> {
+    macros.clear();
+    let mut text: TextBuffer<LINE_LEN> = TextBuffer::new();
+    let mut n = 0;
+    loop {
+        n += 1;
+        match source.next_line(&mut text) {
+            Read::Line(_) => (),
+            Read::TooLong(_) => return Err(Problem::Line(n)),
+            Read::End => return Ok(()),
+            Read::Failed => return Err(Problem::Failed),
+        }
+        let line = text.as_str().trim();
+        if let Some(label) = line.strip_prefix('[') {
+            let label = label.strip_suffix(']').ok_or(Problem::Line(n))?.trim();
+            if label.is_empty() {
+                return Err(Problem::Line(n));
+            }
+            if !macros.add_label(label) {
+                return Err(Problem::TooMany(n));
+            }
+            continue;
+        }
+        let step = match parse_step(line) {
+            Ok(Some(step)) => step,
+            Ok(None) => continue,
+            Err(()) => return Err(Problem::Line(n)),
+        };
+        // Lines before the first label belong to nothing
+        if macros.count == 0 {
+            return Err(Problem::Line(n));
+        }
+        if !macros.add_step(step) {
+            return Err(Problem::TooMany(n));
+        }
+    }
+}
+
+// One line of a button. None for a blank line or a comment.
+fn parse_step(line: &str) -> Result<Option<Step>, ()> {
+    let action = match line.strip_prefix('@') {
+        Some(action) => action,
+        None => {
+            let mut gcode = Line::new();
+            if !clean_line(line, &mut gcode) {
+                return Err(()); // Too long to send as an MDI line
+            }
+            if gcode.as_str().is_empty() {
+                return Ok(None);
+            }
+            return Ok(Some(Step::Send(gcode)));
+        }
+    };
+    let action = action.split(';').next().unwrap_or("");
+    let mut words = action.split_whitespace();
+    let name = words.next().ok_or(())?;
+    let is = |word: &str| name.eq_ignore_ascii_case(word);
+    let step = if is("set") || is("zero") {
+        let axis = match words.next().ok_or(())? {
+            "X" | "x" => 0,
+            "Y" | "y" => 1,
+            "Z" | "z" => 2,
+            _ => return Err(()),
+        };
+        let value = if is("zero") {
+            0.0
+        } else {
+            words.next().ok_or(())?.parse::<f32>().map_err(|_| ())?
+        };
+        Step::Set(axis, value)
+    } else if is("hold") {
+        Step::Command(Command::FeedHold)
+    } else if is("resume") {
+        Step::Command(Command::Resume)
+    } else if is("reset") {
+        Step::Command(Command::Reset)
+    } else if is("home") {
+        Step::Command(Command::Home)
+    } else if is("unlock") {
+        Step::Command(Command::Unlock)
+    } else {
+        return Err(());
+    };
+    if words.next().is_some() {
+        return Err(());
+    }
+    Ok(Some(step))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A file on the card, a line at a time
+    struct File<'a>(core::str::Lines<'a>);
+
+    impl LineSource for File<'_> {
+        fn next_line(&mut self, line: &mut TextBuffer<LINE_LEN>) -> Read {
+            line.clear();
+            match self.0.next() {
+                Some(text) if line.write_str(text).is_ok() => Read::Line(text.len() as u32 + 1),
+                Some(text) => Read::TooLong(text.len() as u32 + 1),
+                None => Read::End,
+            }
+        }
+    }
+
+    fn load_text(text: &str) -> (Macros, Result<(), Problem>) {
+        let mut macros = Macros::new();
+        let result = load(&mut File(text.lines()), &mut macros);
+        (macros, result)
+    }
+
+    // Runs button `i`, answering every line with ok
+    fn steps(macros: &mut Macros, i: usize) -> Vec<String> {
+        macros.start(i);
+        let mut steps = Vec::new();
+        while let Some(step) = macros.take_step() {
+            steps.push(match step {
+                Step::Send(line) => line.as_str().to_string(),
+                Step::Command(command) => format!("{:?}", command),
+                Step::Set(axis, value) => format!("set {} {}", axis, value),
+                Step::Done => break,
+            });
+            macros.reply(Reply::Ok).unwrap();
+        }
+        steps
+    }
+
+    #[test]
+    fn good_file() {
+        let (mut macros, result) = load_text(
+            "[Park]\n\
+             G53 G0 Z0 ; Up first\n\
+             \n\
+             G53 G0 X0 Y0 (then over)\n\
+             [ Edge X ]\n\
+             @set X 25.4\n\
+             @zero y\n\
+             @HOME\n",
+        );
+        assert_eq!(result, Ok(()));
+        assert_eq!(macros.count(), 2);
+        assert_eq!(macros.label(0), Some("Park"));
+        assert_eq!(macros.label(1), Some("Edge X"));
+        assert_eq!(macros.label(2), None);
+        assert_eq!(steps(&mut macros, 0), ["G53G0Z0", "G53G0X0Y0"]);
+        assert_eq!(steps(&mut macros, 1), ["set 0 25.4", "set 1 0", "Home"]);
+    }
+
+    #[test]
+    fn bad_step() {
+        // The button before the bad line is kept
+        for bad in ["@set Q 1", "@set X", "@set X 1 2", "@jump", "@", "[Other"] {
+            let (macros, result) = load_text(&format!("[Park]\nG0 Z0\n{}\n[Next]\n", bad));
+            assert_eq!(result, Err(Problem::Line(3)), "{}", bad);
+            assert_eq!(macros.count(), 1);
+        }
+        // Nothing to belong to
+        assert_eq!(load_text("G0 Z0\n").1, Err(Problem::Line(1)));
+    }
+
+    #[test]
+    fn long_line() {
+        // Too long to send by MDI once the spaces are gone
+        let long = "G1X1.000Y2.000".repeat(5);
+        assert!(long.len() > MDI_LEN && long.len() <= LINE_LEN);
+        let (_, result) = load_text(&format!("[Cut]\n{}\n", long));
+        assert_eq!(result, Err(Problem::Line(2)));
+
+        // Comments and spaces don't count
+        let padded = format!("G1 X1 Y2 ({})", "x".repeat(MDI_LEN));
+        let (mut macros, result) = load_text(&format!("[Cut]\n{}\n", padded));
+        assert_eq!(result, Ok(()));
+        assert_eq!(steps(&mut macros, 0), ["G1X1Y2"]);
+
+        // Too long to read at all
+        let (_, result) = load_text(&format!("[Cut]\n\n{}\n", "G1".repeat(LINE_LEN)));
+        assert_eq!(result, Err(Problem::Line(3)));
+    }
+}
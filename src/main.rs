@@ -1,7 +1,10 @@
 // #![deny(warnings)]
-#![no_main]
-#![no_std]
+// The tests run on the host, without `main`:
+//   cargo test --target x86_64-unknown-linux-gnu
+#![cfg_attr(not(test), no_main)]
+#![cfg_attr(not(test), no_std)]
 
+#[cfg(not(test))]
 use cortex_m_rt::entry;
 // use embedded_graphics::{
 //     mono_font::MonoTextStyle,
@@ -11,13 +14,20 @@ use cortex_m_rt::entry;
 //     // text::Text,
 // };
 
+#[cfg(not(test))]
 use ft5336::Ft5336;
 
+#[cfg(not(test))]
 #[allow(unused_imports)]
 use panic_semihosting;
 
+#[cfg(not(test))]
 use rtt_target::{rprintln, rtt_init_print};
 
+#[cfg(not(test))]
+use backend::MachineBackend;
+
+#[cfg(not(test))]
 use stm32f7xx_hal::{
     delay::Delay,
     gpio::Speed,
@@ -28,14 +38,73 @@ use stm32f7xx_hal::{
     rcc::{HSEClock, HSEClockMode, Rcc},
 };
 
+#[cfg(any(
+    all(feature = "marlin", feature = "sim"),
+    all(feature = "marlin", feature = "stepper"),
+    all(feature = "sim", feature = "stepper"),
+))]
+compile_error!("Pick one of the marlin, sim and stepper features, or none for GRBL");
+
+mod alarm;
+mod backend;
+#[cfg(not(test))]
+mod clock;
 mod consts;
+mod cycle;
+mod dial;
+#[cfg(not(test))]
 mod display;
+mod encoder;
+mod files;
+mod gcode;
+#[cfg(not(any(feature = "marlin", feature = "sim", feature = "stepper")))]
+mod grbl;
+mod history;
+mod job;
+mod jog;
+mod leadscrew;
+mod limits;
+mod macros;
+#[cfg(feature = "marlin")]
+mod marlin;
+mod mdi;
+#[cfg(feature = "stepper")]
+mod planner;
+mod points;
+mod powerfeed;
+mod probe;
+mod relays;
+mod safety;
+#[cfg(not(test))]
 mod screen;
+mod sdmmc;
+#[cfg(feature = "sim")]
+mod sim;
+#[cfg(feature = "stepper")]
+mod stepper;
+mod storage;
+mod text;
+mod toolpath;
+#[cfg(not(test))]
+mod uart;
 mod ui;
+mod velocity;
+#[cfg(not(test))]
 mod view;
+mod wizard;
 
+#[cfg(not(test))]
 #[entry]
 fn main() -> ! {
+    // Before the main loop's frame goes on the stack, there isn't room
+    // for both
+    let view = view::View::init();
+    run(view)
+}
+
+#[cfg(not(test))]
+#[inline(never)]
+fn run(view: &'static mut view::View) -> ! {
     rtt_init_print!();
 
     let perif = pac::Peripherals::take().unwrap();
@@ -44,9 +113,12 @@ fn main() -> ! {
     let mut rcc_hal: Rcc = perif.RCC.constrain();
 
     // Set up pins
-    let _gpioa = perif.GPIOA.split();
-    let _gpiob = perif.GPIOB.split();
+    let gpioa = perif.GPIOA.split();
+    let gpiob = perif.GPIOB.split();
+    let gpioc = perif.GPIOC.split();
+    let gpiod = perif.GPIOD.split();
     let gpioe = perif.GPIOE.split();
+    let gpiof = perif.GPIOF.split();
     let gpiog = perif.GPIOG.split();
     let gpioh = perif.GPIOH.split();
     let gpioi = perif.GPIOI.split();
@@ -86,6 +158,94 @@ fn main() -> ! {
     gpiok.pk6.into_alternate::<14>().set_speed(Speed::VeryHigh); // LTCD_D7
     gpiok.pk7.into_alternate::<14>().set_speed(Speed::VeryHigh); // LTCD_E
 
+    // Quadrature encoder inputs, see encoder.rs
+    gpioa.pa15.into_alternate::<1>(); // TIM2_CH1, X
+    gpiob.pb3.into_alternate::<1>(); // TIM2_CH2, X
+    gpiob.pb4.into_alternate::<2>(); // TIM3_CH1, Y
+    gpiob.pb5.into_alternate::<2>(); // TIM3_CH2, Y
+    gpioh.ph10.into_alternate::<2>(); // TIM5_CH1, Z, DCMI_D1
+    gpioh.ph11.into_alternate::<2>(); // TIM5_CH2, Z, DCMI_D2
+
+    // Machine controller, see uart.rs
+    let uart_tx = gpioc.pc6.into_alternate::<8>(); // USART6_TX, Arduino D1
+    let uart_rx = gpioc.pc7.into_alternate::<8>(); // USART6_RX, Arduino D0
+
+    // Touch probe, pulled low on contact, see probe.rs
+    gpiog.pg6.into_pull_up_input(); // Arduino D2
+
+    // E-stop, door and fault contacts, open on fault, see safety.rs
+    gpioi.pi0.into_pull_up_input(); // Arduino D5
+    gpioi.pi3.into_pull_up_input(); // Arduino D7
+    gpioi.pi2.into_pull_up_input(); // Arduino D8
+
+    // Power feed run, direction and speed, see powerfeed.rs
+    gpiof.pf7.into_push_pull_output(); // Run, Arduino A4
+    gpiof.pf6.into_push_pull_output(); // Dir, Arduino A5
+    gpiob.pb9.into_alternate::<3>(); // TIM11_CH1 speed, Arduino D14
+
+    // Soft limit relay, on once a limit is crossed, see limits.rs. The
+    // Arduino header is full, so it's DCMI_D0 on the camera connector.
+    let mut limit_relay = gpioh.ph9.into_push_pull_output();
+
+    // Coolant, vacuum and spindle relays, in relays::OUTPUTS order, also on
+    // the camera connector, see relays.rs
+    let mut relay_pins = [
+        gpioa.pa4.into_push_pull_output().erase(),  // DCMI_HSYNC
+        gpioa.pa6.into_push_pull_output().erase(),  // DCMI_PIXCLK
+        gpioh.ph12.into_push_pull_output().erase(), // DCMI_D3
+    ];
+
+    // Lathe spindle encoder and leadscrew stepper, on the camera connector
+    // too, see leadscrew.rs
+    gpioi.pi5.into_alternate::<3>(); // TIM8_CH1 spindle A, DCMI_VSYNC
+    gpioi.pi6.into_alternate::<3>(); // TIM8_CH2 spindle B, DCMI_D6
+    gpioi.pi7.into_alternate::<3>(); // TIM8_CH3 spindle index, DCMI_D7
+    gpioh.ph14.into_push_pull_output(); // Step, DCMI_D4
+    gpioi.pi4.into_push_pull_output(); // Dir, DCMI_D5
+
+    // Stepper drivers and limit switches, see stepper.rs
+    #[cfg(feature = "stepper")]
+    {
+        gpiog.pg7.into_push_pull_output(); // X step, Arduino D4
+        gpioh.ph6.into_push_pull_output(); // X dir, Arduino D6
+        gpioa.pa8.into_push_pull_output(); // Y step, Arduino D10
+        gpiob.pb15.into_push_pull_output(); // Y dir, Arduino D11
+        gpiob.pb14.into_push_pull_output(); // Z step, Arduino D12
+        gpioi.pi1.into_push_pull_output(); // Z dir, Arduino D13
+        gpiob.pb8.into_push_pull_output().set_high(); // Enable, Arduino D15
+        gpiof.pf10.into_pull_up_input(); // X limit, Arduino A1
+        gpiof.pf9.into_pull_up_input(); // Y limit, Arduino A2
+        gpiof.pf8.into_pull_up_input(); // Z limit, Arduino A3
+    }
+
+    // SD card slot, see sdmmc.rs
+    gpioc
+        .pc8
+        .into_alternate::<12>()
+        .internal_pull_up(true)
+        .set_speed(Speed::VeryHigh); // SDMMC1_D0
+    gpioc
+        .pc9
+        .into_alternate::<12>()
+        .internal_pull_up(true)
+        .set_speed(Speed::VeryHigh); // SDMMC1_D1
+    gpioc
+        .pc10
+        .into_alternate::<12>()
+        .internal_pull_up(true)
+        .set_speed(Speed::VeryHigh); // SDMMC1_D2
+    gpioc
+        .pc11
+        .into_alternate::<12>()
+        .internal_pull_up(true)
+        .set_speed(Speed::VeryHigh); // SDMMC1_D3
+    gpioc.pc12.into_alternate::<12>().set_speed(Speed::VeryHigh); // SDMMC1_CK
+    gpiod
+        .pd2
+        .into_alternate::<12>()
+        .internal_pull_up(true)
+        .set_speed(Speed::VeryHigh); // SDMMC1_CMD
+
     // HSE osc out in High Z
     gpioh.ph1.into_floating_input();
     let clocks = rcc_hal
@@ -93,8 +253,10 @@ fn main() -> ! {
         .hse(HSEClock::new(25_000_000.Hz(), HSEClockMode::Bypass))
         .sysclk(216_000_000.Hz())
         .hclk(216_000_000.Hz())
+        .use_pll48clk() // For the SD card
         .freeze();
     let mut delay = Delay::new(cp.SYST, clocks);
+    let mut clock = clock::Clock::new(cp.DCB, cp.DWT, clocks.sysclk().0);
 
     rprintln!("Connecting to I2c");
 
@@ -132,23 +294,286 @@ fn main() -> ! {
     );
 
     let update = ui::Update::new();
-    let view = &mut view::View::new();
     let state = ui::State::new();
+    let mut storage = storage::Storage::new(storage::FlashSector::new(perif.FLASH));
+    if let Some(data) = storage.load() {
+        view.load(data);
+    }
     view.fill();
     // rprintln!("view filled");
 
     view.update(&mut display);
 
+    let mut encoders = encoder::Encoders::new(perif.TIM2, perif.TIM3, perif.TIM5);
+    // The probe and the safety inputs each set up their own EXTI lines
+    let (exti, syscfg) = (perif.EXTI, perif.SYSCFG);
+    let mut probe = probe::Probe::new(&exti, &syscfg);
+    let mut safety = safety::Safety::new(&exti, &syscfg);
+    let mut power_feed = powerfeed::Drive::new(perif.TIM11, &clocks);
+    let mut leadscrew = leadscrew::Drive::new(perif.TIM6, perif.TIM8, &clocks);
+
+    // The controller is picked with a cargo feature, GRBL by default
+    let uart = uart::Uart::new(
+        perif.USART6,
+        (uart_tx, uart_rx),
+        clocks,
+        consts::MACHINE_BAUD,
+    );
+    #[cfg(not(any(feature = "marlin", feature = "sim", feature = "stepper")))]
+    let mut machine =
+        grbl::GrblBackend::new(uart, consts::MACHINE_POLL_MS, consts::MACHINE_TIMEOUT_MS);
+    #[cfg(feature = "marlin")]
+    let mut machine =
+        marlin::MarlinBackend::new(uart, consts::MACHINE_POLL_MS, consts::MACHINE_TIMEOUT_MS);
+    #[cfg(feature = "sim")]
+    let mut machine = {
+        drop(uart);
+        sim::SimBackend::new(consts::MACHINE_POLL_MS)
+    };
+    #[cfg(feature = "stepper")]
+    let mut machine = {
+        drop(uart);
+        let mut machine =
+            stepper::StepperBackend::new(perif.TIM7, &clocks, consts::MACHINE_POLL_MS);
+        if let Some(data) = storage.load() {
+            machine.load(data);
+        }
+        machine
+    };
+    // Without a card reader, the files page just says so
+    let mut files = match sdmmc::SdCard::new(perif.SDMMC1, &clocks) {
+        Ok(card) => Some(files::SdFiles::new(card)),
+        Err(e) => {
+            rprintln!("No SD card reader: {:?}", e);
+            None
+        }
+    };
+    let mut job: Option<job::Job> = None;
+    // A wizard's program the job streams from, instead of the open file
+    let mut program: Option<wizard::Program> = None;
+
+    let mut feed = velocity::FeedRate::new(
+        consts::FEED_SAMPLE_MS,
+        consts::FEED_FILTER_ALPHA,
+        consts::ENCODER_MM_PER_COUNT,
+    );
+    let mut last_ms = clock.now_ms();
+
     let mut touch = Ft5336::new(&i2c, 0x38, &mut delay).unwrap();
+    let mut touch_events = ui::TouchEvents::new();
     // let mut msg : &view::Button;/
     loop {
+        let now = clock.now_ms();
+        let counts = encoders.counts();
+
+        // A machine controller, when there is one, is the source of position
+        if let Some(status) = machine.poll(now) {
+            view.set_machine_status(&status, &mut display);
+        }
+        if !machine.connected(now) {
+            view.set_disconnected(&mut display);
+            view.set_machine_position(encoder::counts_to_mm(counts), &mut display);
+        }
+        if let Some(raw) = probe.take_contact() {
+            let latched = encoder::counts_to_mm(encoders.extend(raw));
+            view.probe_contact(latched, &mut display);
+        }
+        if feed.update(counts, now.wrapping_sub(last_ms)) {
+            view.set_feed(&feed, &mut display);
+        }
+        last_ms = now;
+
+        let dirty = view.take_dirty();
+        #[cfg(feature = "stepper")]
+        let dirty = machine.take_dirty() || dirty;
+        if dirty {
+            let saved = storage.save_sections(|writer| {
+                let saved = view.save(writer);
+                #[cfg(feature = "stepper")]
+                let saved = saved.and_then(|_| machine.save(writer));
+                saved
+            });
+            if let Err(e) = saved {
+                rprintln!("Saving settings failed: {:?}", e);
+            }
+        }
+
+        let streaming = job.as_ref().is_some_and(|j| j.state().active());
+
+        // A new trip stops or holds the machine before anything else is sent
+        let tripped = view.set_interlocks(safety.active(), safety.take_tripped(), &mut display);
+        if tripped.stops() {
+            match &mut job {
+                Some(j) if streaming => j.stop(&mut machine, now),
+                _ => machine.reset(),
+            }
+        } else if tripped.any() {
+            match &mut job {
+                Some(j) if streaming => j.hold(&mut machine, now),
+                _ => machine.feed_hold(),
+            }
+        }
+        // The power feed follows the X scale, whatever the controller says
+        let x = encoder::counts_to_mm(counts)[0];
+        let cut = power_feed.take_cut();
+        power_feed.set(view.update_power_feed(x, cut, &mut display));
+        view.update_dial(leadscrew.spindle(), x, now, &mut display);
+        // The leadscrew follows the spindle by itself, once started
+        if let Some(request) = view.update_leadscrew(leadscrew.status(), &mut display) {
+            leadscrew.request(request);
+        }
+        let relays = view.update_relays(now, &mut display);
+        for (pin, on) in relay_pins.iter_mut().zip(relays.iter()) {
+            if *on {
+                pin.set_high();
+            } else {
+                pin.set_low();
+            }
+        }
+        if view.limit_relay() {
+            limit_relay.set_high();
+        } else {
+            limit_relay.set_low();
+        }
+
+        let faulted = view.faulted();
+        match (&mut job, &mut program, &mut files) {
+            (Some(j), Some(p), _) if streaming => j.service(p, &mut machine, now),
+            (Some(j), None, Some(f)) if streaming => j.service(f, &mut machine, now),
+            // Only MDI lines are answered when there's no job
+            _ => {
+                while let Some(reply) = machine.take_reply() {
+                    view.machine_reply(reply, &mut display);
+                }
+            }
+        }
+        while let Some(message) = machine.take_message() {
+            view.machine_message(&message, &mut display);
+        }
+        if let Some(j) = &job {
+            if !j.state().active() {
+                if let Some(f) = &mut files {
+                    f.close();
+                }
+            }
+            view.set_job(Some(j.progress(now)), &mut display);
+        }
+
+        while let Some(command) = view.take_command(now) {
+            // Lines sent in the middle of a job would throw the counting out
+            let allowed = match command {
+                // Only ever stopping while a safety input is latched
+                _ if faulted => {
+                    matches!(
+                        command,
+                        backend::Command::FeedHold | backend::Command::Reset
+                    )
+                }
+                _ => !streaming || command.is_realtime(),
+            };
+            if allowed {
+                machine.send(&command);
+            }
+        }
+
+        match view.take_job_request() {
+            Some(job::Request::ListFiles) => {
+                let mut names = files::FileNames::new();
+                let found = match &mut files {
+                    Some(f) => f.list(&mut names).is_ok(),
+                    None => false,
+                };
+                view.set_files(if found { Some(names) } else { None }, &mut display);
+            }
+            // Nothing starts or carries on while a safety input is latched
+            Some(job::Request::Start(_) | job::Request::RunProgram(_) | job::Request::Resume)
+                if faulted => {}
+            Some(job::Request::Start(name)) if !streaming => {
+                program = None;
+                match files.as_mut().map(|f| f.open(name.as_str())) {
+                    Some(Ok(size)) => job::Job::start(&mut job, size, now),
+                    other => {
+                        rprintln!("Can't open {}: {:?}", name.as_str(), other);
+                        job = None;
+                        view.set_job(None, &mut display);
+                    }
+                }
+            }
+            Some(job::Request::Plot(name)) if !streaming => {
+                let path = view.toolpath_mut();
+                let loaded = match &mut files {
+                    Some(f) => match f.open(name.as_str()) {
+                        Ok(_) => {
+                            toolpath::load(f, path);
+                            f.close();
+                            true
+                        }
+                        Err(e) => {
+                            rprintln!("Can't open {}: {:?}", name.as_str(), e);
+                            false
+                        }
+                    },
+                    None => false,
+                };
+                view.toolpath_loaded(loaded, &mut display);
+            }
+            Some(job::Request::RunProgram(p)) if !streaming => {
+                program = Some(p);
+                job::Job::start(&mut job, p.size(), now);
+            }
+            Some(job::Request::PlotProgram(mut p)) => {
+                toolpath::load(&mut p, view.toolpath_mut());
+                view.toolpath_loaded(true, &mut display);
+            }
+            Some(job::Request::SaveProgram(mut p)) => {
+                // The card is busy while a file is streaming
+                let saved = match &mut files {
+                    Some(f) if !streaming => f.save(p.operation().file_name(), &mut p).is_ok(),
+                    _ => false,
+                };
+                view.program_saved(saved, &mut display);
+            }
+            // The card is busy while a file is streaming, so the buttons
+            // stay as they were
+            Some(job::Request::LoadMacros) if !streaming => {
+                let result = match &mut files {
+                    Some(f) => match f.open(macros::FILE_NAME) {
+                        Ok(_) => {
+                            let result = macros::load(f, view.macros_mut());
+                            f.close();
+                            result
+                        }
+                        Err(_) => Err(macros::Problem::NoFile),
+                    },
+                    None => Err(macros::Problem::NoFile),
+                };
+                view.macros_loaded(result, &mut display);
+            }
+            Some(job::Request::Hold) => match &mut job {
+                Some(j) if streaming => j.hold(&mut machine, now),
+                _ => machine.feed_hold(),
+            },
+            Some(job::Request::Resume) => match &mut job {
+                Some(j) if streaming => j.resume(&mut machine, now),
+                _ => machine.resume(),
+            },
+            Some(job::Request::Stop) => match &mut job {
+                Some(j) if streaming => j.stop(&mut machine, now),
+                _ => machine.reset(),
+            },
+            _ => (),
+        }
+
         // rprintln!("1: {:?}", view.active_id);
         let n = touch.detect_touch(&mut i2c).unwrap();
-        if n == 0 {
-            view.process_button(None, &mut display);
-        } else {
-            let t = touch.get_touch(&mut i2c, 1).unwrap();
-            view.process_button(view.button_id_from_coords(t.y, t.x), &mut display);
+        // The panel's x and y run the other way to the screen's
+        let mut points = [None; 2];
+        for (i, point) in points.iter_mut().enumerate().take(n as usize) {
+            let t = touch.get_touch(&mut i2c, i as u8 + 1).unwrap();
+            *point = Some((t.y, t.x));
+        }
+        if let Some(event) = touch_events.update(points[0], points[1]) {
+            view.touch(event, &mut display);
         }
         // else {
         //     msg = ui::Messages::None;
@@ -0,0 +1,267 @@
+//! Marlin backend.
+//!
+//! Marlin has no status report like GRBL's, so the position is polled with
+//! M114, which answers
+//!
+//!   X:10.00 Y:0.00 Z:5.00 E:0.00 Count X:800 Y:0 Z:2000
+//!
+//! G92 moves Marlin's logical coordinates rather than keeping a separate work
+//! offset, so machine and work position are the same thing here. Marlin
+//! also has no realtime commands: jog cancel uses M410 quick stop and reset
+//! uses M112 emergency stop, which only act at once if Marlin was built
+//! with EMERGENCY_PARSER. M112 kills Marlin until it is restarted. A feed
+//! hold just stops sending lines, so the move Marlin already has finishes.
+//! The feed override is M220, which isn't reported back, and there are no
+//! rapid or spindle overrides.
+//!
+//! Marlin answers each line with `ok` once it has taken it, and has no
+//! fixed receive buffer to count characters against, so programs go one
+//! line at a time. Other lines, like `echo:` and error text, are kept as
+//! messages for the MDI page.
+//!
+//! Nothing in here touches hardware, bytes go through a `Port`.
+
+use core::fmt::Write;
+
+use crate::backend::{
+    message, word, Accessories, MachineBackend, Message, Messages, Override, Overrides, Port,
+    Replies, Reply, Status,
+};
+use crate::jog::JogCommand;
+use crate::text::TextBuffer;
+use crate::ui;
+
+const LINE_LEN: usize = 96;
+const AXIS_NAMES: [char; 3] = ['X', 'Y', 'Z'];
+const BUSY_MS: u32 = 3000; // Marlin sends busy every 2s by default
+
+/// Parses the position from an M114 reply, returning None for any other line
+pub fn parse_position(line: &str) -> Option<[f32; 3]> {
+    // Everything after "Count" is stepper counts, not mm
+    let line = match line.find("Count") {
+        Some(i) => &line[..i],
+        None => line,
+    };
+    if !line.starts_with("X:") {
+        return None;
+    }
+    Some([word(line, 'X')?, word(line, 'Y')?, word(line, 'Z')?])
+}
+
+pub struct MarlinBackend<P: Port> {
+    port: P,
+    line: TextBuffer<LINE_LEN>,
+    feed: f32,
+    overrides: Overrides, // As last set, Marlin doesn't report them
+    last_poll_ms: u32,
+    last_report_ms: Option<u32>,
+    busy_until_ms: u32,
+    replies: Replies,
+    messages: Messages,
+    after_error: bool, // Marlin usually follows an error with an ok too
+    poll_ms: u32,
+    timeout_ms: u32,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl<P: Port> MarlinBackend<P> {
+    /// Polls every `poll_ms`, and counts as disconnected after `timeout_ms`
+    /// without a reply.
+    pub fn new(port: P, poll_ms: u32, timeout_ms: u32) -> MarlinBackend<P> {
+        MarlinBackend {
+            port,
+            line: TextBuffer::new(),
+            feed: 0.0,
+            overrides: Overrides::new(),
+            last_poll_ms: 0,
+            last_report_ms: None,
+            busy_until_ms: 0,
+            replies: Replies::new(),
+            messages: Messages::new(),
+            after_error: false,
+            poll_ms,
+            timeout_ms,
+        }
+    }
+
+    fn send_line(&mut self, line: &str) {
+        self.port.write(line.as_bytes());
+        self.port.write(b"\n");
+        self.replies.sent(false);
+    }
+
+    // Acts on one line from Marlin
+    fn receive_line(&mut self, now_ms: u32) -> Option<Status> {
+        let line = self.line.as_str().trim();
+        if line.starts_with("echo:busy") {
+            // Sent every couple of seconds while a long move or wait runs
+            self.busy_until_ms = now_ms.wrapping_add(BUSY_MS);
+            return None;
+        }
+        if line.starts_with("ok") {
+            if !self.after_error {
+                self.replies.answered(Reply::Ok);
+            }
+            self.after_error = false;
+            return None;
+        }
+        if line.starts_with("Error:") {
+            // No error numbers, just text
+            self.replies.answered(Reply::Error(0));
+            self.after_error = true;
+            if let Some(message) = message(line) {
+                self.messages.push(message);
+            }
+            return None;
+        }
+        let position = match parse_position(line) {
+            Some(position) => position,
+            None => {
+                if let Some(message) = message(line) {
+                    self.messages.push(message);
+                }
+                return None;
+            }
+        };
+        self.last_report_ms = Some(now_ms);
+
+        let busy = (self.busy_until_ms.wrapping_sub(now_ms) as i32) > 0;
+        Some(Status {
+            running: if busy {
+                ui::Running::Yes
+            } else {
+                ui::Running::No
+            },
+            mpos: position,
+            wpos: position,
+            feed: self.feed,
+            overrides: self.overrides,
+            accessories: Accessories::new(),
+            alarm: None,
+            spindle: 0.0,
+            // G92 already moved the reported position, the UI's offsets
+            // must stay at zero
+            new_offset: true,
+        })
+    }
+}
+
+impl<P: Port> MachineBackend for MarlinBackend<P> {
+    fn poll(&mut self, now_ms: u32) -> Option<Status> {
+        if now_ms.wrapping_sub(self.last_poll_ms) >= self.poll_ms {
+            self.last_poll_ms = now_ms;
+            self.send_line("M114");
+        }
+        let mut status = None;
+        while let Some(byte) = self.port.read() {
+            match byte {
+                b'\r' => (),
+                b'\n' => {
+                    if let Some(s) = self.receive_line(now_ms) {
+                        status = Some(s);
+                    }
+                    self.line.clear();
+                }
+                _ => {
+                    // Too long for us means not a position report
+                    self.line.write_char(byte as char).ok();
+                }
+            }
+        }
+        status
+    }
+
+    fn connected(&self, now_ms: u32) -> bool {
+        match self.last_report_ms {
+            Some(t) => now_ms.wrapping_sub(t) < self.timeout_ms,
+            None => false,
+        }
+    }
+
+    fn jog(&mut self, command: JogCommand) {
+        match command {
+            JogCommand::Move {
+                axis,
+                distance,
+                feed,
+            } => {
+                self.feed = feed;
+                let mut line: TextBuffer<48> = TextBuffer::new();
+                if write!(line, "G0{}{:.3}F{:.0}", AXIS_NAMES[axis], distance, feed).is_ok() {
+                    self.send_line("G91");
+                    self.send_line(line.as_str());
+                    self.send_line("G90");
+                }
+            }
+            JogCommand::Cancel => self.send_line("M410"),
+        }
+    }
+
+    fn set_work(&mut self, axis: usize, value: f32) {
+        let mut line: TextBuffer<32> = TextBuffer::new();
+        if write!(line, "G92{}{:.3}", AXIS_NAMES[axis], value).is_ok() {
+            self.send_line(line.as_str());
+        }
+    }
+
+    fn feed_hold(&mut self) {
+        // Holding is up to the job, which stops sending. M410 would throw
+        // away the queued moves and lose the program's place.
+    }
+
+    fn resume(&mut self) {
+        // The job starts sending again
+    }
+
+    fn reset(&mut self) {
+        // The nearest thing to a GRBL soft reset that also stops at once
+        self.send_line("M112");
+        self.replies.clear();
+    }
+
+    fn home(&mut self) {
+        self.send_line("G28");
+    }
+
+    // Marlin has no alarm lock, the nearest thing is a halt after an error
+    fn unlock(&mut self) {
+        self.send_line("M999");
+    }
+
+    fn set_override(&mut self, value: Override) {
+        if let Override::Feed(percent) = value {
+            let mut line: TextBuffer<16> = TextBuffer::new();
+            if write!(line, "M220S{}", percent).is_ok() {
+                self.send_line(line.as_str());
+                value.apply(&mut self.overrides);
+            }
+        }
+    }
+
+    fn mdi(&mut self, line: &str) {
+        if let Some(feed) = word(line, 'F') {
+            self.feed = feed;
+        }
+        self.port.write(line.as_bytes());
+        self.port.write(b"\n");
+        self.replies.sent(true);
+    }
+
+    fn stream(&mut self, line: &str) {
+        self.port.write(line.as_bytes());
+        self.port.write(b"\n");
+        self.replies.sent(true);
+    }
+
+    fn take_reply(&mut self) -> Option<Reply> {
+        self.replies.take()
+    }
+
+    fn take_message(&mut self) -> Option<Message> {
+        self.messages.pop()
+    }
+
+    fn rx_buffer(&self) -> usize {
+        0
+    }
+}
@@ -0,0 +1,275 @@
+//! Manual data input: the line being typed, lines typed before it, and a
+//! scrollback of what went to the controller and what came back.
+//!
+//! Nothing here touches the display or the controller. The view feeds in
+//! keys and answers, and sends whatever `take_line` gives it.
+
+use core::fmt::Write;
+
+use crate::backend::{Message, Reply, MDI_LEN, MESSAGE_LEN};
+use crate::text::TextBuffer;
+
+const RECALL_LEN: usize = 8;
+pub const SCROLLBACK_LEN: usize = 5;
+
+pub type Line = TextBuffer<MDI_LEN>;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum EntryKind {
+    Sent,
+    Ok,
+    Error,
+    Message,
+}
+
+/// One line of the scrollback
+#[derive(Copy, Clone, Debug)]
+pub struct Entry {
+    pub kind: EntryKind,
+    pub text: TextBuffer<MESSAGE_LEN>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Mdi {
+    line: Line,
+    recall: [Line; RECALL_LEN], // Oldest first
+    recall_len: usize,
+    recalling: Option<usize>, // Which of recall is in line
+    scrollback: [Entry; SCROLLBACK_LEN],
+    scroll_head: usize,
+    scroll_len: usize,
+}
+
+impl Mdi {
+    pub fn new() -> Mdi {
+        Mdi {
+            line: Line::new(),
+            recall: [Line::new(); RECALL_LEN],
+            recall_len: 0,
+            recalling: None,
+            scrollback: [Entry {
+                kind: EntryKind::Message,
+                text: TextBuffer::new(),
+            }; SCROLLBACK_LEN],
+            scroll_head: 0,
+            scroll_len: 0,
+        }
+    }
+
+    pub fn line(&self) -> &str {
+        self.line.as_str()
+    }
+
+    /// Adds a character to the line, if there's room
+    pub fn type_char(&mut self, c: char) {
+        self.line.write_char(c).ok();
+        self.recalling = None;
+    }
+
+    pub fn backspace(&mut self) {
+        let kept = self.line.as_str().len().saturating_sub(1);
+        let mut line = Line::new();
+        line.write_str(&self.line.as_str()[..kept]).ok();
+        self.line = line;
+        self.recalling = None;
+    }
+
+    pub fn clear(&mut self) {
+        self.line.clear();
+        self.recalling = None;
+    }
+
+    /// Replaces the line with an older (negative) or newer one from those
+    /// sent before. Going past the newest leaves an empty line.
+    pub fn recall(&mut self, by: i32) {
+        if self.recall_len == 0 {
+            return;
+        }
+        let newest = self.recall_len as i32 - 1;
+        let i = match self.recalling {
+            Some(i) => i as i32 + by,
+            None if by < 0 => newest + 1 + by,
+            None => return,
+        };
+        if i > newest {
+            self.line.clear();
+            self.recalling = None;
+        } else {
+            let i = i.max(0) as usize;
+            self.line = self.recall[i];
+            self.recalling = Some(i);
+        }
+    }
+
+    /// Takes the line to send, remembering it for recall and the scrollback
+    pub fn take_line(&mut self) -> Option<Line> {
+        let line = self.line;
+        if line.as_str().trim().is_empty() {
+            return None;
+        }
+        // Sending a recalled line again doesn't need it twice
+        let newest = self.recall_len.checked_sub(1).map(|i| self.recall[i]);
+        if newest != Some(line) {
+            if self.recall_len == RECALL_LEN {
+                self.recall.copy_within(1.., 0);
+                self.recall_len -= 1;
+            }
+            self.recall[self.recall_len] = line;
+            self.recall_len += 1;
+        }
+        self.log(EntryKind::Sent, line.as_str());
+        self.clear();
+        Some(line)
+    }
+
+    /// Adds the controller's answer to a line to the scrollback
+    pub fn reply(&mut self, reply: Reply) {
+        let mut text: TextBuffer<16> = TextBuffer::new();
+        match reply {
+            Reply::Ok => self.log(EntryKind::Ok, "ok"),
+            Reply::Error(code) => {
+                write!(text, "error:{}", code).ok();
+                self.log(EntryKind::Error, text.as_str());
+            }
+        }
+    }
+
+    pub fn message(&mut self, message: &Message) {
+        self.log(EntryKind::Message, message.as_str());
+    }
+
+    /// Adds a line to the scrollback, cut short if it doesn't fit
+    pub fn log(&mut self, kind: EntryKind, text: &str) {
+        if self.scroll_len == SCROLLBACK_LEN {
+            self.scroll_head = (self.scroll_head + 1) % SCROLLBACK_LEN;
+            self.scroll_len -= 1;
+        }
+        let entry = &mut self.scrollback[(self.scroll_head + self.scroll_len) % SCROLLBACK_LEN];
+        entry.kind = kind;
+        entry.text.clear();
+        for c in text.chars() {
+            if entry.text.write_char(c).is_err() {
+                break;
+            }
+        }
+        self.scroll_len += 1;
+    }
+
+    /// Scrollback entries, oldest first
+    pub fn entry(&self, i: usize) -> Option<&Entry> {
+        if i < self.scroll_len {
+            Some(&self.scrollback[(self.scroll_head + i) % SCROLLBACK_LEN])
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_line(mdi: &mut Mdi, text: &str) -> Option<Line> {
+        text.chars().for_each(|c| mdi.type_char(c));
+        mdi.take_line()
+    }
+
+    fn entries(mdi: &Mdi) -> Vec<(EntryKind, String)> {
+        (0..SCROLLBACK_LEN)
+            .map_while(|i| mdi.entry(i))
+            .map(|entry| (entry.kind, entry.text.as_str().to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn typing() {
+        let mut mdi = Mdi::new();
+        type_line(&mut mdi, "G0X1");
+        assert_eq!(mdi.line(), ""); // Taken
+        "G1X".chars().for_each(|c| mdi.type_char(c));
+        mdi.backspace();
+        assert_eq!(mdi.line(), "G1");
+        mdi.clear();
+        mdi.backspace(); // Nothing to take back
+        assert_eq!(mdi.line(), "");
+        // Blank lines aren't sent
+        assert_eq!(type_line(&mut mdi, "  "), None);
+        // A full line takes no more
+        let long = "X".repeat(MDI_LEN + 5);
+        assert_eq!(type_line(&mut mdi, &long).unwrap().as_str().len(), MDI_LEN);
+    }
+
+    #[test]
+    fn recall() {
+        let mut mdi = Mdi::new();
+        mdi.recall(-1); // Nothing sent yet
+        assert_eq!(mdi.line(), "");
+        for line in ["G0X1", "G0X2", "G0X2", "$H"] {
+            type_line(&mut mdi, line);
+        }
+        // Newest first, the repeat kept once, stopping at the oldest
+        let mut older = Vec::new();
+        for _ in 0..4 {
+            mdi.recall(-1);
+            older.push(mdi.line().to_string());
+        }
+        assert_eq!(older, ["$H", "G0X2", "G0X1", "G0X1"]);
+        // Back down past the newest leaves an empty line
+        mdi.recall(1);
+        assert_eq!(mdi.line(), "G0X2");
+        mdi.recall(2);
+        assert_eq!(mdi.line(), "");
+        mdi.recall(1); // Not recalling, so nothing newer
+        assert_eq!(mdi.line(), "");
+
+        // Sending a recalled line again doesn't add it twice
+        mdi.recall(-1);
+        assert_eq!(mdi.take_line().unwrap().as_str(), "$H");
+        mdi.recall(-2);
+        assert_eq!(mdi.line(), "G0X2");
+        // Typing stops the recall, so the next one starts from the newest
+        mdi.type_char('0');
+        mdi.recall(-1);
+        assert_eq!(mdi.line(), "$H");
+    }
+
+    #[test]
+    fn recall_keeps_the_newest() {
+        let mut mdi = Mdi::new();
+        for i in 0..RECALL_LEN + 2 {
+            type_line(&mut mdi, &format!("G0X{}", i));
+        }
+        mdi.recall(-(RECALL_LEN as i32 + 5));
+        assert_eq!(mdi.line(), "G0X2"); // The first two went
+    }
+
+    #[test]
+    fn scrollback() {
+        let mut mdi = Mdi::new();
+        assert!(mdi.entry(0).is_none());
+        type_line(&mut mdi, "G0X1");
+        mdi.reply(Reply::Ok);
+        type_line(&mut mdi, "G5");
+        mdi.reply(Reply::Error(20));
+        assert_eq!(
+            entries(&mdi),
+            [
+                (EntryKind::Sent, "G0X1".to_string()),
+                (EntryKind::Ok, "ok".to_string()),
+                (EntryKind::Sent, "G5".to_string()),
+                (EntryKind::Error, "error:20".to_string()),
+            ]
+        );
+
+        // The oldest scroll off, and long messages are cut short
+        let mut message = Message::new();
+        message.write_str(&"m".repeat(MESSAGE_LEN)).unwrap();
+        mdi.message(&message);
+        mdi.log(EntryKind::Error, &"e".repeat(MESSAGE_LEN + 10));
+        let entries = entries(&mdi);
+        assert_eq!(entries.len(), SCROLLBACK_LEN);
+        assert_eq!(entries[0], (EntryKind::Ok, "ok".to_string()));
+        assert_eq!(entries[3], (EntryKind::Message, "m".repeat(MESSAGE_LEN)));
+        assert_eq!(entries[4], (EntryKind::Error, "e".repeat(MESSAGE_LEN)));
+    }
+}
@@ -0,0 +1,1067 @@
+//! Motion planning and step generation for driving stepper motors straight
+//! from the board, see stepper.rs for the pins and timer.
+//!
+//! Moves go into a `Planner` as blocks of whole steps. Each block gets the
+//! fastest speed it may enter at: no faster than the corner with the last
+//! block allows (GRBL's junction deviation), and no faster than it could
+//! still stop from by the end of the queue. That is worked out again over
+//! the whole queue every time a block is added, so the look-ahead is as
+//! long as the queue.
+//!
+//! `Motion::tick` runs at `TICK_HZ` from the timer interrupt. Every
+//! `UPDATE_TICKS` it picks a new speed: up at the block's acceleration
+//! towards its feed, but never faster than it can brake from to the next
+//! block's entry speed in the distance left. That gives trapezoidal
+//! profiles; there is no S-curve. Between updates a DDA steps the longest
+//! axis at that rate, and Bresenham steps the others along with it.
+//!
+//! A step pulse lasts one tick, so an axis steps at most every other tick.
+//! A block that changes direction spends its first tick only setting the
+//! direction pins, which gives the drivers their setup time.
+//!
+//! Nothing here touches hardware, so the same steps come out for the same
+//! moves and ticks wherever it runs.
+
+#[cfg(not(test))]
+use micromath::F32Ext;
+
+use crate::alarm::Alarm;
+
+pub const TICK_HZ: u32 = 40_000;
+const UPDATE_TICKS: u32 = 40; // A new speed every millisecond
+const BLOCKS: usize = 16;
+const ONE: u32 = 1 << 24; // DDA fixed point: one step per tick
+const MAX_RATE: u32 = ONE / 2; // One step every other tick
+const MIN_SPEED: f32 = 0.5; // mm/s, so a block braking to a stop ends
+const HOLD_SPEED: f32 = 0.05; // mm/s, slow enough to call stopped
+const HOMING_ORDER: [usize; 3] = [2, 0, 1]; // Z clear first
+pub const N_SETTINGS: usize = 15;
+
+/// Limits of one axis
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Axis {
+    pub steps_per_mm: f32,
+    pub max_rate: f32, // mm/min
+    pub accel: f32,    // mm/s²
+    pub travel: f32,   // mm, the most homing will go looking for a switch
+}
+
+/// Settings, numbered like GRBL's for `$N=value`
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Config {
+    pub axes: [Axis; 3],
+    pub junction_deviation: f32, // $11, mm
+    pub homing_feed: f32,        // $25, mm/min
+    pub pull_off: f32,           // $27, mm
+}
+
+impl Config {
+    pub fn new() -> Config {
+        let axis = Axis {
+            steps_per_mm: 400.0,
+            max_rate: 1500.0,
+            accel: 100.0,
+            travel: 200.0,
+        };
+        let mut axes = [axis; 3];
+        axes[2].max_rate = 600.0;
+        axes[2].travel = 80.0;
+        Config {
+            axes,
+            junction_deviation: 0.01,
+            homing_feed: 300.0,
+            pull_off: 1.0,
+        }
+    }
+
+    // Where setting `n` lives
+    fn setting(&mut self, n: u16) -> Option<&mut f32> {
+        let axis = (n % 10) as usize;
+        match n {
+            11 => Some(&mut self.junction_deviation),
+            25 => Some(&mut self.homing_feed),
+            27 => Some(&mut self.pull_off),
+            100..=102 => Some(&mut self.axes[axis].steps_per_mm),
+            110..=112 => Some(&mut self.axes[axis].max_rate),
+            120..=122 => Some(&mut self.axes[axis].accel),
+            130..=132 => Some(&mut self.axes[axis].travel),
+            _ => None,
+        }
+    }
+
+    /// The numbers of every setting, in order
+    pub fn numbers() -> [u16; N_SETTINGS] {
+        [
+            11, 25, 27, 100, 101, 102, 110, 111, 112, 120, 121, 122, 130, 131, 132,
+        ]
+    }
+
+    pub fn get(&self, n: u16) -> Option<f32> {
+        let mut config = *self;
+        config.setting(n).copied()
+    }
+
+    /// Changes a setting. Returns false if there's no such setting, or the
+    /// value makes no sense for it.
+    pub fn set(&mut self, n: u16, value: f32) -> bool {
+        let positive = value > 0.0 || (value == 0.0 && n == 27);
+        match self.setting(n) {
+            Some(setting) if positive => {
+                *setting = value;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Writes the settings for storage, returning the bytes used
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn write_bytes(&self, buf: &mut [u8]) -> Option<usize> {
+        let bytes = buf.get_mut(..N_SETTINGS * 4)?;
+        for (chunk, n) in bytes.chunks_exact_mut(4).zip(Config::numbers()) {
+            chunk.copy_from_slice(&self.get(n).unwrap_or(0.0).to_le_bytes());
+        }
+        Some(N_SETTINGS * 4)
+    }
+
+    /// Reads back what `write_bytes` wrote, ignoring values that make no
+    /// sense
+    pub fn read_bytes(&mut self, buf: &[u8]) {
+        for (chunk, n) in buf.chunks_exact(4).zip(Config::numbers()) {
+            self.set(
+                n,
+                f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]),
+            );
+        }
+    }
+}
+
+/// What a block is for, which decides the override that applies to it
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Kind {
+    Feed,
+    Rapid,
+    Jog,
+    // Looking for the switch of an axis, or backing off it
+    Seek(usize),
+    PullOff(usize),
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Block {
+    steps: [i32; 3],
+    count: u32,     // Steps of the axis that moves furthest
+    length: f32,    // mm
+    nominal: f32,   // mm/s, before overrides
+    accel: f32,     // mm/s²
+    entry: f32,     // mm/s, as planned
+    max_entry: f32, // mm/s, allowed by the corner into this block
+    unit: [f32; 3], // Direction
+    kind: Kind,
+}
+
+impl Block {
+    fn empty() -> Block {
+        Block {
+            steps: [0; 3],
+            count: 0,
+            length: 0.0,
+            nominal: 0.0,
+            accel: 0.0,
+            entry: 0.0,
+            max_entry: 0.0,
+            unit: [0.0; 3],
+            kind: Kind::Feed,
+        }
+    }
+
+    // Fastest speed at the end, starting at `entry`, or at the start, to
+    // end at `entry`
+    fn reachable(&self, entry: f32) -> f32 {
+        (entry * entry + 2.0 * self.accel * self.length).sqrt()
+    }
+}
+
+/// Blocks waiting to run, the first one running. The first block's entry
+/// speed is never changed, as it may already have started.
+#[derive(Copy, Clone, Debug)]
+pub struct Planner {
+    blocks: [Block; BLOCKS],
+    head: usize,
+    len: usize,
+    position: [i32; 3], // Steps, at the end of the last block
+}
+
+impl Planner {
+    pub fn new() -> Planner {
+        Planner {
+            blocks: [Block::empty(); BLOCKS],
+            head: 0,
+            len: 0,
+            position: [0; 3],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == BLOCKS
+    }
+
+    /// Where the motors will be once every block has run, in steps
+    pub fn position(&self) -> [i32; 3] {
+        self.position
+    }
+
+    /// Throws every block away, with the motors at `position`
+    pub fn clear(&mut self, position: [i32; 3]) {
+        self.len = 0;
+        self.position = position;
+    }
+
+    fn get(&self, i: usize) -> Option<&Block> {
+        if i < self.len {
+            Some(&self.blocks[(self.head + i) % BLOCKS])
+        } else {
+            None
+        }
+    }
+
+    fn pop(&mut self) {
+        if self.len > 0 {
+            self.head = (self.head + 1) % BLOCKS;
+            self.len -= 1;
+        }
+    }
+
+    /// Adds a move to `target`, in mm from machine zero, at `feed` mm/min.
+    /// Rapids and homing ignore the feed and go as fast as the axes allow.
+    /// Returns false if the queue is full.
+    pub fn push(&mut self, config: &Config, target: [f32; 3], feed: f32, kind: Kind) -> bool {
+        if self.is_full() {
+            return false;
+        }
+        let mut steps = [0; 3];
+        let mut mm = [0.0; 3];
+        for axis in 0..3 {
+            let spm = config.axes[axis].steps_per_mm;
+            steps[axis] = (target[axis] * spm).round() as i32 - self.position[axis];
+            mm[axis] = steps[axis] as f32 / spm;
+        }
+        let count = steps.iter().map(|s| s.unsigned_abs()).max().unwrap_or(0);
+        if count == 0 {
+            return true; // Nothing to do
+        }
+        let length = (mm[0] * mm[0] + mm[1] * mm[1] + mm[2] * mm[2]).sqrt();
+        let mut unit = [0.0; 3];
+        let mut nominal = match kind {
+            Kind::Feed | Kind::Jog => feed / 60.0,
+            Kind::Seek(_) | Kind::PullOff(_) => config.homing_feed / 60.0,
+            Kind::Rapid => f32::MAX,
+        };
+        let mut accel = f32::MAX;
+        for axis in 0..3 {
+            unit[axis] = mm[axis] / length;
+            let share = unit[axis].abs();
+            if share > 0.0 {
+                nominal = nominal.min(config.axes[axis].max_rate / 60.0 / share);
+                accel = accel.min(config.axes[axis].accel / share);
+            }
+        }
+
+        // Starting from rest, or round a corner no faster than the
+        // junction deviation allows
+        let max_entry = match self.len.checked_sub(1).and_then(|i| self.get(i)) {
+            None => 0.0,
+            Some(last) => {
+                let cos =
+                    -(last.unit[0] * unit[0] + last.unit[1] * unit[1] + last.unit[2] * unit[2]);
+                let corner = if cos > 0.999_999 {
+                    0.0 // Straight back the way it came
+                } else if cos < -0.999_999 {
+                    f32::MAX // Straight on
+                } else {
+                    let sin_half = (0.5 * (1.0 - cos)).sqrt();
+                    (accel * config.junction_deviation * sin_half / (1.0 - sin_half)).sqrt()
+                };
+                corner.min(nominal).min(last.nominal)
+            }
+        };
+
+        self.blocks[(self.head + self.len) % BLOCKS] = Block {
+            steps,
+            count,
+            length,
+            nominal,
+            accel,
+            entry: max_entry,
+            max_entry,
+            unit,
+            kind,
+        };
+        self.len += 1;
+        for (p, s) in self.position.iter_mut().zip(steps) {
+            *p += s;
+        }
+        self.recalculate();
+        true
+    }
+
+    // Backwards, each block enters no faster than it can stop from by the
+    // end of the queue; then forwards, no faster than the block before can
+    // get up to
+    fn recalculate(&mut self) {
+        let mut exit = 0.0;
+        for i in (1..self.len).rev() {
+            let block = &mut self.blocks[(self.head + i) % BLOCKS];
+            block.entry = block.max_entry.min(block.reachable(exit));
+            exit = block.entry;
+        }
+        for i in 1..self.len {
+            let before = self.blocks[(self.head + i - 1) % BLOCKS];
+            let block = &mut self.blocks[(self.head + i) % BLOCKS];
+            block.entry = block.entry.min(before.reachable(before.entry));
+        }
+    }
+}
+
+/// Pins to change this tick. Bit n is axis n.
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Output {
+    pub step: u8,
+    // Set for an axis moving towards negative
+    pub dir: u8,
+}
+
+/// What the motors are doing
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum State {
+    Idle,
+    Moving,
+    Jogging,
+    Held,
+    Homing,
+    Alarm(Alarm),
+}
+
+/// The planner, the block being stepped out, and homing
+#[derive(Copy, Clone, Debug)]
+pub struct Motion {
+    config: Config,
+    planner: Planner,
+    position: [i32; 3], // Steps, where the motors are
+    speed: f32,         // mm/s
+    rate: u32,          // Steps of the longest axis per tick, fixed point
+    accumulator: u32,
+    counters: [u32; 3], // Bresenham
+    done: u32,          // Steps of the longest axis done in this block
+    started: bool,      // The first block has had its first tick
+    update: u32,        // Ticks until the next speed update
+    dir: u8,
+    limits: u8, // Switches open at the last tick
+    held: bool,
+    // Throw the queue away once held, for a jog cancel
+    flush: bool,
+    homing: Option<usize>, // Index into HOMING_ORDER
+    alarm: Option<Alarm>,
+    feed_override: u16,
+    rapid_override: u16,
+}
+
+impl Motion {
+    pub fn new(config: Config) -> Motion {
+        Motion {
+            config,
+            planner: Planner::new(),
+            position: [0; 3],
+            speed: 0.0,
+            rate: 0,
+            accumulator: 0,
+            counters: [0; 3],
+            done: 0,
+            started: false,
+            update: 0,
+            dir: 0,
+            limits: 0,
+            held: false,
+            flush: false,
+            homing: None,
+            alarm: None,
+            feed_override: 100,
+            rapid_override: 100,
+        }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Takes new settings. Only while stopped, as the queue was planned
+    /// with the old ones.
+    pub fn set_config(&mut self, config: Config) -> bool {
+        if !self.planner.is_empty() {
+            return false;
+        }
+        self.config = config;
+        true
+    }
+
+    /// Where the motors are, in mm from machine zero
+    pub fn position(&self) -> [f32; 3] {
+        let mut mm = [0.0; 3];
+        for (axis, p) in mm.iter_mut().enumerate() {
+            *p = self.position[axis] as f32 / self.config.axes[axis].steps_per_mm;
+        }
+        mm
+    }
+
+    /// Speed along the path, mm/min
+    pub fn feed(&self) -> f32 {
+        self.speed * 60.0
+    }
+
+    pub fn state(&self) -> State {
+        match (self.alarm, self.planner.get(0)) {
+            (Some(alarm), _) => State::Alarm(alarm),
+            _ if self.homing.is_some() => State::Homing,
+            (None, None) => State::Idle,
+            (None, Some(_)) if self.held => State::Held,
+            (None, Some(block)) if block.kind == Kind::Jog => State::Jogging,
+            (None, Some(_)) => State::Moving,
+        }
+    }
+
+    /// Queues a move, see `Planner::push`. Moves are dropped while alarmed
+    /// or homing.
+    pub fn push(&mut self, target: [f32; 3], feed: f32, kind: Kind) -> bool {
+        if self.alarm.is_some() || self.homing.is_some() {
+            return true;
+        }
+        self.planner.push(&self.config, target, feed, kind)
+    }
+
+    /// Queues a jog of `distance` mm from wherever the queue ends
+    pub fn jog(&mut self, axis: usize, distance: f32, feed: f32) -> bool {
+        let mut target = [0.0; 3];
+        let end = self.planner.position();
+        for (a, t) in target.iter_mut().enumerate() {
+            *t = end[a] as f32 / self.config.axes[a].steps_per_mm;
+        }
+        target[axis] += distance;
+        self.push(target, feed, Kind::Jog)
+    }
+
+    /// Brakes to a stop, keeping the rest of the queue for `resume`
+    pub fn hold(&mut self) {
+        if !self.planner.is_empty() {
+            self.held = true;
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if !self.flush {
+            self.held = false;
+        }
+    }
+
+    /// Brakes to a stop and throws away the queue
+    pub fn cancel(&mut self) {
+        if !self.planner.is_empty() {
+            self.held = true;
+            self.flush = true;
+        }
+    }
+
+    /// Stops dead and throws everything away. Steps may have been lost if
+    /// the motors were turning, so that raises an alarm, as in GRBL.
+    pub fn reset(&mut self) {
+        if self.speed > 0.0 && self.alarm.is_none() {
+            self.alarm = Some(if self.homing.is_some() {
+                Alarm::HomingReset
+            } else {
+                Alarm::ResetWhileMoving
+            });
+        }
+        self.stop();
+        self.homing = None;
+    }
+
+    /// Clears an alarm, trusting the position
+    pub fn unlock(&mut self) {
+        self.alarm = None;
+    }
+
+    /// Homes each axis in turn: towards its switch in +, back off it, and
+    /// call that machine zero
+    pub fn home(&mut self) {
+        if !self.planner.is_empty() {
+            return;
+        }
+        self.alarm = None;
+        self.homing = Some(0);
+        self.seek(HOMING_ORDER[0]);
+    }
+
+    pub fn set_overrides(&mut self, feed: u16, rapid: u16) {
+        self.feed_override = feed;
+        self.rapid_override = rapid;
+    }
+
+    fn stop(&mut self) {
+        self.planner.clear(self.position);
+        self.speed = 0.0;
+        self.started = false;
+        self.held = false;
+        self.flush = false;
+    }
+
+    fn alarm(&mut self, alarm: Alarm) {
+        self.stop();
+        self.homing = None;
+        self.alarm = Some(alarm);
+    }
+
+    fn seek(&mut self, axis: usize) {
+        let mut target = self.position();
+        target[axis] += self.config.axes[axis].travel;
+        self.planner
+            .push(&self.config, target, 0.0, Kind::Seek(axis));
+    }
+
+    // A homing move ended. A seek that ends without finding the switch is
+    // an alarm; a pull off sets zero and moves on to the next axis.
+    fn homing_done(&mut self, kind: Kind) {
+        match (kind, self.homing) {
+            (Kind::Seek(_), _) => self.alarm(Alarm::HomingNotFound),
+            (Kind::PullOff(axis), _) if self.limits & (1 << axis) != 0 => {
+                self.alarm(Alarm::HomingPullOff)
+            }
+            (Kind::PullOff(axis), Some(i)) => {
+                self.position[axis] = 0;
+                self.planner.clear(self.position);
+                if i + 1 < HOMING_ORDER.len() {
+                    self.homing = Some(i + 1);
+                    self.seek(HOMING_ORDER[i + 1]);
+                } else {
+                    self.homing = None;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    // A seek stops at its own switch, and may go on past any switch that
+    // was already open. Anything else opening a switch is a hard limit.
+    fn check_limits(&mut self, limits: u8) {
+        let opened = limits & !self.limits;
+        self.limits = limits;
+        match self.planner.get(0).map(|b| b.kind) {
+            Some(Kind::Seek(axis)) if limits & (1 << axis) != 0 => {
+                self.stop();
+                let mut target = self.position();
+                target[axis] -= self.config.pull_off;
+                self.planner
+                    .push(&self.config, target, 0.0, Kind::PullOff(axis));
+            }
+            Some(Kind::Seek(axis)) | Some(Kind::PullOff(axis)) if opened & !(1 << axis) == 0 => (),
+            Some(_) if opened != 0 => self.alarm(Alarm::HardLimit),
+            _ => (),
+        }
+    }
+
+    // Picks the speed for the next UPDATE_TICKS, and the step rate for it
+    fn update_speed(&mut self, block: &Block) {
+        let percent = match block.kind {
+            Kind::Feed => self.feed_override,
+            Kind::Rapid => self.rapid_override,
+            _ => 100,
+        };
+        let nominal = block.nominal * percent as f32 / 100.0;
+        let exit = self.planner.get(1).map_or(0.0, |next| next.entry);
+        let left = (block.count - self.done) as f32 / block.count as f32 * block.length;
+        let brake = (exit * exit + 2.0 * block.accel * left).sqrt();
+        let dt = UPDATE_TICKS as f32 / TICK_HZ as f32;
+
+        let target = if self.held { 0.0 } else { nominal.min(brake) };
+        let speed = if self.speed < target {
+            (self.speed + block.accel * dt).min(target)
+        } else {
+            (self.speed - block.accel * dt).max(target)
+        };
+        self.speed = if self.held {
+            if speed < HOLD_SPEED {
+                0.0
+            } else {
+                speed.min(brake)
+            }
+        } else {
+            speed.min(brake).max(MIN_SPEED.min(nominal))
+        };
+
+        let steps_per_tick = self.speed * block.count as f32 / block.length / TICK_HZ as f32;
+        self.rate = ((steps_per_tick * ONE as f32) as u32).min(MAX_RATE);
+    }
+
+    /// Called at TICK_HZ with the limit switches that are open, bit n for
+    /// axis n. Returns the pins to set.
+    pub fn tick(&mut self, limits: u8) -> Output {
+        self.check_limits(limits);
+        let idle = Output {
+            step: 0,
+            dir: self.dir,
+        };
+        let block = match self.planner.get(0) {
+            Some(block) if self.alarm.is_none() => *block,
+            _ => return idle,
+        };
+
+        if !self.started {
+            self.started = true;
+            self.done = 0;
+            self.update = 0;
+            self.counters = [block.count / 2; 3];
+            let mut dir = 0;
+            for axis in 0..3 {
+                if block.steps[axis] < 0 {
+                    dir |= 1 << axis;
+                }
+            }
+            if dir != self.dir {
+                self.dir = dir;
+                return Output { step: 0, dir };
+            }
+        }
+
+        if self.update == 0 {
+            self.update = UPDATE_TICKS;
+            self.update_speed(&block);
+        }
+        self.update -= 1;
+
+        if self.speed == 0.0 {
+            // Held to a stop
+            if self.flush {
+                self.stop();
+            }
+            return idle;
+        }
+
+        self.accumulator += self.rate;
+        if self.accumulator < ONE {
+            return idle;
+        }
+        self.accumulator -= ONE;
+        let mut step = 0;
+        for axis in 0..3 {
+            self.counters[axis] += block.steps[axis].unsigned_abs();
+            if self.counters[axis] >= block.count {
+                self.counters[axis] -= block.count;
+                step |= 1 << axis;
+                self.position[axis] += block.steps[axis].signum();
+            }
+        }
+        self.done += 1;
+        if self.done == block.count {
+            self.planner.pop();
+            self.started = false;
+            if let Kind::Seek(_) | Kind::PullOff(_) = block.kind {
+                self.homing_done(block.kind);
+            }
+            if self.planner.is_empty() {
+                self.speed = 0.0;
+                self.held = false;
+            }
+        }
+        Output {
+            step,
+            dir: self.dir,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MM: i32 = 400; // Steps, with the default config
+
+    // Steps the motion, keeping count of the steps that came out on the pins
+    struct Rig {
+        motion: Motion,
+        steps: [i32; 3],
+        last: u8,
+    }
+
+    impl Rig {
+        fn new(config: Config) -> Rig {
+            Rig {
+                motion: Motion::new(config),
+                steps: [0; 3],
+                last: 0,
+            }
+        }
+
+        fn tick(&mut self, limits: u8) -> Output {
+            let out = self.motion.tick(limits);
+            assert_eq!(out.step & self.last, 0, "stepped two ticks running");
+            self.last = out.step;
+            for (axis, steps) in self.steps.iter_mut().enumerate() {
+                if out.step & (1 << axis) != 0 {
+                    *steps += if out.dir & (1 << axis) != 0 { -1 } else { 1 };
+                }
+            }
+            out
+        }
+
+        fn ticks(&mut self, n: u32) {
+            for _ in 0..n {
+                self.tick(0);
+            }
+        }
+
+        // Runs until the motors stop, returning the feed at every tick
+        fn run(&mut self) -> Vec<f32> {
+            let mut feeds = Vec::new();
+            while self.motion.state() != State::Idle {
+                assert!(feeds.len() < 10 * TICK_HZ as usize, "never stopped");
+                self.tick(0);
+                feeds.push(self.motion.feed());
+            }
+            feeds
+        }
+
+        // Runs while homing, with a switch at `switches` steps from where
+        // the axis started, returning the order the switches opened in
+        fn home(&mut self, switches: [i32; 3], stuck: bool) -> Vec<usize> {
+            let mut order = Vec::new();
+            let mut limits = 0;
+            self.motion.home();
+            for _ in 0..20 * TICK_HZ {
+                if self.motion.state() != State::Homing {
+                    return order;
+                }
+                for (axis, switch) in switches.iter().enumerate() {
+                    let bit = 1 << axis;
+                    if self.steps[axis] >= *switch {
+                        if limits & bit == 0 && !order.contains(&axis) {
+                            order.push(axis);
+                        }
+                        limits |= bit;
+                    } else if !stuck {
+                        limits &= !bit;
+                    }
+                }
+                self.tick(limits);
+            }
+            panic!("still homing");
+        }
+    }
+
+    fn entry(planner: &Planner, i: usize) -> f32 {
+        planner.get(i).expect("no such block").entry
+    }
+
+    #[test]
+    fn bresenham_step_counts() {
+        let mut rig = Rig::new(Config::new());
+        rig.motion.push([1.0, 0.5, -0.25], 600.0, Kind::Feed);
+        // The first tick only sets the direction pins
+        assert_eq!(
+            rig.tick(0),
+            Output {
+                step: 0,
+                dir: 0b100
+            }
+        );
+        let mut x = 0;
+        while rig.motion.state() != State::Idle {
+            let out = rig.tick(0);
+            if out.step & 1 != 0 {
+                x += 1;
+                // The other axes keep pace with the longest one
+                assert!((rig.steps[1] - x / 2).abs() <= 1);
+                assert!((rig.steps[2] + x / 4).abs() <= 1);
+            } else {
+                assert_eq!(out.step, 0, "a short axis stepped on its own");
+            }
+        }
+        assert_eq!(rig.steps, [MM, MM / 2, -MM / 4]);
+        assert_eq!(rig.motion.position(), [1.0, 0.5, -0.25]);
+    }
+
+    #[test]
+    fn same_moves_same_steps() {
+        let outputs = || {
+            let mut rig = Rig::new(Config::new());
+            rig.motion.push([3.0, 1.0, 0.0], 900.0, Kind::Feed);
+            rig.motion.push([3.0, 4.0, -1.0], 900.0, Kind::Feed);
+            rig.motion.push([0.0, 0.0, 0.0], 0.0, Kind::Rapid);
+            let mut outputs = Vec::new();
+            while rig.motion.state() != State::Idle {
+                outputs.push(rig.tick(0));
+            }
+            outputs
+        };
+        let first = outputs();
+        assert!(!first.is_empty());
+        assert_eq!(first, outputs());
+    }
+
+    #[test]
+    fn trapezoid_profile() {
+        // 10 mm at 10 mm/s: 0.1 s up to speed over 0.5 mm, 0.9 s at speed,
+        // and 0.1 s back down
+        let mut rig = Rig::new(Config::new());
+        rig.motion.push([10.0, 0.0, 0.0], 600.0, Kind::Feed);
+        let feeds = rig.run();
+        assert_eq!(rig.steps, [10 * MM, 0, 0]);
+
+        // A little under, as it starts at MIN_SPEED rather than from rest
+        let seconds = feeds.len() as f32 / TICK_HZ as f32;
+        assert!(seconds > 1.08 && seconds <= 1.1, "took {} s", seconds);
+        let peak = feeds.iter().cloned().fold(0.0, f32::max);
+        assert!((peak - 600.0).abs() < 0.01, "peaked at {}", peak);
+        let cruise = feeds.iter().filter(|f| (*f - 600.0).abs() < 0.01).count();
+        let cruise = cruise as f32 / TICK_HZ as f32;
+        assert!((cruise - 0.9).abs() < 0.01, "cruised for {} s", cruise);
+
+        // Up at the acceleration, level, then down. Braking follows the
+        // steps left, so it only keeps to the acceleration on average.
+        let dv = 100.0 * 60.0 * UPDATE_TICKS as f32 / TICK_HZ as f32;
+        let top = feeds.iter().position(|f| *f == peak).unwrap();
+        let level = feeds.iter().rposition(|f| *f == peak).unwrap();
+        for (i, pair) in feeds.windows(2).enumerate() {
+            let change = pair[1] - pair[0];
+            if i < top {
+                assert!(change >= 0.0 && change <= dv + 0.01, "{} at {}", change, i);
+            } else {
+                assert!(change <= 0.0, "sped up at {}", i);
+            }
+        }
+        let braking = (feeds.len() - level) as f32 / TICK_HZ as f32;
+        assert!((braking - 0.1).abs() < 0.01, "braked for {} s", braking);
+    }
+
+    #[test]
+    fn short_move_is_a_triangle() {
+        // Too short to get up to speed: the peak is √(accel × length), give
+        // or take a speed update
+        let mut rig = Rig::new(Config::new());
+        rig.motion.push([0.2, 0.0, 0.0], 600.0, Kind::Feed);
+        let feeds = rig.run();
+        let peak = feeds.iter().cloned().fold(0.0, f32::max);
+        let most = (100.0f32 * 0.2).sqrt() * 60.0;
+        let dv = 100.0 * 60.0 * UPDATE_TICKS as f32 / TICK_HZ as f32;
+        assert!((peak - most).abs() <= dv, "peaked at {}", peak);
+        assert_eq!(rig.steps, [MM / 5, 0, 0]);
+    }
+
+    #[test]
+    fn feed_limited_by_axis_rate() {
+        let mut planner = Planner::new();
+        planner.push(&Config::new(), [0.0, 0.0, -5.0], 1000.0, Kind::Feed);
+        assert_eq!(planner.get(0).unwrap().nominal, 10.0); // Z's 600 mm/min
+        planner.push(&Config::new(), [3.0, 4.0, -5.0], 0.0, Kind::Rapid);
+        assert_eq!(planner.get(1).unwrap().nominal, 1500.0 / 60.0 / 0.8);
+    }
+
+    #[test]
+    fn junction_speeds() {
+        let config = Config::new();
+        let corner = |end: [f32; 3]| {
+            let mut planner = Planner::new();
+            planner.push(&config, [10.0, 0.0, 0.0], 600.0, Kind::Feed);
+            planner.push(&config, end, 600.0, Kind::Feed);
+            assert_eq!(entry(&planner, 0), 0.0); // From rest
+            entry(&planner, 1)
+        };
+        // Straight on at full speed, straight back from a stop
+        assert_eq!(corner([20.0, 0.0, 0.0]), 10.0);
+        assert_eq!(corner([0.0, 0.0, 0.0]), 0.0);
+        // Round a right angle, √(a δ sin(θ/2) / (1 - sin(θ/2)))
+        let sin = 0.5f32.sqrt();
+        let right = (100.0 * 0.01 * sin / (1.0 - sin)).sqrt();
+        assert!((corner([10.0, 10.0, 0.0]) - right).abs() < 1e-3);
+        // Sharper corners are slower
+        assert!(corner([5.0, 1.0, 0.0]) < right);
+        assert!(corner([15.0, 1.0, 0.0]) > right);
+    }
+
+    #[test]
+    fn look_ahead() {
+        let config = Config::new();
+        let mut planner = Planner::new();
+        planner.push(&config, [10.0, 0.0, 0.0], 600.0, Kind::Feed);
+        planner.push(&config, [10.01, 0.0, 0.0], 600.0, Kind::Feed);
+        // Straight on, but the last block must stop in 0.01 mm
+        let stop = (2.0f32 * 100.0 * 0.01).sqrt();
+        assert!((entry(&planner, 1) - stop).abs() < 1e-3);
+        // More queue behind it lets it go faster
+        planner.push(&config, [20.0, 0.0, 0.0], 600.0, Kind::Feed);
+        assert_eq!(entry(&planner, 1), 10.0);
+        assert_eq!(entry(&planner, 2), 10.0);
+
+        // And the motors keep going through the join
+        let mut rig = Rig::new(config);
+        rig.motion.push([10.0, 0.0, 0.0], 600.0, Kind::Feed);
+        rig.motion.push([20.0, 0.0, 0.0], 600.0, Kind::Feed);
+        while rig.steps[0] < 19 * MM {
+            rig.tick(0);
+            if rig.steps[0] > MM {
+                assert!((rig.motion.feed() - 600.0).abs() < 0.01);
+            }
+        }
+        rig.run();
+        assert_eq!(rig.steps, [20 * MM, 0, 0]);
+    }
+
+    #[test]
+    fn full_queue() {
+        let mut planner = Planner::new();
+        for i in 0..BLOCKS {
+            assert!(planner.push(
+                &Config::new(),
+                [i as f32 + 1.0, 0.0, 0.0],
+                600.0,
+                Kind::Feed
+            ));
+        }
+        assert!(planner.is_full());
+        assert!(!planner.push(&Config::new(), [20.0, 0.0, 0.0], 600.0, Kind::Feed));
+        assert_eq!(planner.position(), [BLOCKS as i32 * MM, 0, 0]);
+    }
+
+    #[test]
+    fn hold_and_resume() {
+        let mut rig = Rig::new(Config::new());
+        rig.motion.push([10.0, 0.0, 0.0], 600.0, Kind::Feed);
+        rig.ticks(TICK_HZ / 2);
+        assert_eq!(rig.motion.state(), State::Moving);
+
+        rig.motion.hold();
+        let from = rig.steps[0];
+        while rig.motion.feed() > 0.0 {
+            rig.tick(0);
+        }
+        // About v² / 2a to brake
+        let braked = (rig.steps[0] - from) as f32 / MM as f32;
+        assert!((braked - 0.5).abs() < 0.05, "braked in {} mm", braked);
+        assert_eq!(rig.motion.state(), State::Held);
+        let stopped = rig.steps;
+        rig.ticks(TICK_HZ);
+        assert_eq!(rig.steps, stopped);
+        assert_eq!(rig.motion.state(), State::Held);
+
+        rig.motion.resume();
+        rig.run();
+        assert_eq!(rig.steps, [10 * MM, 0, 0]);
+        assert_eq!(rig.motion.position(), [10.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn cancel_jog() {
+        let mut rig = Rig::new(Config::new());
+        rig.motion.jog(0, 10.0, 600.0);
+        rig.motion.jog(0, 10.0, 600.0);
+        assert_eq!(rig.motion.state(), State::Jogging);
+        rig.ticks(TICK_HZ / 2);
+
+        rig.motion.cancel();
+        // Resuming can't bring back a cancelled jog
+        rig.motion.resume();
+        rig.run();
+        assert!(rig.steps[0] > 4 * MM && rig.steps[0] < 6 * MM);
+        assert_eq!(rig.motion.position()[0], rig.steps[0] as f32 / MM as f32);
+
+        // The next jog starts from where it stopped
+        let from = rig.steps[0];
+        rig.motion.jog(0, -1.0, 600.0);
+        rig.run();
+        assert_eq!(rig.steps[0], from - MM);
+    }
+
+    #[test]
+    fn homing() {
+        let mut rig = Rig::new(Config::new());
+        rig.motion.push([1.0, 0.0, 0.0], 600.0, Kind::Feed);
+        rig.run();
+
+        // Switches 10, 20 and 5 mm from where each axis is now
+        let switches = [10 * MM, 20 * MM, 5 * MM];
+        assert_eq!(
+            rig.home([MM + switches[0], switches[1], switches[2]], false),
+            [2, 0, 1]
+        );
+        assert_eq!(rig.motion.state(), State::Idle);
+        // Zero is pulled off 1 mm from each switch
+        assert_eq!(rig.motion.position(), [0.0; 3]);
+        assert_eq!(rig.steps, [10 * MM, 19 * MM, 4 * MM]);
+
+        // Moves are from the new zero
+        rig.motion.push([1.0, 1.0, 1.0], 600.0, Kind::Feed);
+        rig.run();
+        assert_eq!(rig.steps, [11 * MM, 20 * MM, 5 * MM]);
+    }
+
+    #[test]
+    fn homing_failures() {
+        // No switch within the travel
+        let mut config = Config::new();
+        config.axes[2].travel = 2.0;
+        let mut rig = Rig::new(config);
+        let never = [i32::MAX; 3];
+        assert_eq!(rig.home(never, false), []);
+        assert_eq!(rig.motion.state(), State::Alarm(Alarm::HomingNotFound));
+        assert_eq!(rig.steps, [0, 0, 2 * MM]);
+
+        // A switch that won't let go
+        let mut rig = Rig::new(Config::new());
+        assert_eq!(rig.home([MM; 3], true), [2]);
+        assert_eq!(rig.motion.state(), State::Alarm(Alarm::HomingPullOff));
+
+        // Reset part way
+        let mut rig = Rig::new(Config::new());
+        rig.motion.home();
+        rig.ticks(TICK_HZ / 10);
+        assert_eq!(rig.motion.state(), State::Homing);
+        rig.motion.reset();
+        assert_eq!(rig.motion.state(), State::Alarm(Alarm::HomingReset));
+    }
+
+    #[test]
+    fn hard_limit() {
+        let mut rig = Rig::new(Config::new());
+        rig.motion.push([10.0, 10.0, 0.0], 600.0, Kind::Feed);
+        rig.ticks(TICK_HZ / 10);
+        // Any switch opening part way through a move
+        rig.tick(0b100);
+        assert_eq!(rig.motion.state(), State::Alarm(Alarm::HardLimit));
+
+        // Stopped dead, and deaf to moves until unlocked
+        let stopped = rig.steps;
+        rig.motion.push([0.0; 3], 600.0, Kind::Feed);
+        for _ in 0..TICK_HZ / 10 {
+            assert_eq!(rig.tick(0b100).step, 0);
+        }
+        assert_eq!(rig.steps, stopped);
+        rig.motion.unlock();
+        assert_eq!(rig.motion.state(), State::Idle);
+        rig.motion.push([0.0; 3], 600.0, Kind::Feed);
+        rig.run();
+        assert_eq!(rig.steps, [0; 3]);
+    }
+
+    #[test]
+    fn reset() {
+        let mut rig = Rig::new(Config::new());
+        rig.motion.reset();
+        assert_eq!(rig.motion.state(), State::Idle);
+        rig.motion.push([10.0, 0.0, 0.0], 600.0, Kind::Feed);
+        rig.ticks(TICK_HZ / 10);
+        rig.motion.reset();
+        assert_eq!(rig.motion.state(), State::Alarm(Alarm::ResetWhileMoving));
+        assert_eq!(rig.motion.position()[0], rig.steps[0] as f32 / MM as f32);
+    }
+}
@@ -0,0 +1,262 @@
+//! Numbered memory slots holding X/Y/Z positions, with an optional label.
+//!
+//! Kept free of hardware so it can be tested on the host. Slots are
+//! serialised in a fixed layout for the storage module.
+
+pub const N_POINTS: usize = 50;
+pub const LABEL_LEN: usize = 12;
+
+// present flag, 3 x f32, label length, label
+const SLOT_BYTES: usize = 1 + 3 * 4 + 1 + LABEL_LEN;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StoredPoint {
+    pub position: [f32; 3],
+    label: [u8; LABEL_LEN],
+    label_len: u8,
+}
+
+impl StoredPoint {
+    pub fn new(position: [f32; 3]) -> StoredPoint {
+        StoredPoint {
+            position,
+            label: [0; LABEL_LEN],
+            label_len: 0,
+        }
+    }
+
+    /// Sets the label, cutting it short at a char boundary if it is too long
+    pub fn set_label(&mut self, label: &str) {
+        let mut len = 0;
+        for c in label.chars() {
+            let n = c.len_utf8();
+            if len + n > LABEL_LEN {
+                break;
+            }
+            c.encode_utf8(&mut self.label[len..len + n]);
+            len += n;
+        }
+        self.label_len = len as u8;
+    }
+
+    pub fn label(&self) -> &str {
+        core::str::from_utf8(&self.label[..self.label_len as usize]).unwrap_or("")
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct PointMemory {
+    slots: [Option<StoredPoint>; N_POINTS],
+}
+
+impl PointMemory {
+    pub fn new() -> PointMemory {
+        PointMemory {
+            slots: [None; N_POINTS],
+        }
+    }
+
+    pub fn get(&self, slot: usize) -> Option<StoredPoint> {
+        self.slots.get(slot).copied().flatten()
+    }
+
+    /// Stores a position, keeping the label of whatever was in the slot
+    pub fn store(&mut self, slot: usize, position: [f32; 3]) {
+        if slot < N_POINTS {
+            let point = match self.slots[slot] {
+                Some(mut point) => {
+                    point.position = position;
+                    point
+                }
+                None => StoredPoint::new(position),
+            };
+            self.slots[slot] = Some(point);
+        }
+    }
+
+    /// Changes one coordinate, creating the point at the origin if the slot is empty
+    pub fn set_coordinate(&mut self, slot: usize, axis: usize, value: f32) {
+        if slot < N_POINTS && axis < 3 {
+            let mut point = self.get(slot).unwrap_or_else(|| StoredPoint::new([0.0; 3]));
+            point.position[axis] = value;
+            self.slots[slot] = Some(point);
+        }
+    }
+
+    /// The first empty slot from `from` on, wrapping round to the start
+    pub fn next_free(&self, from: usize) -> Option<usize> {
+        (0..N_POINTS)
+            .map(|i| (from + i) % N_POINTS)
+            .find(|slot| self.slots[*slot].is_none())
+    }
+
+    /// Labels the point in a slot, if there is one
+    pub fn set_label(&mut self, slot: usize, label: &str) {
+        if let Some(Some(point)) = self.slots.get_mut(slot) {
+            point.set_label(label);
+        }
+    }
+
+    pub fn delete(&mut self, slot: usize) {
+        if slot < N_POINTS {
+            self.slots[slot] = None;
+        }
+    }
+
+    /// Writes every slot into `out`, returning the number of bytes used, or
+    /// None if `out` is too small.
+    pub fn write_bytes(&self, out: &mut [u8]) -> Option<usize> {
+        let size = N_POINTS * SLOT_BYTES;
+        if out.len() < size {
+            return None;
+        }
+        for (slot, bytes) in self.slots.iter().zip(out.chunks_exact_mut(SLOT_BYTES)) {
+            bytes.fill(0);
+            if let Some(point) = slot {
+                bytes[0] = 1;
+                for (i, v) in point.position.iter().enumerate() {
+                    bytes[1 + i * 4..5 + i * 4].copy_from_slice(&v.to_le_bytes());
+                }
+                bytes[13] = point.label_len;
+                bytes[14..].copy_from_slice(&point.label);
+            }
+        }
+        Some(size)
+    }
+
+    /// Replaces the slots with ones written by `write_bytes`. Slots missing from
+    /// `data` are left empty.
+    pub fn read_bytes(&mut self, data: &[u8]) {
+        *self = PointMemory::new();
+        for (slot, bytes) in self.slots.iter_mut().zip(data.chunks_exact(SLOT_BYTES)) {
+            if bytes[0] != 1 {
+                continue;
+            }
+            let mut position = [0.0; 3];
+            for (i, v) in position.iter_mut().enumerate() {
+                *v = f32::from_le_bytes([
+                    bytes[1 + i * 4],
+                    bytes[2 + i * 4],
+                    bytes[3 + i * 4],
+                    bytes[4 + i * 4],
+                ]);
+            }
+            let mut point = StoredPoint::new(position);
+            let len = (bytes[13] as usize).min(LABEL_LEN);
+            if let Ok(label) = core::str::from_utf8(&bytes[14..14 + len]) {
+                point.set_label(label);
+            }
+            *slot = Some(point);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels() {
+        let mut point = StoredPoint::new([1.0, 2.0, 3.0]);
+        assert_eq!(point.label(), "");
+        point.set_label("VISE JAW");
+        assert_eq!(point.label(), "VISE JAW");
+        // Cut short, but never through a character
+        point.set_label("ABCDEFGHIJKLMNOP");
+        assert_eq!(point.label(), "ABCDEFGHIJKL");
+        point.set_label("ABCDEFGHIJK\u{e9}");
+        assert_eq!(point.label(), "ABCDEFGHIJK");
+    }
+
+    #[test]
+    fn slots() {
+        let mut points = PointMemory::new();
+        assert_eq!(points.get(0), None);
+        assert_eq!(points.get(N_POINTS), None);
+
+        // A label needs a point to go on
+        points.set_label(0, "DATUM");
+        assert_eq!(points.get(0), None);
+        points.store(0, [1.0, 2.0, 3.0]);
+        points.set_label(0, "DATUM");
+        // Storing again keeps the label
+        points.store(0, [4.0, 5.0, 6.0]);
+        let point = points.get(0).unwrap();
+        assert_eq!(point.position, [4.0, 5.0, 6.0]);
+        assert_eq!(point.label(), "DATUM");
+
+        // One coordinate of an empty slot starts from the origin
+        points.set_coordinate(2, 1, 7.5);
+        assert_eq!(points.get(2).unwrap().position, [0.0, 7.5, 0.0]);
+        points.set_coordinate(2, 3, 1.0); // No such axis
+        points.set_coordinate(N_POINTS, 0, 1.0);
+        assert_eq!(points.get(2).unwrap().position, [0.0, 7.5, 0.0]);
+
+        points.delete(0);
+        assert_eq!(points.get(0), None);
+        points.store(N_POINTS, [1.0; 3]); // Out of range, ignored
+    }
+
+    #[test]
+    fn next_free() {
+        let mut points = PointMemory::new();
+        assert_eq!(points.next_free(0), Some(0));
+        points.store(0, [0.0; 3]);
+        points.store(N_POINTS - 1, [0.0; 3]);
+        assert_eq!(points.next_free(0), Some(1));
+        // Wraps round past the end
+        assert_eq!(points.next_free(N_POINTS - 1), Some(1));
+        for slot in 0..N_POINTS {
+            points.store(slot, [0.0; 3]);
+        }
+        assert_eq!(points.next_free(3), None);
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut points = PointMemory::new();
+        points.store(0, [1.5, -2.25, 3.0]);
+        points.set_label(0, "PART 1");
+        points.store(7, [0.0, 0.0, -10.0]);
+        points.store(N_POINTS - 1, [100.0, 200.0, 300.0]);
+        points.set_label(N_POINTS - 1, "LAST");
+
+        let mut buf = [0xAA; N_POINTS * SLOT_BYTES + 3];
+        assert_eq!(points.write_bytes(&mut buf), Some(N_POINTS * SLOT_BYTES));
+        assert_eq!(
+            points.write_bytes(&mut buf[..N_POINTS * SLOT_BYTES - 1]),
+            None
+        );
+
+        let mut read = PointMemory::new();
+        read.store(3, [9.0; 3]); // Replaced, not merged
+        read.read_bytes(&buf[..N_POINTS * SLOT_BYTES]);
+        for slot in 0..N_POINTS {
+            assert_eq!(read.get(slot), points.get(slot), "slot {}", slot);
+        }
+
+        // Slots past the end of older, shorter data are left empty
+        read.read_bytes(&buf[..8 * SLOT_BYTES]);
+        assert_eq!(read.get(7), points.get(7));
+        assert_eq!(read.get(N_POINTS - 1), None);
+    }
+
+    #[test]
+    fn bad_labels_read_empty() {
+        let mut points = PointMemory::new();
+        points.store(0, [1.0; 3]);
+        points.set_label(0, "OK");
+        let mut buf = [0; N_POINTS * SLOT_BYTES];
+        points.write_bytes(&mut buf).unwrap();
+
+        buf[14] = 0xFF; // Not UTF-8
+        points.read_bytes(&buf);
+        assert_eq!(points.get(0).unwrap().label(), "");
+        assert_eq!(points.get(0).unwrap().position, [1.0; 3]);
+
+        buf[13] = 200; // Longer than a label can be
+        buf[14..14 + LABEL_LEN].copy_from_slice(b"ABCDEFGHIJKL");
+        points.read_bytes(&buf);
+        assert_eq!(points.get(0).unwrap().label(), "ABCDEFGHIJKL");
+    }
+}
@@ -0,0 +1,386 @@
+//! Closed loop power feed on X, stopping itself at positions taken from
+//! the DRO.
+//!
+//! The feed unit is driven by two outputs and a speed signal:
+//!
+//!   Run    PF7 (Arduino A4)  high to run
+//!   Dir    PF6 (Arduino A5)  high for right (X+)
+//!   Speed  PB9 (Arduino D14) TIM11_CH1 PWM, filtered to a voltage for the
+//!          unit's speed input
+//!
+//! `PowerFeed` decides what those should be from the X scale each time
+//! round the main loop. It only runs towards a stop that has been set,
+//! slows to a creep for the last `POWER_FEED_SLOW_MM`, and cuts out
+//! `POWER_FEED_COAST_MM` early to allow for the table coasting. It doesn't
+//! touch hardware; `Drive` does.
+//!
+//! The main loop can be held up for a long time by SD card and flash
+//! work, so it isn't trusted to cut out on time. `Drive` also sets a
+//! compare on channel 3 of TIM2, the X encoder's counter, at the cut out
+//! count, and the interrupt drops Run the moment the scale gets there.
+
+use core::cell::Cell;
+use core::fmt::{self, Write};
+
+use cortex_m::interrupt::{free, Mutex};
+#[cfg(not(test))]
+use micromath::F32Ext;
+use stm32f7xx_hal::{
+    pac::{interrupt, Interrupt, NVIC, RCC, TIM11, TIM2},
+    rcc::Clocks,
+};
+
+use crate::consts::*;
+
+const PWM_HZ: u32 = 20_000;
+const MIN_FEED: u8 = 5; // Percent of full speed
+const FEED_STEP: i16 = 5;
+
+// GPIO port F, for the run and dir pins
+const GPIOF_BSRR: usize = 0x4002_1400 + 0x18;
+const RUN_PIN: u32 = 7;
+const DIR_PIN: u32 = 6;
+
+// TIM2 DIER and SR bits for the channel 3 compare
+const CC3IE: u32 = 1 << 3;
+const CC3IF: u32 = 1 << 3;
+
+// Set by the interrupt when it cut the feed, until the main loop looks
+static CUT: Mutex<Cell<bool>> = Mutex::new(Cell::new(false));
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub enum Direction {
+    Left,
+    Right,
+}
+
+impl Direction {
+    // Which way X counts when moving this way
+    #[cfg_attr(test, allow(dead_code))]
+    fn sign(self) -> f32 {
+        match self {
+            Direction::Left => -1.0,
+            Direction::Right => 1.0,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Direction::Left => "left",
+            Direction::Right => "right",
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub enum Speed {
+    Feed,
+    Rapid,
+}
+
+/// Why the feed wouldn't start
+#[derive(PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub enum Problem {
+    NoStop(Direction),
+    AtStop(Direction),
+}
+
+impl Problem {
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn describe<W: Write>(self, out: &mut W) -> fmt::Result {
+        match self {
+            Problem::NoStop(d) => write!(out, "Set the {} stop first", d.name()),
+            Problem::AtStop(d) => write!(out, "Already at {} stop", d.name()),
+        }
+    }
+}
+
+/// What the outputs should be
+#[derive(PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub struct Output {
+    pub run: Option<Direction>,
+    // Fraction of full speed, 0 to 1
+    pub duty: f32,
+    // X in machine mm where the drive cuts out by itself, when running
+    pub cut_at: Option<f32>,
+}
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub struct PowerFeed {
+    // Stops, in machine mm so they stay put when X is zeroed
+    left: Option<f32>,
+    right: Option<f32>,
+    speed: Speed,
+    feed: u8, // Percent of full speed for Speed::Feed
+    running: Option<Direction>,
+    // The stop the feed last stopped itself at
+    reached: Option<Direction>,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl PowerFeed {
+    pub fn new() -> PowerFeed {
+        PowerFeed {
+            left: None,
+            right: None,
+            speed: Speed::Feed,
+            feed: 30,
+            running: None,
+            reached: None,
+        }
+    }
+
+    pub fn stop_at(&self, direction: Direction) -> Option<f32> {
+        match direction {
+            Direction::Left => self.left,
+            Direction::Right => self.right,
+        }
+    }
+
+    /// Puts a stop where X is now
+    pub fn set_stop(&mut self, direction: Direction, position: f32) {
+        match direction {
+            Direction::Left => self.left = Some(position),
+            Direction::Right => self.right = Some(position),
+        }
+        self.reached = None;
+    }
+
+    pub fn clear_stops(&mut self) {
+        self.left = None;
+        self.right = None;
+        self.reached = None;
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    /// Changes speed, straight away if running
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+    }
+
+    pub fn feed_percent(&self) -> u8 {
+        self.feed
+    }
+
+    /// Changes the feed speed by a step up (positive) or down
+    pub fn change_feed(&mut self, by: i16) {
+        let feed = self.feed as i16 + by.signum() * FEED_STEP;
+        self.feed = feed.clamp(MIN_FEED as i16, 100) as u8;
+    }
+
+    pub fn running(&self) -> Option<Direction> {
+        self.running
+    }
+
+    pub fn reached(&self) -> Option<Direction> {
+        self.reached
+    }
+
+    // Distance left to the stop in `direction`, negative once past it
+    fn distance(&self, direction: Direction, position: f32) -> Option<f32> {
+        self.stop_at(direction)
+            .map(|stop| (stop - position) * direction.sign())
+    }
+
+    /// Runs towards the stop in `direction`, from X at `position`
+    pub fn start(&mut self, direction: Direction, position: f32) -> Result<(), Problem> {
+        match self.distance(direction, position) {
+            None => Err(Problem::NoStop(direction)),
+            Some(d) if d <= POWER_FEED_COAST_MM => Err(Problem::AtStop(direction)),
+            Some(_) => {
+                self.running = Some(direction);
+                self.reached = None;
+                Ok(())
+            }
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.running = None;
+    }
+
+    /// The drive cut out at the stop before `update` saw it get there
+    pub fn cut_out(&mut self) {
+        if self.running.is_some() {
+            self.reached = self.running.take();
+        }
+    }
+
+    /// Takes X and returns the outputs, stopping at the stop
+    pub fn update(&mut self, position: f32) -> Output {
+        let stopped = Output {
+            run: None,
+            duty: 0.0,
+            cut_at: None,
+        };
+        let direction = match self.running {
+            Some(direction) => direction,
+            None => return stopped,
+        };
+        let stop = match self.stop_at(direction) {
+            Some(stop) => stop,
+            // The stop was cleared under it
+            None => {
+                self.running = None;
+                return stopped;
+            }
+        };
+        let distance = (stop - position) * direction.sign();
+        if distance <= POWER_FEED_COAST_MM {
+            self.running = None;
+            self.reached = Some(direction);
+            return stopped;
+        }
+        let percent = match self.speed {
+            Speed::Feed => self.feed,
+            Speed::Rapid => 100,
+        };
+        let percent = if distance < POWER_FEED_SLOW_MM {
+            percent.min(POWER_FEED_CREEP)
+        } else {
+            percent
+        };
+        Output {
+            run: Some(direction),
+            duty: percent as f32 / 100.0,
+            cut_at: Some(stop - POWER_FEED_COAST_MM * direction.sign()),
+        }
+    }
+
+    /// Writes the stops and feed speed for storage, returning the bytes
+    /// used. A stop that isn't set is stored as NaN.
+    pub fn write_bytes(&self, buf: &mut [u8]) -> Option<usize> {
+        let buf = buf.get_mut(..9)?;
+        buf[0..4].copy_from_slice(&self.left.unwrap_or(f32::NAN).to_le_bytes());
+        buf[4..8].copy_from_slice(&self.right.unwrap_or(f32::NAN).to_le_bytes());
+        buf[8] = self.feed;
+        Some(9)
+    }
+
+    pub fn read_bytes(&mut self, buf: &[u8]) {
+        if buf.len() < 9 {
+            return;
+        }
+        let stop = |b: &[u8]| {
+            let value = f32::from_le_bytes([b[0], b[1], b[2], b[3]]);
+            if value.is_finite() {
+                Some(value)
+            } else {
+                None
+            }
+        };
+        self.left = stop(&buf[0..4]);
+        self.right = stop(&buf[4..8]);
+        self.feed = buf[8].clamp(MIN_FEED, 100);
+    }
+}
+
+/// The run, dir and speed outputs
+#[cfg_attr(test, allow(dead_code))]
+pub struct Drive {
+    tim: TIM11,
+    period: u32,
+    armed: Option<u32>, // X count the compare is set to
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl Drive {
+    /// Starts the PWM, stopped. PB9 must already be TIM11_CH1 (AF3), and
+    /// PF6 and PF7 push pull outputs. TIM2 must already be counting X.
+    pub fn new(tim: TIM11, clocks: &Clocks) -> Drive {
+        // NOTE(unsafe) only touches the TIM11 enable bit
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc.apb2enr.modify(|_, w| w.tim11en().set_bit());
+
+        let period = clocks.timclk2().0 / PWM_HZ;
+        // OC1M = 110: PWM mode 1, OC1PE: preload
+        tim.ccmr1_output()
+            .write(|w| unsafe { w.bits((0b110 << 4) | (1 << 3)) });
+        tim.ccer.write(|w| unsafe { w.bits(1) });
+        tim.arr.write(|w| unsafe { w.bits(period - 1) });
+        tim.ccr1.write(|w| unsafe { w.bits(0) });
+        tim.egr.write(|w| w.ug().set_bit());
+        tim.cr1.write(|w| w.arpe().set_bit().cen().set_bit());
+
+        let mut drive = Drive {
+            tim,
+            period,
+            armed: None,
+        };
+        drive.set(Output {
+            run: None,
+            duty: 0.0,
+            cut_at: None,
+        });
+        // NOTE(unsafe) the handler only shares CUT, behind a Mutex
+        unsafe { NVIC::unmask(Interrupt::TIM2) };
+        drive
+    }
+
+    pub fn set(&mut self, output: Output) {
+        let duty = (output.duty.clamp(0.0, 1.0) * self.period as f32) as u32;
+        self.tim.ccr1.write(|w| unsafe { w.bits(duty) });
+        let count = output
+            .cut_at
+            .map(|mm| (mm / ENCODER_MM_PER_COUNT[0]).round() as i32 as u32);
+        free(|cs| {
+            if count != self.armed {
+                arm(count);
+                self.armed = count;
+            }
+            // Don't start again before the main loop has seen a cut out
+            let run = if CUT.borrow(cs).get() {
+                None
+            } else {
+                output.run
+            };
+            set_pins(run);
+        });
+    }
+
+    /// Whether the interrupt cut the feed since last time
+    pub fn take_cut(&mut self) -> bool {
+        free(|cs| CUT.borrow(cs).replace(false))
+    }
+}
+
+// Sets the TIM2 channel 3 compare to `count`, or turns it off
+#[cfg_attr(test, allow(dead_code))]
+fn arm(count: Option<u32>) {
+    // NOTE(unsafe) only touches channel 3, which the encoder doesn't use
+    let tim = unsafe { &(*TIM2::ptr()) };
+    tim.dier.modify(|r, w| unsafe { w.bits(r.bits() & !CC3IE) });
+    if let Some(count) = count {
+        tim.ccr3.write(|w| unsafe { w.bits(count) });
+        tim.sr.write(|w| unsafe { w.bits(!CC3IF) });
+        tim.dier.modify(|r, w| unsafe { w.bits(r.bits() | CC3IE) });
+    }
+}
+
+fn set_pins(run: Option<Direction>) {
+    let bits = match run {
+        None => 1 << (RUN_PIN + 16),
+        Some(Direction::Left) => (1 << RUN_PIN) | (1 << (DIR_PIN + 16)),
+        Some(Direction::Right) => (1 << RUN_PIN) | (1 << DIR_PIN),
+    };
+    // NOTE(unsafe) atomic write, only to our own pins
+    unsafe { core::ptr::write_volatile(GPIOF_BSRR as *mut u32, bits) };
+}
+
+#[interrupt]
+fn TIM2() {
+    // NOTE(unsafe) only touches channel 3, which the encoder doesn't use
+    let tim = unsafe { &(*TIM2::ptr()) };
+    tim.dier.modify(|r, w| unsafe { w.bits(r.bits() & !CC3IE) });
+    tim.sr.write(|w| unsafe { w.bits(!CC3IF) });
+    set_pins(None);
+    free(|cs| CUT.borrow(cs).set(true));
+}
@@ -0,0 +1,106 @@
+//! Touch probe / tool setter input.
+//!
+//! The probe pulls PG6 (Arduino D2) low on contact. The falling edge fires
+//! EXTI line 6 and the interrupt copies the encoder counters straight away,
+//! so the position is the one at the instant of contact rather than whenever
+//! the main loop next gets round to it.
+
+use core::cell::Cell;
+use core::fmt::{self, Write};
+
+use cortex_m::interrupt::{free, Mutex};
+use stm32f7xx_hal::pac::{interrupt, Interrupt, EXTI, NVIC, RCC, SYSCFG};
+
+use crate::encoder;
+
+const LINE: u32 = 6;
+const PORT_G: u32 = 6; // EXTICR port number
+
+const AXIS_NAMES: [char; 3] = ['X', 'Y', 'Z'];
+
+// Raw counts at the first contact that hasn't been taken by the main loop
+static CONTACT: Mutex<Cell<Option<[u32; 3]>>> = Mutex::new(Cell::new(None));
+
+/// What to do with the next contact
+#[derive(PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub enum ProbeMode {
+    Off,
+    // Zero an axis, moving towards the edge in direction +1.0 or -1.0
+    Edge(usize, f32),
+    // Store the position in the next free point memory slot
+    Record,
+}
+
+impl ProbeMode {
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn describe<W: Write>(&self, out: &mut W) -> fmt::Result {
+        match *self {
+            ProbeMode::Off => write!(out, "Off"),
+            ProbeMode::Edge(axis, direction) => {
+                let sign = if direction < 0.0 { '-' } else { '+' };
+                write!(out, "Zero {}{}", AXIS_NAMES[axis], sign)
+            }
+            ProbeMode::Record => write!(out, "Record point"),
+        }
+    }
+}
+
+/// What an axis should read when the probe touches an edge while moving in
+/// `direction`. The centre of the stylus is one radius short of the edge.
+/// For Z the radius is the thickness of the plate or height of the setter.
+#[cfg_attr(test, allow(dead_code))]
+pub fn contact_value(direction: f32, radius: f32) -> f32 {
+    0.0 - direction * radius
+}
+
+#[cfg_attr(test, allow(dead_code))]
+pub struct Probe {}
+
+#[cfg_attr(test, allow(dead_code))]
+impl Probe {
+    /// Sets up the interrupt. PG6 must already be an input with a pull up.
+    pub fn new(exti: &EXTI, syscfg: &SYSCFG) -> Probe {
+        // NOTE(unsafe) only touches the SYSCFG enable bit
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc.apb2enr.modify(|_, w| w.syscfgen().set_bit());
+
+        // Line 6 is in the second EXTICR register, bits 8 to 11
+        syscfg
+            .exticr2
+            .modify(|r, w| unsafe { w.bits((r.bits() & !(0xF << 8)) | (PORT_G << 8)) });
+        exti.ftsr
+            .modify(|r, w| unsafe { w.bits(r.bits() | (1 << LINE)) });
+        exti.rtsr
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << LINE)) });
+        exti.pr.write(|w| unsafe { w.bits(1 << LINE) });
+        exti.imr
+            .modify(|r, w| unsafe { w.bits(r.bits() | (1 << LINE)) });
+
+        // NOTE(unsafe) the handler only shares CONTACT, behind a Mutex
+        unsafe { NVIC::unmask(Interrupt::EXTI9_5) };
+
+        Probe {}
+    }
+
+    /// Raw encoder counts at the last contact, once. Bounces after the
+    /// first edge are ignored until this has been called.
+    pub fn take_contact(&mut self) -> Option<[u32; 3]> {
+        free(|cs| CONTACT.borrow(cs).replace(None))
+    }
+}
+
+#[interrupt]
+fn EXTI9_5() {
+    let counts = encoder::latch();
+    // NOTE(unsafe) write to clear our own pending bit
+    let exti = unsafe { &(*EXTI::ptr()) };
+    exti.pr.write(|w| unsafe { w.bits(1 << LINE) });
+
+    free(|cs| {
+        let contact = CONTACT.borrow(cs);
+        if contact.get().is_none() {
+            contact.set(Some(counts));
+        }
+    });
+}
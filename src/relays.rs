@@ -0,0 +1,262 @@
+//! Relay outputs for coolant, dust extraction and spindle enable.
+//!
+//! Each output is set on its page to on, off, or auto. On auto it follows
+//! a rule:
+//!
+//!   Coolant  while the controller has coolant on, M7 or M8 until M9
+//!   Vacuum   while the spindle runs, from the controller or the spindle
+//!            relay, and `RELAY_VACUUM_RUN_ON_MS` after to clear the dust
+//!   Spindle  while the controller has the spindle on, M3 or M4 until M5
+//!
+//! A latched safety input turns everything off at once, and anything set
+//! to on goes back to off, so acknowledging the fault doesn't start it
+//! again. Without a controller, auto outputs are off.
+//!
+//! The Arduino header is full, so they are on the camera connector, high
+//! for on:
+//!
+//!   Coolant  PA4 (DCMI_HSYNC)
+//!   Vacuum   PA6 (DCMI_PIXCLK)
+//!   Spindle  PH12 (DCMI_D3)
+//!
+//! Nothing here touches hardware, main.rs sets the pins from `on`.
+
+use crate::backend::Accessories;
+use crate::consts::*;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Output {
+    Coolant,
+    Vacuum,
+    Spindle,
+}
+
+pub const OUTPUTS: [Output; 3] = [Output::Coolant, Output::Vacuum, Output::Spindle];
+
+impl Output {
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn name(self) -> &'static str {
+        match self {
+            Output::Coolant => "Coolant",
+            Output::Vacuum => "Vacuum",
+            Output::Spindle => "Spindle",
+        }
+    }
+
+    /// For the status line, where there's only room for a letter or two
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn short_name(self) -> &'static str {
+        match self {
+            Output::Coolant => "C",
+            Output::Vacuum => "V",
+            Output::Spindle => "S",
+        }
+    }
+
+    // How long it stays on once its rule lets go
+    fn run_on_ms(self) -> u32 {
+        match self {
+            Output::Vacuum => RELAY_VACUUM_RUN_ON_MS,
+            Output::Coolant | Output::Spindle => 0,
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Setting {
+    Auto,
+    On,
+    Off,
+}
+
+impl Setting {
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn name(self) -> &'static str {
+        match self {
+            Setting::Auto => "auto",
+            Setting::On => "on",
+            Setting::Off => "off",
+        }
+    }
+
+    // Each press of an output's key moves it on
+    fn next(self) -> Setting {
+        match self {
+            Setting::Auto => Setting::On,
+            Setting::On => Setting::Off,
+            Setting::Off => Setting::Auto,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Relays {
+    settings: [Setting; 3],
+    on: [bool; 3],
+    // When each rule last wanted its output on
+    wanted_ms: [Option<u32>; 3],
+}
+
+impl Relays {
+    pub fn new() -> Relays {
+        Relays {
+            settings: [Setting::Auto; 3],
+            on: [false; 3],
+            wanted_ms: [None; 3],
+        }
+    }
+
+    pub fn setting(&self, output: Output) -> Setting {
+        self.settings[output as usize]
+    }
+
+    pub fn is_on(&self, output: Output) -> bool {
+        self.on[output as usize]
+    }
+
+    /// Moves an output on to its next setting, skipping on while `faulted`
+    pub fn toggle(&mut self, output: Output, faulted: bool) {
+        let setting = &mut self.settings[output as usize];
+        *setting = setting.next();
+        if faulted && *setting == Setting::On {
+            *setting = setting.next();
+        }
+    }
+
+    /// Takes what the controller has on, if it's connected, and the time.
+    /// Returns true if any output changed.
+    pub fn update(&mut self, accessories: Option<Accessories>, faulted: bool, now: u32) -> bool {
+        let before = self.on;
+        if faulted {
+            for setting in self.settings.iter_mut() {
+                if *setting == Setting::On {
+                    *setting = Setting::Off;
+                }
+            }
+            self.on = [false; 3];
+            self.wanted_ms = [None; 3];
+            return self.on != before;
+        }
+
+        // The spindle first, as the vacuum follows it
+        for output in [Output::Spindle, Output::Coolant, Output::Vacuum] {
+            let i = output as usize;
+            let wanted = match (output, accessories) {
+                (Output::Vacuum, _) if self.on[Output::Spindle as usize] => true,
+                (_, None) => false,
+                (Output::Coolant, Some(a)) => a.coolant,
+                (Output::Spindle, Some(a)) | (Output::Vacuum, Some(a)) => a.spindle,
+            };
+            if wanted {
+                self.wanted_ms[i] = Some(now);
+            }
+            let running_on = match self.wanted_ms[i] {
+                Some(at) => now.wrapping_sub(at) < output.run_on_ms(),
+                None => false,
+            };
+            self.on[i] = match self.settings[i] {
+                Setting::On => true,
+                Setting::Off => false,
+                Setting::Auto => wanted || running_on,
+            };
+        }
+        self.on != before
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn running(spindle: bool, coolant: bool) -> Option<Accessories> {
+        Some(Accessories { spindle, coolant })
+    }
+
+    fn on(relays: &Relays) -> [bool; 3] {
+        OUTPUTS.map(|output| relays.is_on(output))
+    }
+
+    #[test]
+    fn settings_cycle() {
+        let mut relays = Relays::new();
+        let settings: Vec<Setting> = (0..4)
+            .map(|_| {
+                let setting = relays.setting(Output::Coolant);
+                relays.toggle(Output::Coolant, false);
+                setting
+            })
+            .collect();
+        assert_eq!(
+            settings,
+            [Setting::Auto, Setting::On, Setting::Off, Setting::Auto]
+        );
+
+        // Can't be turned on while faulted
+        let mut relays = Relays::new();
+        relays.toggle(Output::Spindle, true);
+        assert_eq!(relays.setting(Output::Spindle), Setting::Off);
+        assert_eq!(relays.setting(Output::Vacuum), Setting::Auto);
+    }
+
+    #[test]
+    fn auto_follows_the_controller() {
+        let mut relays = Relays::new();
+        assert!(!relays.update(None, false, 0));
+        assert!(relays.update(running(false, true), false, 10));
+        assert_eq!(on(&relays), [true, false, false]);
+        assert!(relays.update(running(true, true), false, 20));
+        assert_eq!(on(&relays), [true, true, true]);
+        assert!(!relays.update(running(true, true), false, 30));
+
+        // The vacuum runs on after the spindle stops
+        assert!(relays.update(running(false, false), false, 1000));
+        assert_eq!(on(&relays), [false, true, false]);
+        let end = 30 + RELAY_VACUUM_RUN_ON_MS;
+        assert!(!relays.update(running(false, false), false, end - 1));
+        assert!(relays.update(running(false, false), false, end));
+        assert_eq!(on(&relays), [false, false, false]);
+
+        // Losing the controller turns auto outputs off, bar the run on
+        relays.update(running(true, true), false, 5000);
+        relays.update(None, false, 5010);
+        assert_eq!(on(&relays), [false, true, false]);
+    }
+
+    #[test]
+    fn vacuum_follows_the_spindle_relay() {
+        let mut relays = Relays::new();
+        relays.toggle(Output::Spindle, false);
+        relays.update(None, false, 0);
+        assert_eq!(on(&relays), [false, true, true]);
+
+        // Forced off, the vacuum stays off whatever the spindle does
+        relays.toggle(Output::Vacuum, false);
+        relays.toggle(Output::Vacuum, false);
+        relays.update(running(true, false), false, 10);
+        assert_eq!(on(&relays), [false, false, true]);
+    }
+
+    #[test]
+    fn fault_turns_everything_off() {
+        let mut relays = Relays::new();
+        relays.toggle(Output::Coolant, false);
+        relays.toggle(Output::Vacuum, false);
+        relays.toggle(Output::Vacuum, false);
+        relays.update(running(true, false), false, 0);
+        assert_eq!(on(&relays), [true, false, true]);
+
+        assert!(relays.update(running(true, true), true, 10));
+        assert_eq!(on(&relays), [false, false, false]);
+        // On becomes off, so clearing the fault doesn't start it again.
+        // Off and auto stay as they were.
+        assert_eq!(relays.setting(Output::Coolant), Setting::Off);
+        assert_eq!(relays.setting(Output::Vacuum), Setting::Off);
+        assert_eq!(relays.setting(Output::Spindle), Setting::Auto);
+
+        // No run on either
+        assert!(!relays.update(running(false, false), false, 20));
+        assert_eq!(on(&relays), [false, false, false]);
+        relays.update(running(true, false), false, 30);
+        assert_eq!(on(&relays), [false, false, true]);
+    }
+}
@@ -0,0 +1,286 @@
+//! E-stop, door interlock and external fault inputs.
+//!
+//! Each is a normally closed contact to ground on an Arduino pin with a
+//! pull up, so a pressed E-stop, an open door or a broken wire all read
+//! high. The rising edge fires an EXTI interrupt, which notes the input as
+//! tripped even if it bounces straight back, and the main loop hands that
+//! and the present levels to `Interlocks`.
+//!
+//! `Interlocks` latches every trip until the input is healthy again and
+//! the trip has been acknowledged on screen. It doesn't touch hardware.
+//!
+//!   E-stop  PI0 (Arduino D5)  EXTI line 0
+//!   Door    PI3 (Arduino D7)  EXTI line 3
+//!   Fault   PI2 (Arduino D8)  EXTI line 2
+
+use core::cell::Cell;
+
+use cortex_m::interrupt::{free, Mutex};
+use stm32f7xx_hal::pac::{interrupt, Interrupt, EXTI, GPIOI, NVIC, RCC, SYSCFG};
+
+const PORT_I: u32 = 8; // EXTICR port number
+
+// Inputs that went into their fault state since the main loop last looked
+static TRIPPED: Mutex<Cell<u8>> = Mutex::new(Cell::new(0));
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Input {
+    EStop,
+    Door,
+    Fault,
+}
+
+pub const INPUTS: [Input; 3] = [Input::EStop, Input::Door, Input::Fault];
+
+impl Input {
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    // Pin on port I, which is also the EXTI line
+    fn line(self) -> u32 {
+        match self {
+            Input::EStop => 0,
+            Input::Door => 3,
+            Input::Fault => 2,
+        }
+    }
+
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn name(self) -> &'static str {
+        match self {
+            Input::EStop => "E-stop",
+            Input::Door => "Door",
+            Input::Fault => "Fault",
+        }
+    }
+}
+
+/// A set of inputs
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub struct Inputs(u8);
+
+impl Inputs {
+    pub fn none() -> Inputs {
+        Inputs(0)
+    }
+
+    pub fn contains(self, input: Input) -> bool {
+        self.0 & input.bit() != 0
+    }
+
+    pub fn any(self) -> bool {
+        self.0 != 0
+    }
+
+    /// An E-stop or fault stops the machine, an open door only holds it
+    pub fn stops(self) -> bool {
+        self.contains(Input::EStop) || self.contains(Input::Fault)
+    }
+}
+
+/// Which inputs are latched, and which are still in their fault state
+#[derive(Copy, Clone, Debug)]
+pub struct Interlocks {
+    latched: Inputs,
+    active: Inputs,
+}
+
+impl Interlocks {
+    pub fn new() -> Interlocks {
+        Interlocks {
+            latched: Inputs::none(),
+            active: Inputs::none(),
+        }
+    }
+
+    /// Takes the inputs in their fault state now, and those that tripped
+    /// since the last update even if healthy again. Returns the inputs
+    /// that weren't already latched.
+    pub fn update(&mut self, active: Inputs, tripped: Inputs) -> Inputs {
+        self.active = active;
+        let new = Inputs((active.0 | tripped.0) & !self.latched.0);
+        self.latched.0 |= new.0;
+        new
+    }
+
+    pub fn latched(&self) -> Inputs {
+        self.latched
+    }
+
+    pub fn active(&self) -> Inputs {
+        self.active
+    }
+
+    pub fn faulted(&self) -> bool {
+        self.latched.any()
+    }
+
+    /// Every latched input is healthy again
+    pub fn can_acknowledge(&self) -> bool {
+        self.latched.0 & self.active.0 == 0
+    }
+
+    /// Clears the latch, if every input is healthy. Returns true if it did.
+    pub fn acknowledge(&mut self) -> bool {
+        if !self.faulted() || !self.can_acknowledge() {
+            return false;
+        }
+        self.latched = Inputs::none();
+        true
+    }
+}
+
+#[cfg_attr(test, allow(dead_code))]
+pub struct Safety {}
+
+#[cfg_attr(test, allow(dead_code))]
+impl Safety {
+    /// Sets up the interrupts. The pins must already be inputs with pull
+    /// ups.
+    pub fn new(exti: &EXTI, syscfg: &SYSCFG) -> Safety {
+        // NOTE(unsafe) only touches the SYSCFG enable bit
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc.apb2enr.modify(|_, w| w.syscfgen().set_bit());
+
+        for input in INPUTS {
+            let line = input.line();
+            // Lines 0 to 3 are in the first EXTICR register, 4 bits each
+            let shift = line * 4;
+            syscfg
+                .exticr1
+                .modify(|r, w| unsafe { w.bits((r.bits() & !(0xF << shift)) | (PORT_I << shift)) });
+            exti.rtsr
+                .modify(|r, w| unsafe { w.bits(r.bits() | (1 << line)) });
+            exti.ftsr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << line)) });
+            exti.pr.write(|w| unsafe { w.bits(1 << line) });
+            exti.imr
+                .modify(|r, w| unsafe { w.bits(r.bits() | (1 << line)) });
+        }
+
+        // NOTE(unsafe) the handlers only share TRIPPED, behind a Mutex
+        unsafe {
+            NVIC::unmask(Interrupt::EXTI0);
+            NVIC::unmask(Interrupt::EXTI2);
+            NVIC::unmask(Interrupt::EXTI3);
+        }
+        Safety {}
+    }
+
+    /// The inputs in their fault state now
+    pub fn active(&self) -> Inputs {
+        // NOTE(unsafe) atomic read of the input data register
+        let levels = unsafe { (*GPIOI::ptr()).idr.read().bits() };
+        let mut active = Inputs::none();
+        for input in INPUTS {
+            if levels & (1 << input.line()) != 0 {
+                active.0 |= input.bit();
+            }
+        }
+        active
+    }
+
+    /// The inputs that tripped since last time
+    pub fn take_tripped(&mut self) -> Inputs {
+        Inputs(free(|cs| TRIPPED.borrow(cs).replace(0)))
+    }
+}
+
+fn tripped(input: Input) {
+    // NOTE(unsafe) write to clear our own pending bit
+    let exti = unsafe { &(*EXTI::ptr()) };
+    exti.pr.write(|w| unsafe { w.bits(1 << input.line()) });
+
+    free(|cs| {
+        let tripped = TRIPPED.borrow(cs);
+        tripped.set(tripped.get() | input.bit());
+    });
+}
+
+#[interrupt]
+fn EXTI0() {
+    tripped(Input::EStop);
+}
+
+#[interrupt]
+fn EXTI2() {
+    tripped(Input::Fault);
+}
+
+#[interrupt]
+fn EXTI3() {
+    tripped(Input::Door);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs(list: &[Input]) -> Inputs {
+        Inputs(list.iter().fold(0, |bits, input| bits | input.bit()))
+    }
+
+    #[test]
+    fn latches_until_acknowledged() {
+        let mut interlocks = Interlocks::new();
+        assert!(!interlocks.faulted());
+        assert!(!interlocks.acknowledge()); // Nothing to acknowledge
+
+        let estop = inputs(&[Input::EStop]);
+        assert_eq!(interlocks.update(estop, estop), estop);
+        assert!(interlocks.faulted());
+        // Still pressed: latched, and it won't clear
+        assert_eq!(interlocks.update(estop, Inputs::none()), Inputs::none());
+        assert!(!interlocks.can_acknowledge());
+        assert!(!interlocks.acknowledge());
+        assert_eq!(interlocks.latched(), estop);
+
+        // Released: stays latched until acknowledged
+        interlocks.update(Inputs::none(), Inputs::none());
+        assert!(interlocks.faulted());
+        assert_eq!(interlocks.active(), Inputs::none());
+        assert!(interlocks.can_acknowledge());
+        assert!(interlocks.acknowledge());
+        assert!(!interlocks.faulted());
+        // Pressed again is news again
+        assert_eq!(interlocks.update(estop, Inputs::none()), estop);
+    }
+
+    #[test]
+    fn bounce_still_latches() {
+        // Tripped and healthy again before the main loop looked
+        let mut interlocks = Interlocks::new();
+        let door = inputs(&[Input::Door]);
+        assert_eq!(interlocks.update(Inputs::none(), door), door);
+        assert_eq!(interlocks.latched(), door);
+        assert!(interlocks.can_acknowledge());
+        assert!(interlocks.acknowledge());
+    }
+
+    #[test]
+    fn every_input_healthy_to_acknowledge() {
+        let mut interlocks = Interlocks::new();
+        let both = inputs(&[Input::Door, Input::Fault]);
+        assert_eq!(interlocks.update(both, both), both);
+        // A second trip of what's latched isn't news
+        let fault = inputs(&[Input::Fault]);
+        assert_eq!(interlocks.update(fault, fault), Inputs::none());
+        assert!(!interlocks.acknowledge());
+        assert_eq!(interlocks.latched(), both);
+        interlocks.update(Inputs::none(), Inputs::none());
+        assert!(interlocks.acknowledge());
+        assert_eq!(interlocks.latched(), Inputs::none());
+    }
+
+    #[test]
+    fn what_stops() {
+        assert!(inputs(&[Input::EStop]).stops());
+        assert!(inputs(&[Input::Fault]).stops());
+        assert!(!inputs(&[Input::Door]).stops());
+        assert!(inputs(&[Input::Door]).any());
+        assert!(!Inputs::none().any());
+        let all = inputs(&INPUTS);
+        assert!(INPUTS.iter().all(|input| all.contains(*input)));
+    }
+}
@@ -1,11 +1,13 @@
 use embedded_graphics::{
-    // drawable::Pixel,
-    geometry::Size,
-    pixelcolor::{Rgb565, RgbColor},
+    image::Image,
+    mono_font::{MonoFont, MonoTextStyle},
+    pixelcolor::{BinaryColor, Rgb565, RgbColor},
     prelude::*,
     primitives::Rectangle,
+    text::Text,
     Pixel,
 };
+use rtt_target::rprintln;
 
 use stm32f7xx_hal::{
     ltdc::{DisplayConfig, DisplayController, Layer, PixelFormat, SupportedWord},
@@ -49,41 +51,244 @@ impl<T: 'static + SupportedWord> Stm32F7DiscoDisplay<T> {
     }
 }
 
+// Pixels staged per transfer by `fill_contiguous`, in each of two halves
+// so one fills while DMA2D copies the other. Two rows at full width.
+const STAGE_PIXELS: usize = 960;
+
+// Largest glyph `draw_glyphs` blends, one byte a pixel
+const GLYPH_BYTES: usize = 1024;
+
+static mut STAGING: [[u16; STAGE_PIXELS]; 2] = [[0; STAGE_PIXELS]; 2];
+static mut GLYPH: [u8; GLYPH_BYTES] = [0; GLYPH_BYTES];
+
+/// How a mask for `blit_alpha` holds its alpha
+#[derive(PartialEq, Copy, Clone, Debug)]
+enum Alpha {
+    // A byte a pixel
+    A8,
+    // Two pixels a byte, the first in the low nibble. Rows must be even.
+    A4,
+}
+
+fn raw(color: Rgb565) -> u16 {
+    (color.b() as u16 & 0x1F) | ((color.g() as u16 & 0x3F) << 5) | ((color.r() as u16 & 0x1F) << 11)
+}
+
+// Rgb565 to 8 bits a channel, repeating the top bits into the bottom
+fn rgb888(color: Rgb565) -> (u8, u8, u8) {
+    (
+        color.r() << 3 | color.r() >> 2,
+        color.g() << 2 | color.g() >> 4,
+        color.b() << 3 | color.b() >> 2,
+    )
+}
+
+fn dma2d() -> &'static stm32f7xx_hal::pac::dma2d::RegisterBlock {
+    // NOTE(unsafe) the controller owns DMA2D but only uses it from its
+    // draw_rectangle, which nothing calls
+    unsafe { &*DMA2D::ptr() }
+}
+
+/// Builds an alpha mask of a glyph, for `blit_alpha`
+struct Mask<'a> {
+    data: &'a mut [u8],
+    size: Size,
+    alpha: Alpha,
+}
+
+impl OriginDimensions for Mask<'_> {
+    fn size(&self) -> Size {
+        self.size
+    }
+}
+
+impl DrawTarget for Mask<'_> {
+    type Color = BinaryColor;
+    type Error = core::convert::Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(coord, color) in pixels.into_iter() {
+            if color.is_off() || !self.bounding_box().contains(coord) {
+                continue;
+            }
+            let i = (coord.x + coord.y * self.size.width as i32) as usize;
+            match self.alpha {
+                Alpha::A8 => self.data[i] = 0xFF,
+                Alpha::A4 => self.data[i / 2] |= 0x0F << (4 * (i % 2)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fills, copies and glyph blits go through DMA2D, each waiting until it's
+/// done, so the framebuffer is never written by both it and the CPU.
 impl Stm32F7DiscoDisplay<u16> {
-    /// Draw a hardware accelerated (by DMA2D) rectangle
-    #[allow(dead_code)]
-    pub fn draw_rectangle(
+    // Address in the framebuffer of a point, which must be on the screen
+    fn address(&self, at: Point) -> u32 {
+        // NOTE(unsafe) atomic read of the layer's framebuffer address,
+        // set by config_layer
+        let base = unsafe { (*LTDC::ptr()).layer1.cfbar.read().bits() };
+        base + 2 * (at.x as u32 + at.y as u32 * DISCO_SCREEN_CONFIG.active_width as u32)
+    }
+
+    // Area given as on the screen, and its line offset
+    fn set_output(&self, area: &Rectangle) -> u16 {
+        let dma2d = dma2d();
+        let offset = DISCO_SCREEN_CONFIG.active_width - area.size.width as u16;
+        // NOTE(unsafe) the address is within the framebuffer
+        dma2d
+            .omar
+            .write(|w| unsafe { w.bits(self.address(area.top_left)) });
+        dma2d.oor.write(|w| w.lo().bits(offset));
+        dma2d.opfccr.write(|w| w.cm().rgb565());
+        dma2d.nlr.write(|w| {
+            w.pl()
+                .bits(area.size.width as u16)
+                .nl()
+                .bits(area.size.height as u16)
+        });
+        offset
+    }
+
+    // Waits for the transfer under way, if any
+    fn wait(&self) {
+        let dma2d = dma2d();
+        while dma2d.cr.read().start().bit_is_set() {}
+        if dma2d.isr.read().teif().bit_is_set() || dma2d.isr.read().ceif().bit_is_set() {
+            rprintln!("DMA2D transfer error");
+        }
+        dma2d.ifcr.write(|w| {
+            w.ctcif()
+                .clear()
+                .cteif()
+                .clear()
+                .cceif()
+                .clear()
+                .caecif()
+                .clear()
+                .ctwif()
+                .clear()
+                .cctcif()
+                .clear()
+        });
+    }
+
+    // Register to memory, for an area on the screen
+    fn fill_area(&mut self, area: &Rectangle, color: Rgb565) {
+        let dma2d = dma2d();
+        self.set_output(area);
+        // NOTE(unsafe) RGB565 takes the colour in the low half as is
+        dma2d.ocolr.write(|w| unsafe { w.bits(raw(color) as u32) });
+        dma2d
+            .cr
+            .write(|w| w.mode().register_to_memory().start().start());
+        self.wait();
+    }
+
+    // Memory to memory, `rows` of `pixels` to the screen at `at`. Doesn't
+    // wait, so the caller mustn't touch `pixels` until it has.
+    fn start_copy(&mut self, pixels: &[u16], at: Point, width: u32, rows: u32) {
+        let dma2d = dma2d();
+        self.set_output(&Rectangle::new(at, Size::new(width, rows)));
+        // NOTE(unsafe) DMA2D reads `width` times `rows` of them
+        dma2d
+            .fgmar
+            .write(|w| unsafe { w.bits(pixels.as_ptr() as u32) });
+        dma2d.fgor.write(|w| w.lo().bits(0));
+        dma2d.fgpfccr.write(|w| w.cm().rgb565());
+        dma2d
+            .cr
+            .write(|w| w.mode().memory_to_memory().start().start());
+    }
+
+    // Blends `color` onto the screen through an alpha mask covering `area`,
+    // which must be on the screen
+    fn blit_alpha(&mut self, area: &Rectangle, mask: &[u8], alpha: Alpha, color: Rgb565) {
+        let dma2d = dma2d();
+        let (r, g, b) = rgb888(color);
+        let offset = self.set_output(area);
+        // NOTE(unsafe) the background is the output, which is on the
+        // screen, and the mask covers the area
+        dma2d
+            .bgmar
+            .write(|w| unsafe { w.bits(self.address(area.top_left)) });
+        dma2d.bgor.write(|w| w.lo().bits(offset));
+        dma2d.bgpfccr.write(|w| w.cm().rgb565());
+        dma2d
+            .fgmar
+            .write(|w| unsafe { w.bits(mask.as_ptr() as u32) });
+        dma2d.fgor.write(|w| w.lo().bits(0));
+        dma2d
+            .fgcolr
+            .write(|w| w.red().bits(r).green().bits(g).blue().bits(b));
+        dma2d.fgpfccr.write(|w| match alpha {
+            Alpha::A8 => w.am().no_modify().cm().a8(),
+            Alpha::A4 => w.am().no_modify().cm().a4(),
+        });
+        dma2d
+            .cr
+            .write(|w| w.mode().memory_to_memory_pfcblending().start().start());
+        self.wait();
+    }
+
+    /// Draws `text` in a mono font with a clear background, the same as
+    /// `Text` with the alphabetic baseline at `position`, blending each
+    /// glyph through DMA2D. Returns where the next character would go.
+    pub fn draw_glyphs(
         &mut self,
-        rect: Rectangle,
-        _c: Rgb565, // item: &Styled<primitives::rectangle::Rectangle, PrimitiveStyle<Rgb565>>,
-    ) -> Result<(), <Stm32F7DiscoDisplay<u16> as DrawTarget>::Error> {
-        if let Some(_bottom_right) = rect.bottom_right() {
-            // if item.style.stroke_color.is_none() {
-            // let color = match item.style.fill_color {
-            //     Some(c) => {
-            //         (c.b() as u32 & 0x1F)
-            //             | ((c.g() as u32 & 0x3F) << 5)
-            //             | ((c.r() as u32 & 0x1F) << 11)
-            //     }
-            //     None => 0u32,
-            // };
-
-            // Note(unsafe) because transfert might not be before an other write
-            // to the buffer occurs. However, such Register -> Buffer transfert
-            // is so fast that such issue does not occur
-            // TODO : use safer DMA api when the embedde-hal DMA traits will be stabilised
-            // unsafe {
-            //     self.controller
-            //         .draw_rectangle(Layer::L1,
-            //             (item.primitive.top_left.x as usize, item.primitive.top_left.y as usize),
-            //             (bottom_right.x as usize, bottom_right.y as usize),
-            //             (c.b() as u32 & 0x1F)
-            //                         | ((c.g() as u32 & 0x3F) << 5)
-            //                         | ((c.r() as u32 & 0x1F) << 11));
-            // }
-            // }
+        text: &str,
+        font: &MonoFont,
+        position: Point,
+        color: Rgb565,
+    ) -> Point {
+        let size = font.character_size;
+        let advance = (size.width + font.character_spacing) as i32;
+        let per_row = font.image.size().width / size.width;
+        // Packing two pixels a byte halves what DMA2D reads
+        let alpha = if size.width % 2 == 1 {
+            Alpha::A8
+        } else {
+            Alpha::A4
+        };
+        let bytes = match alpha {
+            Alpha::A8 => (size.width * size.height) as usize,
+            Alpha::A4 => (size.width * size.height) as usize / 2,
+        };
+
+        let mut at = position;
+        for c in text.chars() {
+            let area = Rectangle::new(at - Point::new(0, font.baseline as i32), size);
+            if bytes > GLYPH_BYTES || self.bounding_box().intersection(&area) != area {
+                // Too big for the mask, or partly off the screen
+                let mut b = [0; 4];
+                let style = MonoTextStyle::new(font, color);
+                Text::new(c.encode_utf8(&mut b), at, style).draw(self).ok();
+            } else {
+                let glyph = font.glyph_mapping.index(c) as u32;
+                let source = Rectangle::new(
+                    Point::new(
+                        ((glyph % per_row) * size.width) as i32,
+                        ((glyph / per_row) * size.height) as i32,
+                    ),
+                    size,
+                );
+                // NOTE(unsafe) only used here, and DMA2D is done with it
+                // by the time this returns
+                let data = unsafe { &mut GLYPH[..bytes] };
+                data.iter_mut().for_each(|a| *a = 0);
+                let mut mask = Mask { data, size, alpha };
+                Image::new(&font.image.sub_image(&source), Point::zero())
+                    .draw(&mut mask)
+                    .ok();
+                self.blit_alpha(&area, mask.data, alpha, color);
+            }
+            at.x += advance;
         }
-        Ok(())
+        at
     }
 }
 
@@ -91,34 +296,71 @@ impl DrawTarget for Stm32F7DiscoDisplay<u16> {
     type Color = Rgb565;
     type Error = core::convert::Infallible;
 
-    /// Draw a `Pixel` that has a color defined
-    // fn draw_pixel(&mut self, pixel: Pixel<Rgb565>) -> Result<(), Self::Error> {
-    //     let Pixel(coord, color) = pixel;
-    //     let value: u16 = (color.b() as u16 & 0x1F)
-    //         | ((color.g() as u16 & 0x3F) << 5)
-    //         | ((color.r() as u16 & 0x1F) << 11);
-
-    //     // TODO : draw pixel
-    //     self.controller
-    //         .draw_pixel(Layer::L1, coord.x as usize, coord.y as usize, value);
-    //     Ok(())
-    // }
-
     fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
     where
         I: IntoIterator<Item = Pixel<Self::Color>>,
     {
         for Pixel(coord, color) in pixels.into_iter() {
-            let value: u16 = (color.b() as u16 & 0x1F)
-                | ((color.g() as u16 & 0x3F) << 5)
-                | ((color.r() as u16 & 0x1F) << 11);
-
-            // TODO : draw pixel
             self.controller
-                .draw_pixel(Layer::L1, coord.x as usize, coord.y as usize, value);
+                .draw_pixel(Layer::L1, coord.x as usize, coord.y as usize, raw(color));
         }
         Ok(())
     }
+
+    fn fill_contiguous<I>(&mut self, area: &Rectangle, colors: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Self::Color>,
+    {
+        let screen = self.bounding_box();
+        if area.is_zero_sized() {
+            return Ok(());
+        }
+        if screen.intersection(area) != *area {
+            // Partly off the screen, so some of the colours are skipped
+            let pixels = area
+                .points()
+                .zip(colors)
+                .filter(|(p, _)| screen.contains(*p))
+                .map(|(p, c)| Pixel(p, c));
+            return self.draw_iter(pixels);
+        }
+
+        // Stages whole rows in one half while DMA2D copies the other
+        let width = area.size.width;
+        let rows = STAGE_PIXELS as u32 / width;
+        let end = area.top_left.y + area.size.height as i32;
+        let mut colors = colors.into_iter();
+        let mut y = area.top_left.y;
+        let mut half = 0;
+        while y < end {
+            let n = rows.min((end - y) as u32);
+            // NOTE(unsafe) only used here, and DMA2D only ever reads the
+            // other half while this one is filled
+            let staged = unsafe { &mut STAGING[half][..(n * width) as usize] };
+            for (pixel, color) in staged.iter_mut().zip(&mut colors) {
+                *pixel = raw(color);
+            }
+            self.wait();
+            self.start_copy(staged, Point::new(area.top_left.x, y), width, n);
+            half = 1 - half;
+            y += n as i32;
+        }
+        self.wait();
+        Ok(())
+    }
+
+    fn fill_solid(&mut self, area: &Rectangle, color: Self::Color) -> Result<(), Self::Error> {
+        let area = self.bounding_box().intersection(area);
+        if !area.is_zero_sized() {
+            self.fill_area(&area, color);
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, color: Self::Color) -> Result<(), Self::Error> {
+        self.fill_area(&self.bounding_box(), color);
+        Ok(())
+    }
 }
 impl OriginDimensions for Stm32F7DiscoDisplay<u16> {
     /// Return the size of the screen
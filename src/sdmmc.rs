@@ -0,0 +1,329 @@
+//! SD card on SDMMC1: PC8-PC11 data, PC12 clock, PD2 command.
+//!
+//! Polled, one block at a time, which is plenty for reading G-code. The
+//! card is brought up at 400kHz from the 48MHz PLL48CLK, then switched to
+//! 4 bit mode at 24MHz. Hardware flow control stops the card clock when the
+//! FIFO fills, so a slow main loop can't overrun it.
+//!
+//! `SdCard` is an embedded-sdmmc `BlockDevice`, which only ever borrows it
+//! shared. That's fine here: the registers are volatile cells anyway.
+
+use embedded_sdmmc::{Block, BlockCount, BlockDevice, BlockIdx};
+use stm32f7xx_hal::{pac::SDMMC1, rcc::Clocks};
+
+const INIT_CLKDIV: u8 = 118; // 48MHz / (118 + 2) = 400kHz
+const FAST_CLKDIV: u8 = 0; // 48MHz / 2 = 24MHz
+const DATA_TIMEOUT: u32 = 24_000_000; // Card clocks, one second at 24MHz
+const INIT_TRIES: u32 = 2000; // ACMD41 polls before giving up, ~1s at 400kHz
+const BUSY_TRIES: u32 = 100_000;
+
+// Every static flag in STA/ICR
+const CLEAR_FLAGS: u32 = 0x5ff;
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub enum Error {
+    NoClock, // PLL48CLK isn't running
+    NoCard,
+    Timeout,
+    Crc,
+    Overrun,
+    Unsupported,
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+enum Response {
+    None,
+    Short,
+    Long,
+    // R3 has no CRC, so the CRC check always fails
+    ShortNoCrc,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+pub struct SdCard {
+    sdmmc: SDMMC1,
+    ready: bool,
+    high_capacity: bool, // Addressed in blocks, not bytes
+    rca: u32,
+    blocks: u32,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl SdCard {
+    /// Needs `use_pll48clk()` in the clock setup. Nothing is sent to the
+    /// card until `init`.
+    pub fn new(sdmmc: SDMMC1, clocks: &Clocks) -> Result<SdCard, Error> {
+        if !clocks.is_pll48clk_valid() {
+            return Err(Error::NoClock);
+        }
+
+        // NOTE(unsafe) only the SDMMC1 enable and reset bits are touched
+        let rcc = unsafe { &(*stm32f7xx_hal::pac::RCC::ptr()) };
+        rcc.apb2enr.modify(|_, w| w.sdmmc1en().set_bit());
+        rcc.apb2rstr.modify(|_, w| w.sdmmc1rst().set_bit());
+        rcc.apb2rstr.modify(|_, w| w.sdmmc1rst().clear_bit());
+        // Kernel clock from PLL48CLK rather than SYSCLK
+        rcc.dckcfgr2.modify(|_, w| w.sdmmc1sel().clear_bit());
+
+        Ok(SdCard {
+            sdmmc,
+            ready: false,
+            high_capacity: false,
+            rca: 0,
+            blocks: 0,
+        })
+    }
+
+    /// Identifies the card and gets it ready for reads and writes. Call
+    /// again after a card is swapped.
+    pub fn init(&mut self) -> Result<(), Error> {
+        self.ready = false;
+        let sdmmc = &self.sdmmc;
+        sdmmc.power.write(|w| unsafe { w.pwrctrl().bits(0) });
+        sdmmc
+            .clkcr
+            .write(|w| unsafe { w.clkdiv().bits(INIT_CLKDIV).clken().set_bit() });
+        sdmmc.power.write(|w| unsafe { w.pwrctrl().bits(0b11) });
+        // The card wants 74 clocks before the first command, 200us or so
+        for _ in 0..100_000 {
+            cortex_m::asm::nop();
+        }
+
+        self.command(0, 0, Response::None)?;
+
+        // Only version 2 cards answer CMD8, with the check pattern echoed
+        let v2 = match self.command(8, 0x1aa, Response::Short) {
+            Ok(r) if r & 0xfff == 0x1aa => true,
+            Ok(_) => return Err(Error::Unsupported),
+            Err(Error::Timeout) => false,
+            Err(e) => return Err(e),
+        };
+
+        let hcs = if v2 { 0x4000_0000 } else { 0 };
+        let mut ocr = 0;
+        for _ in 0..INIT_TRIES {
+            match self.command(55, 0, Response::Short) {
+                Ok(_) => (),
+                Err(Error::Timeout) => return Err(Error::NoCard),
+                Err(e) => return Err(e),
+            }
+            ocr = self.command(41, 0x8010_0000 | hcs, Response::ShortNoCrc)?;
+            if ocr & 0x8000_0000 != 0 {
+                break;
+            }
+        }
+        if ocr & 0x8000_0000 == 0 {
+            return Err(Error::Timeout);
+        }
+        self.high_capacity = ocr & 0x4000_0000 != 0;
+
+        self.command(2, 0, Response::Long)?;
+        self.rca = self.command(3, 0, Response::Short)? & 0xffff_0000;
+        self.command(9, self.rca, Response::Long)?;
+        self.blocks = self.csd_blocks();
+
+        self.command(7, self.rca, Response::Short)?;
+        self.command(55, self.rca, Response::Short)?;
+        self.command(6, 2, Response::Short)?; // 4 bit bus
+        if !self.high_capacity {
+            self.command(16, Block::LEN_U32, Response::Short)?;
+        }
+
+        self.sdmmc.clkcr.write(|w| unsafe {
+            w.clkdiv()
+                .bits(FAST_CLKDIV)
+                .widbus()
+                .bits(0b01)
+                .hwfc_en()
+                .set_bit()
+                .clken()
+                .set_bit()
+        });
+        self.ready = true;
+        Ok(())
+    }
+
+    // Card size from the CSD in RESP1-4, bits 127:96 down to 31:0
+    fn csd_blocks(&self) -> u32 {
+        let r1 = self.sdmmc.resp1.read().bits();
+        let r2 = self.sdmmc.resp2.read().bits();
+        let r3 = self.sdmmc.resp3.read().bits();
+        if r1 >> 30 == 1 {
+            // CSD version 2: C_SIZE is bits 69:48, in units of 512kB
+            let c_size = ((r2 & 0x3f) << 16) | (r3 >> 16);
+            (c_size + 1) * 1024
+        } else {
+            // Version 1: C_SIZE bits 73:62, C_SIZE_MULT 49:47, READ_BL_LEN 83:80
+            let c_size = ((r2 & 0x3ff) << 2) | (r3 >> 30);
+            let mult = (r3 >> 15) & 0x7;
+            let block_len = (r2 >> 16) & 0xf;
+            ((c_size + 1) << (mult + 2 + block_len)) / Block::LEN_U32
+        }
+    }
+
+    // Sends a command and waits for it to finish, returning RESP1
+    fn command(&self, index: u8, arg: u32, response: Response) -> Result<u32, Error> {
+        let sdmmc = &self.sdmmc;
+        sdmmc.icr.write(|w| unsafe { w.bits(CLEAR_FLAGS) });
+        sdmmc.arg.write(|w| unsafe { w.bits(arg) });
+        let waitresp = match response {
+            Response::None => 0b00,
+            Response::Short | Response::ShortNoCrc => 0b01,
+            Response::Long => 0b11,
+        };
+        sdmmc.cmd.write(|w| unsafe {
+            w.cmdindex()
+                .bits(index)
+                .waitresp()
+                .bits(waitresp)
+                .cpsmen()
+                .set_bit()
+        });
+
+        loop {
+            let sta = sdmmc.sta.read();
+            if response == Response::None {
+                if sta.cmdsent().bit_is_set() {
+                    break;
+                }
+            } else if sta.cmdrend().bit_is_set() {
+                break;
+            } else if sta.ctimeout().bit_is_set() {
+                return Err(Error::Timeout);
+            } else if sta.ccrcfail().bit_is_set() {
+                if response == Response::ShortNoCrc {
+                    break;
+                }
+                return Err(Error::Crc);
+            }
+        }
+        sdmmc.icr.write(|w| unsafe { w.bits(CLEAR_FLAGS) });
+        Ok(sdmmc.resp1.read().bits())
+    }
+
+    fn address(&self, block: u32) -> u32 {
+        if self.high_capacity {
+            block
+        } else {
+            block * Block::LEN_U32
+        }
+    }
+
+    // Sets the data path up for one 512 byte block
+    fn start_data(&self, to_card: bool) {
+        let sdmmc = &self.sdmmc;
+        sdmmc.dtimer.write(|w| unsafe { w.bits(DATA_TIMEOUT) });
+        sdmmc.dlen.write(|w| unsafe { w.bits(Block::LEN_U32) });
+        sdmmc.dctrl.write(|w| unsafe {
+            w.dblocksize()
+                .bits(9)
+                .dtdir()
+                .bit(!to_card)
+                .dten()
+                .set_bit()
+        });
+    }
+
+    fn data_error(&self) -> Option<Error> {
+        let sta = self.sdmmc.sta.read();
+        if sta.dtimeout().bit_is_set() {
+            Some(Error::Timeout)
+        } else if sta.dcrcfail().bit_is_set() {
+            Some(Error::Crc)
+        } else if sta.rxoverr().bit_is_set() || sta.txunderr().bit_is_set() {
+            Some(Error::Overrun)
+        } else {
+            None
+        }
+    }
+
+    fn read_block(&self, block: &mut Block, index: u32) -> Result<(), Error> {
+        self.start_data(false);
+        self.command(17, self.address(index), Response::Short)?;
+
+        let sdmmc = &self.sdmmc;
+        let mut n = 0;
+        loop {
+            if let Some(e) = self.data_error() {
+                sdmmc.icr.write(|w| unsafe { w.bits(CLEAR_FLAGS) });
+                return Err(e);
+            }
+            while sdmmc.sta.read().rxdavl().bit_is_set() && n < Block::LEN {
+                let word = sdmmc.fifo.read().bits();
+                block.contents[n..n + 4].copy_from_slice(&word.to_le_bytes());
+                n += 4;
+            }
+            if sdmmc.sta.read().dataend().bit_is_set() && n == Block::LEN {
+                break;
+            }
+        }
+        sdmmc.icr.write(|w| unsafe { w.bits(CLEAR_FLAGS) });
+        Ok(())
+    }
+
+    fn write_block(&self, block: &Block, index: u32) -> Result<(), Error> {
+        self.command(24, self.address(index), Response::Short)?;
+        self.start_data(true);
+
+        let sdmmc = &self.sdmmc;
+        let mut n = 0;
+        loop {
+            if let Some(e) = self.data_error() {
+                sdmmc.icr.write(|w| unsafe { w.bits(CLEAR_FLAGS) });
+                return Err(e);
+            }
+            while n < Block::LEN && sdmmc.sta.read().txfifof().bit_is_clear() {
+                let mut word = [0; 4];
+                word.copy_from_slice(&block.contents[n..n + 4]);
+                sdmmc
+                    .fifo
+                    .write(|w| unsafe { w.bits(u32::from_le_bytes(word)) });
+                n += 4;
+            }
+            if sdmmc.sta.read().dataend().bit_is_set() {
+                break;
+            }
+        }
+        sdmmc.icr.write(|w| unsafe { w.bits(CLEAR_FLAGS) });
+
+        // Wait for the card to finish programming: ready for data, in the
+        // transfer state
+        for _ in 0..BUSY_TRIES {
+            let status = self.command(13, self.rca, Response::Short)?;
+            if status & 0x100 != 0 && (status >> 9) & 0xf == 4 {
+                return Ok(());
+            }
+        }
+        Err(Error::Timeout)
+    }
+}
+
+impl BlockDevice for SdCard {
+    type Error = Error;
+
+    fn read(&self, blocks: &mut [Block], start: BlockIdx, _reason: &str) -> Result<(), Error> {
+        if !self.ready {
+            return Err(Error::NoCard);
+        }
+        for (i, block) in blocks.iter_mut().enumerate() {
+            self.read_block(block, start.0 + i as u32)?;
+        }
+        Ok(())
+    }
+
+    fn write(&self, blocks: &[Block], start: BlockIdx) -> Result<(), Error> {
+        if !self.ready {
+            return Err(Error::NoCard);
+        }
+        for (i, block) in blocks.iter().enumerate() {
+            self.write_block(block, start.0 + i as u32)?;
+        }
+        Ok(())
+    }
+
+    fn num_blocks(&self) -> Result<BlockCount, Error> {
+        Ok(BlockCount(self.blocks))
+    }
+}
@@ -0,0 +1,535 @@
+//! Simulated machine, for trying the UI without a controller attached.
+//!
+//! Models a three axis machine moving at its feed rate towards a target,
+//! with jogs, work offsets, feed hold, overrides and simple G0/G1 MDI
+//! moves, absolute or relative. G38.2 probes move like G1 and never touch,
+//! so they end at the full distance. There is no acceleration. Homing goes
+//! straight to machine zero, and a reset during a move raises an alarm,
+//! like GRBL. Streamed lines are taken one at a time, each answered once
+//! its move is done.
+
+#[cfg(not(test))]
+use micromath::F32Ext;
+
+use crate::alarm::Alarm;
+use crate::backend::{
+    word, Accessories, MachineBackend, Override, Overrides, Replies, Reply, Status,
+};
+use crate::jog::JogCommand;
+use crate::ui;
+
+const AXIS_NAMES: [char; 3] = ['X', 'Y', 'Z'];
+const RAPID_MM_MIN: f32 = 3000.0;
+const LOCKED_OUT: u8 = 9; // GRBL's error for G-code during an alarm
+
+pub struct SimBackend {
+    position: [f32; 3],
+    target: [f32; 3],
+    wco: [f32; 3],
+    feed: f32,
+    overrides: Overrides,
+    jogging: bool,
+    homing: bool,
+    held: bool,
+    alarm: Option<Alarm>,
+    new_offset: bool,
+    rapid: Option<bool>, // Modal G0 or G1, once one has been seen
+    relative: bool,      // G91
+    replies: Replies,
+    answer_when_stopped: bool, // A streamed move is under way
+    last_ms: Option<u32>,
+    last_poll_ms: u32,
+    poll_ms: u32,
+}
+
+impl SimBackend {
+    /// Reports a status every `poll_ms`, like a polled controller would
+    pub fn new(poll_ms: u32) -> SimBackend {
+        SimBackend {
+            position: [0.0; 3],
+            target: [0.0; 3],
+            wco: [0.0; 3],
+            feed: 0.0,
+            overrides: Overrides::new(),
+            jogging: false,
+            homing: false,
+            held: false,
+            alarm: None,
+            new_offset: true,
+            rapid: None,
+            relative: false,
+            replies: Replies::new(),
+            answer_when_stopped: false,
+            last_ms: None,
+            last_poll_ms: 0,
+            poll_ms,
+        }
+    }
+
+    fn moving(&self) -> bool {
+        self.position != self.target
+    }
+
+    // The feed with overrides applied. Like GRBL, jogs and homing aren't
+    // overridden.
+    fn actual_feed(&self) -> f32 {
+        let percent = if self.jogging || self.homing {
+            100
+        } else if self.rapid == Some(true) {
+            self.overrides.rapid
+        } else {
+            self.overrides.feed
+        };
+        self.feed * percent as f32 / 100.0
+    }
+
+    // Moves towards the target for dt_ms at the current feed
+    fn advance(&mut self, dt_ms: u32) {
+        if self.held || !self.moving() {
+            return;
+        }
+        let mut delta = [0.0; 3];
+        for (i, d) in delta.iter_mut().enumerate() {
+            *d = self.target[i] - self.position[i];
+        }
+        let distance = (delta[0] * delta[0] + delta[1] * delta[1] + delta[2] * delta[2]).sqrt();
+        let step = self.actual_feed() * dt_ms as f32 / 60_000.0;
+        if step >= distance {
+            self.position = self.target;
+            self.jogging = false;
+            self.homing = false;
+        } else {
+            for (p, d) in self.position.iter_mut().zip(delta.iter()) {
+                *p += d * step / distance;
+            }
+        }
+    }
+
+    // Starts a G0 or G1 move. Returns false if the line doesn't move.
+    fn execute(&mut self, line: &str) -> bool {
+        let mut machine = false; // G53, for this line only
+        for g in g_codes(line) {
+            match (g * 10.0).round() as i32 {
+                0 => self.rapid = Some(true),
+                10 | 382 => self.rapid = Some(false),
+                530 => machine = true,
+                900 => self.relative = false,
+                910 => self.relative = true,
+                _ => (),
+            }
+        }
+        let rapid = match self.rapid {
+            Some(rapid) => rapid,
+            None => return false,
+        };
+        if let Some(feed) = word(line, 'F') {
+            self.feed = feed;
+        }
+        let mut moved = false;
+        for (axis, name) in AXIS_NAMES.iter().enumerate() {
+            if let Some(value) = word(line, *name) {
+                self.target[axis] = if machine {
+                    value
+                } else if self.relative {
+                    self.position[axis] + value
+                } else {
+                    value + self.wco[axis]
+                };
+                moved = true;
+            }
+        }
+        if rapid {
+            self.feed = RAPID_MM_MIN;
+        }
+        moved
+    }
+}
+
+// The number after each G on the line
+fn g_codes(line: &str) -> impl Iterator<Item = f32> + '_ {
+    line.split(['G', 'g']).skip(1).filter_map(|rest| {
+        let end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        rest[..end].parse().ok()
+    })
+}
+
+impl MachineBackend for SimBackend {
+    fn poll(&mut self, now_ms: u32) -> Option<Status> {
+        if let Some(last) = self.last_ms {
+            self.advance(now_ms.wrapping_sub(last));
+        }
+        self.last_ms = Some(now_ms);
+        if self.answer_when_stopped && !self.moving() {
+            self.answer_when_stopped = false;
+            self.replies.answered(Reply::Ok);
+        }
+
+        if now_ms.wrapping_sub(self.last_poll_ms) < self.poll_ms {
+            return None;
+        }
+        self.last_poll_ms = now_ms;
+
+        let running = if self.alarm.is_some() {
+            ui::Running::Alarm
+        } else if self.homing {
+            ui::Running::Home
+        } else if self.held {
+            ui::Running::Hold
+        } else if !self.moving() {
+            ui::Running::No
+        } else if self.jogging {
+            ui::Running::Jog
+        } else {
+            ui::Running::Yes
+        };
+        let p = self.position;
+        let status = Status {
+            running,
+            mpos: p,
+            wpos: [p[0] - self.wco[0], p[1] - self.wco[1], p[2] - self.wco[2]],
+            feed: if self.moving() {
+                self.actual_feed()
+            } else {
+                0.0
+            },
+            spindle: 0.0,
+            overrides: self.overrides,
+            accessories: Accessories::new(),
+            alarm: self.alarm,
+            new_offset: self.new_offset,
+        };
+        self.new_offset = false;
+        Some(status)
+    }
+
+    fn connected(&self, _now_ms: u32) -> bool {
+        true
+    }
+
+    fn jog(&mut self, command: JogCommand) {
+        match command {
+            JogCommand::Move {
+                axis,
+                distance,
+                feed,
+            } => {
+                if (self.moving() && !self.jogging) || self.alarm.is_some() {
+                    return; // Like GRBL, no jogging during a move or alarm
+                }
+                self.target[axis] += distance;
+                self.feed = feed;
+                self.jogging = true;
+            }
+            JogCommand::Cancel => {
+                if self.jogging {
+                    self.target = self.position;
+                    self.jogging = false;
+                }
+            }
+        }
+    }
+
+    fn set_work(&mut self, axis: usize, value: f32) {
+        self.wco[axis] = self.position[axis] - value;
+        self.new_offset = true;
+    }
+
+    fn feed_hold(&mut self) {
+        if self.moving() {
+            self.held = true;
+        }
+    }
+
+    fn resume(&mut self) {
+        self.held = false;
+    }
+
+    fn reset(&mut self) {
+        if self.moving() {
+            self.alarm = Some(if self.homing {
+                Alarm::HomingReset
+            } else {
+                Alarm::ResetWhileMoving
+            });
+        }
+        self.target = self.position;
+        self.homing = false;
+        self.held = false;
+        self.jogging = false;
+        // Like GRBL, a reset puts the modal state back
+        self.relative = false;
+        self.answer_when_stopped = false;
+        self.replies.clear();
+    }
+
+    fn set_override(&mut self, value: Override) {
+        value.apply(&mut self.overrides);
+    }
+
+    fn home(&mut self) {
+        if self.moving() {
+            return;
+        }
+        self.alarm = None;
+        self.target = [0.0; 3];
+        self.feed = RAPID_MM_MIN;
+        self.homing = self.moving();
+    }
+
+    fn unlock(&mut self) {
+        self.alarm = None;
+    }
+
+    /// Understands G0 and G1 with X, Y, Z in work coordinates and F. Lines
+    /// typed during a move are refused, there's no buffer to queue them in.
+    fn mdi(&mut self, line: &str) {
+        if self.alarm.is_some() {
+            self.replies.sent(true);
+            self.replies.answered(Reply::Error(LOCKED_OUT));
+        } else if self.moving() {
+            self.replies.sent(true);
+            self.replies.answered(Reply::Error(0));
+        } else {
+            self.stream(line);
+        }
+    }
+
+    fn stream(&mut self, line: &str) {
+        self.replies.sent(true);
+        if self.execute(line) {
+            self.answer_when_stopped = true;
+        } else {
+            self.replies.answered(Reply::Ok);
+        }
+    }
+
+    fn take_reply(&mut self) -> Option<Reply> {
+        self.replies.take()
+    }
+
+    fn rx_buffer(&self) -> usize {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLL_MS: u32 = 100;
+
+    fn close(a: [f32; 3], b: [f32; 3]) -> bool {
+        a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1e-3)
+    }
+
+    // Started at 0 ms, as the clock only starts at the first poll
+    fn started() -> SimBackend {
+        let mut sim = SimBackend::new(POLL_MS);
+        assert_eq!(sim.poll(0), None); // Not time for a status yet
+        sim
+    }
+
+    // The status at `now_ms`, which must be time for one
+    fn status(sim: &mut SimBackend, now_ms: u32) -> Status {
+        sim.poll(now_ms).expect("no status")
+    }
+
+    fn replies(sim: &mut SimBackend) -> Vec<Reply> {
+        core::iter::from_fn(|| sim.take_reply()).collect()
+    }
+
+    #[test]
+    fn moves_at_the_feed() {
+        let mut sim = started();
+        sim.mdi("G1 X10 F600");
+        let s = status(&mut sim, 500);
+        assert!(close(s.mpos, [5.0, 0.0, 0.0]));
+        assert_eq!(s.running, ui::Running::Yes);
+        assert_eq!(s.feed, 600.0);
+        // Only answered once it gets there
+        assert_eq!(replies(&mut sim), []);
+        let s = status(&mut sim, 1000);
+        assert!(close(s.mpos, [10.0, 0.0, 0.0]));
+        assert_eq!(s.running, ui::Running::No);
+        assert_eq!(s.feed, 0.0);
+        assert_eq!(replies(&mut sim), [Reply::Ok]);
+
+        // Rapids ignore F, and the mode carries over
+        sim.mdi("G0 Z-25 F100");
+        assert!(close(status(&mut sim, 1500).mpos, [10.0, 0.0, -25.0]));
+        sim.mdi("X0");
+        assert!(close(status(&mut sim, 1600).mpos, [5.0, 0.0, -25.0]));
+    }
+
+    #[test]
+    fn relative_machine_and_work_coordinates() {
+        let mut sim = started();
+        sim.mdi("G0 X10 Y10");
+        status(&mut sim, 1000);
+        sim.set_work(0, 0.0);
+        sim.set_work(1, 5.0);
+        let s = status(&mut sim, 1100);
+        assert!(close(s.wpos, [0.0, 5.0, 0.0]));
+        assert!(s.new_offset);
+        assert!(!status(&mut sim, 1200).new_offset);
+
+        sim.mdi("G1 X1 Y1 F6000");
+        assert!(close(status(&mut sim, 2000).mpos, [11.0, 6.0, 0.0]));
+        sim.mdi("G91 X1");
+        assert!(close(status(&mut sim, 3000).mpos, [12.0, 6.0, 0.0]));
+        sim.mdi("G90 G53 X1");
+        assert!(close(status(&mut sim, 4000).mpos, [1.0, 6.0, 0.0]));
+        // G53 was for that line only
+        sim.mdi("X1");
+        assert!(close(status(&mut sim, 5000).wpos, [1.0, 1.0, 0.0]));
+    }
+
+    #[test]
+    fn lines_that_dont_move() {
+        let mut sim = started();
+        // Before any G0 or G1 there's no mode to move in
+        sim.stream("X10");
+        sim.stream("M3 S1000");
+        assert_eq!(replies(&mut sim), [Reply::Ok, Reply::Ok]);
+        assert!(close(status(&mut sim, 100).mpos, [0.0; 3]));
+        // Probing runs the whole distance
+        sim.stream("G38.2 Z-1 F60");
+        assert!(close(status(&mut sim, 1100).mpos, [0.0, 0.0, -1.0]));
+        assert_eq!(replies(&mut sim), [Reply::Ok]);
+    }
+
+    #[test]
+    fn mdi_refused_while_moving() {
+        let mut sim = started();
+        sim.mdi("G1 X10 F600");
+        sim.mdi("G1 Y10");
+        assert_eq!(replies(&mut sim), [Reply::Error(0)]);
+        status(&mut sim, 1000);
+        assert_eq!(replies(&mut sim), [Reply::Ok]);
+    }
+
+    #[test]
+    fn overrides() {
+        let mut sim = started();
+        sim.set_override(Override::Feed(50));
+        sim.set_override(Override::Rapid(25));
+        sim.mdi("G1 X10 F600");
+        let s = status(&mut sim, 1000);
+        assert_eq!(s.feed, 300.0);
+        assert_eq!(s.overrides.feed, 50);
+        assert!(close(s.mpos, [5.0, 0.0, 0.0]));
+        status(&mut sim, 2000);
+        sim.mdi("G0 X0");
+        assert_eq!(status(&mut sim, 2100).feed, 750.0);
+
+        // Not for jogs
+        let mut sim = started();
+        sim.set_override(Override::Feed(50));
+        sim.jog(JogCommand::Move {
+            axis: 1,
+            distance: 10.0,
+            feed: 600.0,
+        });
+        let s = status(&mut sim, 500);
+        assert_eq!(s.feed, 600.0);
+        assert_eq!(s.running, ui::Running::Jog);
+    }
+
+    #[test]
+    fn jogging() {
+        let mut sim = started();
+        let jog = |distance| JogCommand::Move {
+            axis: 0,
+            distance,
+            feed: 600.0,
+        };
+        sim.jog(jog(10.0));
+        status(&mut sim, 500);
+        // Jogs add up, and a cancel stops where it is
+        sim.jog(jog(10.0));
+        sim.jog(JogCommand::Cancel);
+        let s = status(&mut sim, 600);
+        assert!(close(s.mpos, [5.0, 0.0, 0.0]));
+        assert_eq!(s.running, ui::Running::No);
+
+        // No jogging during a move
+        sim.mdi("G1 Y1 F60");
+        sim.jog(jog(10.0));
+        sim.jog(JogCommand::Cancel);
+        let s = status(&mut sim, 700);
+        assert_eq!(s.running, ui::Running::Yes);
+        assert!(close(s.mpos, [5.0, 0.1, 0.0]));
+    }
+
+    #[test]
+    fn hold_and_resume() {
+        let mut sim = started();
+        sim.feed_hold(); // Nothing to hold
+        assert_eq!(status(&mut sim, 100).running, ui::Running::No);
+        sim.mdi("G1 X10 F600");
+        status(&mut sim, 200);
+        sim.feed_hold();
+        let s = status(&mut sim, 1200);
+        assert_eq!(s.running, ui::Running::Hold);
+        assert!(close(s.mpos, [1.0, 0.0, 0.0]));
+        sim.resume();
+        assert!(close(status(&mut sim, 1300).mpos, [2.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn reset_and_alarms() {
+        let mut sim = started();
+        sim.reset(); // Standing still, no alarm
+        assert_eq!(status(&mut sim, 100).alarm, None);
+
+        sim.stream("G91 G1 X10 F600");
+        status(&mut sim, 200);
+        sim.reset();
+        let s = status(&mut sim, 300);
+        assert_eq!(s.alarm, Some(Alarm::ResetWhileMoving));
+        assert_eq!(s.running, ui::Running::Alarm);
+        assert!(close(s.mpos, [1.0, 0.0, 0.0]));
+        // The streamed line was thrown away unanswered
+        assert_eq!(replies(&mut sim), []);
+
+        // Locked out until unlocked, jogs too
+        sim.mdi("G1 X5");
+        assert_eq!(replies(&mut sim), [Reply::Error(LOCKED_OUT)]);
+        sim.jog(JogCommand::Move {
+            axis: 0,
+            distance: 1.0,
+            feed: 600.0,
+        });
+        assert!(close(status(&mut sim, 400).mpos, [1.0, 0.0, 0.0]));
+        sim.unlock();
+        // The reset went back to absolute
+        sim.mdi("G1 X5");
+        let s = status(&mut sim, 1000);
+        assert_eq!(s.alarm, None);
+        assert!(close(s.mpos, [5.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn homing() {
+        let mut sim = started();
+        sim.mdi("G0 X50 Y50");
+        status(&mut sim, 5000);
+        sim.reset();
+        sim.home();
+        let s = status(&mut sim, 5100);
+        assert_eq!(s.running, ui::Running::Home);
+        // Reset while homing
+        sim.reset();
+        assert_eq!(status(&mut sim, 5200).alarm, Some(Alarm::HomingReset));
+        // Homing clears the alarm, and goes to machine zero
+        sim.home();
+        let s = status(&mut sim, 10_000);
+        assert_eq!(s.alarm, None);
+        assert_eq!(s.running, ui::Running::No);
+        assert_eq!(s.mpos, [0.0; 3]);
+        assert!(sim.connected(10_000));
+        assert_eq!(sim.rx_buffer(), 0);
+    }
+}
@@ -0,0 +1,475 @@
+//! Step/dir outputs driven by the board itself, for small machines without
+//! a separate controller. Built with the `stepper` feature.
+//!
+//! TIM7 interrupts at `planner::TICK_HZ` and runs `Motion::tick`, which
+//! does the planning and step timing, see planner.rs. The handler ends the
+//! last tick's step pulses, sets the direction pins and starts new pulses,
+//! so a pulse is one tick long. Drivers get one enable pin, active low,
+//! which is on from start up.
+//!
+//!   X  step PG7 (Arduino D4)   dir PH6 (D6)    limit PF10 (A1)
+//!   Y  step PA8 (Arduino D10)  dir PB15 (D11)  limit PF9 (A2)
+//!   Z  step PB14 (Arduino D12) dir PI1 (D13)   limit PF8 (A3)
+//!   Enable PB8 (Arduino D15)
+//!
+//! Dir pins are high to move towards negative. Limit switches are normally
+//! closed to ground with pull ups, so a hit switch or broken wire reads
+//! high. Homing goes towards positive, to the same switches.
+//!
+//! `StepperBackend` stands in for a GRBL controller: it runs lines through
+//! `gcode::Interpreter`, so it understands what the previews do and no
+//! more (no G38 probing or G53), and answers `$$`, `$N=value`, `$H` and
+//! `$X` with GRBL's setting numbers and error codes.
+
+use core::cell::RefCell;
+use core::fmt::Write;
+
+use cortex_m::interrupt::{free, Mutex};
+#[cfg(not(test))]
+use micromath::F32Ext;
+use stm32f7xx_hal::{
+    pac::{interrupt, Interrupt, NVIC, RCC, TIM7},
+    rcc::Clocks,
+};
+
+use crate::backend::{
+    message, Accessories, MachineBackend, Message, Messages, Override, Overrides, Replies, Reply,
+    Status, MDI_LEN,
+};
+use crate::gcode::{Error, Interpreter, Segment};
+use crate::jog::JogCommand;
+use crate::planner::{Config, Kind, Motion, State, TICK_HZ};
+use crate::storage;
+use crate::text::TextBuffer;
+use crate::ui;
+
+const ARC_MM: f32 = 0.5; // Arcs go to the planner as lines this long
+const LINES: usize = 4;
+
+// GRBL's error codes
+const BAD_STATEMENT: u8 = 3;
+const NOT_IDLE: u8 = 8;
+const LOCKED_OUT: u8 = 9;
+const OVERFLOW: u8 = 11;
+
+// GPIO ports, by their offset from GPIOA
+const GPIO_BASE: usize = 0x4002_0000;
+const PORT_A: usize = 0;
+const PORT_B: usize = 1;
+const PORT_F: usize = 5;
+const PORT_G: usize = 6;
+const PORT_H: usize = 7;
+const PORT_I: usize = 8;
+const IDR: usize = 0x10;
+const BSRR: usize = 0x18;
+
+const STEP_PINS: [(usize, u32); 3] = [(PORT_G, 7), (PORT_A, 8), (PORT_B, 14)];
+const DIR_PINS: [(usize, u32); 3] = [(PORT_H, 6), (PORT_B, 15), (PORT_I, 1)];
+const LIMIT_PINS: [(usize, u32); 3] = [(PORT_F, 10), (PORT_F, 9), (PORT_F, 8)];
+const ENABLE_PIN: (usize, u32) = (PORT_B, 8);
+
+static MOTION: Mutex<RefCell<Option<Motion>>> = Mutex::new(RefCell::new(None));
+
+type Line = TextBuffer<MDI_LEN>;
+
+// NOTE(unsafe) BSRR writes are atomic and only touch the pins set in bits
+unsafe fn set_pins(port: usize, bits: u32) {
+    core::ptr::write_volatile((GPIO_BASE + port * 0x400 + BSRR) as *mut u32, bits);
+}
+
+fn read_pins(port: usize) -> u32 {
+    // NOTE(unsafe) atomic read of the input data register
+    unsafe { core::ptr::read_volatile((GPIO_BASE + port * 0x400 + IDR) as *const u32) }
+}
+
+fn with_motion<R>(f: impl FnOnce(&mut Motion) -> R) -> Option<R> {
+    free(|cs| MOTION.borrow(cs).borrow_mut().as_mut().map(f))
+}
+
+// Maps an interpreter error to GRBL's nearest code
+fn error_code(error: Error) -> u8 {
+    match error {
+        Error::UnknownWord(_) | Error::Unsupported(_) => 20,
+        Error::ModalConflict => 21,
+        Error::NoFeed => 22,
+        Error::Repeated(_) => 25,
+        Error::NoAxes => 26,
+        Error::ArcRadius => 33,
+        _ => 2,
+    }
+}
+
+// A move being handed to the planner, a piece at a time
+#[derive(Copy, Clone, Debug)]
+struct Pending {
+    segment: Segment,
+    pieces: u32,
+    done: u32,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+pub struct StepperBackend {
+    interpreter: Interpreter,
+    lines: [Line; LINES], // Waiting to be run, oldest first
+    n_lines: usize,
+    pending: Option<Pending>,
+    overrides: Overrides,
+    replies: Replies,
+    messages: Messages,
+    new_offset: bool,
+    dirty: bool, // Settings changed since last saved
+    last_poll_ms: u32,
+    poll_ms: u32,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl StepperBackend {
+    /// Starts the step timer. The pins must already be set up: step, dir
+    /// and enable as push pull outputs, limits as inputs with pull ups.
+    pub fn new(tim: TIM7, clocks: &Clocks, poll_ms: u32) -> StepperBackend {
+        free(|cs| *MOTION.borrow(cs).borrow_mut() = Some(Motion::new(Config::new())));
+        // NOTE(unsafe) drivers on
+        unsafe { set_pins(ENABLE_PIN.0, 1 << (ENABLE_PIN.1 + 16)) };
+
+        // NOTE(unsafe) only touches the TIM7 enable bit
+        let rcc = unsafe { &(*RCC::ptr()) };
+        rcc.apb1enr.modify(|_, w| w.tim7en().set_bit());
+        let reload = clocks.timclk1().0 / TICK_HZ - 1;
+        tim.psc.write(|w| unsafe { w.bits(0) });
+        tim.arr.write(|w| unsafe { w.bits(reload) });
+        tim.egr.write(|w| w.ug().set_bit());
+        tim.sr.write(|w| unsafe { w.bits(0) });
+        tim.dier.write(|w| w.uie().set_bit());
+        tim.cr1.write(|w| w.cen().set_bit());
+
+        // NOTE(unsafe) the handler only shares MOTION, behind a Mutex
+        unsafe { NVIC::unmask(Interrupt::TIM7) };
+
+        StepperBackend {
+            interpreter: Interpreter::new(),
+            lines: [Line::new(); LINES],
+            n_lines: 0,
+            pending: None,
+            overrides: Overrides::new(),
+            replies: Replies::new(),
+            messages: Messages::new(),
+            new_offset: true,
+            dirty: false,
+            last_poll_ms: 0,
+            poll_ms,
+        }
+    }
+
+    fn state(&self) -> State {
+        with_motion(|m| m.state()).unwrap_or(State::Idle)
+    }
+
+    fn config(&self) -> Config {
+        with_motion(|m| *m.config()).unwrap_or_else(Config::new)
+    }
+
+    /// Settings were changed with `$N=value` since last asked
+    pub fn take_dirty(&mut self) -> bool {
+        core::mem::replace(&mut self.dirty, false)
+    }
+
+    pub fn save(&self, writer: &mut storage::Writer) -> Result<(), storage::Error> {
+        let config = self.config();
+        writer.section(storage::TAG_MOTION, |buf| config.write_bytes(buf))
+    }
+
+    pub fn load(&mut self, data: &[u8]) {
+        for (tag, section) in storage::Sections::new(data) {
+            if tag == storage::TAG_MOTION {
+                let mut config = self.config();
+                config.read_bytes(section);
+                with_motion(|m| m.set_config(config));
+            }
+        }
+    }
+
+    // Runs the oldest waiting line, once the last one's move is planned
+    fn run_lines(&mut self) {
+        while self.pending.is_none() && self.n_lines > 0 {
+            let line = self.lines[0];
+            self.lines.copy_within(1.., 0);
+            self.n_lines -= 1;
+            let reply = self.run(line.as_str().trim());
+            if let Some(reply) = reply {
+                self.replies.answered(reply);
+            }
+        }
+    }
+
+    // Returns the answer, or None if the line made a move, which `plan`
+    // answers
+    fn run(&mut self, line: &str) -> Option<Reply> {
+        if let Some(setting) = line.strip_prefix('$') {
+            return Some(self.setting(setting));
+        }
+        if let State::Alarm(_) = self.state() {
+            return Some(Reply::Error(LOCKED_OUT));
+        }
+        match self.interpreter.execute_line(line) {
+            Ok(Some(segment)) => {
+                let pieces = match segment {
+                    Segment::Line { .. } => 1,
+                    Segment::Arc { .. } => ((segment.length() / ARC_MM).ceil() as u32).max(1),
+                };
+                self.pending = Some(Pending {
+                    segment,
+                    pieces,
+                    done: 0,
+                });
+                self.plan();
+                None
+            }
+            Ok(None) => Some(Reply::Ok),
+            Err(e) => Some(Reply::Error(error_code(e))),
+        }
+    }
+
+    // A `$` line, without the `$`
+    fn setting(&mut self, line: &str) -> Reply {
+        let idle = matches!(self.state(), State::Idle | State::Alarm(_));
+        match line {
+            "$" => {
+                let config = self.config();
+                for n in Config::numbers() {
+                    let mut text: TextBuffer<24> = TextBuffer::new();
+                    write!(text, "${}={:.3}", n, config.get(n).unwrap_or(0.0)).ok();
+                    if let Some(m) = message(text.as_str()) {
+                        self.messages.push(m);
+                    }
+                }
+                Reply::Ok
+            }
+            "H" | "h" if idle => {
+                with_motion(|m| m.home());
+                Reply::Ok
+            }
+            "X" | "x" => {
+                with_motion(|m| m.unlock());
+                Reply::Ok
+            }
+            "H" | "h" => Reply::Error(NOT_IDLE),
+            _ => {
+                let (n, value) = match line.split_once('=') {
+                    Some((n, value)) => (n.trim().parse().ok(), value.trim().parse().ok()),
+                    None => (None, None),
+                };
+                let mut config = self.config();
+                match (n, value) {
+                    _ if !idle => Reply::Error(NOT_IDLE),
+                    (Some(n), Some(value)) if config.set(n, value) => {
+                        with_motion(|m| m.set_config(config));
+                        self.dirty = true;
+                        Reply::Ok
+                    }
+                    _ => Reply::Error(BAD_STATEMENT),
+                }
+            }
+        }
+    }
+
+    // Queues a line to run, see `stream`. `wanted` is as for `Replies::sent`.
+    fn queue(&mut self, line: &str, wanted: bool) {
+        self.replies.sent(wanted);
+        let mut text = Line::new();
+        if self.n_lines == LINES || text.write_str(line).is_err() {
+            self.replies.answered(Reply::Error(OVERFLOW));
+            return;
+        }
+        self.lines[self.n_lines] = text;
+        self.n_lines += 1;
+        self.run_lines();
+    }
+
+    // Hands pieces of the pending move to the planner while it has room,
+    // answering the line once the last one is in
+    fn plan(&mut self) {
+        while let Some(pending) = &mut self.pending {
+            let segment = pending.segment;
+            let kind = if segment.is_rapid() {
+                Kind::Rapid
+            } else {
+                Kind::Feed
+            };
+            let target = segment.point_at((pending.done + 1) as f32 / pending.pieces as f32);
+            let pushed = with_motion(|m| m.push(target, segment.feed(), kind)).unwrap_or(true);
+            if !pushed {
+                return;
+            }
+            pending.done += 1;
+            if pending.done == pending.pieces {
+                self.pending = None;
+                self.replies.answered(Reply::Ok);
+            }
+        }
+    }
+}
+
+impl MachineBackend for StepperBackend {
+    fn poll(&mut self, now_ms: u32) -> Option<Status> {
+        self.plan();
+        self.run_lines();
+
+        let (state, mpos, feed) = with_motion(|m| (m.state(), m.position(), m.feed())).unwrap_or((
+            State::Idle,
+            [0.0; 3],
+            0.0,
+        ));
+        // Jogs and homing move without the interpreter knowing
+        if state == State::Idle && self.pending.is_none() && self.n_lines == 0 {
+            self.interpreter.set_position(mpos);
+        }
+
+        if now_ms.wrapping_sub(self.last_poll_ms) < self.poll_ms {
+            return None;
+        }
+        self.last_poll_ms = now_ms;
+
+        let (running, alarm) = match state {
+            State::Idle => (ui::Running::No, None),
+            State::Moving => (ui::Running::Yes, None),
+            State::Jogging => (ui::Running::Jog, None),
+            State::Held => (ui::Running::Hold, None),
+            State::Homing => (ui::Running::Home, None),
+            State::Alarm(alarm) => (ui::Running::Alarm, Some(alarm)),
+        };
+        let wco = self.interpreter.work_offset();
+        let status = Status {
+            running,
+            mpos,
+            wpos: [mpos[0] - wco[0], mpos[1] - wco[1], mpos[2] - wco[2]],
+            feed,
+            spindle: 0.0,
+            overrides: self.overrides,
+            accessories: Accessories::new(),
+            alarm,
+            new_offset: self.new_offset,
+        };
+        self.new_offset = false;
+        Some(status)
+    }
+
+    fn connected(&self, _now_ms: u32) -> bool {
+        true
+    }
+
+    fn jog(&mut self, command: JogCommand) {
+        match command {
+            JogCommand::Move {
+                axis,
+                distance,
+                feed,
+            } => {
+                // Like GRBL, no jogging during a move or alarm
+                if matches!(self.state(), State::Idle | State::Jogging) {
+                    with_motion(|m| m.jog(axis, distance, feed));
+                }
+            }
+            JogCommand::Cancel => {
+                if self.state() == State::Jogging {
+                    with_motion(|m| m.cancel());
+                }
+            }
+        }
+    }
+
+    fn set_work(&mut self, axis: usize, value: f32) {
+        let mpos = with_motion(|m| m.position()).unwrap_or([0.0; 3]);
+        let mut offset = self.interpreter.work_offset();
+        offset[axis] = mpos[axis] - value;
+        self.interpreter.set_work_offset(offset);
+        self.new_offset = true;
+    }
+
+    fn feed_hold(&mut self) {
+        with_motion(|m| m.hold());
+    }
+
+    fn resume(&mut self) {
+        with_motion(|m| m.resume());
+    }
+
+    fn reset(&mut self) {
+        with_motion(|m| m.reset());
+        self.interpreter.reset();
+        self.pending = None;
+        self.n_lines = 0;
+        self.replies.clear();
+    }
+
+    fn set_override(&mut self, value: Override) {
+        value.apply(&mut self.overrides);
+        let overrides = self.overrides;
+        with_motion(|m| m.set_overrides(overrides.feed, overrides.rapid));
+    }
+
+    fn home(&mut self) {
+        self.queue("$H", false);
+    }
+
+    fn unlock(&mut self) {
+        self.queue("$X", false);
+    }
+
+    fn mdi(&mut self, line: &str) {
+        self.stream(line);
+    }
+
+    /// Lines wait their turn behind the move the last one made. More than
+    /// fit are refused, which only happens if lines are sent without
+    /// waiting for answers.
+    fn stream(&mut self, line: &str) {
+        self.queue(line, true);
+    }
+
+    fn take_reply(&mut self) -> Option<Reply> {
+        self.replies.take()
+    }
+
+    fn take_message(&mut self) -> Option<Message> {
+        self.messages.pop()
+    }
+
+    fn rx_buffer(&self) -> usize {
+        0
+    }
+}
+
+#[interrupt]
+fn TIM7() {
+    // NOTE(unsafe) write to clear our own update flag
+    let tim = unsafe { &(*TIM7::ptr()) };
+    tim.sr.write(|w| unsafe { w.bits(0) });
+
+    let mut limits = 0;
+    for (axis, (port, pin)) in LIMIT_PINS.iter().enumerate() {
+        if read_pins(*port) & (1 << pin) != 0 {
+            limits |= 1 << axis;
+        }
+    }
+    let output = match with_motion(|m| m.tick(limits)) {
+        Some(output) => output,
+        None => return,
+    };
+
+    // NOTE(unsafe) only the step, dir and enable pins are written
+    unsafe {
+        // Last tick's pulses end, then directions, then new pulses
+        for (port, pin) in STEP_PINS {
+            set_pins(port, 1 << (pin + 16));
+        }
+        for (axis, (port, pin)) in DIR_PINS.iter().enumerate() {
+            let high = output.dir & (1 << axis) != 0;
+            set_pins(*port, 1 << if high { *pin } else { pin + 16 });
+        }
+        for (axis, (port, pin)) in STEP_PINS.iter().enumerate() {
+            if output.step & (1 << axis) != 0 {
+                set_pins(*port, 1 << pin);
+            }
+        }
+    }
+}
@@ -0,0 +1,420 @@
+//! Persistent storage in the last flash sector.
+//!
+//! Every save appends a record to the sector, so the (slow) sector erase
+//! only happens when it fills up. On start up the last good record wins.
+//!
+//! A record is a header followed by tagged sections, so each part of the
+//! UI can store its own data without knowing about the others:
+//!
+//!   magic: u32, length: u32, checksum: u32, sections..., padding to 4 bytes
+//!   section: tag: u8, length: u16, data
+//!
+//! The log only touches flash through a `Sector`, so it can be tested on
+//! the host.
+
+use stm32f7xx_hal::{flash::Flash, pac::FLASH};
+
+const SECTOR: u8 = 7;
+const SECTOR_OFFSET: usize = 0xC_0000; // From the start of flash
+const SECTOR_SIZE: usize = 0x4_0000;
+const FLASH_BASE: usize = 0x0800_0000;
+const HEADER_SIZE: usize = 12;
+const MAGIC: u32 = 0x314F_5244; // "DRO1"
+const ERASED: u32 = 0xFFFF_FFFF;
+
+const BUFFER_SIZE: usize = 4096;
+
+// Section tags. Never reuse a number - old records may still hold it.
+pub const TAG_POINTS: u8 = 1;
+pub const TAG_PROBE: u8 = 2;
+#[cfg_attr(test, allow(dead_code))]
+pub const TAG_CYCLE: u8 = 3;
+#[cfg(feature = "stepper")]
+pub const TAG_MOTION: u8 = 4;
+#[cfg_attr(test, allow(dead_code))]
+pub const TAG_POWER_FEED: u8 = 5;
+#[cfg_attr(test, allow(dead_code))]
+pub const TAG_LIMITS: u8 = 6;
+#[cfg_attr(test, allow(dead_code))]
+pub const TAG_LEADSCREW: u8 = 7;
+pub const TAG_DIAL: u8 = 8;
+
+#[derive(Debug)]
+pub enum Error {
+    TooBig,
+    Flash(stm32f7xx_hal::flash::Error),
+}
+
+impl From<stm32f7xx_hal::flash::Error> for Error {
+    fn from(error: stm32f7xx_hal::flash::Error) -> Self {
+        Self::Flash(error)
+    }
+}
+
+fn read_u32(sector: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([sector[at], sector[at + 1], sector[at + 2], sector[at + 3]])
+}
+
+fn checksum(data: &[u8]) -> u32 {
+    // FNV-1a: cheap, and good enough to spot a half written record
+    data.iter().fold(0x811C_9DC5, |hash: u32, b| {
+        (hash ^ *b as u32).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// Somewhere to keep the log: bytes that read back as 0xFF once erased,
+/// and can then be written once
+pub trait Sector {
+    fn bytes(&self) -> &[u8];
+    fn erase(&mut self) -> Result<(), Error>;
+    /// Writes `data` at `offset` from the start of the sector
+    fn program(&mut self, offset: usize, data: &[u8]) -> Result<(), Error>;
+}
+
+/// The last sector of the on-chip flash
+#[cfg_attr(test, allow(dead_code))]
+pub struct FlashSector {
+    flash: Flash,
+}
+
+#[cfg_attr(test, allow(dead_code))]
+impl FlashSector {
+    pub fn new(flash: FLASH) -> FlashSector {
+        FlashSector {
+            flash: Flash::new(flash),
+        }
+    }
+}
+
+impl Sector for FlashSector {
+    fn bytes(&self) -> &[u8] {
+        // NOTE(unsafe) the sector is kept out of the program by memory.x and
+        // only ever changes through self.flash
+        unsafe {
+            core::slice::from_raw_parts((FLASH_BASE + SECTOR_OFFSET) as *const u8, SECTOR_SIZE)
+        }
+    }
+
+    fn erase(&mut self) -> Result<(), Error> {
+        self.flash.unlock();
+        let result = self.flash.blocking_erase_sector(SECTOR);
+        self.flash.lock();
+        Ok(result?)
+    }
+
+    fn program(&mut self, offset: usize, data: &[u8]) -> Result<(), Error> {
+        self.flash.unlock();
+        let result = self.flash.blocking_program(SECTOR_OFFSET + offset, data);
+        self.flash.lock();
+        Ok(result?)
+    }
+}
+
+pub struct Storage<S: Sector> {
+    sector: S,
+    latest: Option<(usize, usize)>, // Offset and length of newest good record's data
+    next: usize,                    // Offset in the sector of the first free byte
+}
+
+impl<S: Sector> Storage<S> {
+    pub fn new(sector: S) -> Storage<S> {
+        let mut storage = Storage {
+            sector,
+            latest: None,
+            next: 0,
+        };
+        storage.scan();
+        storage
+    }
+
+    // Walks the records to find the newest good one and the end of the log
+    fn scan(&mut self) {
+        let sector = self.sector.bytes();
+        let size = sector.len();
+        let mut at = 0;
+        while at + HEADER_SIZE <= size {
+            let magic = read_u32(sector, at);
+            if magic == ERASED {
+                break;
+            }
+            let len = read_u32(sector, at + 4) as usize;
+            if magic != MAGIC || at + HEADER_SIZE + len > size {
+                // Garbage: treat the rest of the sector as used
+                at = size;
+                break;
+            }
+            let data = &sector[at + HEADER_SIZE..at + HEADER_SIZE + len];
+            if checksum(data) == read_u32(sector, at + 8) {
+                self.latest = Some((at + HEADER_SIZE, len));
+            }
+            at += (HEADER_SIZE + len + 3) & !3;
+        }
+        self.next = at;
+    }
+
+    /// The data of the newest saved record, if there is one
+    pub fn load(&self) -> Option<&[u8]> {
+        self.latest
+            .map(|(at, len)| &self.sector.bytes()[at..at + len])
+    }
+
+    /// Saves a record whose sections `fill` writes. Out of line, so the
+    /// buffer is only on the stack while saving.
+    #[inline(never)]
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn save_sections<F>(&mut self, fill: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut Writer) -> Result<(), Error>,
+    {
+        let mut buf = [0u8; BUFFER_SIZE];
+        let mut writer = Writer::new(&mut buf);
+        fill(&mut writer)?;
+        self.save(writer.data())
+    }
+
+    pub fn save(&mut self, data: &[u8]) -> Result<(), Error> {
+        let size = (HEADER_SIZE + data.len() + 3) & !3;
+        let sector_size = self.sector.bytes().len();
+        if size > sector_size {
+            return Err(Error::TooBig);
+        }
+        if self.next + size > sector_size {
+            self.sector.erase()?;
+            self.latest = None;
+            self.next = 0;
+        }
+
+        let mut header = [0u8; HEADER_SIZE];
+        header[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        header[4..8].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        header[8..12].copy_from_slice(&checksum(data).to_le_bytes());
+
+        // Data first, so a record with a header always has its data
+        self.sector.program(self.next + HEADER_SIZE, data)?;
+        self.sector.program(self.next, &header)?;
+
+        self.latest = Some((self.next + HEADER_SIZE, data.len()));
+        self.next += size;
+        Ok(())
+    }
+}
+
+/// Builds the sections of a record in a RAM buffer
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Writer<'a> {
+        Writer { buf, len: 0 }
+    }
+
+    /// Adds a section, `fill` writes the data and returns how many bytes it used
+    pub fn section<F>(&mut self, tag: u8, fill: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut [u8]) -> Option<usize>,
+    {
+        let start = self.len + 3;
+        if start > self.buf.len() {
+            return Err(Error::TooBig);
+        }
+        let n = fill(&mut self.buf[start..]).ok_or(Error::TooBig)?;
+        self.buf[self.len] = tag;
+        self.buf[self.len + 1..start].copy_from_slice(&(n as u16).to_le_bytes());
+        self.len = start + n;
+        Ok(())
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+/// Iterates over the (tag, data) sections of a record
+pub struct Sections<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Sections<'a> {
+    pub fn new(data: &'a [u8]) -> Sections<'a> {
+        Sections { data }
+    }
+}
+
+impl<'a> Iterator for Sections<'a> {
+    type Item = (u8, &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.len() < 3 {
+            return None;
+        }
+        let tag = self.data[0];
+        let len = u16::from_le_bytes([self.data[1], self.data[2]]) as usize;
+        if 3 + len > self.data.len() {
+            return None;
+        }
+        let section = &self.data[3..3 + len];
+        self.data = &self.data[3 + len..];
+        Some((tag, section))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Flash in RAM: programming can only clear bits, like the real thing
+    #[derive(Clone)]
+    struct Ram {
+        bytes: Vec<u8>,
+        erases: usize,
+    }
+
+    impl Ram {
+        fn new(size: usize) -> Ram {
+            Ram {
+                bytes: vec![0xFF; size],
+                erases: 0,
+            }
+        }
+    }
+
+    impl Sector for Ram {
+        fn bytes(&self) -> &[u8] {
+            &self.bytes
+        }
+
+        fn erase(&mut self) -> Result<(), Error> {
+            self.bytes.fill(0xFF);
+            self.erases += 1;
+            Ok(())
+        }
+
+        fn program(&mut self, offset: usize, data: &[u8]) -> Result<(), Error> {
+            for (byte, new) in self.bytes[offset..offset + data.len()].iter_mut().zip(data) {
+                assert_eq!(*byte, 0xFF, "programmed twice at {}", offset);
+                *byte = *new;
+            }
+            Ok(())
+        }
+    }
+
+    // What a fresh start would find in the sector
+    fn reopen(storage: &Storage<Ram>) -> Storage<Ram> {
+        Storage::new(storage.sector.clone())
+    }
+
+    #[test]
+    fn sections_round_trip() {
+        let mut buf = [0; 32];
+        let mut writer = Writer::new(&mut buf);
+        writer
+            .section(TAG_POINTS, |b| {
+                b[..3].copy_from_slice(&[1, 2, 3]);
+                Some(3)
+            })
+            .unwrap();
+        writer.section(TAG_DIAL, |_| Some(0)).unwrap();
+        writer
+            .section(TAG_PROBE, |b| {
+                b[..4].copy_from_slice(&2.5f32.to_le_bytes());
+                Some(4)
+            })
+            .unwrap();
+        let data = writer.data().to_vec();
+        assert_eq!(data.len(), 3 * 3 + 3 + 4);
+
+        let sections: Vec<_> = Sections::new(&data).collect();
+        assert_eq!(
+            sections,
+            [
+                (TAG_POINTS, &[1, 2, 3][..]),
+                (TAG_DIAL, &[][..]),
+                (TAG_PROBE, &2.5f32.to_le_bytes()[..]),
+            ]
+        );
+
+        // A section cut short ends the record there
+        let sections: Vec<_> = Sections::new(&data[..data.len() - 1]).collect();
+        assert_eq!(sections.len(), 2);
+        assert_eq!(Sections::new(&data[..2]).count(), 0);
+    }
+
+    #[test]
+    fn sections_that_dont_fit() {
+        let mut buf = [0; 8];
+        let mut writer = Writer::new(&mut buf);
+        assert!(matches!(writer.section(1, |_| None), Err(Error::TooBig)));
+        writer.section(1, |_| Some(5)).unwrap();
+        // No room left even for a header
+        assert!(matches!(writer.section(2, |_| Some(0)), Err(Error::TooBig)));
+        assert_eq!(writer.data().len(), 8);
+    }
+
+    #[test]
+    fn newest_record_wins() {
+        let mut storage = Storage::new(Ram::new(1024));
+        assert_eq!(storage.load(), None);
+        storage.save(&[1, 2, 3]).unwrap();
+        storage.save(&[4, 5, 6, 7, 8]).unwrap();
+        assert_eq!(storage.load(), Some(&[4, 5, 6, 7, 8][..]));
+
+        // Found again after a restart, and the log carries on after it
+        let mut storage = reopen(&storage);
+        assert_eq!(storage.load(), Some(&[4, 5, 6, 7, 8][..]));
+        assert_eq!(storage.next, 2 * HEADER_SIZE + 4 + 8);
+        storage.save(&[]).unwrap();
+        assert_eq!(reopen(&storage).load(), Some(&[][..]));
+        assert_eq!(storage.sector.erases, 0);
+    }
+
+    #[test]
+    fn half_written_record() {
+        let mut storage = Storage::new(Ram::new(1024));
+        storage.save(&[1, 2, 3]).unwrap();
+        storage.save(&[4, 5, 6]).unwrap();
+        // Power lost part way through the second record's data
+        let at = HEADER_SIZE + 4 + HEADER_SIZE;
+        storage.sector.bytes[at + 2] = 0xFF;
+        let mut storage = reopen(&storage);
+        assert_eq!(storage.load(), Some(&[1, 2, 3][..]));
+        // The next record goes after it all the same
+        storage.save(&[9]).unwrap();
+        assert_eq!(reopen(&storage).load(), Some(&[9][..]));
+    }
+
+    #[test]
+    fn rolls_over_when_full() {
+        // Room for three 16 byte records and a bit
+        let mut storage = Storage::new(Ram::new(56));
+        for i in 0..3 {
+            storage.save(&[i; 4]).unwrap();
+        }
+        assert_eq!(storage.sector.erases, 0);
+        storage.save(&[3; 4]).unwrap();
+        assert_eq!(storage.sector.erases, 1);
+        assert_eq!(storage.next, 16);
+        assert_eq!(storage.load(), Some(&[3; 4][..]));
+        // Only the new record survives the erase
+        assert_eq!(reopen(&storage).load(), Some(&[3; 4][..]));
+    }
+
+    #[test]
+    fn garbage_is_erased() {
+        let mut ram = Ram::new(64);
+        ram.bytes[..4].copy_from_slice(b"junk");
+        let mut storage = Storage::new(ram);
+        assert_eq!(storage.load(), None);
+        storage.save(&[1]).unwrap();
+        assert_eq!(storage.sector.erases, 1);
+        assert_eq!(reopen(&storage).load(), Some(&[1][..]));
+    }
+
+    #[test]
+    fn too_big() {
+        let mut storage = Storage::new(Ram::new(64));
+        assert!(matches!(storage.save(&[0; 53]), Err(Error::TooBig)));
+        storage.save(&[0; 52]).unwrap();
+    }
+}
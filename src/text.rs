@@ -0,0 +1,54 @@
+use core::fmt;
+
+/// Fixed size text buffer for formatting numbers and labels without
+/// an allocator. Anything that doesn't fit is silently dropped.
+#[derive(Copy, Clone)]
+pub struct TextBuffer<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> TextBuffer<N> {
+    pub const fn new() -> TextBuffer<N> {
+        TextBuffer {
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only whole chars are ever copied in, so this can't fail
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+impl<const N: usize> fmt::Write for TextBuffer<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let mut b = [0; 4];
+            let bytes = c.encode_utf8(&mut b).as_bytes();
+            if self.len + bytes.len() > N {
+                return Err(fmt::Error);
+            }
+            self.buf[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+            self.len += bytes.len();
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> PartialEq for TextBuffer<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl<const N: usize> fmt::Debug for TextBuffer<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
@@ -0,0 +1,477 @@
+//! The toolpath of a G-code program, boiled down for plotting.
+//!
+//! Every move is turned into straight lines, arcs into chords of at most
+//! `ARC_STEP`, and kept as a list of vertices in work coordinates. The list
+//! has a fixed size: when it fills up, every other vertex is dropped and
+//! vertices closer together than the doubled minimum step are skipped from
+//! then on, so a program of any length fits, just with less detail.
+//!
+//! `Viewport` maps those vertices to pixels in one of four projections.
+//! Nothing here touches hardware or the display.
+
+use core::f32::consts::PI;
+use core::fmt::{self, Write};
+
+#[cfg(not(test))]
+use micromath::F32Ext;
+
+use crate::gcode::{self, Interpreter, Segment};
+use crate::job::{LineSource, Read, LINE_LEN};
+use crate::text::TextBuffer;
+
+pub const MAX_VERTICES: usize = 1024;
+const ARC_STEP: f32 = PI / 18.0; // 10 degrees
+const MIN_STEP_MM: f32 = 0.05;
+
+// Zoom limits, in pixels per mm
+const MIN_SCALE: f32 = 0.01;
+const MAX_SCALE: f32 = 200.0;
+const FIT_MARGIN: f32 = 0.9; // Fraction of the plot the toolpath fills
+
+// 30 degree isometric
+const COS_30: f32 = 0.866_025_4;
+const SIN_30: f32 = 0.5;
+
+// Keeps far off-plot points from overflowing when converted to pixels
+const SCREEN_LIMIT: f32 = 4_000.0;
+
+/// A line that couldn't be followed. `error` is None when the line
+/// couldn't be read at all.
+#[derive(Copy, Clone, Debug)]
+pub struct Problem {
+    pub line: u32,
+    pub error: Option<gcode::Error>,
+}
+
+impl Problem {
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn describe<W: Write>(&self, out: &mut W) -> fmt::Result {
+        write!(out, "Line {}: ", self.line)?;
+        match self.error {
+            Some(e) => e.describe(out),
+            None => write!(out, "can't read it"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Vertex {
+    position: [f32; 3],
+    rapid: bool, // The move that ends here
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Toolpath {
+    vertices: [Vertex; MAX_VERTICES],
+    count: usize,
+    min_step: f32,
+    problem: Option<Problem>,
+    feed_minutes: f32, // Cutting time, leaving out rapids and acceleration
+}
+
+impl Toolpath {
+    pub const fn new() -> Toolpath {
+        Toolpath {
+            vertices: [Vertex {
+                position: [0.0; 3],
+                rapid: true,
+            }; MAX_VERTICES],
+            count: 0,
+            min_step: MIN_STEP_MM,
+            problem: None,
+            feed_minutes: 0.0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.count = 0;
+        self.min_step = MIN_STEP_MM;
+        self.problem = None;
+        self.feed_minutes = 0.0;
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Position of a vertex, and whether the move to it was a rapid
+    pub fn vertex(&self, i: usize) -> Option<([f32; 3], bool)> {
+        if i < self.count {
+            let v = self.vertices[i];
+            Some((v.position, v.rapid))
+        } else {
+            None
+        }
+    }
+
+    /// The first line that couldn't be followed, if any
+    pub fn problem(&self) -> Option<Problem> {
+        self.problem
+    }
+
+    /// A rough cutting time, from the feed rates and lengths of the moves
+    pub fn feed_minutes(&self) -> f32 {
+        self.feed_minutes
+    }
+
+    fn fault(&mut self, line: u32, error: Option<gcode::Error>) {
+        if self.problem.is_none() {
+            self.problem = Some(Problem { line, error });
+        }
+    }
+
+    pub fn add(&mut self, segment: &Segment) {
+        if self.count == 0 {
+            self.push(segment.from(), true);
+        }
+        let rapid = segment.is_rapid();
+        if !rapid {
+            self.feed_minutes += segment.length() / segment.feed();
+        }
+        let sweep = segment.sweep();
+        if sweep > 0.0 {
+            let steps = (sweep / ARC_STEP).ceil().max(1.0) as u32;
+            for i in 1..steps {
+                self.push(segment.point_at(i as f32 / steps as f32), rapid);
+            }
+        }
+        self.push(segment.to(), rapid);
+    }
+
+    fn push(&mut self, position: [f32; 3], rapid: bool) {
+        if self.count > 0 {
+            let last = self.vertices[self.count - 1].position;
+            let d = [
+                position[0] - last[0],
+                position[1] - last[1],
+                position[2] - last[2],
+            ];
+            if (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt() < self.min_step {
+                return;
+            }
+        }
+        if self.count == MAX_VERTICES {
+            self.thin();
+        }
+        self.vertices[self.count] = Vertex { position, rapid };
+        self.count += 1;
+    }
+
+    // Halves the number of vertices, keeping the last so the path still
+    // reaches the newest move. A vertex standing in for a dropped one counts
+    // as a feed if either move was, so cuts never turn into rapids.
+    fn thin(&mut self) {
+        let mut kept = 1;
+        let mut i = 1;
+        while i < self.count {
+            let end = (i + 1).min(self.count - 1);
+            let rapid = self.vertices[i..=end].iter().all(|v| v.rapid);
+            self.vertices[kept] = Vertex {
+                position: self.vertices[end].position,
+                rapid,
+            };
+            kept += 1;
+            i = end + 1;
+        }
+        self.count = kept;
+        self.min_step *= 2.0;
+    }
+}
+
+/// Follows a whole program into `path`. Lines that can't be followed are
+/// skipped, and the first of them is kept as the path's problem.
+pub fn load<S: LineSource>(source: &mut S, path: &mut Toolpath) {
+    path.clear();
+    let mut machine = Interpreter::new();
+    let mut text: TextBuffer<LINE_LEN> = TextBuffer::new();
+    let mut line = 0;
+    loop {
+        match source.next_line(&mut text) {
+            Read::Line(_) => {
+                line += 1;
+                match machine.execute_line(text.as_str()) {
+                    Ok(Some(segment)) => path.add(&segment),
+                    Ok(None) => (),
+                    Err(e) => path.fault(line, Some(e)),
+                }
+            }
+            Read::TooLong(_) => {
+                line += 1;
+                path.fault(line, None);
+            }
+            Read::End => break,
+            Read::Failed => {
+                path.fault(line + 1, None);
+                break;
+            }
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+#[cfg_attr(test, allow(dead_code))]
+pub enum Projection {
+    Xy,
+    Xz,
+    Yz,
+    Iso,
+}
+
+impl Projection {
+    /// The one after this, for a key that steps through them all
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn next(self) -> Projection {
+        match self {
+            Projection::Xy => Projection::Xz,
+            Projection::Xz => Projection::Yz,
+            Projection::Yz => Projection::Iso,
+            Projection::Iso => Projection::Xy,
+        }
+    }
+
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn name(self) -> &'static str {
+        match self {
+            Projection::Xy => "XY",
+            Projection::Xz => "XZ",
+            Projection::Yz => "YZ",
+            Projection::Iso => "Iso",
+        }
+    }
+
+    /// Flattens a point to plot coordinates, up the plot being positive
+    pub fn project(self, p: [f32; 3]) -> (f32, f32) {
+        match self {
+            Projection::Xy => (p[0], p[1]),
+            Projection::Xz => (p[0], p[2]),
+            Projection::Yz => (p[1], p[2]),
+            Projection::Iso => ((p[0] - p[1]) * COS_30, (p[0] + p[1]) * SIN_30 + p[2]),
+        }
+    }
+}
+
+/// Which part of the toolpath a plot of `width` by `height` pixels shows
+#[derive(Copy, Clone, Debug)]
+pub struct Viewport {
+    projection: Projection,
+    centre: (f32, f32),
+    scale: f32, // Pixels per mm
+    width: u16,
+    height: u16,
+}
+
+impl Viewport {
+    pub fn new(width: u16, height: u16) -> Viewport {
+        Viewport {
+            projection: Projection::Xy,
+            centre: (0.0, 0.0),
+            scale: 1.0,
+            width,
+            height,
+        }
+    }
+
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    /// Switches projection and fits the whole path into the new one
+    pub fn set_projection(&mut self, projection: Projection, path: &Toolpath) {
+        self.projection = projection;
+        self.fit(path);
+    }
+
+    /// Zooms and pans so the whole path just fits
+    pub fn fit(&mut self, path: &Toolpath) {
+        let mut low = (f32::MAX, f32::MAX);
+        let mut high = (f32::MIN, f32::MIN);
+        for i in 0..path.count() {
+            if let Some((position, _)) = path.vertex(i) {
+                let (x, y) = self.projection.project(position);
+                low = (low.0.min(x), low.1.min(y));
+                high = (high.0.max(x), high.1.max(y));
+            }
+        }
+        if path.count() == 0 {
+            self.centre = (0.0, 0.0);
+            self.scale = 1.0;
+            return;
+        }
+        self.centre = ((low.0 + high.0) / 2.0, (low.1 + high.1) / 2.0);
+        // A path that is a point or a straight line along one axis has no
+        // size in that direction, which the other one decides instead
+        let fit = |pixels: u16, mm: f32| {
+            if mm > 0.0 {
+                pixels as f32 * FIT_MARGIN / mm
+            } else {
+                MAX_SCALE
+            }
+        };
+        let scale = fit(self.width, high.0 - low.0).min(fit(self.height, high.1 - low.1));
+        self.scale = scale.clamp(MIN_SCALE, MAX_SCALE);
+    }
+
+    /// Moves the plot along with a finger, by pixels
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn pan(&mut self, dx: i16, dy: i16) {
+        self.centre.0 -= dx as f32 / self.scale;
+        self.centre.1 += dy as f32 / self.scale;
+    }
+
+    /// Zooms about the middle of the plot. More than 1 zooms in.
+    pub fn zoom(&mut self, factor: f32) {
+        self.scale = (self.scale * factor).clamp(MIN_SCALE, MAX_SCALE);
+    }
+
+    /// Where a point in work coordinates lands, in pixels from the top left
+    /// of the plot. It may be well off the plot.
+    pub fn pixel(&self, position: [f32; 3]) -> (i32, i32) {
+        let (x, y) = self.projection.project(position);
+        let sx = self.width as f32 / 2.0 + (x - self.centre.0) * self.scale;
+        let sy = self.height as f32 / 2.0 - (y - self.centre.1) * self.scale;
+        (
+            sx.clamp(-SCREEN_LIMIT, SCREEN_LIMIT) as i32,
+            sy.clamp(-SCREEN_LIMIT, SCREEN_LIMIT) as i32,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A program in memory, a line at a time
+    struct File<'a>(core::str::Lines<'a>);
+
+    impl LineSource for File<'_> {
+        fn next_line(&mut self, line: &mut TextBuffer<LINE_LEN>) -> Read {
+            line.clear();
+            match self.0.next() {
+                Some(text) if line.write_str(text).is_ok() => Read::Line(text.len() as u32 + 1),
+                Some(text) => Read::TooLong(text.len() as u32 + 1),
+                None => Read::End,
+            }
+        }
+    }
+
+    fn load_text(text: &str) -> Toolpath {
+        let mut path = Toolpath::new();
+        load(&mut File(text.lines()), &mut path);
+        path
+    }
+
+    fn close(a: [f32; 3], b: [f32; 3]) -> bool {
+        a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 1e-3)
+    }
+
+    #[test]
+    fn lines_and_arcs() {
+        let path = load_text("G0 X10\nG1 Y10 F100\nG3 X0 Y10 I-5 J0\n");
+        assert_eq!(path.vertex(0), Some(([0.0; 3], true)));
+        assert_eq!(path.vertex(1), Some(([10.0, 0.0, 0.0], true)));
+        assert_eq!(path.vertex(2), Some(([10.0, 10.0, 0.0], false)));
+        // A half circle in 10 degree chords
+        assert_eq!(path.count(), 3 + 18);
+        let (end, rapid) = path.vertex(path.count() - 1).unwrap();
+        assert!(close(end, [0.0, 10.0, 0.0]) && !rapid);
+        let (middle, _) = path.vertex(3 + 8).unwrap();
+        assert!(close(middle, [5.0, 15.0, 0.0]), "{:?}", middle);
+        assert!(path.problem().is_none());
+        // The rapid takes no time
+        let minutes = (10.0 + 5.0 * PI) / 100.0;
+        assert!((path.feed_minutes() - minutes).abs() < 1e-4);
+    }
+
+    #[test]
+    fn problems() {
+        let long = "G1".repeat(LINE_LEN);
+        let path = load_text(&format!("G0 X1\nG28\n{}\nG0 Y1\n", long));
+        let problem = path.problem().unwrap();
+        assert_eq!(problem.line, 2);
+        assert!(problem.error.is_some());
+        // The moves either side are still there
+        assert_eq!(path.count(), 3);
+
+        let path = load_text(&format!("G0 X1\n{}\n", long));
+        let problem = path.problem().unwrap();
+        assert_eq!((problem.line, problem.error.is_none()), (2, true));
+    }
+
+    #[test]
+    fn tiny_moves_are_skipped() {
+        let path = load_text("G1 X0.01 F100\nG1 X0.02\nG1 X1\n");
+        assert_eq!(path.count(), 2);
+        assert_eq!(path.vertex(1), Some(([1.0, 0.0, 0.0], false)));
+    }
+
+    #[test]
+    fn thin_keeps_the_ends() {
+        // Fills every vertex, then one more
+        let mut text = String::from("G1 F100\n");
+        for x in 1..=MAX_VERTICES {
+            text.push_str(&format!("X{}\n", x));
+        }
+        let path = load_text(&text);
+        assert_eq!(path.count(), MAX_VERTICES / 2 + 2);
+        assert_eq!(path.vertex(0), Some(([0.0; 3], true)));
+        let last = MAX_VERTICES as f32;
+        assert_eq!(
+            path.vertex(path.count() - 2),
+            Some(([last - 1.0, 0.0, 0.0], false))
+        );
+        assert_eq!(
+            path.vertex(path.count() - 1),
+            Some(([last, 0.0, 0.0], false))
+        );
+    }
+
+    #[test]
+    fn thin_keeps_feeds() {
+        // Rapids and feeds in turn: every kept vertex stands in for a feed
+        let mut text = String::new();
+        for x in 1..MAX_VERTICES {
+            text.push_str(&format!("G{} X{} F100\n", x % 2, x));
+        }
+        let mut path = load_text(&text);
+        path.push([0.0, 1.0, 0.0], true);
+        for i in 1..path.count() - 2 {
+            assert!(!path.vertex(i).unwrap().1, "{}", i);
+        }
+        // Moves closer together than the new minimum step are skipped
+        let count = path.count();
+        path.push([0.0, 1.05, 0.0], true);
+        assert_eq!(path.count(), count);
+    }
+
+    #[test]
+    fn fit_bounds() {
+        let path = load_text("G0 X100 Y50 Z-5\n");
+        let mut view = Viewport::new(200, 100);
+        view.fit(&path);
+        // Wide and short, so the height decides the scale
+        let (left, bottom) = view.pixel([0.0, 0.0, 0.0]);
+        let (right, top) = view.pixel([100.0, 50.0, 0.0]);
+        assert_eq!((left + right, top + bottom), (200, 100));
+        assert!(left >= 0 && right <= 200 && top >= 0 && bottom <= 100);
+        assert_eq!(bottom - top, 90);
+
+        view.set_projection(Projection::Xz, &path);
+        let (_, high) = view.pixel([0.0, 0.0, 0.0]);
+        let (_, low) = view.pixel([0.0, 0.0, -5.0]);
+        // Long and thin, so the width decides the scale
+        assert_eq!((high, low), (45, 54));
+
+        // Nothing to fit, and a path that is only a point
+        view.fit(&Toolpath::new());
+        assert_eq!(view.pixel([0.0; 3]), (100, 50));
+        let point = load_text("G0 X0\n");
+        view.fit(&point);
+        assert_eq!(view.pixel([0.0; 3]), (100, 50));
+
+        // Far off the plot stays in range
+        view.zoom(1e9);
+        let (x, _) = view.pixel([1e6, 0.0, 0.0]);
+        assert_eq!(x, SCREEN_LIMIT as i32);
+    }
+}
@@ -0,0 +1,123 @@
+//! Interrupt driven UART on USART6: PC6 TX, PC7 RX (Arduino D1 and D0).
+//!
+//! Received bytes go into a ring buffer from the interrupt handler, because
+//! the main loop can spend longer drawing than one character takes to
+//! arrive and the USART only holds a single byte. Sending blocks, which is
+//! fine for the short lines we send.
+
+use core::cell::RefCell;
+
+use cortex_m::interrupt::{free, Mutex};
+use stm32f7xx_hal::{
+    pac::{interrupt, Interrupt, NVIC, USART6},
+    prelude::*,
+    rcc::Clocks,
+    serial::{Config, Event, Pins, Serial, Tx},
+};
+
+use crate::backend::Port;
+
+const RX_BUFFER_SIZE: usize = 512;
+
+static RX: Mutex<RefCell<RingBuffer<RX_BUFFER_SIZE>>> = Mutex::new(RefCell::new(RingBuffer::new()));
+
+/// Fixed size byte FIFO. New bytes are dropped when it is full.
+#[derive(Copy, Clone, Debug)]
+pub struct RingBuffer<const N: usize> {
+    buf: [u8; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    pub const fn new() -> RingBuffer<N> {
+        RingBuffer {
+            buf: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Returns false if the byte was dropped
+    pub fn push(&mut self, byte: u8) -> bool {
+        if self.len == N {
+            return false;
+        }
+        self.buf[(self.head + self.len) % N] = byte;
+        self.len += 1;
+        true
+    }
+
+    pub fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.buf[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+pub struct Uart {
+    tx: Tx<USART6>,
+}
+
+impl Uart {
+    pub fn new<PINS>(usart: USART6, pins: PINS, clocks: Clocks, baud: u32) -> Uart
+    where
+        PINS: Pins<USART6>,
+    {
+        let config = Config {
+            baud_rate: baud.Bps(),
+            ..Config::default()
+        };
+        let mut serial = Serial::new(usart, pins, clocks, config);
+        serial.listen(Event::Rxne);
+        let (tx, _rx) = serial.split();
+
+        // NOTE(unsafe) the handler only shares RX, behind a Mutex
+        unsafe { NVIC::unmask(Interrupt::USART6) };
+
+        Uart { tx }
+    }
+
+    /// The next received byte, if there is one
+    pub fn read(&mut self) -> Option<u8> {
+        free(|cs| RX.borrow(cs).borrow_mut().pop())
+    }
+
+    pub fn write(&mut self, data: &[u8]) {
+        for byte in data {
+            while self.tx.write(*byte).is_err() {}
+        }
+    }
+}
+
+#[interrupt]
+fn USART6() {
+    // NOTE(unsafe) reading RDR and clearing error flags only affects the
+    // receive side, which nothing else touches
+    let usart = unsafe { &(*USART6::ptr()) };
+    let isr = usart.isr.read();
+
+    // Clear parity, framing, noise and overrun errors, or they'd keep the
+    // interrupt firing. The byte is lost either way.
+    if isr.bits() & 0b1111 != 0 {
+        usart.icr.write(|w| unsafe { w.bits(0b1111) });
+    }
+    if isr.rxne().bit_is_set() {
+        let byte = usart.rdr.read().bits() as u8;
+        free(|cs| RX.borrow(cs).borrow_mut().push(byte));
+    }
+}
+
+impl Port for Uart {
+    fn read(&mut self) -> Option<u8> {
+        Uart::read(self)
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        Uart::write(self, data)
+    }
+}
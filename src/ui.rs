@@ -1,5 +1,17 @@
+// Touches, keys and pages are the view's, which the host tests leave out.
+// They only need `Running`.
+#![cfg_attr(test, allow(dead_code))]
+
 use rtt_target::rprintln;
 
+use crate::cycle::Kind;
+use crate::jog::JogStep;
+use crate::leadscrew::Mode;
+use crate::powerfeed::{Direction, Speed};
+use crate::probe::ProbeMode;
+use crate::relays::Output;
+use crate::wizard::Operation;
+
 const MAX_WHOLE_NUMS: u8 = 3;
 const N_DECIMALS: u8 = 3;
 
@@ -50,11 +62,15 @@ enum UIMode {
     NumberEntry,
 }
 
-#[derive(Copy, Clone, Debug)]
-enum Running {
+/// What the machine controller is doing
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Running {
     Yes,
     No,
     Jog,
+    Hold,
+    Alarm,
+    Home,
 }
 #[derive(PartialEq, Copy, Clone, Debug)]
 pub enum Ids {
@@ -74,9 +90,185 @@ pub enum Ids {
     Y(u32),
     Z(u32),
     Working(u32),
+    FeedUnits,
+    Target,
+    Page(Page),
+    Row(u8),
+    Up,
+    Down,
+    Store,
+    Recall,
+    GoTo,
+    Delete,
+    Name,
+    Undo,
+    Redo,
+    Probe(ProbeMode),
+    Radius,
+    Jog(usize, i8),
+    JogStep(JogStep),
+    FeedHold,
+    Resume,
+    Reset,
+    Run,
+    Stop,
+    Plot,
+    Projection,
+    Fit,
+    Operation(Operation),
+    // A character key on the MDI keyboard
+    Char(char),
+    Backspace,
+    // Override keys: a change in percent, or back to 100% for 0
+    FeedOverride(i16),
+    RapidOverride(u16),
+    SpindleOverride(i16),
+    Home,
+    Unlock,
+    Acknowledge,
+    // Start a probing cycle
+    Cycle(Kind),
+    // Power feed: run to a stop, put a stop at X, feed speed up or down
+    PowerFeed(Direction),
+    SetStop(Direction),
+    ClearStops,
+    FeedSpeed(Speed),
+    FeedPercent(i16),
+    // Soft limits: whether crossing one drives the relay
+    LimitRelay,
+    // One of the user's buttons from the card
+    Macro(u8),
+    // Move a relay output on to its next setting
+    Relay(Output),
+    // Leadscrew: feed or thread table, engage towards a side, go back to
+    // the start of the pass, put the end here or clear it
+    LeadscrewMode(Mode),
+    Leadscrew(Direction),
+    LeadscrewReturn,
+    LeadscrewEnd(bool),
     Empty,
 }
 
+/// A finger landing on, moving over, or leaving the touch screen
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Touch {
+    Down(u16, u16),
+    // Moved by this many pixels since the last event
+    Drag(i16, i16),
+    // Two fingers, moved apart by this factor since the last event
+    Pinch(f32),
+    Up,
+}
+
+const DRAG_STEP: u16 = 4; // Pixels a finger moves before it counts
+const PINCH_STEP: u16 = 8;
+
+fn distance(a: (u16, u16), b: (u16, u16)) -> u16 {
+    let dx = a.0.abs_diff(b.0) as u32;
+    let dy = a.1.abs_diff(b.1) as u32;
+    // Integer square root is plenty for a few hundred pixels
+    let square = dx * dx + dy * dy;
+    let mut root = 0;
+    while (root + 1) * (root + 1) <= square {
+        root += 1;
+    }
+    root as u16
+}
+
+/// Turns the touch panel's polled state into events
+#[derive(Copy, Clone, Debug)]
+pub struct TouchEvents {
+    down: bool,
+    last: (u16, u16),
+    spread: Option<u16>, // Between two fingers
+}
+
+impl TouchEvents {
+    pub fn new() -> TouchEvents {
+        TouchEvents {
+            down: false,
+            last: (0, 0),
+            spread: None,
+        }
+    }
+
+    /// Takes where the screen is being touched by up to two fingers
+    pub fn update(
+        &mut self,
+        first: Option<(u16, u16)>,
+        second: Option<(u16, u16)>,
+    ) -> Option<Touch> {
+        match (self.down, first, second) {
+            (false, Some((x, y)), _) => {
+                self.down = true;
+                self.last = (x, y);
+                Some(Touch::Down(x, y))
+            }
+            (true, None, _) => {
+                self.down = false;
+                self.spread = None;
+                Some(Touch::Up)
+            }
+            (true, Some(a), Some(b)) => {
+                let spread = distance(a, b);
+                match self.spread {
+                    Some(last) if last > 0 && spread.abs_diff(last) >= PINCH_STEP => {
+                        self.spread = Some(spread);
+                        Some(Touch::Pinch(spread as f32 / last as f32))
+                    }
+                    Some(_) => None,
+                    None => {
+                        self.spread = Some(spread);
+                        None
+                    }
+                }
+            }
+            (true, Some(point), None) => {
+                // Lifting one of two fingers shouldn't jump the plot about
+                if self.spread.take().is_some() {
+                    self.last = point;
+                    return None;
+                }
+                if distance(point, self.last) < DRAG_STEP {
+                    return None;
+                }
+                let dx = point.0 as i16 - self.last.0 as i16;
+                let dy = point.1 as i16 - self.last.1 as i16;
+                self.last = point;
+                Some(Touch::Drag(dx, dy))
+            }
+            (false, None, _) => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Page {
+    Dro,
+    Menu,
+    Points,
+    // Typing a label for the selected point
+    Label,
+    History,
+    Probe,
+    Jog,
+    Files,
+    Job,
+    Preview,
+    Wizard,
+    Mdi,
+    Overrides,
+    Alarm,
+    Safety,
+    Cycle,
+    PowerFeed,
+    Limits,
+    Macros,
+    Leadscrew,
+    Dial,
+    Relays,
+}
+
 pub struct Update {}
 
 impl Update {
@@ -0,0 +1,220 @@
+//! Feed rate estimation from encoder counts.
+//!
+//! Nothing in here touches hardware: counts and elapsed time are fed in from
+//! the main loop, so the maths can be checked on the host.
+
+#[cfg(not(test))]
+use micromath::F32Ext;
+
+pub const MM_PER_INCH: f32 = 25.4;
+
+/// Estimates the velocity of a single axis from its count.
+///
+/// Counts are sampled over a fixed time base so that slow movements still
+/// produce whole counts per sample, then smoothed with a first order IIR filter.
+#[derive(Copy, Clone, Debug)]
+pub struct VelocityEstimator {
+    period_ms: u32,
+    alpha: f32,
+    last_count: Option<i32>,
+    elapsed_ms: u32,
+    counts_per_sec: f32,
+}
+
+impl VelocityEstimator {
+    /// `period_ms` is the sample time base, `alpha` (0 < alpha <= 1) the weight
+    /// given to each new sample. An alpha of 1 turns the filter off.
+    pub fn new(period_ms: u32, alpha: f32) -> VelocityEstimator {
+        VelocityEstimator {
+            period_ms,
+            alpha,
+            last_count: None,
+            elapsed_ms: 0,
+            counts_per_sec: 0.0,
+        }
+    }
+
+    /// Feed in the current count and the time since the last call. Returns
+    /// true when a time base has elapsed and the estimate was updated.
+    pub fn update(&mut self, count: i32, dt_ms: u32) -> bool {
+        let last = match self.last_count {
+            Some(last) => last,
+            None => {
+                // First sample just establishes the reference
+                self.last_count = Some(count);
+                self.elapsed_ms = 0;
+                return false;
+            }
+        };
+
+        self.elapsed_ms += dt_ms;
+        if self.elapsed_ms < self.period_ms {
+            return false;
+        }
+
+        let delta = count.wrapping_sub(last);
+        let sample = delta as f32 * 1000.0 / self.elapsed_ms as f32;
+        self.counts_per_sec += self.alpha * (sample - self.counts_per_sec);
+
+        // Let the filter settle on a true zero rather than creep towards it
+        if delta == 0 && self.counts_per_sec.abs() < 0.5 {
+            self.counts_per_sec = 0.0;
+        }
+
+        self.last_count = Some(count);
+        self.elapsed_ms = 0;
+        true
+    }
+
+    pub fn counts_per_sec(&self) -> f32 {
+        self.counts_per_sec
+    }
+}
+
+/// Per axis and vector feed rate for X, Y and Z
+#[derive(Copy, Clone, Debug)]
+pub struct FeedRate {
+    axes: [VelocityEstimator; 3],
+    mm_per_count: [f32; 3],
+}
+
+impl FeedRate {
+    pub fn new(period_ms: u32, alpha: f32, mm_per_count: [f32; 3]) -> FeedRate {
+        FeedRate {
+            axes: [VelocityEstimator::new(period_ms, alpha); 3],
+            mm_per_count,
+        }
+    }
+
+    /// Returns true if the estimates changed. All three axes share a time
+    /// base so they always update together.
+    pub fn update(&mut self, counts: [i32; 3], dt_ms: u32) -> bool {
+        let mut updated = false;
+        for (axis, count) in self.axes.iter_mut().zip(counts.iter()) {
+            updated |= axis.update(*count, dt_ms);
+        }
+        updated
+    }
+
+    /// Signed feed rate of one axis in mm/min
+    pub fn axis_mm_per_min(&self, axis: usize) -> f32 {
+        self.axes[axis].counts_per_sec() * self.mm_per_count[axis] * 60.0
+    }
+
+    /// Magnitude of the combined XYZ feed rate in mm/min
+    pub fn vector_mm_per_min(&self) -> f32 {
+        let x = self.axis_mm_per_min(0);
+        let y = self.axis_mm_per_min(1);
+        let z = self.axis_mm_per_min(2);
+        (x * x + y * y + z * z).sqrt()
+    }
+}
+
+/// Converts mm/min to inches per minute
+pub fn to_ipm(mm_per_min: f32) -> f32 {
+    mm_per_min / MM_PER_INCH
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    // Feeds `counts` in, `dt_ms` apart, returning the new estimates
+    fn run(estimator: &mut VelocityEstimator, counts: &[i32], dt_ms: u32) -> Vec<f32> {
+        let mut estimates = Vec::new();
+        for count in counts {
+            if estimator.update(*count, dt_ms) {
+                estimates.push(estimator.counts_per_sec());
+            }
+        }
+        estimates
+    }
+
+    #[test]
+    fn first_count_is_the_reference() {
+        let mut estimator = VelocityEstimator::new(100, 1.0);
+        assert!(!estimator.update(1_000_000, 100));
+        assert!(estimator.update(1_000_050, 100));
+        assert!(close(estimator.counts_per_sec(), 500.0));
+    }
+
+    #[test]
+    fn time_base() {
+        let mut estimator = VelocityEstimator::new(100, 1.0);
+        estimator.update(0, 0);
+        // Short loops add up to a time base
+        assert_eq!(run(&mut estimator, &[10, 20, 30, 40], 25), [400.0]);
+        // A late one is worked out over the time it really took
+        assert_eq!(run(&mut estimator, &[100], 150), [400.0]);
+        assert_eq!(run(&mut estimator, &[40], 200), [-300.0]);
+    }
+
+    #[test]
+    fn wraps_around() {
+        // The counter rolls over either way without a jump
+        let mut estimator = VelocityEstimator::new(100, 1.0);
+        let start = i32::MAX - 20;
+        let counts = [start, start.wrapping_add(50), start.wrapping_add(100)];
+        assert_eq!(run(&mut estimator, &counts, 100), [500.0, 500.0]);
+        let end = counts[2];
+        let counts = [end.wrapping_sub(50), end.wrapping_sub(100)];
+        assert_eq!(run(&mut estimator, &counts, 100), [-500.0, -500.0]);
+    }
+
+    #[test]
+    fn smoothing() {
+        // Each sample moves a quarter of the way to the new rate
+        let mut estimator = VelocityEstimator::new(100, 0.25);
+        estimator.update(0, 0);
+        let counts: Vec<i32> = (1..=5).map(|n| n * 100).collect();
+        let estimates = run(&mut estimator, &counts, 100);
+        for (n, estimate) in estimates.iter().enumerate() {
+            let expected = 1000.0 * (1.0 - 0.75f32.powi(n as i32 + 1));
+            assert!(close(*estimate, expected), "{}: {}", n, estimate);
+        }
+        // Evens out a count that comes in lumps
+        let mut estimator = VelocityEstimator::new(100, 0.25);
+        let lumpy: Vec<i32> = (0..200).map(|n| n / 2 * 20).collect();
+        let estimates = run(&mut estimator, &lumpy, 100);
+        for estimate in &estimates[100..] {
+            assert!((estimate - 100.0).abs() < 15.0, "{}", estimate);
+        }
+    }
+
+    #[test]
+    fn stall() {
+        let mut estimator = VelocityEstimator::new(100, 0.5);
+        estimator.update(0, 0);
+        run(&mut estimator, &[100, 200, 300, 400, 500, 600], 100);
+        assert!(estimator.counts_per_sec() > 900.0);
+        // Stopped: it decays, then settles on exactly zero
+        let estimates = run(&mut estimator, &[600; 20], 100);
+        assert!(estimates.windows(2).all(|pair| pair[1] <= pair[0]));
+        assert_eq!(estimator.counts_per_sec(), 0.0);
+        let first_zero = estimates.iter().position(|e| *e == 0.0).unwrap();
+        assert!(estimates[first_zero - 1] < 1.0);
+        // But not while it still creeps a count at a time
+        let mut estimator = VelocityEstimator::new(1000, 0.1);
+        estimator.update(0, 0);
+        run(&mut estimator, &[1, 2, 3], 1000);
+        assert!(estimator.counts_per_sec() > 0.0);
+    }
+
+    #[test]
+    fn feed_rate() {
+        // 5 µm a count
+        let mut feed = FeedRate::new(100, 1.0, [0.005; 3]);
+        assert!(!feed.update([0, 0, 0], 0));
+        assert!(!feed.update([30, -40, 0], 50));
+        assert!(feed.update([60, -80, 0], 50));
+        assert!(close(feed.axis_mm_per_min(0), 180.0));
+        assert!(close(feed.axis_mm_per_min(1), -240.0));
+        assert!(close(feed.axis_mm_per_min(2), 0.0));
+        assert!(close(feed.vector_mm_per_min(), 300.0));
+        assert!(close(to_ipm(254.0), 10.0));
+    }
+}
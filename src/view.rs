@@ -1,26 +1,76 @@
 use embedded_graphics::{
-    mono_font::MonoTextStyle,
+    mono_font::{MonoFont, MonoTextStyle},
     pixelcolor::{Rgb565, RgbColor},
     prelude::*,
     primitives::{PrimitiveStyleBuilder, Rectangle, RoundedRectangle},
     text::Text,
 };
 
-use stm32f7xx_hal::ltdc::Layer;
-
+#[cfg(not(test))]
 #[allow(unused_imports)]
 use panic_semihosting;
 
+use core::fmt::Write;
+use core::mem::MaybeUninit;
+
+use crate::alarm::Alarm;
+use crate::backend::{self, Accessories, Command, Commands, Message, Override, Overrides, Reply};
 use crate::consts::*;
-use crate::display::SevenSegDisplay;
+use crate::cycle::{self, Cycle, Step};
+use crate::dial::Dial;
+use crate::display::{
+    AlarmBanner, DialIndicator, FeedDisplay, FileList, HistoryList, JobStatus, JogStatus,
+    LabelEntry, MachineStatus, MacroStatus, MdiConsole, OverrideStatus, PointList, PowerFeedStatus,
+    ProbeStatus, RelayList, SafetyBanner, SevenSegDisplay, ToolpathPlot, WizardList,
+};
+use crate::files::FileNames;
+use crate::history::{Change, ChangeKind, History};
+use crate::job::{self, Progress};
+use crate::jog::{JogStep, Jogger};
+use crate::leadscrew::{self, Mode};
+use crate::limits::{Envelope, Zone};
+use crate::macros::{self, Macros};
+use crate::mdi::{EntryKind, Mdi};
+use crate::points::{PointMemory, LABEL_LEN};
+use crate::powerfeed::{self, Direction, PowerFeed, Speed};
+use crate::probe::{self, ProbeMode};
+use crate::relays::{Output, Relays, OUTPUTS};
+use crate::safety::{Inputs, Interlocks};
 use crate::screen::Stm32F7DiscoDisplay;
+use crate::storage;
+use crate::text::TextBuffer;
+use crate::toolpath::Toolpath;
 use crate::ui;
-use profont::PROFONT_24_POINT;
+use crate::velocity::FeedRate;
+use crate::wizard::{Fields, Operation, Problem, Program, Wizard};
+use profont::{PROFONT_14_POINT, PROFONT_18_POINT, PROFONT_24_POINT};
 
 pub static mut FB_LAYER1: [u16; FB_GRAPHICS_SIZE] = [0; FB_GRAPHICS_SIZE];
+// Too big for the stack, so the view keeps references to them
+static mut TOOLPATH: Toolpath = Toolpath::new();
+static mut MACROS: Macros = Macros::new();
+// The view itself is too big for main's stack frame as well
+static mut VIEW: MaybeUninit<View> = MaybeUninit::uninit();
 
 use rtt_target::rprintln;
 
+#[derive(Copy, Clone, Debug)]
+pub enum FontSize {
+    Large,
+    Medium,
+    Small,
+}
+
+impl FontSize {
+    fn font(self) -> &'static MonoFont<'static> {
+        match self {
+            FontSize::Large => &PROFONT_24_POINT,
+            FontSize::Medium => &PROFONT_18_POINT,
+            FontSize::Small => &PROFONT_14_POINT,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub struct Button {
     x: u16,
@@ -35,26 +85,27 @@ pub struct Button {
     text_color: Rgb565,
     push_fill: Rgb565,
     push_text: Rgb565,
-    text: Option<&'static str>,
+    // Owned, as the macro keys' labels come from the card
+    text: Option<TextBuffer<BUTTON_LABEL_LEN>>,
+    font: FontSize,
 }
 
 impl Button {
-    fn new(
-        x: u16,
-        y: u16,
-        width: u16,
-        height: u16,
-        text: Option<&'static str>,
-        id: ui::Ids,
-    ) -> Button {
+    fn new(x: u16, y: u16, width: u16, height: u16, text: Option<&str>, id: ui::Ids) -> Button {
         let h = PROFONT_24_POINT.character_size.height;
         let w = PROFONT_24_POINT.character_size.width;
+        let text = text.map(|t| {
+            let mut label = TextBuffer::new();
+            label.write_str(t).ok();
+            label
+        });
+        let n = text.map_or(1, |t| t.as_str().chars().count()) as u16;
         return Button {
             x,
             y,
             width,
             height,
-            text_x: x + (width - w as u16) / 2 + 1,
+            text_x: x + (width - n * w as u16) / 2 + 1,
             text_y: y + (height + h as u16) / 2 - 3,
             active: false,
             id,
@@ -63,6 +114,7 @@ impl Button {
             push_fill: BUTTON_PUSH_COLOR,
             push_text: TEXT_PUSH_COLOR,
             text,
+            font: FontSize::Large,
         };
     }
 
@@ -87,9 +139,9 @@ impl Button {
         .draw(display)
         .ok();
 
-        let style = MonoTextStyle::new(&PROFONT_24_POINT, self.text_color);
+        let style = MonoTextStyle::new(self.font.font(), self.text_color);
         Text::new(
-            self.text.unwrap(),
+            self.text.as_ref().map_or("", |t| t.as_str()),
             Point::new(self.text_x.into(), self.text_y.into()),
             style,
         )
@@ -107,8 +159,21 @@ impl Button {
         self.text_y = text_y;
     }
 
+    /// Switches to a smaller font, centring the text again
+    fn change_font(&mut self, font: FontSize) {
+        self.font = font;
+        let f = font.font();
+        let n = self.text.map_or(1, |t| t.as_str().chars().count()) as u32;
+        let text_width = n * (f.character_size.width + f.character_spacing);
+        self.text_x = self.x + (self.width.saturating_sub(text_width as u16)) / 2 + 1;
+        self.text_y = self.y
+            + (self.height.saturating_sub(f.character_size.height as u16)) / 2
+            + f.baseline as u16
+            + 1;
+    }
+
     // returns true if coords x and y fall within the edges of the button:
-    fn inside(&mut self, x: u16, y: u16) -> bool {
+    fn inside(&self, x: u16, y: u16) -> bool {
         x >= self.x && x <= (self.x + self.width) && y >= self.y && y <= (self.y + self.height)
     }
 
@@ -129,15 +194,7 @@ impl Button {
     }
 }
 pub fn draw_background(display: &mut Stm32F7DiscoDisplay<u16>) {
-    let c = BACKGROUND_COLOR;
-    let background_color: u32 =
-        c.b() as u32 & 0x1F | ((c.g() as u32 & 0x3F) << 5) | ((c.r() as u32 & 0x1F) << 11);
-
-    unsafe {
-        display
-            .controller
-            .draw_rectangle(Layer::L1, (0, 0), (480, 272), background_color);
-    }
+    display.clear(BACKGROUND_COLOR).ok();
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -162,6 +219,7 @@ impl Buttons {
             push_fill: Rgb565::BLACK,
             push_text: BUTTON_FILL_COLOR,
             text: None,
+            font: FontSize::Large,
         }; MAXKEYS];
         Buttons {
             buttons,
@@ -176,7 +234,21 @@ impl Buttons {
         };
     }
 
-    fn make_keys(&mut self) {
+    /// Removes every button, ready to make the keys of another page
+    pub fn clear(&mut self) {
+        *self = Buttons::new();
+    }
+
+    // Adds a key with a smaller font, for labels longer than a character or two
+    fn add_soft_key(&mut self, x: u16, y: u16, width: u16, text: &str, id: ui::Ids, fill: Rgb565) {
+        let mut button = Button::new(x, y, width, BUTTON_HEIGHT, Some(text), id);
+        button.change_colors(fill, Rgb565::BLACK);
+        button.change_font(FontSize::Small);
+        self.add(button);
+    }
+
+    // Digits, decimal point, plus/minus, half, clear and enter
+    fn make_keypad(&mut self) {
         for i in 0..3 {
             for j in 0..3 {
                 let index = 1 + i * 3 + j;
@@ -259,6 +331,11 @@ impl Buttons {
 
         button.change_colors(ORANGE, Rgb565::BLACK);
         self.add(button);
+    }
+
+    // The DRO page: keypad, zero buttons beside the readouts
+    fn make_keys(&mut self) {
+        self.make_keypad();
 
         let x = SEVEN_SEG_WIDTH + 8;
         let y = SEVEN_SEG_TOP + 0 * SEVEN_SEG_VSPACE + (SEVEN_SEG_HEIGHT - BUTTON_HEIGHT) / 2;
@@ -303,6 +380,41 @@ impl Buttons {
         button.change_text_position(x, y);
         self.add(button);
 
+        // Menu, undo and redo take the top row of the keypad, where other
+        // pages have axis keys. Here the readouts pick the axis instead.
+        let keys = [
+            ("Menu", ui::Ids::Page(ui::Page::Menu)),
+            ("Undo", ui::Ids::Undo),
+            ("Redo", ui::Ids::Redo),
+        ];
+        for (i, (text, id)) in keys.iter().enumerate() {
+            let mut button = Button::new(
+                KEY_X_OFFSET + i as u16 * KEY_X_SPACING,
+                1,
+                BUTTON_WIDTH,
+                BUTTON_HEIGHT,
+                Some(text),
+                *id,
+            );
+            button.change_colors(LIGHT_BLUE, Rgb565::BLACK);
+            button.change_font(FontSize::Small);
+            self.add(button);
+        }
+
+        let mut button = Button::new(
+            KEY_X_OFFSET + 3 * KEY_X_SPACING,
+            1,
+            BUTTON_WIDTH,
+            BUTTON_HEIGHT,
+            Some("DT"),
+            ui::Ids::Target,
+        );
+        button.change_colors(ORANGE, Rgb565::BLACK);
+        self.add(button);
+    }
+
+    // X, Y and Z along the top of the keypad
+    fn make_axis_keys(&mut self) {
         let mut button = Button::new(
             KEY_X_OFFSET,
             1,
@@ -335,6 +447,687 @@ impl Buttons {
         self.add(button);
     }
 
+    // One large button per page
+    fn make_menu_keys(&mut self) {
+        let pages = [
+            ("DRO", ui::Page::Dro),
+            ("Points", ui::Page::Points),
+            ("History", ui::Page::History),
+            ("Probe", ui::Page::Probe),
+            ("Jog", ui::Page::Jog),
+            ("Files", ui::Page::Files),
+            ("Job", ui::Page::Job),
+            ("Wizard", ui::Page::Wizard),
+            ("MDI", ui::Page::Mdi),
+            ("Home", ui::Page::Alarm),
+            ("Touch", ui::Page::Cycle),
+            ("Feed", ui::Page::PowerFeed),
+            ("Limits", ui::Page::Limits),
+            ("Macros", ui::Page::Macros),
+            ("Lathe", ui::Page::Leadscrew),
+            ("Dial", ui::Page::Dial),
+            ("Relays", ui::Page::Relays),
+        ];
+        for (i, (text, page)) in pages.iter().enumerate() {
+            let mut button = Button::new(
+                MENU_LEFT + (i as u16 % MENU_COLUMNS) * MENU_X_SPACING,
+                MENU_TOP + (i as u16 / MENU_COLUMNS) * MENU_Y_SPACING,
+                MENU_BUTTON_WIDTH,
+                MENU_BUTTON_HEIGHT,
+                Some(text),
+                ui::Ids::Page(*page),
+            );
+            button.change_colors(LIGHT_BLUE, Rgb565::BLACK);
+            button.change_font(FontSize::Medium);
+            self.add(button);
+        }
+    }
+
+    // Point memory: keypad and axis keys for editing, list controls on the left
+    fn make_points_keys(&mut self) {
+        self.make_keypad();
+        self.make_axis_keys();
+
+        let mut button = Button::new(
+            SEVEN_SEG_WIDTH + 8,
+            POINTS_EDIT_TOP,
+            BUTTON_WIDTH - 1,
+            SEVEN_SEG_HEIGHT / 2 - 1,
+            Some("^"),
+            ui::Ids::Up,
+        );
+        button.change_font(FontSize::Medium);
+        self.add(button);
+        let mut button = Button::new(
+            SEVEN_SEG_WIDTH + 8,
+            POINTS_EDIT_TOP + SEVEN_SEG_HEIGHT / 2 + 1,
+            BUTTON_WIDTH - 1,
+            SEVEN_SEG_HEIGHT / 2 - 1,
+            Some("v"),
+            ui::Ids::Down,
+        );
+        button.change_font(FontSize::Medium);
+        self.add(button);
+
+        let keys = [
+            ("Save", ui::Ids::Store, LIGHT_BLUE),
+            ("Pre", ui::Ids::Recall, LIGHT_BLUE),
+            ("Go", ui::Ids::GoTo, ORANGE),
+            ("Del", ui::Ids::Delete, Rgb565::RED),
+            ("Back", ui::Ids::Page(ui::Page::Dro), BUTTON_FILL_COLOR),
+        ];
+        for (i, (text, id, fill)) in keys.iter().enumerate() {
+            self.add_soft_key(
+                SOFT_KEY_LEFT + i as u16 * SOFT_KEY_SPACING,
+                SOFT_KEY_TOP,
+                SOFT_KEY_WIDTH,
+                text,
+                *id,
+                *fill,
+            );
+        }
+        // Beside the axis keys, where the DRO page has DT
+        self.add_soft_key(
+            KEY_X_OFFSET + 3 * KEY_X_SPACING,
+            1,
+            BUTTON_WIDTH,
+            "Name",
+            ui::Ids::Name,
+            LIGHT_BLUE,
+        );
+    }
+
+    // Point label: the keyboard, with the label above it
+    fn make_label_keys(&mut self) {
+        self.make_keyboard(&[
+            ("Spc", ui::Ids::Char(' '), BUTTON_FILL_COLOR),
+            ("Del", ui::Ids::Backspace, LIGHT_BLUE),
+            ("Clr", ui::Ids::Clear, LIGHT_BLUE),
+            ("OK", ui::Ids::Enter, Rgb565::GREEN),
+            ("Back", ui::Ids::Page(ui::Page::Points), LIGHT_BLUE),
+        ]);
+    }
+
+    // Touch probe: edge modes along the bottom, keypad for the radius
+    fn make_probe_keys(&mut self) {
+        self.make_keypad();
+
+        let keys = [
+            ("Rad", ui::Ids::Radius, LIGHT_BLUE),
+            ("Rec", ui::Ids::Probe(ProbeMode::Record), LIGHT_BLUE),
+            ("Off", ui::Ids::Probe(ProbeMode::Off), Rgb565::RED),
+            ("Back", ui::Ids::Page(ui::Page::Dro), BUTTON_FILL_COLOR),
+        ];
+        for (i, (text, id, fill)) in keys.iter().enumerate() {
+            self.add_soft_key(
+                KEY_X_OFFSET + i as u16 * KEY_X_SPACING,
+                1,
+                BUTTON_WIDTH,
+                text,
+                *id,
+                *fill,
+            );
+        }
+
+        let edges = [
+            ("X+", ProbeMode::Edge(0, 1.0)),
+            ("X-", ProbeMode::Edge(0, -1.0)),
+            ("Y+", ProbeMode::Edge(1, 1.0)),
+            ("Y-", ProbeMode::Edge(1, -1.0)),
+            ("Z-", ProbeMode::Edge(2, -1.0)),
+        ];
+        for (i, (text, mode)) in edges.iter().enumerate() {
+            self.add_soft_key(
+                SOFT_KEY_LEFT + i as u16 * SOFT_KEY_SPACING,
+                SOFT_KEY_TOP,
+                SOFT_KEY_WIDTH,
+                text,
+                ui::Ids::Probe(*mode),
+                ORANGE,
+            );
+        }
+    }
+
+    // Jog: arrows laid out like a pendant over the keypad, step sizes below
+    fn make_jog_keys(&mut self) {
+        let arrows = [
+            ("Y+", 1, 1, 0),
+            ("X-", 0, 0, 1),
+            ("X+", 0, 2, 1),
+            ("Y-", 1, 1, 2),
+            ("Z+", 2, 3, 0),
+            ("Z-", 2, 3, 2),
+        ];
+        for (text, axis, col, row) in arrows.iter() {
+            let direction = if text.ends_with('-') { -1 } else { 1 };
+            self.add_soft_key(
+                KEY_X_OFFSET + col * KEY_X_SPACING,
+                KEY_Y_OFFSET + row * KEY_Y_SPACING,
+                BUTTON_WIDTH,
+                text,
+                ui::Ids::Jog(*axis, direction),
+                ORANGE,
+            );
+        }
+
+        let steps = [
+            (".001", JogStep::Step(0.001)),
+            (".01", JogStep::Step(0.01)),
+            (".1", JogStep::Step(0.1)),
+            ("1", JogStep::Step(1.0)),
+            ("10", JogStep::Step(10.0)),
+            ("Cont", JogStep::Continuous),
+        ];
+        for (i, (text, step)) in steps.iter().enumerate() {
+            let i = i as u16;
+            self.add_soft_key(
+                KEY_X_OFFSET + (i % 4) * KEY_X_SPACING,
+                KEY_Y_OFFSET + (3 + i / 4) * KEY_Y_SPACING,
+                BUTTON_WIDTH,
+                text,
+                ui::Ids::JogStep(*step),
+                LIGHT_BLUE,
+            );
+        }
+        self.add_soft_key(
+            KEY_X_OFFSET + 3 * KEY_X_SPACING,
+            KEY_Y_OFFSET + 4 * KEY_Y_SPACING,
+            BUTTON_WIDTH,
+            "Back",
+            ui::Ids::Page(ui::Page::Dro),
+            BUTTON_FILL_COLOR,
+        );
+
+        // Controller keys beside the readouts
+        let keys = [
+            ("Hold", ui::Ids::FeedHold, ORANGE),
+            ("Run", ui::Ids::Resume, Rgb565::GREEN),
+            ("Rst", ui::Ids::Reset, Rgb565::RED),
+        ];
+        for (i, (text, id, fill)) in keys.iter().enumerate() {
+            self.add_soft_key(
+                SEVEN_SEG_WIDTH + 8,
+                SEVEN_SEG_TOP
+                    + i as u16 * SEVEN_SEG_VSPACE
+                    + (SEVEN_SEG_HEIGHT - BUTTON_HEIGHT) / 2,
+                BUTTON_WIDTH - 1,
+                text,
+                *id,
+                *fill,
+            );
+        }
+    }
+
+    // A column of large keys on the right, for pages with a list or status
+    // filling the left
+    fn make_page_keys(&mut self, keys: &[(&'static str, ui::Ids, u16, Rgb565)]) {
+        for (text, id, row, fill) in keys.iter() {
+            let mut button = Button::new(
+                PAGE_KEY_LEFT,
+                MENU_TOP + row * MENU_Y_SPACING,
+                MENU_BUTTON_WIDTH,
+                MENU_BUTTON_HEIGHT,
+                Some(text),
+                *id,
+            );
+            button.change_colors(*fill, Rgb565::BLACK);
+            button.change_font(FontSize::Medium);
+            self.add(button);
+        }
+    }
+
+    // Datum history: the list fills the left, large keys on the right
+    fn make_history_keys(&mut self) {
+        self.make_page_keys(&[
+            ("Undo", ui::Ids::Undo, 0, LIGHT_BLUE),
+            ("Redo", ui::Ids::Redo, 1, LIGHT_BLUE),
+            ("Back", ui::Ids::Page(ui::Page::Dro), 3, LIGHT_BLUE),
+        ]);
+    }
+
+    // SD card files, laid out like the history page
+    fn make_files_keys(&mut self) {
+        self.make_page_keys(&[
+            ("^", ui::Ids::Up, 0, LIGHT_BLUE),
+            ("v", ui::Ids::Down, 1, LIGHT_BLUE),
+            ("Plot", ui::Ids::Plot, 2, Rgb565::GREEN),
+            ("Back", ui::Ids::Page(ui::Page::Dro), 3, LIGHT_BLUE),
+        ]);
+    }
+
+    // Toolpath plot on the left. Once a job is running the plot follows
+    // it, and the third key goes back to its progress. Back returns to
+    // the page the plot came from.
+    fn make_preview_keys(&mut self, job_active: bool, back: ui::Page) {
+        let go = if job_active {
+            ("Job", ui::Ids::Page(ui::Page::Job), 2, LIGHT_BLUE)
+        } else {
+            ("Run", ui::Ids::Run, 2, Rgb565::GREEN)
+        };
+        self.make_page_keys(&[
+            ("View", ui::Ids::Projection, 0, LIGHT_BLUE),
+            ("Fit", ui::Ids::Fit, 1, LIGHT_BLUE),
+            go,
+            ("Back", ui::Ids::Page(back), 3, LIGHT_BLUE),
+        ]);
+    }
+
+    // Conversational wizard: fields on the left edited with the keypad,
+    // operations along the bottom
+    fn make_wizard_keys(&mut self) {
+        self.make_keypad();
+
+        let keys = [
+            ("Plot", ui::Ids::Plot, LIGHT_BLUE),
+            ("Save", ui::Ids::Store, LIGHT_BLUE),
+            ("Run", ui::Ids::Run, Rgb565::GREEN),
+            ("Back", ui::Ids::Page(ui::Page::Menu), BUTTON_FILL_COLOR),
+        ];
+        for (i, (text, id, fill)) in keys.iter().enumerate() {
+            self.add_soft_key(
+                KEY_X_OFFSET + i as u16 * KEY_X_SPACING,
+                1,
+                BUTTON_WIDTH,
+                text,
+                *id,
+                *fill,
+            );
+        }
+
+        self.make_list_arrows();
+
+        let operations = [
+            ("Face", Operation::Facing),
+            ("RPkt", Operation::RectPocket),
+            ("CPkt", Operation::CircPocket),
+            ("Grid", Operation::DrillGrid),
+            ("Circ", Operation::BoltCircle),
+        ];
+        for (i, (text, operation)) in operations.iter().enumerate() {
+            self.add_soft_key(
+                SOFT_KEY_LEFT + i as u16 * SOFT_KEY_SPACING,
+                SOFT_KEY_TOP,
+                SOFT_KEY_WIDTH,
+                text,
+                ui::Ids::Operation(*operation),
+                ORANGE,
+            );
+        }
+    }
+
+    // Up and down keys beside the number being edited, for the list above
+    fn make_list_arrows(&mut self) {
+        let mut button = Button::new(
+            SEVEN_SEG_WIDTH + 8,
+            POINTS_EDIT_TOP,
+            BUTTON_WIDTH - 1,
+            SEVEN_SEG_HEIGHT / 2 - 1,
+            Some("^"),
+            ui::Ids::Up,
+        );
+        button.change_font(FontSize::Medium);
+        self.add(button);
+        let mut button = Button::new(
+            SEVEN_SEG_WIDTH + 8,
+            POINTS_EDIT_TOP + SEVEN_SEG_HEIGHT / 2 + 1,
+            BUTTON_WIDTH - 1,
+            SEVEN_SEG_HEIGHT / 2 - 1,
+            Some("v"),
+            ui::Ids::Down,
+        );
+        button.change_font(FontSize::Medium);
+        self.add(button);
+    }
+
+    // Probe cycles: settings on the left edited with the keypad, like a
+    // wizard's fields
+    fn make_cycle_keys(&mut self) {
+        self.make_keypad();
+        self.make_list_arrows();
+
+        let keys = [
+            ("Z", ui::Ids::Cycle(cycle::Kind::Z), Rgb565::GREEN),
+            ("XYZ", ui::Ids::Cycle(cycle::Kind::Corner), Rgb565::GREEN),
+            ("Stop", ui::Ids::Stop, Rgb565::RED),
+            ("Back", ui::Ids::Page(ui::Page::Menu), BUTTON_FILL_COLOR),
+        ];
+        for (i, (text, id, fill)) in keys.iter().enumerate() {
+            self.add_soft_key(
+                KEY_X_OFFSET + i as u16 * KEY_X_SPACING,
+                1,
+                BUTTON_WIDTH,
+                text,
+                *id,
+                *fill,
+            );
+        }
+    }
+
+    // Power feed: stops along the top, run keys under them, then speed
+    fn make_power_feed_keys(&mut self) {
+        let keys = [
+            ("Set L", ui::Ids::SetStop(Direction::Left), 0, 0, LIGHT_BLUE),
+            (
+                "Set R",
+                ui::Ids::SetStop(Direction::Right),
+                1,
+                0,
+                LIGHT_BLUE,
+            ),
+            ("Clr", ui::Ids::ClearStops, 2, 0, LIGHT_BLUE),
+            (
+                "Back",
+                ui::Ids::Page(ui::Page::Menu),
+                3,
+                0,
+                BUTTON_FILL_COLOR,
+            ),
+            (
+                "<<",
+                ui::Ids::PowerFeed(Direction::Left),
+                0,
+                1,
+                Rgb565::GREEN,
+            ),
+            ("Stop", ui::Ids::Stop, 1, 1, Rgb565::RED),
+            (
+                ">>",
+                ui::Ids::PowerFeed(Direction::Right),
+                2,
+                1,
+                Rgb565::GREEN,
+            ),
+            ("Feed", ui::Ids::FeedSpeed(Speed::Feed), 0, 2, ORANGE),
+            ("Rapid", ui::Ids::FeedSpeed(Speed::Rapid), 1, 2, ORANGE),
+            ("-", ui::Ids::FeedPercent(-1), 2, 2, LIGHT_BLUE),
+            ("+", ui::Ids::FeedPercent(1), 3, 2, LIGHT_BLUE),
+        ];
+        for (text, id, col, row, fill) in keys.iter() {
+            self.add_soft_key(
+                KEY_X_OFFSET + col * KEY_X_SPACING,
+                KEY_Y_OFFSET + row * KEY_Y_SPACING,
+                BUTTON_WIDTH,
+                text,
+                *id,
+                *fill,
+            );
+        }
+    }
+
+    // Soft limits: like the probe cycle page, with keys to put the selected
+    // limit where the machine is, turn it off, or drive the relay
+    fn make_limit_keys(&mut self) {
+        self.make_keypad();
+        self.make_list_arrows();
+
+        let keys = [
+            ("Here", ui::Ids::Store, Rgb565::GREEN),
+            ("Off", ui::Ids::Delete, ORANGE),
+            ("Relay", ui::Ids::LimitRelay, LIGHT_BLUE),
+            ("Back", ui::Ids::Page(ui::Page::Menu), BUTTON_FILL_COLOR),
+        ];
+        for (i, (text, id, fill)) in keys.iter().enumerate() {
+            self.add_soft_key(
+                KEY_X_OFFSET + i as u16 * KEY_X_SPACING,
+                1,
+                BUTTON_WIDTH,
+                text,
+                *id,
+                *fill,
+            );
+        }
+    }
+
+    // Leadscrew: the feed or thread table on the left with the pitch in use
+    // under it, typed in with the keypad, and the run keys along the bottom
+    fn make_leadscrew_keys(&mut self) {
+        self.make_keypad();
+        self.make_list_arrows();
+
+        let keys = [
+            ("Feed", ui::Ids::LeadscrewMode(Mode::Feed), ORANGE),
+            ("Thrd", ui::Ids::LeadscrewMode(Mode::Thread), ORANGE),
+            ("Stop", ui::Ids::Stop, Rgb565::RED),
+            ("Back", ui::Ids::Page(ui::Page::Menu), BUTTON_FILL_COLOR),
+        ];
+        for (i, (text, id, fill)) in keys.iter().enumerate() {
+            self.add_soft_key(
+                KEY_X_OFFSET + i as u16 * KEY_X_SPACING,
+                1,
+                BUTTON_WIDTH,
+                text,
+                *id,
+                *fill,
+            );
+        }
+
+        let keys = [
+            ("<<", ui::Ids::Leadscrew(Direction::Left), Rgb565::GREEN),
+            (">>", ui::Ids::Leadscrew(Direction::Right), Rgb565::GREEN),
+            ("Ret", ui::Ids::LeadscrewReturn, LIGHT_BLUE),
+            ("End", ui::Ids::LeadscrewEnd(true), LIGHT_BLUE),
+            ("ClrE", ui::Ids::LeadscrewEnd(false), LIGHT_BLUE),
+        ];
+        for (i, (text, id, fill)) in keys.iter().enumerate() {
+            self.add_soft_key(
+                SOFT_KEY_LEFT + i as u16 * SOFT_KEY_SPACING,
+                SOFT_KEY_TOP,
+                SOFT_KEY_WIDTH,
+                text,
+                *id,
+                *fill,
+            );
+        }
+    }
+
+    // Threading dial: the thread table on the left, the indicator under
+    // the keys on the right
+    fn make_dial_keys(&mut self) {
+        self.make_list_arrows();
+
+        let keys = [
+            ("Clr", ui::Ids::Delete, ORANGE),
+            ("Back", ui::Ids::Page(ui::Page::Menu), BUTTON_FILL_COLOR),
+        ];
+        for (i, (text, id, fill)) in keys.iter().enumerate() {
+            self.add_soft_key(
+                KEY_X_OFFSET + (i as u16 + 2) * KEY_X_SPACING,
+                1,
+                BUTTON_WIDTH,
+                text,
+                *id,
+                *fill,
+            );
+        }
+    }
+
+    // Relays: the outputs listed on the left, a key each to move it on
+    fn make_relay_keys(&mut self) {
+        self.make_page_keys(&[
+            ("Coolant", ui::Ids::Relay(Output::Coolant), 0, LIGHT_BLUE),
+            ("Vacuum", ui::Ids::Relay(Output::Vacuum), 1, LIGHT_BLUE),
+            ("Spindle", ui::Ids::Relay(Output::Spindle), 2, ORANGE),
+            ("Back", ui::Ids::Page(ui::Page::Menu), 3, BUTTON_FILL_COLOR),
+        ]);
+    }
+
+    // User macros: the buttons read from the card, laid out like the menu,
+    // with Stop and Back beside the status line along the bottom
+    fn make_macro_keys(&mut self, macros: &Macros) {
+        for i in 0..macros.count() {
+            let mut button = Button::new(
+                MENU_LEFT + (i as u16 % MENU_COLUMNS) * MENU_X_SPACING,
+                MENU_TOP + (i as u16 / MENU_COLUMNS) * MENU_Y_SPACING,
+                MENU_BUTTON_WIDTH,
+                MENU_BUTTON_HEIGHT,
+                macros.label(i),
+                ui::Ids::Macro(i as u8),
+            );
+            button.change_colors(ORANGE, Rgb565::BLACK);
+            button.change_font(FontSize::Medium);
+            self.add(button);
+        }
+
+        let keys = [
+            ("Stop", ui::Ids::Stop, Rgb565::RED),
+            ("Back", ui::Ids::Page(ui::Page::Menu), BUTTON_FILL_COLOR),
+        ];
+        for (i, (text, id, fill)) in keys.iter().enumerate() {
+            self.add_soft_key(
+                KEY_X_OFFSET + (i as u16 + 2) * KEY_X_SPACING,
+                MACRO_KEY_TOP,
+                BUTTON_WIDTH,
+                text,
+                *id,
+                *fill,
+            );
+        }
+    }
+
+    // MDI: a full keyboard across the bottom, for G-code and GRBL's $, #
+    // and [ commands, with the scrollback and command line above it
+    fn make_mdi_keys(&mut self) {
+        self.make_keyboard(&[
+            ("Spc", ui::Ids::Char(' '), BUTTON_FILL_COLOR),
+            ("Del", ui::Ids::Backspace, LIGHT_BLUE),
+            ("Clr", ui::Ids::Clear, LIGHT_BLUE),
+            ("^", ui::Ids::Up, LIGHT_BLUE),
+            ("v", ui::Ids::Down, LIGHT_BLUE),
+            ("Send", ui::Ids::Enter, Rgb565::GREEN),
+            ("Back", ui::Ids::Page(ui::Page::Menu), LIGHT_BLUE),
+        ]);
+    }
+
+    // A QWERTY keyboard of small keys, with `keys` to finish the bottom row
+    fn make_keyboard(&mut self, keys: &[(&str, ui::Ids, Rgb565)]) {
+        let rows = [
+            "1234567890",
+            "QWERTYUIOP",
+            "ASDFGHJKL=",
+            "ZXCVBNM.-$",
+            "#[?",
+        ];
+        for (row, chars) in rows.iter().enumerate() {
+            for (column, c) in chars.char_indices() {
+                let mut button = Button::new(
+                    MDI_KEYS_LEFT + column as u16 * MDI_KEY_X_SPACING,
+                    MDI_KEYS_TOP + row as u16 * MDI_KEY_Y_SPACING,
+                    MDI_KEY_WIDTH,
+                    MDI_KEY_HEIGHT,
+                    Some(&chars[column..column + 1]),
+                    ui::Ids::Char(c),
+                );
+                button.change_font(FontSize::Medium);
+                self.add(button);
+            }
+        }
+
+        let last = rows.len() - 1;
+        for (i, (text, id, fill)) in keys.iter().enumerate() {
+            let mut button = Button::new(
+                MDI_KEYS_LEFT + (rows[last].len() + i) as u16 * MDI_KEY_X_SPACING,
+                MDI_KEYS_TOP + last as u16 * MDI_KEY_Y_SPACING,
+                MDI_KEY_WIDTH,
+                MDI_KEY_HEIGHT,
+                Some(text),
+                *id,
+            );
+            button.change_colors(*fill, Rgb565::BLACK);
+            button.change_font(FontSize::Small);
+            self.add(button);
+        }
+    }
+
+    // Overrides: a row of keys for each, beside its percentage
+    fn make_override_keys(&mut self) {
+        let keys = [
+            ("-10", ui::Ids::FeedOverride(-10), 0),
+            ("+10", ui::Ids::FeedOverride(10), 0),
+            ("100", ui::Ids::FeedOverride(0), 0),
+            ("25", ui::Ids::RapidOverride(25), 1),
+            ("50", ui::Ids::RapidOverride(50), 1),
+            ("100", ui::Ids::RapidOverride(100), 1),
+            ("-10", ui::Ids::SpindleOverride(-10), 2),
+            ("+10", ui::Ids::SpindleOverride(10), 2),
+            ("100", ui::Ids::SpindleOverride(0), 2),
+        ];
+        for (i, (text, id, row)) in keys.iter().enumerate() {
+            self.add_soft_key(
+                KEY_X_OFFSET + (i as u16 % 3) * KEY_X_SPACING,
+                KEY_Y_OFFSET + row * KEY_Y_SPACING,
+                BUTTON_WIDTH,
+                text,
+                *id,
+                ORANGE,
+            );
+        }
+
+        let keys = [
+            ("Hold", ui::Ids::FeedHold, 0, ORANGE),
+            ("Run", ui::Ids::Resume, 1, Rgb565::GREEN),
+            ("Back", ui::Ids::Page(ui::Page::Job), 4, BUTTON_FILL_COLOR),
+        ];
+        for (text, id, row, fill) in keys.iter() {
+            self.add_soft_key(
+                KEY_X_OFFSET + 3 * KEY_X_SPACING,
+                KEY_Y_OFFSET + row * KEY_Y_SPACING,
+                BUTTON_WIDTH,
+                text,
+                *id,
+                *fill,
+            );
+        }
+    }
+
+    // Alarm: a row of large keys under the banner
+    fn make_alarm_keys(&mut self) {
+        let keys = [
+            ("Home", ui::Ids::Home, ORANGE),
+            ("Unlock", ui::Ids::Unlock, LIGHT_BLUE),
+            ("Reset", ui::Ids::Reset, Rgb565::RED),
+            ("Back", ui::Ids::Page(ui::Page::Dro), LIGHT_BLUE),
+        ];
+        for (i, (text, id, fill)) in keys.iter().enumerate() {
+            let mut button = Button::new(
+                MENU_LEFT + i as u16 * ALARM_KEY_SPACING,
+                ALARM_KEY_TOP,
+                ALARM_KEY_WIDTH,
+                MENU_BUTTON_HEIGHT,
+                Some(text),
+                *id,
+            );
+            button.change_colors(*fill, Rgb565::BLACK);
+            button.change_font(FontSize::Medium);
+            self.add(button);
+        }
+    }
+
+    // Safety: the banner covers everything, the only way out is to
+    // acknowledge once the inputs are healthy
+    fn make_safety_keys(&mut self) {
+        let mut button = Button::new(
+            MENU_LEFT,
+            ALARM_KEY_TOP,
+            ALARM_KEY_WIDTH,
+            MENU_BUTTON_HEIGHT,
+            Some("Ack"),
+            ui::Ids::Acknowledge,
+        );
+        button.change_colors(ORANGE, Rgb565::BLACK);
+        button.change_font(FontSize::Medium);
+        self.add(button);
+    }
+
+    // Running job: progress on the left
+    fn make_job_keys(&mut self) {
+        self.make_page_keys(&[
+            ("Hold", ui::Ids::FeedHold, 0, ORANGE),
+            ("Resume", ui::Ids::Resume, 1, Rgb565::GREEN),
+            ("Stop", ui::Ids::Stop, 2, Rgb565::RED),
+            ("Back", ui::Ids::Page(ui::Page::Dro), 3, LIGHT_BLUE),
+        ]);
+    }
+
     pub fn draw(&mut self, display: &mut Stm32F7DiscoDisplay<u16>) {
         for i in 0..self.counter {
             let mut button = self.buttons[i];
@@ -342,8 +1135,8 @@ impl Buttons {
         }
     }
 
-    pub fn locate(&mut self, x: u16, y: u16) -> Option<ui::Ids> {
-        for mut button in self.buttons {
+    pub fn locate(&self, x: u16, y: u16) -> Option<ui::Ids> {
+        for button in self.buttons.iter() {
             if button.inside(x, y) {
                 return Some(button.id);
             };
@@ -368,18 +1161,92 @@ pub enum Axis {
     Z,
     None,
 }
+
+impl Axis {
+    fn index(self) -> Option<usize> {
+        match self {
+            Axis::X => Some(0),
+            Axis::Y => Some(1),
+            Axis::Z => Some(2),
+            Axis::None => None,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 struct NumberEntryState {
     which_number: Axis,
     state: KeyState,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Debug)]
 pub struct View {
     buttons: Buttons,
     x: SevenSegDisplay,
     y: SevenSegDisplay,
     z: SevenSegDisplay,
+    feed: FeedDisplay,
+    feed_rate: FeedRate,
+    machine: MachineStatus,
+    points: PointMemory,
+    point_list: PointList,
+    point_edit: SevenSegDisplay,
+    label: TextBuffer<LABEL_LEN>, // Being typed for the selected point
+    label_entry: LabelEntry,
+    history: History,
+    history_list: HistoryList,
+    probe_mode: ProbeMode,
+    probe_radius: f32,
+    probe_edit: SevenSegDisplay,
+    probe_status: ProbeStatus,
+    probe_contact: Option<[f32; 3]>, // Work position of the last contact
+    jog: Jogger,
+    jog_status: JogStatus,
+    commands: Commands,       // Waiting to go to the machine controller
+    files: Option<FileNames>, // None if the SD card couldn't be read
+    file_list: FileList,
+    job_status: JobStatus,
+    job_request: Option<job::Request>,
+    toolpath: &'static mut Toolpath,
+    plot: ToolpathPlot,
+    dragging: bool,                // A finger went down on the plot
+    plot_program: Option<Program>, // What the plot shows, if not a file
+    wizard: Wizard,
+    wizard_list: WizardList,
+    wizard_edit: SevenSegDisplay,
+    cycle_settings: cycle::Settings,
+    cycle: Option<Cycle>,
+    cycle_list: WizardList,
+    cycle_edit: SevenSegDisplay,
+    power_feed: PowerFeed,
+    power_feed_status: PowerFeedStatus,
+    power_feed_x: f32, // Machine mm, from the scale
+    envelope: Envelope,
+    limit_list: WizardList,
+    limit_edit: SevenSegDisplay,
+    machine_position: [f32; 3], // Last fed in, for capturing limits
+    macros: &'static mut Macros,
+    macro_status: MacroStatus,
+    leadscrew: leadscrew::Settings,
+    leadscrew_list: WizardList,
+    leadscrew_edit: SevenSegDisplay,
+    leadscrew_status: Option<leadscrew::Status>, // None without the drive
+    leadscrew_request: Option<leadscrew::Request>,
+    dial: Dial,
+    dial_list: WizardList,
+    dial_indicator: DialIndicator,
+    relays: Relays,
+    relay_list: RelayList,
+    accessories: Option<Accessories>, // As the controller last said
+    mdi: Mdi,
+    mdi_console: MdiConsole,
+    overrides: Overrides, // As last reported, or just asked for
+    override_status: OverrideStatus,
+    alarm_banner: AlarmBanner,
+    interlocks: Interlocks,
+    safety_banner: SafetyBanner,
+    page: ui::Page,
+    dirty: bool, // Something that is saved to flash has changed
     pub active_id: Option<ui::Ids>,
     key_state: KeyState,
     current_axis: Axis,
@@ -392,11 +1259,43 @@ pub enum KeyState {
     NumberEntry(Axis),
     PlusMinus,
     Half,
+    // DT pressed, waiting for an axis
+    Target,
+    // Typing in a distance to go target
+    TargetEntry(Axis),
+    // DT pressed twice, waiting for an axis
+    Approach,
+    // Typing in the approach band for DT colours
+    ApproachEntry(Axis),
+    // Typing in the probe radius
+    RadiusEntry,
+    // Typing in a wizard field
+    WizardEntry,
+    // Typing in a probe cycle setting
+    CycleEntry,
+    // Typing in a soft limit
+    LimitEntry,
+    // Typing in a leadscrew feed
+    LeadscrewEntry,
     // UseNumber(Axis),
 }
 
 impl View {
-    pub fn new() -> View {
+    /// Builds the view in its static. Call it once, before anything with
+    /// a big stack frame, as building it takes two copies on the stack.
+    #[inline(never)]
+    pub fn init() -> &'static mut View {
+        // NOTE(unsafe) the only references to the statics, taken once here
+        unsafe {
+            let toolpath = &mut *core::ptr::addr_of_mut!(TOOLPATH);
+            let macros = &mut *core::ptr::addr_of_mut!(MACROS);
+            let view = core::ptr::addr_of_mut!(VIEW).cast::<View>();
+            view.write(View::new(toolpath, macros));
+            &mut *view
+        }
+    }
+
+    fn new(toolpath: &'static mut Toolpath, macros: &'static mut Macros) -> View {
         let mut x = SevenSegDisplay::new(
             SEVEN_SEG_LEFT,
             SEVEN_SEG_TOP + 0 * SEVEN_SEG_VSPACE,
@@ -421,11 +1320,130 @@ impl View {
         );
         z.set_value(20.14540);
 
+        let mut probe_edit = SevenSegDisplay::new(
+            SEVEN_SEG_LEFT,
+            SEVEN_SEG_TOP,
+            SEVEN_SEG_WIDTH,
+            SEVEN_SEG_HEIGHT,
+        );
+        probe_edit.preset(PROBE_RADIUS);
+
         View {
             buttons: Buttons::new(),
             x,
             y,
             z,
+            feed: FeedDisplay::new(FEED_LEFT, FEED_TOP, FEED_WIDTH, FEED_HEIGHT),
+            feed_rate: FeedRate::new(FEED_SAMPLE_MS, FEED_FILTER_ALPHA, ENCODER_MM_PER_COUNT),
+            machine: MachineStatus::new(SEVEN_SEG_LEFT, 0, SEVEN_SEG_WIDTH),
+            points: PointMemory::new(),
+            point_list: PointList::new(SEVEN_SEG_LEFT, POINTS_LIST_TOP, POINTS_LIST_WIDTH),
+            point_edit: SevenSegDisplay::new(
+                SEVEN_SEG_LEFT,
+                POINTS_EDIT_TOP,
+                SEVEN_SEG_WIDTH,
+                SEVEN_SEG_HEIGHT,
+            ),
+            label: TextBuffer::new(),
+            label_entry: LabelEntry::new(0, 0, 480),
+            history: History::new(),
+            history_list: HistoryList::new(SEVEN_SEG_LEFT, POINTS_LIST_TOP, HISTORY_LIST_WIDTH),
+            probe_mode: ProbeMode::Off,
+            probe_radius: PROBE_RADIUS,
+            probe_edit,
+            probe_status: ProbeStatus::new(SEVEN_SEG_LEFT, PROBE_STATUS_TOP, POINTS_LIST_WIDTH),
+            probe_contact: None,
+            jog: Jogger::new(JogStep::Step(1.0), JOG_FEED_MM_MIN, JOG_SEGMENT_MS),
+            jog_status: JogStatus::new(FEED_LEFT, FEED_TOP, FEED_WIDTH, FEED_HEIGHT),
+            commands: Commands::new(),
+            files: Some(FileNames::new()),
+            file_list: FileList::new(SEVEN_SEG_LEFT, POINTS_LIST_TOP, HISTORY_LIST_WIDTH),
+            job_status: JobStatus::new(
+                SEVEN_SEG_LEFT,
+                POINTS_LIST_TOP,
+                HISTORY_LIST_WIDTH,
+                JOB_STATUS_HEIGHT,
+            ),
+            job_request: None,
+            toolpath,
+            plot: ToolpathPlot::new(SEVEN_SEG_LEFT, POINTS_LIST_TOP, PLOT_WIDTH, PLOT_HEIGHT),
+            dragging: false,
+            plot_program: None,
+            wizard: Wizard::new(),
+            wizard_list: WizardList::new(SEVEN_SEG_LEFT, POINTS_LIST_TOP, POINTS_LIST_WIDTH),
+            wizard_edit: SevenSegDisplay::new(
+                SEVEN_SEG_LEFT,
+                POINTS_EDIT_TOP,
+                SEVEN_SEG_WIDTH,
+                SEVEN_SEG_HEIGHT,
+            ),
+            cycle_settings: cycle::Settings::new(),
+            cycle: None,
+            cycle_list: WizardList::new(SEVEN_SEG_LEFT, POINTS_LIST_TOP, POINTS_LIST_WIDTH),
+            cycle_edit: SevenSegDisplay::new(
+                SEVEN_SEG_LEFT,
+                POINTS_EDIT_TOP,
+                SEVEN_SEG_WIDTH,
+                SEVEN_SEG_HEIGHT,
+            ),
+            power_feed: PowerFeed::new(),
+            power_feed_status: PowerFeedStatus::new(
+                SEVEN_SEG_LEFT,
+                PROBE_STATUS_TOP,
+                POINTS_LIST_WIDTH,
+            ),
+            power_feed_x: 0.0,
+            envelope: Envelope::new(),
+            limit_list: WizardList::new(SEVEN_SEG_LEFT, POINTS_LIST_TOP, POINTS_LIST_WIDTH),
+            limit_edit: SevenSegDisplay::new(
+                SEVEN_SEG_LEFT,
+                POINTS_EDIT_TOP,
+                SEVEN_SEG_WIDTH,
+                SEVEN_SEG_HEIGHT,
+            ),
+            machine_position: [0.0; 3],
+            macros,
+            macro_status: MacroStatus::new(
+                SEVEN_SEG_LEFT,
+                MACRO_KEY_TOP,
+                MACRO_STATUS_WIDTH,
+                BUTTON_HEIGHT,
+            ),
+            leadscrew: leadscrew::Settings::new(),
+            leadscrew_list: WizardList::new(SEVEN_SEG_LEFT, POINTS_LIST_TOP, POINTS_LIST_WIDTH),
+            leadscrew_edit: SevenSegDisplay::new(
+                SEVEN_SEG_LEFT,
+                POINTS_EDIT_TOP,
+                SEVEN_SEG_WIDTH,
+                SEVEN_SEG_HEIGHT,
+            ),
+            leadscrew_status: None,
+            leadscrew_request: None,
+            dial: Dial::new(),
+            dial_list: WizardList::new(SEVEN_SEG_LEFT, POINTS_LIST_TOP, POINTS_LIST_WIDTH),
+            dial_indicator: DialIndicator::new(
+                KEY_X_OFFSET,
+                DIAL_INDICATOR_TOP,
+                480 - KEY_X_OFFSET - 2,
+                272 - DIAL_INDICATOR_TOP - 2,
+            ),
+            relays: Relays::new(),
+            relay_list: RelayList::new(SEVEN_SEG_LEFT, SEVEN_SEG_TOP, PAGE_KEY_LEFT - 8),
+            accessories: None,
+            mdi: Mdi::new(),
+            mdi_console: MdiConsole::new(0, 0, 480),
+            overrides: Overrides::new(),
+            override_status: OverrideStatus::new(
+                SEVEN_SEG_LEFT,
+                KEY_Y_OFFSET,
+                POINTS_LIST_WIDTH - BUTTON_WIDTH,
+                KEY_Y_SPACING,
+            ),
+            alarm_banner: AlarmBanner::new(0, 0, 480, ALARM_KEY_TOP - 4),
+            interlocks: Interlocks::new(),
+            safety_banner: SafetyBanner::new(0, 0, 480, ALARM_KEY_TOP - 4),
+            page: ui::Page::Dro,
+            dirty: false,
             active_id: None,
             key_state: KeyState::Waiting,
             current_axis: Axis::None,
@@ -439,91 +1457,1912 @@ impl View {
     pub fn update(&mut self, display: &mut Stm32F7DiscoDisplay<u16>) {
         draw_background(display);
         self.buttons.draw(display);
-        self.x.draw(display);
-        self.y.draw(display);
-        self.z.draw(display);
+        match self.page {
+            ui::Page::Dro => {
+                self.x.draw(display);
+                self.y.draw(display);
+                self.z.draw(display);
+                self.feed.draw(display);
+                self.machine.draw(display);
+            }
+            ui::Page::Points => {
+                self.point_list.draw(&self.points, display);
+                self.point_list.draw_hint(POINTS_EDIT_TOP, display);
+            }
+            ui::Page::Label => {
+                self.label_entry
+                    .draw(self.point_list.selected(), self.label.as_str(), display)
+            }
+            ui::Page::History => self.history_list.draw(&self.history, display),
+            ui::Page::Probe => {
+                self.probe_edit.draw(display);
+                self.probe_status
+                    .draw(self.probe_mode, self.probe_contact, display);
+            }
+            ui::Page::Jog => {
+                self.x.draw(display);
+                self.y.draw(display);
+                self.z.draw(display);
+                self.machine.draw(display);
+                self.jog_status
+                    .draw(self.jog.step(), self.jog.feed(), display);
+            }
+            ui::Page::Files => self.file_list.draw(self.files.as_ref(), display),
+            ui::Page::Job => self.job_status.draw(display),
+            ui::Page::Preview => self.plot.draw(self.toolpath, display),
+            ui::Page::Wizard => {
+                self.wizard_list.draw(&self.wizard, display);
+                self.wizard_edit.draw(display);
+            }
+            ui::Page::Cycle => {
+                self.cycle_list.draw(&self.cycle_settings, display);
+                self.cycle_edit.draw(display);
+            }
+            ui::Page::PowerFeed => {
+                self.x.draw(display);
+                self.power_feed_status
+                    .draw(&self.power_feed, self.x.get_offset(), display);
+            }
+            ui::Page::Limits => {
+                self.limit_list.draw(&self.envelope, display);
+                self.limit_edit.draw(display);
+            }
+            ui::Page::Macros => self.macro_status.draw(display),
+            ui::Page::Leadscrew => {
+                self.leadscrew_list.draw(&self.leadscrew, display);
+                self.leadscrew_edit.draw(display);
+            }
+            ui::Page::Dial => {
+                self.dial_list.draw(&self.dial, display);
+                self.dial_indicator.draw(display);
+            }
+            ui::Page::Relays => self.relay_list.draw(&self.relays, display),
+            ui::Page::Mdi => self.mdi_console.draw(&self.mdi, display),
+            ui::Page::Overrides => self.override_status.draw(display),
+            ui::Page::Alarm => self.alarm_banner.draw(display),
+            ui::Page::Safety => self.safety_banner.draw(&self.interlocks, display),
+            ui::Page::Menu => (),
+        }
     }
 
-    pub fn button_id_from_coords(mut self, x: u16, y: u16) -> Option<ui::Ids> {
-        self.buttons.locate(x, y)
+    // The DRO and jog pages both show the axis readouts
+    fn readouts_visible(&self) -> bool {
+        self.page == ui::Page::Dro || self.page == ui::Page::Jog
     }
 
-    // Takes an Option<id> and de/activates the button if there
-    // is an id and if it is valid.
-    pub fn activate_button_from_id(
-        self,
-        id_in: Option<ui::Ids>,
-        display: &mut Stm32F7DiscoDisplay<u16>,
-    ) {
-        if let Some(id) = id_in {
-            for mut button in self.buttons.buttons {
-                if button.id == id {
-                    button.activate(display);
+    /// Swaps the buttons over to those of another page and redraws everything
+    pub fn show_page(&mut self, page: ui::Page, display: &mut Stm32F7DiscoDisplay<u16>) {
+        // Nothing else shows until a latched safety input is acknowledged
+        let page = if self.interlocks.faulted() {
+            ui::Page::Safety
+        } else {
+            page
+        };
+        self.page = page;
+        self.key_state = KeyState::Waiting;
+        self.buttons.clear();
+        match page {
+            ui::Page::Dro => self.buttons.make_keys(),
+            ui::Page::Menu => self.buttons.make_menu_keys(),
+            ui::Page::Points => self.buttons.make_points_keys(),
+            ui::Page::Label => self.buttons.make_label_keys(),
+            ui::Page::History => self.buttons.make_history_keys(),
+            ui::Page::Probe => self.buttons.make_probe_keys(),
+            ui::Page::Jog => self.buttons.make_jog_keys(),
+            ui::Page::Files => {
+                self.buttons.make_files_keys();
+                // The card may have been swapped since last time
+                self.job_request = Some(job::Request::ListFiles);
+            }
+            ui::Page::Job => self.buttons.make_job_keys(),
+            ui::Page::Preview => {
+                let back = match self.plot_program {
+                    Some(_) => ui::Page::Wizard,
+                    None => ui::Page::Files,
+                };
+                self.buttons
+                    .make_preview_keys(self.job_status.active(), back);
+            }
+            ui::Page::Wizard => {
+                self.buttons.make_wizard_keys();
+                self.show_wizard_value();
+            }
+            ui::Page::Cycle => {
+                self.buttons.make_cycle_keys();
+                self.show_cycle_value();
+            }
+            ui::Page::PowerFeed => {
+                self.buttons.make_power_feed_keys();
+                self.power_feed_status.set_problem(None);
+            }
+            ui::Page::Limits => {
+                self.buttons.make_limit_keys();
+                self.show_limit_value();
+            }
+            ui::Page::Macros => {
+                self.buttons.make_macro_keys(self.macros);
+                // The file may have been edited since, but the buttons
+                // stay put while one of them runs
+                if self.macros.running().is_none() {
+                    self.job_request = Some(job::Request::LoadMacros);
                 }
             }
+            ui::Page::Leadscrew => {
+                self.buttons.make_leadscrew_keys();
+                self.leadscrew_list
+                    .select(self.leadscrew.row(), &self.leadscrew);
+                self.show_leadscrew_status();
+                self.leadscrew_edit.preset(self.leadscrew.pitch().mm());
+            }
+            ui::Page::Dial => {
+                self.buttons.make_dial_keys();
+                self.dial_list.select(self.dial.thread(), &self.dial);
+            }
+            ui::Page::Relays => self.buttons.make_relay_keys(),
+            ui::Page::Mdi => self.buttons.make_mdi_keys(),
+            ui::Page::Overrides => self.buttons.make_override_keys(),
+            ui::Page::Alarm => self.buttons.make_alarm_keys(),
+            ui::Page::Safety => self.buttons.make_safety_keys(),
         }
+        self.update(display);
     }
 
-    // Takes an Option<id> and de/activates the button if there
-    // is an id and if it is valid.
-    pub fn deactivate_button_from_id(
-        self,
-        id_in: Option<ui::Ids>,
-        display: &mut Stm32F7DiscoDisplay<u16>,
-    ) {
-        if let Some(id) = id_in {
-            for mut button in self.buttons.buttons {
-                if button.id == id {
-                    button.deactivate(display);
-                }
-            }
-        }
+    fn offsets(&self) -> [f32; 3] {
+        [
+            self.x.get_offset(),
+            self.y.get_offset(),
+            self.z.get_offset(),
+        ]
     }
 
-    pub fn use_number(self, axis: Axis, number: f32) {
-        // rprintln!("Use number: {:.3}", number);
+    fn set_offsets(&mut self, offsets: [f32; 3], display: &mut Stm32F7DiscoDisplay<u16>) {
+        self.x.set_offset(offsets[0]);
+        self.y.set_offset(offsets[1]);
+        self.z.set_offset(offsets[2]);
+        if self.readouts_visible() {
+            self.x.draw(display);
+            self.y.draw(display);
+            self.z.draw(display);
+        }
     }
 
-    pub fn process_button(&mut self, src: Option<ui::Ids>, display: &mut Stm32F7DiscoDisplay<u16>) {
-        if self.active_id == src {
-            // Button still pushed down - ignore
+    // Adds a datum change to the history if the offsets moved away from `before`
+    fn record(&mut self, kind: ChangeKind, before: [f32; 3]) {
+        let after = self.offsets();
+        if after == before {
             return;
+        }
+        let axis = match kind {
+            ChangeKind::Zero(a)
+            | ChangeKind::Preset(a, _)
+            | ChangeKind::Half(a)
+            | ChangeKind::PlusMinus(a)
+            | ChangeKind::Probe(a) => a,
+            ChangeKind::Recall(_) => 0,
         };
-        self.activate_button_from_id(src, display);
-        self.deactivate_button_from_id(self.active_id, display);
-        self.active_id = src;
+        let now = [self.x.get_value(), self.y.get_value(), self.z.get_value()];
+        self.history.push(Change {
+            kind,
+            before,
+            after,
+            was: now[axis] - after[axis] + before[axis],
+        });
+        self.send_offsets(before);
+    }
 
-        // rprintln!("Src: {:?}", src);
+    fn undo(&mut self, display: &mut Stm32F7DiscoDisplay<u16>) {
+        if let Some(change) = self.history.undo() {
+            self.set_offsets(change.before, display);
+            self.send_offsets(change.after);
+        }
+    }
 
-        match self.key_state {
-            KeyState::Waiting => {
-                // rprintln!("View: Waiting");
-                if let Some(src) = src {
-                    match src {
-                        ui::Ids::X0Button => {
-                            self.x.set_value(10.0);
-                            self.x.draw(display);
-                        }
-                        ui::Ids::Y0Button => {
-                            self.y.set_value(0.0);
-                            self.y.draw(display);
-                        }
-                        ui::Ids::Z0Button => {
-                            self.z.set_value(-10.0);
-                            self.z.draw(display);
-                        }
-                        ui::Ids::XButton => {
-                            // rprintln!("X");
-                            self.x.start(display);
-                            self.key_state = KeyState::NumberEntry(Axis::X);
-                        }
-                        ui::Ids::YButton => {
-                            // rprintln!("Y");
-                            self.y.start(display);
-                            self.key_state = KeyState::NumberEntry(Axis::Y);
-                        }
-                        ui::Ids::ZButton => {
-                            // rprintln!("Z");
+    fn redo(&mut self, display: &mut Stm32F7DiscoDisplay<u16>) {
+        if let Some(change) = self.history.redo() {
+            self.set_offsets(change.after, display);
+            self.send_offsets(change.before);
+        }
+    }
+
+    /// Returns true, once, after anything kept in flash has changed
+    pub fn take_dirty(&mut self) -> bool {
+        let dirty = self.dirty;
+        self.dirty = false;
+        dirty
+    }
+
+    /// Writes everything that should survive a power cycle
+    pub fn save(&self, writer: &mut storage::Writer) -> Result<(), storage::Error> {
+        writer.section(storage::TAG_POINTS, |buf| self.points.write_bytes(buf))?;
+        writer.section(storage::TAG_PROBE, |buf| {
+            buf.get_mut(..4)?
+                .copy_from_slice(&self.probe_radius.to_le_bytes());
+            Some(4)
+        })?;
+        writer.section(storage::TAG_CYCLE, |buf| {
+            self.cycle_settings.write_bytes(buf)
+        })?;
+        writer.section(storage::TAG_POWER_FEED, |buf| {
+            self.power_feed.write_bytes(buf)
+        })?;
+        writer.section(storage::TAG_LIMITS, |buf| self.envelope.write_bytes(buf))?;
+        writer.section(storage::TAG_LEADSCREW, |buf| {
+            self.leadscrew.write_bytes(buf)
+        })?;
+        writer.section(storage::TAG_DIAL, |buf| self.dial.write_bytes(buf))
+    }
+
+    /// Restores what `save` wrote. Unknown sections are skipped.
+    pub fn load(&mut self, data: &[u8]) {
+        for (tag, section) in storage::Sections::new(data) {
+            match tag {
+                storage::TAG_POINTS => self.points.read_bytes(section),
+                storage::TAG_PROBE if section.len() >= 4 => {
+                    let radius = [section[0], section[1], section[2], section[3]];
+                    self.probe_radius = f32::from_le_bytes(radius);
+                    self.probe_edit.preset(self.probe_radius);
+                }
+                storage::TAG_CYCLE => self.cycle_settings.read_bytes(section),
+                storage::TAG_POWER_FEED => self.power_feed.read_bytes(section),
+                storage::TAG_LIMITS => self.envelope.read_bytes(section),
+                storage::TAG_LEADSCREW => self.leadscrew.read_bytes(section),
+                storage::TAG_DIAL => self.dial.read_bytes(section),
+                _ => (),
+            }
+        }
+    }
+
+    /// Feed in the machine position of each axis, in mm, redrawing any
+    /// readout whose digits changed.
+    pub fn set_machine_position(
+        &mut self,
+        position: [f32; 3],
+        display: &mut Stm32F7DiscoDisplay<u16>,
+    ) {
+        let visible = self.readouts_visible();
+        // The power feed page shows X alone
+        let x_visible = visible || self.page == ui::Page::PowerFeed;
+        if self.x.set_position(position[0]) && x_visible {
+            self.x.draw(display);
+        }
+        if self.y.set_position(position[1]) && visible {
+            self.y.draw(display);
+        }
+        if self.z.set_position(position[2]) && visible {
+            self.z.draw(display);
+        }
+
+        let offsets = self.offsets();
+        let work = [
+            position[0] + offsets[0],
+            position[1] + offsets[1],
+            position[2] + offsets[2],
+        ];
+        if self.plot.set_tool(work) && self.page == ui::Page::Preview {
+            self.plot.draw(self.toolpath, display);
+        }
+        self.machine_position = position;
+        self.check_limits(position, display);
+    }
+
+    // Colours each readout by how close it is to its soft limits, and
+    // raises the alarm on a new crossing
+    fn check_limits(&mut self, position: [f32; 3], display: &mut Stm32F7DiscoDisplay<u16>) {
+        let visible = self.readouts_visible();
+        let x_visible = visible || self.page == ui::Page::PowerFeed;
+        for (axis, p) in position.iter().enumerate() {
+            let warning = match self.envelope.axis_zone(axis, *p) {
+                Zone::Inside => None,
+                Zone::Near(_, _) => Some(SOFT_LIMIT_NEAR_COLOR),
+                Zone::Over(_, _) => Some(SOFT_LIMIT_OVER_COLOR),
+            };
+            let (readout, shown) = match axis {
+                0 => (&mut self.x, x_visible),
+                1 => (&mut self.y, visible),
+                _ => (&mut self.z, visible),
+            };
+            if readout.set_warning(warning) && shown {
+                readout.draw(display);
+            }
+        }
+
+        let (zone, crossed) = self.envelope.update(position);
+        if self.machine.set_limit(zone) && visible {
+            self.machine.draw(display);
+        }
+        if !crossed {
+            return;
+        }
+        // Hold whatever is moving, then say why
+        self.job_request = Some(job::Request::Hold);
+        self.power_feed.stop();
+        self.jog.release();
+        if self.cycle.is_some() {
+            self.end_cycle(Err(cycle::Problem::Alarm), display);
+        }
+        self.end_macro("stopped", display);
+        self.alarm_banner
+            .set(Some(Alarm::SoftLimit), self.machine.running(), position);
+        self.show_page(ui::Page::Alarm, display);
+    }
+
+    // The soft limit alarm, until it is unlocked
+    fn limit_alarm(&self) -> Option<Alarm> {
+        if self.envelope.tripped() {
+            Some(Alarm::SoftLimit)
+        } else {
+            None
+        }
+    }
+
+    /// True while the relay should be on: a soft limit was crossed, and
+    /// the relay is wanted
+    pub fn limit_relay(&self) -> bool {
+        self.envelope.relay() && self.envelope.tripped()
+    }
+
+    /// Acts on a touch probe contact at `machine`, the machine position
+    /// latched when the probe fired. Ignored while a controller is
+    /// connected: the latch is the encoders', which don't share its machine
+    /// zero, and its next report comes too late to say where the contact was.
+    /// An armed probe is disarmed then, and the Probe page says why.
+    pub fn probe_contact(&mut self, machine: [f32; 3], display: &mut Stm32F7DiscoDisplay<u16>) {
+        if self.machine.running().is_some() {
+            if self.probe_mode != ProbeMode::Off {
+                rprintln!("Probe contact ignored: controller connected");
+                self.probe_mode = ProbeMode::Off;
+                self.probe_status.set_ignored(true);
+                if self.page == ui::Page::Probe {
+                    self.probe_status
+                        .draw(self.probe_mode, self.probe_contact, display);
+                }
+            }
+            return;
+        }
+        let before = self.offsets();
+        let work = [
+            machine[0] + before[0],
+            machine[1] + before[1],
+            machine[2] + before[2],
+        ];
+
+        match self.probe_mode {
+            ProbeMode::Off => return,
+            ProbeMode::Edge(axis, direction) => {
+                let mut offsets = before;
+                offsets[axis] = probe::contact_value(direction, self.probe_radius) - machine[axis];
+                self.set_offsets(offsets, display);
+                self.record(ChangeKind::Probe(axis), before);
+            }
+            ProbeMode::Record => {
+                if let Some(slot) = self.points.next_free(self.point_list.selected()) {
+                    self.points.store(slot, work);
+                    self.point_list.select(slot);
+                    self.dirty = true;
+                }
+            }
+        }
+
+        // One contact per arming, so bounce or backing off can't fire it again
+        self.probe_mode = ProbeMode::Off;
+        self.probe_contact = Some(work);
+        self.probe_status.set_ignored(false);
+        if self.page == ui::Page::Probe {
+            self.probe_status
+                .draw(self.probe_mode, self.probe_contact, display);
+        }
+    }
+
+    /// Shows a status report from the machine controller. The readouts
+    /// follow the controller's work position whenever its work offset changes.
+    pub fn set_machine_status(
+        &mut self,
+        status: &backend::Status,
+        display: &mut Stm32F7DiscoDisplay<u16>,
+    ) {
+        self.set_machine_position(status.mpos, display);
+        if status.new_offset {
+            let mut offsets = [0.0; 3];
+            for (i, offset) in offsets.iter_mut().enumerate() {
+                *offset = status.wpos[i] - status.mpos[i];
+            }
+            if offsets != self.offsets() {
+                self.set_offsets(offsets, display);
+            }
+        }
+        self.set_running(Some(status.running), status.feed, status.spindle, display);
+        self.accessories = Some(status.accessories);
+        self.show_overrides(status.overrides, display);
+
+        // A new alarm takes over the screen. The controller's own comes
+        // first, then a soft limit.
+        let alarm = status.alarm.or(self.limit_alarm());
+        let was_alarmed = self.alarm_banner.alarm().is_some();
+        if self
+            .alarm_banner
+            .set(alarm, Some(status.running), status.mpos)
+            && self.page == ui::Page::Alarm
+        {
+            self.alarm_banner.draw(display);
+        }
+        if alarm.is_some() && self.cycle.is_some() {
+            self.end_cycle(Err(cycle::Problem::Alarm), display);
+        }
+        if alarm.is_some() {
+            self.end_macro("alarm", display);
+        }
+        if alarm.is_some() && !was_alarmed && self.page != ui::Page::Alarm {
+            self.jog.release();
+            self.show_page(ui::Page::Alarm, display);
+        }
+
+        if let Some(cycle) = &mut self.cycle {
+            cycle.status(status.running, status.mpos);
+            self.run_cycle(display);
+        }
+    }
+
+    /// Takes the safety inputs in their fault state, and those that tripped
+    /// since last time. Returns the ones newly latched, for the main loop
+    /// to stop or hold the machine.
+    pub fn set_interlocks(
+        &mut self,
+        active: Inputs,
+        tripped: Inputs,
+        display: &mut Stm32F7DiscoDisplay<u16>,
+    ) -> Inputs {
+        let was_active = self.interlocks.active();
+        let new = self.interlocks.update(active, tripped);
+        if new.any() {
+            self.jog.release();
+            if self.cycle.is_some() {
+                self.end_cycle(Err(cycle::Problem::Stopped), display);
+            }
+            self.end_macro("stopped", display);
+            if self.page != ui::Page::Safety {
+                self.show_page(ui::Page::Safety, display);
+                return new;
+            }
+        }
+        if (new.any() || active != was_active) && self.page == ui::Page::Safety {
+            self.safety_banner.draw(&self.interlocks, display);
+        }
+        new
+    }
+
+    /// Takes X from the scale, in machine mm, and whether the drive cut
+    /// out at a stop by itself, and returns what the power feed outputs
+    /// should be. Nothing runs while a safety input is latched.
+    pub fn update_power_feed(
+        &mut self,
+        x: f32,
+        cut: bool,
+        display: &mut Stm32F7DiscoDisplay<u16>,
+    ) -> powerfeed::Output {
+        self.power_feed_x = x;
+        let before = (self.power_feed.running(), self.power_feed.reached());
+        if cut {
+            self.power_feed.cut_out();
+        }
+        if self.faulted() || self.envelope.tripped() {
+            self.power_feed.stop();
+        }
+        let output = self.power_feed.update(x);
+        let after = (self.power_feed.running(), self.power_feed.reached());
+        if before != after && self.page == ui::Page::PowerFeed {
+            self.power_feed_status
+                .draw(&self.power_feed, self.x.get_offset(), display);
+        }
+        output
+    }
+
+    /// Takes how the leadscrew stands and returns what it should do next,
+    /// if anything. It stops while a safety input or soft limit is latched.
+    pub fn update_leadscrew(
+        &mut self,
+        status: Option<leadscrew::Status>,
+        display: &mut Stm32F7DiscoDisplay<u16>,
+    ) -> Option<leadscrew::Request> {
+        let running = matches!(status, Some(s) if s.state != leadscrew::State::Idle);
+        if running && (self.faulted() || self.envelope.tripped()) {
+            self.leadscrew_request = Some(leadscrew::Request::Stop);
+        }
+        if status != self.leadscrew_status {
+            self.leadscrew_status = status;
+            if self.page == ui::Page::Leadscrew {
+                self.show_leadscrew_status();
+                self.leadscrew_list.draw(&self.leadscrew, display);
+            }
+        }
+        self.leadscrew_request.take()
+    }
+
+    /// Takes the spindle count, if there's an encoder, and X from the scale
+    /// in machine mm, for the threading dial. It watches for passes whatever
+    /// page is showing.
+    pub fn update_dial(
+        &mut self,
+        spindle: Option<i64>,
+        x: f32,
+        now: u32,
+        display: &mut Stm32F7DiscoDisplay<u16>,
+    ) {
+        let spindle = match spindle {
+            Some(spindle) => spindle,
+            None => return,
+        };
+        let signal = self.dial.update(spindle, x);
+        let lit = now % (2 * DIAL_FLASH_MS) < DIAL_FLASH_MS;
+        if self.dial_indicator.set(signal, lit, self.dial.every()) && self.page == ui::Page::Dial {
+            self.dial_indicator.draw(display);
+        }
+    }
+
+    /// Returns whether each relay should be on, in `OUTPUTS` order. All
+    /// are off while a safety input is latched.
+    pub fn update_relays(&mut self, now: u32, display: &mut Stm32F7DiscoDisplay<u16>) -> [bool; 3] {
+        let changed = self.relays.update(self.accessories, self.faulted(), now);
+        if changed && self.page == ui::Page::Relays {
+            self.relay_list.draw(&self.relays, display);
+        }
+        let on = OUTPUTS.map(|output| self.relays.is_on(output));
+        if self.machine.set_relays(on) && self.readouts_visible() {
+            self.machine.draw(display);
+        }
+        on
+    }
+
+    /// A safety input is latched, so nothing may move
+    pub fn faulted(&self) -> bool {
+        self.interlocks.faulted()
+    }
+
+    fn alarmed(&self) -> bool {
+        self.alarm_banner.alarm().is_some()
+    }
+
+    fn show_overrides(&mut self, overrides: Overrides, display: &mut Stm32F7DiscoDisplay<u16>) {
+        self.overrides = overrides;
+        if self.job_status.set_overrides(overrides) && self.page == ui::Page::Job {
+            self.job_status.draw_overrides(display);
+        }
+        if self.override_status.set(overrides) && self.page == ui::Page::Overrides {
+            self.override_status.draw(display);
+        }
+    }
+
+    /// Blanks the machine status once the controller stops answering
+    pub fn set_disconnected(&mut self, display: &mut Stm32F7DiscoDisplay<u16>) {
+        self.accessories = None;
+        let alarm = self.limit_alarm();
+        if self.alarm_banner.set(alarm, None, [0.0; 3]) && self.page == ui::Page::Alarm {
+            self.alarm_banner.draw(display);
+        }
+        if self.machine.running().is_some() {
+            self.set_running(None, 0.0, 0.0, display);
+        }
+    }
+
+    fn set_running(
+        &mut self,
+        running: Option<ui::Running>,
+        feed: f32,
+        spindle: f32,
+        display: &mut Stm32F7DiscoDisplay<u16>,
+    ) {
+        if self.machine.set(running, feed, spindle) && self.readouts_visible() {
+            self.machine.draw(display);
+        }
+    }
+
+    /// Takes the latest feed rate estimate and redraws the feed readout if it changed
+    pub fn set_feed(&mut self, feed: &FeedRate, display: &mut Stm32F7DiscoDisplay<u16>) {
+        self.feed_rate = *feed;
+        if self.feed.set_feed(feed) && self.page == ui::Page::Dro {
+            self.feed.draw(display);
+        }
+    }
+
+    pub fn button_id_from_coords(&self, x: u16, y: u16) -> Option<ui::Ids> {
+        match self.page {
+            ui::Page::Dro => {
+                if self.feed.inside(x, y) {
+                    return Some(ui::Ids::FeedUnits);
+                }
+                // The readouts stand in for the axis keys
+                if self.x.inside(x, y) {
+                    return Some(ui::Ids::XButton);
+                }
+                if self.y.inside(x, y) {
+                    return Some(ui::Ids::YButton);
+                }
+                if self.z.inside(x, y) {
+                    return Some(ui::Ids::ZButton);
+                }
+            }
+            ui::Page::Points => {
+                if let Some(slot) = self.point_list.row_at(x, y) {
+                    return Some(ui::Ids::Row(slot as u8));
+                }
+            }
+            ui::Page::History => {
+                if let Some(i) = self.history_list.row_at(&self.history, x, y) {
+                    return Some(ui::Ids::Row(i as u8));
+                }
+            }
+            ui::Page::Files => {
+                if let Some(files) = &self.files {
+                    if let Some(i) = self.file_list.row_at(files, x, y) {
+                        return Some(ui::Ids::Row(i as u8));
+                    }
+                }
+            }
+            ui::Page::Job => {
+                if self.job_status.overrides_inside(x, y) {
+                    return Some(ui::Ids::Page(ui::Page::Overrides));
+                }
+                // The plot follows the job, if there is one to show
+                if self.job_status.inside(x, y) && self.plot.is_loaded() {
+                    return Some(ui::Ids::Page(ui::Page::Preview));
+                }
+            }
+            ui::Page::Wizard => {
+                if let Some(i) = self.wizard_list.row_at(&self.wizard, x, y) {
+                    return Some(ui::Ids::Row(i as u8));
+                }
+            }
+            ui::Page::Cycle => {
+                if let Some(i) = self.cycle_list.row_at(&self.cycle_settings, x, y) {
+                    return Some(ui::Ids::Row(i as u8));
+                }
+            }
+            ui::Page::Limits => {
+                if let Some(i) = self.limit_list.row_at(&self.envelope, x, y) {
+                    return Some(ui::Ids::Row(i as u8));
+                }
+            }
+            ui::Page::Leadscrew => {
+                if let Some(i) = self.leadscrew_list.row_at(&self.leadscrew, x, y) {
+                    return Some(ui::Ids::Row(i as u8));
+                }
+            }
+            ui::Page::Dial => {
+                if let Some(i) = self.dial_list.row_at(&self.dial, x, y) {
+                    return Some(ui::Ids::Row(i as u8));
+                }
+            }
+            ui::Page::Probe
+            | ui::Page::Jog
+            | ui::Page::Preview
+            | ui::Page::Mdi
+            | ui::Page::Label
+            | ui::Page::Overrides
+            | ui::Page::Alarm
+            | ui::Page::Safety
+            | ui::Page::PowerFeed
+            | ui::Page::Macros
+            | ui::Page::Relays
+            | ui::Page::Menu => (),
+        }
+        self.buttons.locate(x, y)
+    }
+
+    /// Handles a finger landing on, moving over or leaving the screen
+    pub fn touch(&mut self, touch: ui::Touch, display: &mut Stm32F7DiscoDisplay<u16>) {
+        match touch {
+            ui::Touch::Down(x, y) => {
+                self.dragging = self.page == ui::Page::Preview && self.plot.inside(x, y);
+                self.process_button(self.button_id_from_coords(x, y), display);
+            }
+            ui::Touch::Drag(dx, dy) => {
+                if self.dragging {
+                    self.plot.viewport().pan(dx, dy);
+                    self.plot.draw(self.toolpath, display);
+                }
+            }
+            ui::Touch::Pinch(factor) => {
+                if self.page == ui::Page::Preview {
+                    self.plot.viewport().zoom(factor);
+                    self.plot.draw(self.toolpath, display);
+                }
+            }
+            ui::Touch::Up => {
+                self.jog.release();
+                self.dragging = false;
+                self.process_button(None, display);
+            }
+        }
+    }
+
+    /// The next command for the machine controller, if any
+    pub fn take_command(&mut self, now_ms: u32) -> Option<Command> {
+        self.commands
+            .pop()
+            .or_else(|| self.jog.next(now_ms).map(Command::Jog))
+    }
+
+    /// What the files, job, preview or wizard page wants done, if anything
+    pub fn take_job_request(&mut self) -> Option<job::Request> {
+        self.job_request.take()
+    }
+
+    /// Shows the files found on the SD card, or None if it couldn't be read
+    pub fn set_files(&mut self, files: Option<FileNames>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        self.files = files;
+        if let Some(files) = &self.files {
+            self.file_list.select(self.file_list.selected(), files);
+        }
+        if self.page == ui::Page::Files {
+            self.file_list.draw(self.files.as_ref(), display);
+        }
+    }
+
+    /// Where the main loop reads a file into for the preview
+    pub fn toolpath_mut(&mut self) -> &mut Toolpath {
+        self.toolpath
+    }
+
+    /// Shows the toolpath once it has been read, or that it couldn't be
+    pub fn toolpath_loaded(&mut self, ok: bool, display: &mut Stm32F7DiscoDisplay<u16>) {
+        self.plot.loaded(ok, self.toolpath);
+        if self.page == ui::Page::Preview {
+            self.plot.draw(self.toolpath, display);
+        }
+    }
+
+    /// Where the main loop reads the macro file into
+    pub fn macros_mut(&mut self) -> &mut Macros {
+        self.macros
+    }
+
+    /// Shows the buttons read from the card, and any problem with the file
+    pub fn macros_loaded(
+        &mut self,
+        result: Result<(), macros::Problem>,
+        display: &mut Stm32F7DiscoDisplay<u16>,
+    ) {
+        let mut message: TextBuffer<40> = TextBuffer::new();
+        match result {
+            Ok(()) => write!(message, "{} macros", self.macros.count()).ok(),
+            Err(problem) => problem.describe(&mut message).ok(),
+        };
+        self.macro_status
+            .set_message(message.as_str(), result.is_err());
+        if self.page == ui::Page::Macros {
+            self.buttons.clear();
+            self.buttons.make_macro_keys(self.macros);
+            self.update(display);
+        }
+    }
+
+    /// Reports whether a wizard's program was saved to the SD card
+    pub fn program_saved(&mut self, ok: bool, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let mut message: TextBuffer<40> = TextBuffer::new();
+        let name = self.wizard.operation().file_name();
+        if ok {
+            write!(message, "Saved {}", name).ok();
+        } else {
+            write!(message, "Can't save {}", name).ok();
+        }
+        self.wizard_list.set_message(message.as_str(), !ok);
+        if self.page == ui::Page::Wizard {
+            self.wizard_list.draw(&self.wizard, display);
+        }
+    }
+
+    /// The controller's answer to a line typed on the MDI page
+    pub fn machine_reply(&mut self, reply: Reply, display: &mut Stm32F7DiscoDisplay<u16>) {
+        self.mdi.reply(reply);
+        match self.macros.reply(reply) {
+            Ok(()) => self.run_macro(display),
+            Err(code) => {
+                let mut why: TextBuffer<16> = TextBuffer::new();
+                write!(why, "error {}", code).ok();
+                self.end_macro(why.as_str(), display);
+            }
+        }
+        if let Some(cycle) = &mut self.cycle {
+            match cycle.reply(reply) {
+                Ok(()) => self.run_cycle(display),
+                Err(problem) => self.end_cycle(Err(problem), display),
+            }
+        }
+        if self.page == ui::Page::Mdi {
+            self.mdi_console.draw(&self.mdi, display);
+        }
+    }
+
+    /// Something else the controller said, shown in the MDI scrollback
+    pub fn machine_message(&mut self, message: &Message, display: &mut Stm32F7DiscoDisplay<u16>) {
+        self.mdi.message(message);
+        if let (Some(cycle), Some(contact)) =
+            (&mut self.cycle, cycle::parse_probe(message.as_str()))
+        {
+            cycle.probed(contact);
+        }
+        if self.page == ui::Page::Mdi {
+            self.mdi_console.draw(&self.mdi, display);
+        }
+    }
+
+    /// Takes the progress of the running job, or None if there isn't one
+    pub fn set_job(&mut self, progress: Option<Progress>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        if self.job_status.set(progress) && self.page == ui::Page::Job {
+            self.job_status.draw(display);
+        }
+    }
+
+    // Passes datum changes on to the controller, so its work offsets match
+    // the readouts
+    fn send_offsets(&mut self, before: [f32; 3]) {
+        if self.machine.running().is_none() {
+            return;
+        }
+        let after = self.offsets();
+        let values = [self.x.get_value(), self.y.get_value(), self.z.get_value()];
+        for axis in 0..3 {
+            if after[axis] != before[axis] {
+                self.commands.push(Command::SetWork(axis, values[axis]));
+            }
+        }
+    }
+
+    // Takes an Option<id> and de/activates the button if there
+    // is an id and if it is valid.
+    pub fn activate_button_from_id(
+        &self,
+        id_in: Option<ui::Ids>,
+        display: &mut Stm32F7DiscoDisplay<u16>,
+    ) {
+        if let Some(id) = id_in {
+            for button in self.buttons.buttons.iter() {
+                if button.id == id {
+                    let mut button = *button;
+                    button.activate(display);
+                }
+            }
+        }
+    }
+
+    // Takes an Option<id> and de/activates the button if there
+    // is an id and if it is valid.
+    pub fn deactivate_button_from_id(
+        &self,
+        id_in: Option<ui::Ids>,
+        display: &mut Stm32F7DiscoDisplay<u16>,
+    ) {
+        if let Some(id) = id_in {
+            for button in self.buttons.buttons.iter() {
+                if button.id == id {
+                    let mut button = *button;
+                    button.deactivate(display);
+                }
+            }
+        }
+    }
+
+    fn axis_display(&mut self, axis: Axis) -> Option<&mut SevenSegDisplay> {
+        match axis {
+            Axis::X => Some(&mut self.x),
+            Axis::Y => Some(&mut self.y),
+            Axis::Z => Some(&mut self.z),
+            Axis::None => None,
+        }
+    }
+
+    fn axis_from_id(id: ui::Ids) -> Axis {
+        match id {
+            ui::Ids::XButton => Axis::X,
+            ui::Ids::YButton => Axis::Y,
+            ui::Ids::ZButton => Axis::Z,
+            _ => Axis::None,
+        }
+    }
+
+    // Passes a key to the display of an axis that is having a number typed in
+    fn axis_input(
+        &mut self,
+        axis: Axis,
+        src: ui::Ids,
+        display: &mut Stm32F7DiscoDisplay<u16>,
+    ) -> Option<Result<f32, u8>> {
+        match self.axis_display(axis) {
+            Some(d) => d.input(src, display),
+            None => Some(Err(0xFD)),
+        }
+    }
+
+    // Starts number entry on the axis picked by src. Returns the axis,
+    // which is None if src wasn't an axis button.
+    fn start_axis_entry(&mut self, src: ui::Ids, display: &mut Stm32F7DiscoDisplay<u16>) -> Axis {
+        let axis = View::axis_from_id(src);
+        if let Some(d) = self.axis_display(axis) {
+            d.start(display);
+        }
+        axis
+    }
+
+    pub fn use_number(&mut self, axis: Axis, number: f32) {
+        // rprintln!("Use number: {:.3}", number);
+        match axis {
+            Axis::X => self.x.preset(number),
+            Axis::Y => self.y.preset(number),
+            Axis::Z => self.z.preset(number),
+            Axis::None => (),
+        }
+    }
+
+    pub fn process_button(&mut self, src: Option<ui::Ids>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        if self.active_id == src {
+            // Button still pushed down - ignore
+            return;
+        };
+        self.activate_button_from_id(src, display);
+        self.deactivate_button_from_id(self.active_id, display);
+        self.active_id = src;
+
+        // rprintln!("Src: {:?}", src);
+
+        // Page buttons work the same from every page
+        if let Some(ui::Ids::Page(page)) = src {
+            self.show_page(page, display);
+            return;
+        }
+
+        match self.page {
+            ui::Page::Dro => self.process_dro(src, display),
+            ui::Page::Points => self.process_points(src, display),
+            ui::Page::Label => self.process_label(src, display),
+            ui::Page::History => self.process_history(src, display),
+            ui::Page::Probe => self.process_probe(src, display),
+            ui::Page::Jog => self.process_jog(src, display),
+            ui::Page::Files => self.process_files(src, display),
+            ui::Page::Job => self.process_job(src),
+            ui::Page::Preview => self.process_preview(src, display),
+            ui::Page::Wizard => self.process_wizard(src, display),
+            ui::Page::Cycle => self.process_cycle(src, display),
+            ui::Page::PowerFeed => self.process_power_feed(src, display),
+            ui::Page::Limits => self.process_limits(src, display),
+            ui::Page::Macros => self.process_macros(src, display),
+            ui::Page::Leadscrew => self.process_leadscrew(src, display),
+            ui::Page::Dial => self.process_dial(src, display),
+            ui::Page::Relays => {
+                if let Some(ui::Ids::Relay(output)) = src {
+                    self.relays.toggle(output, self.faulted());
+                    self.relay_list.draw(&self.relays, display);
+                }
+            }
+            ui::Page::Mdi => self.process_mdi(src, display),
+            ui::Page::Overrides => self.process_overrides(src, display),
+            ui::Page::Alarm => self.process_alarm(src),
+            ui::Page::Safety => self.process_safety(src, display),
+            ui::Page::Menu => (),
+        }
+    }
+
+    fn process_files(&mut self, src: Option<ui::Ids>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let files = match &self.files {
+            Some(files) => *files,
+            None => return,
+        };
+        match src {
+            Some(ui::Ids::Row(i)) => self.file_list.select(i as usize, &files),
+            Some(ui::Ids::Up) => self.file_list.step(-1, &files),
+            Some(ui::Ids::Down) => self.file_list.step(1, &files),
+            Some(ui::Ids::Plot) => {
+                // The card is busy with the job, whose plot is still there
+                if self.job_status.active() {
+                    self.show_page(ui::Page::Preview, display);
+                } else if let Some((name, _)) = files.get(self.file_list.selected()) {
+                    let mut request = TextBuffer::new();
+                    request.write_str(name).ok();
+                    self.plot.start(name);
+                    self.plot_program = None;
+                    self.job_request = Some(job::Request::Plot(request));
+                    self.show_page(ui::Page::Preview, display);
+                }
+                return;
+            }
+            _ => return,
+        }
+        self.file_list.draw(self.files.as_ref(), display);
+    }
+
+    fn process_preview(&mut self, src: Option<ui::Ids>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        match src {
+            Some(ui::Ids::Projection) => {
+                let next = self.plot.viewport().projection().next();
+                self.plot.viewport().set_projection(next, self.toolpath);
+            }
+            Some(ui::Ids::Fit) => self.plot.viewport().fit(self.toolpath),
+            Some(ui::Ids::Run) => {
+                // One job at a time
+                if !self.job_status.active() && self.plot.is_loaded() {
+                    self.job_status.set_name(self.plot.name());
+                    self.job_request = match self.plot_program {
+                        Some(program) => Some(job::Request::RunProgram(program)),
+                        None => {
+                            let mut request = TextBuffer::new();
+                            request.write_str(self.plot.name()).ok();
+                            Some(job::Request::Start(request))
+                        }
+                    };
+                }
+                self.show_page(ui::Page::Job, display);
+                return;
+            }
+            _ => return,
+        }
+        self.plot.draw(self.toolpath, display);
+    }
+
+    // The edit readout shows the selected field while it isn't being typed in
+    fn show_wizard_value(&mut self) {
+        if let Some((_, value)) = self.wizard.get(self.wizard_list.selected()) {
+            self.wizard_edit.preset(value);
+        }
+    }
+
+    fn process_wizard(&mut self, src: Option<ui::Ids>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let src = match src {
+            Some(src) => src,
+            None => return,
+        };
+        let field = self.wizard_list.selected();
+
+        if let KeyState::WizardEntry = self.key_state {
+            if let Some(result) = self.wizard_edit.input(src, display) {
+                if let Ok(value) = result {
+                    self.wizard.set(field, value);
+                }
+                self.key_state = KeyState::Waiting;
+                self.show_wizard_value();
+                self.wizard_edit.draw(display);
+                self.wizard_list.draw(&self.wizard, display);
+            }
+            return;
+        }
+
+        match src {
+            ui::Ids::Row(i) => self.wizard_list.select(i as usize, &self.wizard),
+            ui::Ids::Up => self.wizard_list.step(-1, &self.wizard),
+            ui::Ids::Down => self.wizard_list.step(1, &self.wizard),
+            ui::Ids::Operation(operation) => {
+                self.wizard.set_operation(operation);
+                self.wizard_list.select(0, &self.wizard);
+                self.wizard_list.clear_message();
+            }
+            ui::Ids::PlusMinus => {
+                if let Some((_, value)) = self.wizard.get(field) {
+                    self.wizard.set(field, -value);
+                }
+            }
+            // Typing a number starts editing the selected field
+            ui::Ids::Key(_) | ui::Ids::DecimalPoint => {
+                self.wizard_list.clear_message();
+                self.wizard_list.draw(&self.wizard, display);
+                self.wizard_edit.start(display);
+                self.wizard_edit.input(src, display);
+                self.key_state = KeyState::WizardEntry;
+                return;
+            }
+            ui::Ids::Plot | ui::Ids::Store | ui::Ids::Run => match self.wizard.program() {
+                Ok(program) => {
+                    let name = self.wizard.operation().file_name();
+                    match src {
+                        ui::Ids::Plot => {
+                            self.plot.start(name);
+                            self.plot_program = Some(program);
+                            self.job_request = Some(job::Request::PlotProgram(program));
+                            self.show_page(ui::Page::Preview, display);
+                            return;
+                        }
+                        ui::Ids::Run => {
+                            // One job at a time
+                            if !self.job_status.active() {
+                                self.job_status.set_name(name);
+                                self.job_request = Some(job::Request::RunProgram(program));
+                            }
+                            self.show_page(ui::Page::Job, display);
+                            return;
+                        }
+                        _ => {
+                            self.wizard_list.set_message("Saving...", false);
+                            self.job_request = Some(job::Request::SaveProgram(program));
+                        }
+                    }
+                }
+                Err(problem) => {
+                    let mut message: TextBuffer<40> = TextBuffer::new();
+                    problem.describe(self.wizard.operation(), &mut message).ok();
+                    self.wizard_list.set_message(message.as_str(), true);
+                    if let Problem::Field(i) = problem {
+                        self.wizard_list.select(i, &self.wizard);
+                    }
+                }
+            },
+            _ => return,
+        }
+        self.show_wizard_value();
+        self.wizard_edit.draw(display);
+        self.wizard_list.draw(&self.wizard, display);
+    }
+
+    fn show_cycle_value(&mut self) {
+        if let Some((_, value)) = self.cycle_settings.get(self.cycle_list.selected()) {
+            self.cycle_edit.preset(value);
+        }
+    }
+
+    fn process_cycle(&mut self, src: Option<ui::Ids>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let src = match src {
+            Some(src) => src,
+            None => return,
+        };
+        let field = self.cycle_list.selected();
+
+        if let KeyState::CycleEntry = self.key_state {
+            if let Some(result) = self.cycle_edit.input(src, display) {
+                if let Ok(value) = result {
+                    self.cycle_settings.set(field, value);
+                    self.dirty = true;
+                }
+                self.key_state = KeyState::Waiting;
+                self.show_cycle_value();
+                self.cycle_edit.draw(display);
+                self.cycle_list.draw(&self.cycle_settings, display);
+            }
+            return;
+        }
+
+        match src {
+            ui::Ids::Row(i) => self.cycle_list.select(i as usize, &self.cycle_settings),
+            ui::Ids::Up => self.cycle_list.step(-1, &self.cycle_settings),
+            ui::Ids::Down => self.cycle_list.step(1, &self.cycle_settings),
+            ui::Ids::PlusMinus => {
+                if let Some((_, value)) = self.cycle_settings.get(field) {
+                    self.cycle_settings.set(field, -value);
+                    self.dirty = true;
+                }
+            }
+            // Typing a number starts editing the selected setting
+            ui::Ids::Key(_) | ui::Ids::DecimalPoint => {
+                self.cycle_list.clear_message();
+                self.cycle_list.draw(&self.cycle_settings, display);
+                self.cycle_edit.start(display);
+                self.cycle_edit.input(src, display);
+                self.key_state = KeyState::CycleEntry;
+                return;
+            }
+            ui::Ids::Cycle(kind) => self.start_cycle(kind, display),
+            ui::Ids::Stop if self.cycle.is_some() => {
+                self.commands.push(Command::Reset);
+                self.end_cycle(Err(cycle::Problem::Stopped), display);
+            }
+            _ => return,
+        }
+        self.show_cycle_value();
+        self.cycle_edit.draw(display);
+        self.cycle_list.draw(&self.cycle_settings, display);
+    }
+
+    // Starts a probe cycle from where the machine is, if it is free to move
+    fn start_cycle(&mut self, kind: cycle::Kind, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let busy = if self.cycle.is_some() {
+            Some("Already probing")
+        } else if self.job_status.active() {
+            Some("Busy with a job")
+        } else if self.macros.running().is_some() {
+            Some("Busy with a macro")
+        } else if self.machine.running() != Some(ui::Running::No) {
+            Some("Machine not idle")
+        } else {
+            None
+        };
+        if let Some(busy) = busy {
+            self.cycle_list.set_message(busy, true);
+            return;
+        }
+
+        let start = [self.x.machine(), self.y.machine(), self.z.machine()];
+        match Cycle::new(kind, &self.cycle_settings, start) {
+            Ok(cycle) => {
+                self.cycle = Some(cycle);
+                self.cycle_list.set_message("Probing...", false);
+                self.run_cycle(display);
+            }
+            Err(problem) => {
+                let mut message: TextBuffer<40> = TextBuffer::new();
+                problem.describe(&mut message).ok();
+                self.cycle_list.set_message(message.as_str(), true);
+                if let Some(i) = problem.field() {
+                    self.cycle_list.select(i, &self.cycle_settings);
+                }
+            }
+        }
+    }
+
+    // Does whatever the probe cycle can do before it next has to wait for
+    // the controller
+    fn run_cycle(&mut self, display: &mut Stm32F7DiscoDisplay<u16>) {
+        while let Some(step) = self.cycle.as_mut().and_then(Cycle::take_step) {
+            match step {
+                Step::Send(line) => {
+                    self.mdi.log(EntryKind::Sent, line.as_str());
+                    self.commands.push(Command::Mdi(line));
+                }
+                Step::Zero(axis, value, contact) => {
+                    let before = self.offsets();
+                    let mut offsets = before;
+                    offsets[axis] = value - contact;
+                    self.set_offsets(offsets, display);
+                    self.record(ChangeKind::Probe(axis), before);
+                }
+                Step::Done => self.end_cycle(Ok(()), display),
+            }
+        }
+    }
+
+    // Finishes the probe cycle. Success shows the new readings on the DRO.
+    fn end_cycle(
+        &mut self,
+        result: Result<(), cycle::Problem>,
+        display: &mut Stm32F7DiscoDisplay<u16>,
+    ) {
+        let cycle = match self.cycle.take() {
+            Some(cycle) => cycle,
+            None => return,
+        };
+        let kind = cycle.kind();
+        if result.is_err() {
+            if let Some(line) = cycle.abort() {
+                self.mdi.log(EntryKind::Sent, line.as_str());
+                self.commands.push(Command::Mdi(line));
+            }
+        }
+        let mut message: TextBuffer<40> = TextBuffer::new();
+        match result {
+            Ok(()) => write!(message, "{} done", kind.name()).ok(),
+            Err(problem) => problem.describe(&mut message).ok(),
+        };
+        self.cycle_list
+            .set_message(message.as_str(), result.is_err());
+        match result {
+            Ok(()) if self.page == ui::Page::Cycle => self.show_page(ui::Page::Dro, display),
+            _ if self.page == ui::Page::Cycle => {
+                self.cycle_list.draw(&self.cycle_settings, display)
+            }
+            _ => (),
+        }
+    }
+
+    fn process_macros(&mut self, src: Option<ui::Ids>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        match src {
+            Some(ui::Ids::Macro(_)) if self.faulted() => self.show_page(ui::Page::Safety, display),
+            Some(ui::Ids::Macro(i)) => self.start_macro(i as usize, display),
+            Some(ui::Ids::Stop) if self.macros.running().is_some() => {
+                self.commands.push(Command::Reset);
+                self.end_macro("stopped", display);
+            }
+            _ => (),
+        }
+    }
+
+    // Starts one of the user's buttons, if nothing else is using the machine
+    fn start_macro(&mut self, i: usize, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let busy = if self.macros.running().is_some() {
+            Some("Already running a macro")
+        } else if self.job_status.active() {
+            Some("Busy with a job")
+        } else if self.cycle.is_some() {
+            Some("Busy probing")
+        } else {
+            None
+        };
+        let mut message: TextBuffer<40> = TextBuffer::new();
+        match busy {
+            Some(busy) => message.write_str(busy).ok(),
+            None => {
+                self.macros.start(i);
+                write!(message, "Running {}", self.macros.label(i).unwrap_or("")).ok()
+            }
+        };
+        self.macro_status
+            .set_message(message.as_str(), busy.is_some());
+        self.macro_status.draw(display);
+        if busy.is_none() {
+            self.run_macro(display);
+        }
+    }
+
+    // Does the running macro's steps up to the next line the controller
+    // has to answer
+    fn run_macro(&mut self, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let i = match self.macros.running() {
+            Some(i) => i,
+            None => return,
+        };
+        while let Some(step) = self.macros.take_step() {
+            match step {
+                // Without a controller there'd be no answer to wait for
+                macros::Step::Send(_) if self.machine.running().is_none() => {
+                    self.end_macro("needs the machine", display);
+                    return;
+                }
+                macros::Step::Send(line) => {
+                    self.mdi.log(EntryKind::Sent, line.as_str());
+                    self.commands.push(Command::Mdi(line));
+                }
+                macros::Step::Command(command) => self.commands.push(command),
+                macros::Step::Set(axis, value) => {
+                    let before = self.offsets();
+                    match axis {
+                        0 => self.x.preset(value),
+                        1 => self.y.preset(value),
+                        _ => self.z.preset(value),
+                    }
+                    self.record(ChangeKind::Preset(axis, value), before);
+                }
+                macros::Step::Done => {
+                    let mut message: TextBuffer<40> = TextBuffer::new();
+                    write!(message, "{} done", self.macros.label(i).unwrap_or("")).ok();
+                    self.macro_status.set_message(message.as_str(), false);
+                    if self.page == ui::Page::Macros {
+                        self.macro_status.draw(display);
+                    }
+                }
+            }
+        }
+    }
+
+    // Stops the running macro, if there is one, saying why
+    fn end_macro(&mut self, why: &str, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let i = match self.macros.running() {
+            Some(i) => i,
+            None => return,
+        };
+        self.macros.stop();
+        let mut message: TextBuffer<40> = TextBuffer::new();
+        write!(message, "{} {}", self.macros.label(i).unwrap_or(""), why).ok();
+        self.macro_status.set_message(message.as_str(), true);
+        if self.page == ui::Page::Macros {
+            self.macro_status.draw(display);
+        }
+    }
+
+    fn process_mdi(&mut self, src: Option<ui::Ids>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        match src {
+            Some(ui::Ids::Char(c)) => self.mdi.type_char(c),
+            Some(ui::Ids::Backspace) => self.mdi.backspace(),
+            Some(ui::Ids::Clear) => self.mdi.clear(),
+            Some(ui::Ids::Up) => self.mdi.recall(-1),
+            Some(ui::Ids::Down) => self.mdi.recall(1),
+            Some(ui::Ids::Enter) => {
+                // The main loop holds lines back while a job streams
+                if self.job_status.active() {
+                    self.mdi.log(EntryKind::Error, "Busy with a job");
+                } else if self.cycle.is_some() {
+                    self.mdi.log(EntryKind::Error, "Busy probing");
+                } else if self.macros.running().is_some() {
+                    self.mdi.log(EntryKind::Error, "Busy with a macro");
+                } else if let Some(line) = self.mdi.take_line() {
+                    self.commands.push(Command::Mdi(line));
+                }
+                self.mdi_console.draw(&self.mdi, display);
+                return;
+            }
+            _ => return,
+        }
+        self.mdi_console.draw_line(&self.mdi, display);
+    }
+
+    fn process_overrides(&mut self, src: Option<ui::Ids>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        // Steps go from the last known percentage, so quick presses add up
+        let step = |percent: u16, change: i16| match change {
+            0 => 100,
+            _ => (percent as i16 + change).max(0) as u16,
+        };
+        let value = match src {
+            Some(ui::Ids::FeedOverride(change)) => {
+                Override::Feed(step(self.overrides.feed, change))
+            }
+            Some(ui::Ids::RapidOverride(percent)) => Override::Rapid(percent),
+            Some(ui::Ids::SpindleOverride(change)) => {
+                Override::Spindle(step(self.overrides.spindle, change))
+            }
+            // Through the job, if there is one, like on the job page
+            Some(ui::Ids::FeedHold) => {
+                self.job_request = Some(job::Request::Hold);
+                return;
+            }
+            Some(ui::Ids::Resume) => {
+                self.job_request = Some(job::Request::Resume);
+                return;
+            }
+            _ => return,
+        };
+        let value = value.limited();
+        self.commands.push(Command::Override(value));
+        let mut overrides = self.overrides;
+        value.apply(&mut overrides);
+        self.show_overrides(overrides, display);
+    }
+
+    fn process_safety(&mut self, src: Option<ui::Ids>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        if src == Some(ui::Ids::Acknowledge) && self.interlocks.acknowledge() {
+            // A reset usually leaves the controller alarmed
+            let page = if self.alarmed() {
+                ui::Page::Alarm
+            } else {
+                ui::Page::Dro
+            };
+            self.show_page(page, display);
+        }
+    }
+
+    fn process_power_feed(&mut self, src: Option<ui::Ids>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let x = self.power_feed_x;
+        let mut problem = None;
+        match src {
+            Some(ui::Ids::PowerFeed(_)) if self.faulted() => {
+                self.show_page(ui::Page::Safety, display);
+                return;
+            }
+            Some(ui::Ids::PowerFeed(_)) if self.envelope.tripped() => {
+                self.show_page(ui::Page::Alarm, display);
+                return;
+            }
+            Some(ui::Ids::PowerFeed(direction)) => {
+                problem = self.power_feed.start(direction, x).err();
+            }
+            Some(ui::Ids::Stop) => self.power_feed.stop(),
+            Some(ui::Ids::SetStop(direction)) => {
+                self.power_feed.set_stop(direction, x);
+                self.dirty = true;
+            }
+            Some(ui::Ids::ClearStops) => {
+                self.power_feed.clear_stops();
+                self.dirty = true;
+            }
+            Some(ui::Ids::FeedSpeed(speed)) => self.power_feed.set_speed(speed),
+            Some(ui::Ids::FeedPercent(by)) => {
+                self.power_feed.change_feed(by);
+                self.dirty = true;
+            }
+            _ => return,
+        }
+        self.power_feed_status.set_problem(problem);
+        self.power_feed_status
+            .draw(&self.power_feed, self.x.get_offset(), display);
+    }
+
+    fn process_dial(&mut self, src: Option<ui::Ids>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        match src {
+            Some(ui::Ids::Row(i)) => self.dial_list.select(i as usize, &self.dial),
+            Some(ui::Ids::Up) => self.dial_list.step(-1, &self.dial),
+            Some(ui::Ids::Down) => self.dial_list.step(1, &self.dial),
+            Some(ui::Ids::Delete) => self.dial.clear(),
+            _ => return,
+        }
+        if self.dial_list.selected() != self.dial.thread() {
+            self.dial.select(self.dial_list.selected());
+            self.dirty = true;
+        }
+        self.dial_list.draw(&self.dial, display);
+    }
+
+    // Over the table, while it's doing something or has an end set
+    fn show_leadscrew_status(&mut self) {
+        let mut text: TextBuffer<40> = TextBuffer::new();
+        match self.leadscrew_status {
+            None => self.leadscrew_list.set_message("No leadscrew drive", true),
+            Some(status) if status.state == leadscrew::State::Idle && !status.end => {
+                self.leadscrew_list.clear_message()
+            }
+            Some(status) => {
+                status.describe(&mut text).ok();
+                self.leadscrew_list.set_message(text.as_str(), false);
+            }
+        }
+    }
+
+    fn process_leadscrew(&mut self, src: Option<ui::Ids>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let src = match src {
+            Some(src) => src,
+            None => return,
+        };
+
+        if let KeyState::LeadscrewEntry = self.key_state {
+            if let Some(result) = self.leadscrew_edit.input(src, display) {
+                if let Ok(feed) = result {
+                    self.leadscrew.set_feed(feed);
+                    self.dirty = true;
+                }
+                self.key_state = KeyState::Waiting;
+                self.leadscrew_edit.preset(self.leadscrew.pitch().mm());
+                self.leadscrew_edit.draw(display);
+            }
+            return;
+        }
+
+        match src {
+            // Picking a row of the table puts it in use
+            ui::Ids::Row(_) | ui::Ids::Up | ui::Ids::Down => {
+                let selected = self.leadscrew_list.selected();
+                let i = match src {
+                    ui::Ids::Row(i) => i as usize,
+                    ui::Ids::Up => selected.saturating_sub(1),
+                    _ => selected + 1,
+                };
+                self.leadscrew_list.select(i, &self.leadscrew);
+                self.leadscrew.select(self.leadscrew_list.selected());
+                self.dirty = true;
+            }
+            ui::Ids::LeadscrewMode(mode) => {
+                self.leadscrew.set_mode(mode);
+                self.leadscrew_list
+                    .select(self.leadscrew.row(), &self.leadscrew);
+                self.dirty = true;
+            }
+            // Only a feed can be typed in, threads come from the table
+            ui::Ids::Key(_) | ui::Ids::DecimalPoint if self.leadscrew.mode() == Mode::Feed => {
+                self.leadscrew_edit.start(display);
+                self.leadscrew_edit.input(src, display);
+                self.key_state = KeyState::LeadscrewEntry;
+                return;
+            }
+            ui::Ids::Leadscrew(_) if self.faulted() => {
+                self.show_page(ui::Page::Safety, display);
+                return;
+            }
+            ui::Ids::Leadscrew(_) if self.envelope.tripped() => {
+                self.show_page(ui::Page::Alarm, display);
+                return;
+            }
+            ui::Ids::Leadscrew(direction) => match self.leadscrew.ratio() {
+                // Threads start on the index pulse, so every pass lines up
+                Some(ratio) => {
+                    let sync = self.leadscrew.mode() == Mode::Thread;
+                    self.leadscrew_request =
+                        Some(leadscrew::Request::Engage(ratio, direction, sync));
+                    return;
+                }
+                None => self
+                    .leadscrew_list
+                    .set_message("Can't make that pitch", true),
+            },
+            ui::Ids::Stop => {
+                self.leadscrew_request = Some(leadscrew::Request::Stop);
+                return;
+            }
+            ui::Ids::LeadscrewReturn => {
+                self.leadscrew_request = Some(leadscrew::Request::Return);
+                return;
+            }
+            ui::Ids::LeadscrewEnd(set) => {
+                self.leadscrew_request = Some(if set {
+                    leadscrew::Request::SetEnd
+                } else {
+                    leadscrew::Request::ClearEnd
+                });
+                return;
+            }
+            _ => return,
+        }
+        self.leadscrew_edit.preset(self.leadscrew.pitch().mm());
+        self.leadscrew_edit.draw(display);
+        self.leadscrew_list.draw(&self.leadscrew, display);
+    }
+
+    fn show_limit_value(&mut self) {
+        if let Some((_, value)) = self.envelope.get(self.limit_list.selected()) {
+            self.limit_edit
+                .preset(if value.is_finite() { value } else { 0.0 });
+        }
+    }
+
+    fn process_limits(&mut self, src: Option<ui::Ids>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let src = match src {
+            Some(src) => src,
+            None => return,
+        };
+        let field = self.limit_list.selected();
+
+        if let KeyState::LimitEntry = self.key_state {
+            if let Some(result) = self.limit_edit.input(src, display) {
+                if let Ok(value) = result {
+                    self.envelope.set(field, value);
+                    self.dirty = true;
+                }
+                self.key_state = KeyState::Waiting;
+                self.show_limit_value();
+                self.limit_edit.draw(display);
+                self.limit_list.draw(&self.envelope, display);
+            }
+            return;
+        }
+
+        match src {
+            ui::Ids::Row(i) => self.limit_list.select(i as usize, &self.envelope),
+            ui::Ids::Up => self.limit_list.step(-1, &self.envelope),
+            ui::Ids::Down => self.limit_list.step(1, &self.envelope),
+            ui::Ids::PlusMinus => {
+                if let Some((_, value)) = self.envelope.get(field) {
+                    self.envelope.set(field, -value);
+                    self.dirty = true;
+                }
+            }
+            // Typing a number starts editing the selected limit
+            ui::Ids::Key(_) | ui::Ids::DecimalPoint => {
+                self.limit_edit.start(display);
+                self.limit_edit.input(src, display);
+                self.key_state = KeyState::LimitEntry;
+                return;
+            }
+            // Field i is on axis i / 2
+            ui::Ids::Store => {
+                self.envelope.set(field, self.machine_position[field / 2]);
+                self.dirty = true;
+            }
+            ui::Ids::Delete => {
+                self.envelope.clear(field);
+                self.dirty = true;
+            }
+            ui::Ids::LimitRelay => {
+                self.envelope.toggle_relay();
+                self.dirty = true;
+            }
+            _ => return,
+        }
+        self.show_limit_value();
+        self.limit_edit.draw(display);
+        self.limit_list.draw(&self.envelope, display);
+    }
+
+    fn process_alarm(&mut self, src: Option<ui::Ids>) {
+        match src {
+            Some(ui::Ids::Home) => self.commands.push(Command::Home),
+            Some(ui::Ids::Unlock) => {
+                self.envelope.unlock();
+                self.commands.push(Command::Unlock);
+            }
+            // Stops a job too, if one was running when the alarm went off
+            Some(ui::Ids::Reset) => self.job_request = Some(job::Request::Stop),
+            _ => (),
+        }
+    }
+
+    fn process_job(&mut self, src: Option<ui::Ids>) {
+        self.job_request = match src {
+            Some(ui::Ids::FeedHold) => Some(job::Request::Hold),
+            Some(ui::Ids::Resume) => Some(job::Request::Resume),
+            Some(ui::Ids::Stop) => Some(job::Request::Stop),
+            _ => return,
+        };
+    }
+
+    fn process_jog(&mut self, src: Option<ui::Ids>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        match src {
+            // The controller would refuse, so say why instead
+            Some(ui::Ids::Jog(_, _)) if self.faulted() => self.show_page(ui::Page::Safety, display),
+            Some(ui::Ids::Jog(_, _)) if self.cycle.is_some() => (),
+            Some(ui::Ids::Jog(_, _)) if self.alarmed() => self.show_page(ui::Page::Alarm, display),
+            Some(ui::Ids::Jog(axis, direction)) => self.jog.press(axis, direction as f32),
+            Some(ui::Ids::FeedHold) => self.commands.push(Command::FeedHold),
+            Some(ui::Ids::Resume) => self.commands.push(Command::Resume),
+            Some(ui::Ids::Reset) => self.commands.push(Command::Reset),
+            Some(ui::Ids::JogStep(step)) => {
+                self.jog.set_step(step);
+                self.jog_status
+                    .draw(self.jog.step(), self.jog.feed(), display);
+            }
+            _ => (),
+        }
+    }
+
+    fn process_probe(&mut self, src: Option<ui::Ids>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let src = match src {
+            Some(src) => src,
+            None => return,
+        };
+
+        if let KeyState::RadiusEntry = self.key_state {
+            if let Some(result) = self.probe_edit.input(src, display) {
+                if let Ok(radius) = result {
+                    self.probe_radius = if radius < 0.0 { 0.0 - radius } else { radius };
+                    self.dirty = true;
+                }
+                self.probe_edit.preset(self.probe_radius);
+                self.probe_edit.draw(display);
+                self.key_state = KeyState::Waiting;
+            }
+            return;
+        }
+
+        match src {
+            ui::Ids::Radius => {
+                self.probe_edit.start(display);
+                self.key_state = KeyState::RadiusEntry;
+            }
+            ui::Ids::Probe(mode) => {
+                // Pressing the armed mode again disarms it
+                self.probe_mode = if mode == self.probe_mode {
+                    ProbeMode::Off
+                } else {
+                    mode
+                };
+                self.probe_status.set_ignored(false);
+                self.probe_status
+                    .draw(self.probe_mode, self.probe_contact, display);
+            }
+            _ => (),
+        }
+    }
+
+    fn process_history(&mut self, src: Option<ui::Ids>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        match src {
+            Some(ui::Ids::Undo) => self.undo(display),
+            Some(ui::Ids::Redo) => self.redo(display),
+            // Tapping a change undoes or redoes up to just after it
+            Some(ui::Ids::Row(i)) => {
+                let wanted = i as usize + 1;
+                while self.history.applied() > wanted {
+                    self.undo(display);
+                }
+                while self.history.applied() < wanted {
+                    self.redo(display);
+                }
+            }
+            _ => return,
+        }
+        self.history_list.draw(&self.history, display);
+    }
+
+    fn process_points(&mut self, src: Option<ui::Ids>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let src = match src {
+            Some(src) => src,
+            None => return,
+        };
+        let slot = self.point_list.selected();
+
+        if let KeyState::NumberEntry(axis) = self.key_state {
+            if let Some(result) = self.point_edit.input(src, display) {
+                if let (Ok(n), Some(i)) = (result, axis.index()) {
+                    self.points.set_coordinate(slot, i, n);
+                    self.dirty = true;
+                }
+                self.key_state = KeyState::Waiting;
+                self.point_list.draw(&self.points, display);
+                self.point_list.draw_hint(POINTS_EDIT_TOP, display);
+            }
+            return;
+        }
+
+        match src {
+            ui::Ids::Row(row) => {
+                self.point_list.select(row as usize);
+                self.point_list.draw(&self.points, display);
+            }
+            ui::Ids::Up => {
+                self.point_list.step(-1);
+                self.point_list.draw(&self.points, display);
+            }
+            ui::Ids::Down => {
+                self.point_list.step(1);
+                self.point_list.draw(&self.points, display);
+            }
+            ui::Ids::XButton | ui::Ids::YButton | ui::Ids::ZButton => {
+                self.point_edit.start(display);
+                self.key_state = KeyState::NumberEntry(View::axis_from_id(src));
+            }
+            ui::Ids::Store => {
+                let position = [self.x.get_value(), self.y.get_value(), self.z.get_value()];
+                self.points.store(slot, position);
+                self.dirty = true;
+                self.point_list.draw(&self.points, display);
+            }
+            ui::Ids::Delete => {
+                self.points.delete(slot);
+                self.dirty = true;
+                self.point_list.draw(&self.points, display);
+            }
+            ui::Ids::Name => {
+                if let Some(point) = self.points.get(slot) {
+                    self.label.clear();
+                    self.label.write_str(point.label()).ok();
+                    self.show_page(ui::Page::Label, display);
+                }
+            }
+            ui::Ids::Recall => {
+                if let Some(point) = self.points.get(slot) {
+                    let before = self.offsets();
+                    self.x.preset(point.position[0]);
+                    self.y.preset(point.position[1]);
+                    self.z.preset(point.position[2]);
+                    self.record(ChangeKind::Recall(slot), before);
+                    self.show_page(ui::Page::Dro, display);
+                }
+            }
+            ui::Ids::GoTo => {
+                if let Some(point) = self.points.get(slot) {
+                    self.x.set_target(Some(point.position[0]));
+                    self.y.set_target(Some(point.position[1]));
+                    self.z.set_target(Some(point.position[2]));
+                    self.show_page(ui::Page::Dro, display);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn process_label(&mut self, src: Option<ui::Ids>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        match src {
+            // Cut short once full, like a label read from storage
+            Some(ui::Ids::Char(c)) => {
+                self.label.write_char(c).ok();
+            }
+            Some(ui::Ids::Backspace) => {
+                let kept = self.label.as_str().len().saturating_sub(1);
+                let mut label = TextBuffer::new();
+                label.write_str(&self.label.as_str()[..kept]).ok();
+                self.label = label;
+            }
+            Some(ui::Ids::Clear) => self.label.clear(),
+            Some(ui::Ids::Enter) => {
+                let label = self.label.as_str().trim();
+                self.points.set_label(self.point_list.selected(), label);
+                self.dirty = true;
+                self.show_page(ui::Page::Points, display);
+                return;
+            }
+            _ => return,
+        }
+        self.label_entry
+            .draw(self.point_list.selected(), self.label.as_str(), display);
+    }
+
+    fn process_dro(&mut self, src: Option<ui::Ids>, display: &mut Stm32F7DiscoDisplay<u16>) {
+        let before = self.offsets();
+        let mut change = None;
+
+        match self.key_state {
+            KeyState::Waiting => {
+                // rprintln!("View: Waiting");
+                if let Some(src) = src {
+                    match src {
+                        ui::Ids::X0Button => {
+                            self.x.zero();
+                            self.x.draw(display);
+                            change = Some(ChangeKind::Zero(0));
+                        }
+                        ui::Ids::Y0Button => {
+                            self.y.zero();
+                            self.y.draw(display);
+                            change = Some(ChangeKind::Zero(1));
+                        }
+                        ui::Ids::Z0Button => {
+                            self.z.zero();
+                            self.z.draw(display);
+                            change = Some(ChangeKind::Zero(2));
+                        }
+                        ui::Ids::Undo => self.undo(display),
+                        ui::Ids::Redo => self.redo(display),
+                        ui::Ids::FeedUnits => {
+                            self.feed.toggle_units(&self.feed_rate);
+                            self.feed.draw(display);
+                        }
+                        ui::Ids::XButton => {
+                            // rprintln!("X");
+                            self.x.start(display);
+                            self.key_state = KeyState::NumberEntry(Axis::X);
+                        }
+                        ui::Ids::YButton => {
+                            // rprintln!("Y");
+                            self.y.start(display);
+                            self.key_state = KeyState::NumberEntry(Axis::Y);
+                        }
+                        ui::Ids::ZButton => {
+                            // rprintln!("Z");
                             self.z.start(display);
                             self.key_state = KeyState::NumberEntry(Axis::Z);
                         }
@@ -533,6 +3372,9 @@ impl View {
                         ui::Ids::Half => {
                             self.key_state = KeyState::Half;
                         }
+                        ui::Ids::Target => {
+                            self.key_state = KeyState::Target;
+                        }
                         _ => (),
                     };
                 };
@@ -541,12 +3383,7 @@ impl View {
             KeyState::NumberEntry(axis) => {
                 // rprintln!("View: Number entry: {:?}", axis);
                 if let Some(src) = src {
-                    let result = match axis {
-                        Axis::X => self.x.input(src, display),
-                        Axis::Y => self.y.input(src, display),
-                        Axis::Z => self.z.input(src, display),
-                        _ => Some(Err(0xFD)),
-                    };
+                    let result = self.axis_input(axis, src, display);
 
                     match result {
                         None => self.key_state = KeyState::NumberEntry(axis),
@@ -555,6 +3392,7 @@ impl View {
                             Ok(n) => {
                                 // rprintln!("In view, number is: {:.3}", n);
                                 self.use_number(axis, n);
+                                change = axis.index().map(|i| ChangeKind::Preset(i, n));
                                 self.key_state = KeyState::Waiting;
                                 // rprintln!("Axis: {:?} and number: {}", axis, n);
                             }
@@ -570,14 +3408,17 @@ impl View {
                         ui::Ids::XButton => {
                             // rprintln!("X+-");
                             self.x.plus_minus(display);
+                            change = Some(ChangeKind::PlusMinus(0));
                         }
                         ui::Ids::YButton => {
                             // rprintln!("Y+-");
                             self.y.plus_minus(display);
+                            change = Some(ChangeKind::PlusMinus(1));
                         }
                         ui::Ids::ZButton => {
                             // rprintln!("Z+-");
                             self.z.plus_minus(display);
+                            change = Some(ChangeKind::PlusMinus(2));
                         }
                         _ => {}
                     }
@@ -593,14 +3434,17 @@ impl View {
                         ui::Ids::XButton => {
                             // rprintln!("X/2");
                             self.x.half(display);
+                            change = Some(ChangeKind::Half(0));
                         }
                         ui::Ids::YButton => {
                             // rprintln!("Y/2");
                             self.y.half(display);
+                            change = Some(ChangeKind::Half(1));
                         }
                         ui::Ids::ZButton => {
                             // rprintln!("Z/2");
                             self.z.half(display);
+                            change = Some(ChangeKind::Half(2));
                         }
                         _ => {}
                     }
@@ -609,6 +3453,72 @@ impl View {
                     self.key_state = KeyState::Half;
                 }
             }
+
+            KeyState::Target => {
+                if let Some(src) = src {
+                    self.key_state = if src == ui::Ids::Target {
+                        KeyState::Approach
+                    } else {
+                        match self.start_axis_entry(src, display) {
+                            Axis::None => KeyState::Waiting,
+                            axis => KeyState::TargetEntry(axis),
+                        }
+                    };
+                }
+            }
+
+            KeyState::TargetEntry(axis) => {
+                if let Some(src) = src {
+                    if let Some(result) = self.axis_input(axis, src, display) {
+                        if let Some(d) = self.axis_display(axis) {
+                            match result {
+                                Ok(n) => d.set_target(Some(n)),
+                                // Clear during target entry drops the target
+                                Err(0xFF) => d.set_target(None),
+                                Err(_e) => d.restore(),
+                            }
+                            d.draw(display);
+                        }
+                        self.key_state = KeyState::Waiting;
+                    }
+                }
+            }
+
+            KeyState::Approach => {
+                if let Some(src) = src {
+                    self.key_state = match self.start_axis_entry(src, display) {
+                        Axis::None => KeyState::Waiting,
+                        axis => KeyState::ApproachEntry(axis),
+                    };
+                }
+            }
+
+            KeyState::ApproachEntry(axis) => {
+                if let Some(src) = src {
+                    if let Some(result) = self.axis_input(axis, src, display) {
+                        if let Some(d) = self.axis_display(axis) {
+                            if let Ok(band) = result {
+                                let band = if band < 0.0 { 0.0 - band } else { band };
+                                d.set_approach(band, DTG_TOLERANCE);
+                            }
+                            d.restore();
+                            d.draw(display);
+                        }
+                        self.key_state = KeyState::Waiting;
+                    }
+                }
+            }
+
+            // Only used on the probe page, which resets the state on the way out
+            KeyState::RadiusEntry
+            | KeyState::WizardEntry
+            | KeyState::CycleEntry
+            | KeyState::LimitEntry
+            | KeyState::LeadscrewEntry => self.key_state = KeyState::Waiting,
         };
+
+        if let Some(kind) = change {
+            self.record(kind, before);
+        }
     }
 }
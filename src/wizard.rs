@@ -0,0 +1,934 @@
+//! Conversational G-code: facing, pockets and drilling without a CAM
+//! program.
+//!
+//! A `Wizard` holds the numbers typed in for each operation. Once they
+//! check out, `program` gives a `Program` that writes the G-code a line at
+//! a time, so it can be saved, plotted or streamed through the same
+//! `LineSource` as a file, without ever being held in memory whole.
+//!
+//! Work zero is the top of the stock. Facing starts at the X0 Y0 corner and
+//! covers the stock in +X and +Y; pockets and bolt circles are centred on
+//! X0 Y0; a drilling grid starts at X0 Y0. Pockets are cut from the middle
+//! out, anticlockwise, which is climb milling with a clockwise spindle.
+//! Drilling pecks with plain G0/G1 moves, as GRBL has no canned cycles.
+
+use core::fmt::{self, Write};
+
+#[cfg(not(test))]
+use micromath::F32Ext;
+
+use crate::job::{LineSource, Read, LINE_LEN};
+use crate::text::TextBuffer;
+
+pub const MAX_FIELDS: usize = 10;
+const N_OPERATIONS: usize = 5;
+const MAX_HOLES: f32 = 100.0;
+const CLEARANCE: f32 = 2.0; // mm past the stock that facing starts and ends
+const RETRACT: f32 = 1.0; // mm above the surface, or the last peck, to rapid to
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Operation {
+    Facing,
+    RectPocket,
+    CircPocket,
+    DrillGrid,
+    BoltCircle,
+}
+
+/// One number a wizard asks for
+#[derive(Copy, Clone, Debug)]
+pub struct Field {
+    pub name: &'static str,
+    pub default: f32,
+}
+
+pub const fn field(name: &'static str, default: f32) -> Field {
+    Field { name, default }
+}
+
+const FACING: [Field; 9] = [
+    field("Stock X", 100.0),
+    field("Stock Y", 50.0),
+    field("Tool dia", 25.0),
+    field("Stepover %", 60.0),
+    field("Depth", 1.0),
+    field("Stepdown", 0.5),
+    field("Feed", 600.0),
+    field("Spindle krpm", 10.0),
+    field("Safe Z", 5.0),
+];
+
+const RECT_POCKET: [Field; 10] = [
+    field("Width X", 40.0),
+    field("Length Y", 20.0),
+    field("Tool dia", 6.0),
+    field("Stepover %", 40.0),
+    field("Depth", 5.0),
+    field("Stepdown", 1.0),
+    field("Feed", 400.0),
+    field("Plunge", 100.0),
+    field("Spindle krpm", 10.0),
+    field("Safe Z", 5.0),
+];
+
+const CIRC_POCKET: [Field; 9] = [
+    field("Diameter", 30.0),
+    field("Tool dia", 6.0),
+    field("Stepover %", 40.0),
+    field("Depth", 5.0),
+    field("Stepdown", 1.0),
+    field("Feed", 400.0),
+    field("Plunge", 100.0),
+    field("Spindle krpm", 10.0),
+    field("Safe Z", 5.0),
+];
+
+const DRILL_GRID: [Field; 9] = [
+    field("Holes X", 3.0),
+    field("Holes Y", 2.0),
+    field("Pitch X", 20.0),
+    field("Pitch Y", 20.0),
+    field("Depth", 10.0),
+    field("Peck", 2.0),
+    field("Feed", 100.0),
+    field("Spindle krpm", 3.0),
+    field("Safe Z", 5.0),
+];
+
+const BOLT_CIRCLE: [Field; 8] = [
+    field("Holes", 6.0),
+    field("Diameter", 50.0),
+    field("Start angle", 0.0),
+    field("Depth", 10.0),
+    field("Peck", 2.0),
+    field("Feed", 100.0),
+    field("Spindle krpm", 3.0),
+    field("Safe Z", 5.0),
+];
+
+/// Named numbers for a `WizardList` to show and the keypad to edit
+pub trait Fields {
+    #[cfg_attr(test, allow(dead_code))]
+    fn title(&self) -> &str;
+    fn count(&self) -> usize;
+    /// Name and value of a field
+    fn get(&self, i: usize) -> Option<(&'static str, f32)>;
+}
+
+impl Operation {
+    pub fn name(self) -> &'static str {
+        match self {
+            Operation::Facing => "Facing",
+            Operation::RectPocket => "Rectangular pocket",
+            Operation::CircPocket => "Circular pocket",
+            Operation::DrillGrid => "Drill grid",
+            Operation::BoltCircle => "Bolt circle",
+        }
+    }
+
+    /// What a program is saved as on the card
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn file_name(self) -> &'static str {
+        match self {
+            Operation::Facing => "FACE.NC",
+            Operation::RectPocket => "RPOCKET.NC",
+            Operation::CircPocket => "CPOCKET.NC",
+            Operation::DrillGrid => "DRILL.NC",
+            Operation::BoltCircle => "BOLTS.NC",
+        }
+    }
+
+    pub fn fields(self) -> &'static [Field] {
+        match self {
+            Operation::Facing => &FACING,
+            Operation::RectPocket => &RECT_POCKET,
+            Operation::CircPocket => &CIRC_POCKET,
+            Operation::DrillGrid => &DRILL_GRID,
+            Operation::BoltCircle => &BOLT_CIRCLE,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Why the numbers won't make a program
+#[derive(PartialEq, Copy, Clone, Debug)]
+pub enum Problem {
+    // The field with this index is out of range
+    Field(usize),
+    // Too wide for the pocket
+    ToolTooBig,
+}
+
+impl Problem {
+    pub fn describe<W: Write>(self, operation: Operation, out: &mut W) -> fmt::Result {
+        match self {
+            Problem::Field(i) => write!(out, "Check {}", operation.fields()[i].name),
+            Problem::ToolTooBig => write!(out, "Tool too big for the pocket"),
+        }
+    }
+}
+
+/// The numbers typed in for every operation, and which one is showing
+#[derive(Copy, Clone, Debug)]
+pub struct Wizard {
+    operation: Operation,
+    values: [[f32; MAX_FIELDS]; N_OPERATIONS],
+}
+
+impl Wizard {
+    pub fn new() -> Wizard {
+        let mut values = [[0.0; MAX_FIELDS]; N_OPERATIONS];
+        for operation in [
+            Operation::Facing,
+            Operation::RectPocket,
+            Operation::CircPocket,
+            Operation::DrillGrid,
+            Operation::BoltCircle,
+        ] {
+            for (i, f) in operation.fields().iter().enumerate() {
+                values[operation.index()][i] = f.default;
+            }
+        }
+        Wizard {
+            operation: Operation::Facing,
+            values,
+        }
+    }
+
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn operation(&self) -> Operation {
+        self.operation
+    }
+
+    pub fn set_operation(&mut self, operation: Operation) {
+        self.operation = operation;
+    }
+
+    pub fn set(&mut self, i: usize, value: f32) {
+        if i < self.count() {
+            self.values[self.operation.index()][i] = value;
+        }
+    }
+
+    // Value of the current operation's field called `name`
+    fn value(&self, name: &str) -> f32 {
+        let fields = self.operation.fields();
+        match fields.iter().position(|f| f.name == name) {
+            Some(i) => self.values[self.operation.index()][i],
+            None => 0.0,
+        }
+    }
+
+    // Checks a field is above `low` (or at it, if `inclusive`) and at most `high`
+    fn check(&self, name: &str, low: f32, inclusive: bool, high: f32) -> Result<f32, Problem> {
+        let value = self.value(name);
+        let above = value > low || (inclusive && value == low);
+        if above && value <= high {
+            Ok(value)
+        } else {
+            let i = self.operation.fields().iter().position(|f| f.name == name);
+            Err(Problem::Field(i.unwrap_or(0)))
+        }
+    }
+
+    /// Checks the numbers, and gives the program they make
+    pub fn program(&self) -> Result<Program, Problem> {
+        let max = f32::MAX;
+        let common = Common {
+            safe_z: self.check("Safe Z", 0.0, false, max)?,
+            rpm: self.check("Spindle krpm", 0.0, true, max)? * 1000.0,
+            depth: self.check("Depth", 0.0, false, max)?,
+            feed: self.check("Feed", 0.0, false, max)?,
+        };
+
+        let body = match self.operation {
+            Operation::Facing => {
+                let tool = self.check("Tool dia", 0.0, false, max)?;
+                let stepover = self.check("Stepover %", 0.0, false, 100.0)?;
+                Body::Facing {
+                    size: [
+                        self.check("Stock X", 0.0, false, max)?,
+                        self.check("Stock Y", 0.0, false, max)?,
+                    ],
+                    radius: tool / 2.0,
+                    step: tool * stepover / 100.0,
+                    stepdown: self.check("Stepdown", 0.0, false, max)?,
+                }
+            }
+            Operation::RectPocket => {
+                let tool = self.check("Tool dia", 0.0, false, max)?;
+                let stepover = self.check("Stepover %", 0.0, false, 100.0)?;
+                let width = self.check("Width X", 0.0, false, max)?;
+                let length = self.check("Length Y", 0.0, false, max)?;
+                if tool > width || tool > length {
+                    return Err(Problem::ToolTooBig);
+                }
+                Body::RectPocket {
+                    half: [(width - tool) / 2.0, (length - tool) / 2.0],
+                    step: tool * stepover / 100.0,
+                    stepdown: self.check("Stepdown", 0.0, false, max)?,
+                    plunge: self.check("Plunge", 0.0, false, max)?,
+                }
+            }
+            Operation::CircPocket => {
+                let tool = self.check("Tool dia", 0.0, false, max)?;
+                let stepover = self.check("Stepover %", 0.0, false, 100.0)?;
+                let diameter = self.check("Diameter", 0.0, false, max)?;
+                if tool > diameter {
+                    return Err(Problem::ToolTooBig);
+                }
+                Body::CircPocket {
+                    radius: (diameter - tool) / 2.0,
+                    step: tool * stepover / 100.0,
+                    stepdown: self.check("Stepdown", 0.0, false, max)?,
+                    plunge: self.check("Plunge", 0.0, false, max)?,
+                }
+            }
+            Operation::DrillGrid => Body::Drill {
+                pattern: Pattern::Grid {
+                    count: [
+                        self.check("Holes X", 1.0, true, MAX_HOLES)?.round() as u32,
+                        self.check("Holes Y", 1.0, true, MAX_HOLES)?.round() as u32,
+                    ],
+                    pitch: [self.value("Pitch X"), self.value("Pitch Y")],
+                },
+                peck: self.check("Peck", 0.0, true, max)?,
+            },
+            Operation::BoltCircle => Body::Drill {
+                pattern: Pattern::Circle {
+                    count: self.check("Holes", 1.0, true, MAX_HOLES)?.round() as u32,
+                    radius: self.check("Diameter", 0.0, false, max)? / 2.0,
+                    start: self.value("Start angle").to_radians(),
+                },
+                peck: self.check("Peck", 0.0, true, max)?,
+            },
+        };
+
+        Ok(Program {
+            operation: self.operation,
+            common,
+            body,
+            section: Section::Header,
+            pass: 0,
+            line: 0,
+        })
+    }
+}
+
+impl Fields for Wizard {
+    fn title(&self) -> &str {
+        self.operation.name()
+    }
+
+    fn count(&self) -> usize {
+        self.operation.fields().len()
+    }
+
+    /// Name and value of a field of the current operation
+    fn get(&self, i: usize) -> Option<(&'static str, f32)> {
+        let field = self.operation.fields().get(i)?;
+        Some((field.name, self.values[self.operation.index()][i]))
+    }
+}
+
+// Taken by every operation
+#[derive(Copy, Clone, Debug)]
+struct Common {
+    safe_z: f32,
+    rpm: f32,
+    depth: f32,
+    feed: f32,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Pattern {
+    Grid { count: [u32; 2], pitch: [f32; 2] },
+    Circle { count: u32, radius: f32, start: f32 },
+}
+
+impl Pattern {
+    fn holes(&self) -> u32 {
+        match *self {
+            Pattern::Grid { count, .. } => count[0] * count[1],
+            Pattern::Circle { count, .. } => count,
+        }
+    }
+
+    // Grid rows go back and forth, so there are no long moves between them
+    fn hole(&self, n: u32) -> [f32; 2] {
+        match *self {
+            Pattern::Grid { count, pitch } => {
+                let row = n / count[0];
+                let mut column = n % count[0];
+                if !row.is_multiple_of(2) {
+                    column = count[0] - 1 - column;
+                }
+                [column as f32 * pitch[0], row as f32 * pitch[1]]
+            }
+            Pattern::Circle {
+                count,
+                radius,
+                start,
+            } => {
+                let angle = start + 2.0 * core::f32::consts::PI * n as f32 / count as f32;
+                [radius * angle.cos(), radius * angle.sin()]
+            }
+        }
+    }
+}
+
+// The operation's own moves. Sizes are where the tool centre goes.
+#[derive(Copy, Clone, Debug)]
+enum Body {
+    Facing {
+        size: [f32; 2],
+        radius: f32,
+        step: f32,
+        stepdown: f32,
+    },
+    RectPocket {
+        half: [f32; 2],
+        step: f32,
+        stepdown: f32,
+        plunge: f32,
+    },
+    CircPocket {
+        radius: f32,
+        step: f32,
+        stepdown: f32,
+        plunge: f32,
+    },
+    Drill {
+        pattern: Pattern,
+        peck: f32,
+    },
+}
+
+#[derive(PartialEq, Copy, Clone, Debug)]
+enum Section {
+    Header,
+    Body,
+    Footer,
+    Done,
+}
+
+// Number of steps of at most `step` to cover `distance`, at least one
+fn steps(distance: f32, step: f32) -> u32 {
+    if step <= 0.0 || distance <= 0.0 {
+        1
+    } else {
+        ((distance / step) - 1e-4).ceil().max(1.0) as u32
+    }
+}
+
+/// A generated program, read a line at a time
+#[derive(Copy, Clone, Debug)]
+pub struct Program {
+    operation: Operation,
+    common: Common,
+    body: Body,
+    section: Section,
+    pass: u32, // Depth level, or hole
+    line: u32, // Within the section or pass
+}
+
+impl Program {
+    #[cfg_attr(test, allow(dead_code))]
+    pub fn operation(&self) -> Operation {
+        self.operation
+    }
+
+    /// Length in bytes with a newline after each line, for job progress
+    pub fn size(&self) -> u32 {
+        let mut program = *self;
+        program.section = Section::Header;
+        program.pass = 0;
+        program.line = 0;
+        let mut line = TextBuffer::new();
+        let mut size = 0;
+        while let Read::Line(bytes) = program.next_line(&mut line) {
+            size += bytes;
+        }
+        size
+    }
+
+    fn passes(&self) -> u32 {
+        match self.body {
+            Body::Facing { stepdown, .. }
+            | Body::RectPocket { stepdown, .. }
+            | Body::CircPocket { stepdown, .. } => steps(self.common.depth, stepdown),
+            Body::Drill { pattern, .. } => pattern.holes(),
+        }
+    }
+
+    // Z of the n'th of several cuts, `each` deeper than the last, down to
+    // the full depth
+    fn level(&self, n: u32, each: f32) -> f32 {
+        let depth = self.common.depth;
+        if each <= 0.0 {
+            -depth
+        } else {
+            -((n + 1) as f32 * each).min(depth)
+        }
+    }
+
+    fn header<W: Write>(&self, line: u32, out: &mut W) -> Option<fmt::Result> {
+        Some(match line {
+            0 => write!(out, "({})", self.operation.name()),
+            1 => write!(out, "G21 G90 G17"),
+            2 => write!(out, "G0 Z{:.3}", self.common.safe_z),
+            3 => write!(out, "M3 S{:.0}", self.common.rpm),
+            _ => return None,
+        })
+    }
+
+    fn footer<W: Write>(&self, line: u32, out: &mut W) -> Option<fmt::Result> {
+        Some(match line {
+            0 => write!(out, "G0 X0 Y0"),
+            1 => write!(out, "M5"),
+            2 => write!(out, "M2"),
+            _ => return None,
+        })
+    }
+
+    // Line `line` of pass `pass` of the body, or None once the pass is done
+    fn body<W: Write>(&self, pass: u32, line: u32, out: &mut W) -> Option<fmt::Result> {
+        let Common {
+            safe_z,
+            feed,
+            depth,
+            ..
+        } = self.common;
+        match self.body {
+            Body::Facing {
+                size,
+                radius,
+                step,
+                stepdown,
+            } => {
+                let z = self.level(pass, stepdown);
+                let rows = steps(size[1], step) + 1;
+                let row_y = |row: u32| (row as f32 * step).min(size[1]);
+                let left = -radius - CLEARANCE;
+                let right = size[0] + radius + CLEARANCE;
+                Some(match line {
+                    0 => write!(out, "G0 X{:.3} Y0", left),
+                    1 => write!(out, "G1 Z{:.3} F{:.0}", z, feed),
+                    _ => {
+                        let row = (line - 2) / 2;
+                        if row >= rows {
+                            return None;
+                        }
+                        if (line - 2).is_multiple_of(2) {
+                            let x = if row.is_multiple_of(2) { right } else { left };
+                            write!(out, "G1 X{:.3}", x)
+                        } else if row + 1 < rows {
+                            write!(out, "G1 Y{:.3}", row_y(row + 1))
+                        } else {
+                            write!(out, "G0 Z{:.3}", safe_z)
+                        }
+                    }
+                })
+            }
+            Body::RectPocket {
+                half,
+                step,
+                stepdown,
+                plunge,
+            } => {
+                let z = self.level(pass, stepdown);
+                let rings = steps(half[0].min(half[1]), step);
+                Some(match line {
+                    0 => write!(out, "G0 X0 Y0"),
+                    1 => write!(out, "G1 Z{:.3} F{:.0}", z, plunge),
+                    _ => {
+                        let ring = (line - 2) / 5;
+                        if ring > rings {
+                            return None;
+                        }
+                        if ring == rings {
+                            if !(line - 2).is_multiple_of(5) {
+                                return None;
+                            }
+                            return Some(write!(out, "G0 Z{:.3}", safe_z));
+                        }
+                        // Innermost first; the outermost is the finished wall
+                        let inset = (rings - 1 - ring) as f32 * step;
+                        let a = (half[0] - inset).max(0.0);
+                        let b = (half[1] - inset).max(0.0);
+                        match (line - 2) % 5 {
+                            0 => write!(out, "G1 X{:.3} Y{:.3} F{:.0}", a, -b, feed),
+                            1 => write!(out, "G1 Y{:.3}", b),
+                            2 => write!(out, "G1 X{:.3}", -a),
+                            3 => write!(out, "G1 Y{:.3}", -b),
+                            _ => write!(out, "G1 X{:.3}", a),
+                        }
+                    }
+                })
+            }
+            Body::CircPocket {
+                radius,
+                step,
+                stepdown,
+                plunge,
+            } => {
+                let z = self.level(pass, stepdown);
+                // A tool the size of the pocket just plunges
+                let rings = if radius > 0.0 { steps(radius, step) } else { 0 };
+                Some(match line {
+                    0 => write!(out, "G0 X0 Y0"),
+                    1 => write!(out, "G1 Z{:.3} F{:.0}", z, plunge),
+                    _ => {
+                        let ring = (line - 2) / 2;
+                        if ring > rings || (ring == rings && !(line - 2).is_multiple_of(2)) {
+                            return None;
+                        }
+                        if ring == rings {
+                            return Some(write!(out, "G0 Z{:.3}", safe_z));
+                        }
+                        let r = ((ring + 1) as f32 * step).min(radius);
+                        if (line - 2).is_multiple_of(2) {
+                            write!(out, "G1 X{:.3} Y0 F{:.0}", r, feed)
+                        } else {
+                            write!(out, "G3 X{:.3} Y0 I{:.3} J0", r, -r)
+                        }
+                    }
+                })
+            }
+            Body::Drill { pattern, peck } => {
+                let [x, y] = pattern.hole(pass);
+                let pecks = steps(depth, peck);
+                Some(match line {
+                    0 => write!(out, "G0 X{:.3} Y{:.3}", x, y),
+                    1 => write!(out, "G0 Z{:.3}", RETRACT),
+                    _ => {
+                        let n = (line - 2) / 3;
+                        if n >= pecks {
+                            return None;
+                        }
+                        let z = self.level(n, peck);
+                        match (line - 2) % 3 {
+                            0 => write!(out, "G1 Z{:.3} F{:.0}", z, feed),
+                            1 => write!(out, "G0 Z{:.3}", RETRACT),
+                            // Back down to just above the last peck
+                            _ if n + 1 < pecks => write!(out, "G0 Z{:.3}", z + RETRACT),
+                            _ => write!(out, "G0 Z{:.3}", safe_z),
+                        }
+                    }
+                })
+            }
+        }
+    }
+}
+
+impl LineSource for Program {
+    fn next_line(&mut self, out: &mut TextBuffer<LINE_LEN>) -> Read {
+        out.clear();
+        loop {
+            let written = match self.section {
+                Section::Header => self.header(self.line, out),
+                Section::Body if self.pass < self.passes() => self.body(self.pass, self.line, out),
+                Section::Body => {
+                    self.section = Section::Footer;
+                    self.line = 0;
+                    continue;
+                }
+                Section::Footer => self.footer(self.line, out),
+                Section::Done => return Read::End,
+            };
+            match written {
+                Some(result) => {
+                    self.line += 1;
+                    let bytes = out.as_str().len() as u32 + 1;
+                    return match result {
+                        Ok(()) => Read::Line(bytes),
+                        Err(_) => Read::TooLong(bytes),
+                    };
+                }
+                None => {
+                    // On to the next pass or section
+                    self.line = 0;
+                    match self.section {
+                        Section::Header => self.section = Section::Body,
+                        Section::Body => self.pass += 1,
+                        _ => self.section = Section::Done,
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gcode::Interpreter;
+
+    const OPERATIONS: [Operation; N_OPERATIONS] = [
+        Operation::Facing,
+        Operation::RectPocket,
+        Operation::CircPocket,
+        Operation::DrillGrid,
+        Operation::BoltCircle,
+    ];
+
+    // The defaults of `operation`, with some fields changed by name
+    fn wizard(operation: Operation, values: &[(&str, f32)]) -> Wizard {
+        let mut wizard = Wizard::new();
+        wizard.set_operation(operation);
+        for (name, value) in values {
+            let i = operation.fields().iter().position(|f| f.name == *name);
+            wizard.set(i.expect("no such field"), *value);
+        }
+        wizard
+    }
+
+    fn lines(wizard: &Wizard) -> Vec<String> {
+        let mut program = wizard.program().expect("no program");
+        let mut line = TextBuffer::new();
+        let mut lines = Vec::new();
+        while let Read::Line(_) = program.next_line(&mut line) {
+            lines.push(line.as_str().to_string());
+        }
+        lines
+    }
+
+    // The whole program around `body`, with the default safe Z
+    fn program(name: &str, rpm: &str, body: &[&str]) -> Vec<String> {
+        let header = [&*format!("({})", name), "G21 G90 G17", "G0 Z5.000", rpm];
+        let footer = ["G0 X0 Y0", "M5", "M2"];
+        header
+            .iter()
+            .chain(body)
+            .chain(&footer)
+            .map(|s| s.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn facing() {
+        // Three rows 6 mm apart, the last at the far edge, at two depths
+        let wizard = wizard(
+            Operation::Facing,
+            &[("Stock X", 10.0), ("Stock Y", 10.0), ("Tool dia", 10.0)],
+        );
+        let pass = |z: &str| {
+            vec![
+                "G0 X-7.000 Y0".to_string(),
+                format!("G1 Z{} F600", z),
+                "G1 X17.000".to_string(),
+                "G1 Y6.000".to_string(),
+                "G1 X-7.000".to_string(),
+                "G1 Y10.000".to_string(),
+                "G1 X17.000".to_string(),
+                "G0 Z5.000".to_string(),
+            ]
+        };
+        let body = [pass("-0.500"), pass("-1.000")].concat();
+        let body: Vec<&str> = body.iter().map(|s| s.as_str()).collect();
+        assert_eq!(lines(&wizard), program("Facing", "M3 S10000", &body));
+    }
+
+    #[test]
+    fn rect_pocket() {
+        // Tool centre within 7 by 4 of the middle, rings 3 mm apart
+        let wizard = wizard(
+            Operation::RectPocket,
+            &[
+                ("Width X", 20.0),
+                ("Length Y", 14.0),
+                ("Stepover %", 50.0),
+                ("Depth", 1.0),
+            ],
+        );
+        #[rustfmt::skip]
+        let body = [
+            "G0 X0 Y0", "G1 Z-1.000 F100",
+            "G1 X4.000 Y-1.000 F400", "G1 Y1.000", "G1 X-4.000", "G1 Y-1.000", "G1 X4.000",
+            "G1 X7.000 Y-4.000 F400", "G1 Y4.000", "G1 X-7.000", "G1 Y-4.000", "G1 X7.000",
+            "G0 Z5.000",
+        ];
+        assert_eq!(
+            lines(&wizard),
+            program("Rectangular pocket", "M3 S10000", &body)
+        );
+
+        let wizard = self::wizard(Operation::RectPocket, &[("Tool dia", 25.0)]);
+        assert_eq!(wizard.program().err(), Some(Problem::ToolTooBig));
+    }
+
+    #[test]
+    fn circ_pocket() {
+        // Rings at 2.4, 4.8 and the 6 mm wall, at two depths
+        let wizard = wizard(Operation::CircPocket, &[("Diameter", 18.0), ("Depth", 2.0)]);
+        let pass = |z: &str| {
+            let mut pass = vec!["G0 X0 Y0".to_string(), format!("G1 Z{} F100", z)];
+            for r in ["2.400", "4.800", "6.000"] {
+                pass.push(format!("G1 X{} Y0 F400", r));
+                pass.push(format!("G3 X{} Y0 I-{} J0", r, r));
+            }
+            pass.push("G0 Z5.000".to_string());
+            pass
+        };
+        let body = [pass("-1.000"), pass("-2.000")].concat();
+        let body: Vec<&str> = body.iter().map(|s| s.as_str()).collect();
+        assert_eq!(
+            lines(&wizard),
+            program("Circular pocket", "M3 S10000", &body)
+        );
+
+        // A tool the size of the pocket only plunges
+        let wizard = self::wizard(Operation::CircPocket, &[("Diameter", 6.0), ("Depth", 1.0)]);
+        let body = ["G0 X0 Y0", "G1 Z-1.000 F100", "G0 Z5.000"];
+        assert_eq!(
+            lines(&wizard),
+            program("Circular pocket", "M3 S10000", &body)
+        );
+        let wizard = self::wizard(Operation::CircPocket, &[("Tool dia", 31.0)]);
+        assert_eq!(wizard.program().err(), Some(Problem::ToolTooBig));
+    }
+
+    #[test]
+    fn drill_grid() {
+        let wizard = wizard(
+            Operation::DrillGrid,
+            &[
+                ("Holes X", 2.0),
+                ("Holes Y", 2.0),
+                ("Pitch X", 10.0),
+                ("Depth", 3.0),
+            ],
+        );
+        let lines = lines(&wizard);
+        // Two pecks, the second from just above the first
+        #[rustfmt::skip]
+        let hole = [
+            "G0 X0.000 Y0.000", "G0 Z1.000",
+            "G1 Z-2.000 F100", "G0 Z1.000", "G0 Z-1.000",
+            "G1 Z-3.000 F100", "G0 Z1.000", "G0 Z5.000",
+        ];
+        assert_eq!(&lines[4..12], hole);
+        assert_eq!(&lines[..4], &program("Drill grid", "M3 S3000", &[])[..4]);
+        // Back along the second row
+        let holes: Vec<&str> = lines
+            .iter()
+            .filter(|l| l.starts_with("G0 X"))
+            .map(|l| l.as_str())
+            .collect();
+        assert_eq!(
+            holes,
+            [
+                "G0 X0.000 Y0.000",
+                "G0 X10.000 Y0.000",
+                "G0 X10.000 Y20.000",
+                "G0 X0.000 Y20.000",
+                "G0 X0 Y0",
+            ]
+        );
+        assert_eq!(lines.len(), 4 + 4 * 8 + 3);
+
+        // No peck drills in one go
+        let wizard = self::wizard(Operation::DrillGrid, &[("Peck", 0.0)]);
+        let lines = self::lines(&wizard);
+        assert_eq!(
+            &lines[4..8],
+            [
+                "G0 X0.000 Y0.000",
+                "G0 Z1.000",
+                "G1 Z-10.000 F100",
+                "G0 Z1.000"
+            ]
+        );
+        assert_eq!(lines[8], "G0 Z5.000");
+    }
+
+    #[test]
+    fn bolt_circle() {
+        let wizard = wizard(
+            Operation::BoltCircle,
+            &[("Holes", 4.0), ("Diameter", 20.0), ("Start angle", 45.0)],
+        );
+        let holes: Vec<String> = lines(&wizard)
+            .into_iter()
+            .filter(|l| l.starts_with("G0 X") && l != "G0 X0 Y0")
+            .collect();
+        assert_eq!(
+            holes,
+            [
+                "G0 X7.071 Y7.071",
+                "G0 X-7.071 Y7.071",
+                "G0 X-7.071 Y-7.071",
+                "G0 X7.071 Y-7.071",
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_fields() {
+        let problem = |operation, values: &[(&str, f32)]| {
+            let wizard = wizard(operation, values);
+            let problem = wizard.program().err();
+            let mut text = String::new();
+            if let Some(problem) = problem {
+                problem.describe(operation, &mut text).unwrap();
+            }
+            text
+        };
+        assert_eq!(
+            problem(Operation::Facing, &[("Stepover %", 0.0)]),
+            "Check Stepover %"
+        );
+        assert_eq!(
+            problem(Operation::Facing, &[("Stepover %", 101.0)]),
+            "Check Stepover %"
+        );
+        assert_eq!(
+            problem(Operation::Facing, &[("Safe Z", 0.0)]),
+            "Check Safe Z"
+        );
+        assert_eq!(
+            problem(Operation::RectPocket, &[("Depth", -1.0)]),
+            "Check Depth"
+        );
+        assert_eq!(
+            problem(Operation::DrillGrid, &[("Holes Y", 0.0)]),
+            "Check Holes Y"
+        );
+        assert_eq!(
+            problem(Operation::BoltCircle, &[("Holes", 101.0)]),
+            "Check Holes"
+        );
+        assert_eq!(
+            problem(Operation::CircPocket, &[("Tool dia", 40.0)]),
+            "Tool too big for the pocket"
+        );
+        // A spindle that isn't turned on is allowed, as is a peck of 0
+        assert_eq!(problem(Operation::Facing, &[("Spindle krpm", 0.0)]), "");
+        assert_eq!(problem(Operation::BoltCircle, &[("Peck", 0.0)]), "");
+    }
+
+    #[test]
+    fn defaults_run_cleanly() {
+        for operation in OPERATIONS {
+            let wizard = wizard(operation, &[]);
+            let depth = wizard.value("Depth");
+            let lines = lines(&wizard);
+            let mut machine = Interpreter::new();
+            for line in &lines {
+                machine
+                    .execute_line(line)
+                    .unwrap_or_else(|e| panic!("{}: {:?}", line, e));
+                let z = machine.position()[2];
+                assert!(z >= -depth - 1e-3, "{} too deep", line);
+            }
+            // Back to the start, clear of the work
+            assert_eq!(machine.position(), [0.0, 0.0, 5.0]);
+            // Every line with its newline
+            let size: usize = lines.iter().map(|l| l.len() + 1).sum();
+            assert_eq!(wizard.program().unwrap().size() as usize, size);
+        }
+    }
+}